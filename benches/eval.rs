@@ -0,0 +1,45 @@
+#![feature(test)]
+
+extern crate hubris;
+extern crate test;
+
+use hubris::ast::ModuleId;
+use hubris::core::Name;
+use hubris::elaborate::ElabCx;
+use hubris::parser;
+use hubris::session::Session;
+use test::Bencher;
+
+use std::path::Path;
+
+/// Elaborate `benches/arith.hbr`, whose `big` is the unary number 1000
+/// built out of multiplications.
+fn arith() -> ElabCx {
+    let path = Path::new("benches/arith.hbr");
+    let module_id = ModuleId(0);
+    let parser = parser::from_file(path, module_id).unwrap();
+    let module = parser.parse().unwrap();
+
+    let session = Session::from_root(path);
+    session.add_source_map_for(module_id, parser.source_map);
+
+    let mut ecx = ElabCx::from_module(module, session);
+    ecx.elaborate_module().unwrap();
+    ecx
+}
+
+#[bench]
+fn eval_nbe(b: &mut Bencher) {
+    let ecx = arith();
+    let big = Name::from_str("big").to_term();
+
+    b.iter(|| ecx.ty_cx.eval(&big).unwrap());
+}
+
+#[bench]
+fn eval_substitution(b: &mut Bencher) {
+    let ecx = arith();
+    let big = Name::from_str("big").to_term();
+
+    b.iter(|| ecx.ty_cx.eval_by_substitution(&big).unwrap());
+}
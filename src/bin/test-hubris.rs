@@ -1,10 +1,15 @@
 extern crate hubris;
 extern crate term;
 
+use hubris::ast::ModuleId;
+use hubris::elaborate::ElabCx;
+use hubris::parser;
+use hubris::session::Session;
+
 use std::env;
-use std::fs::read_dir;
+use std::fs::{read_dir, File};
 use std::path::{Path, PathBuf};
-use std::io;
+use std::io::{self, Read};
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 enum Outcome {
@@ -24,28 +29,42 @@ fn main() {
 fn run_tests_in_dir(path: &Path, expected_outcome: Outcome) -> io::Result<()> {
     let mut should_of_failed = vec![];
     let mut should_of_passed = vec![];
+    let mut failed_differently = vec![];
 
     for file in try!(read_dir(path)) {
         let entry = try!(file);
         let test = entry.path();
 
-        let result = hubris::compile_file(
-            &test,
-            Some(PathBuf::from("/tmp/duh")));
-
         match expected_outcome {
-            Outcome::Fail => match result {
-                Err(_) => {},
-                Ok(_) => {
-                    should_of_failed.push(test.to_owned());
+            Outcome::Fail => {
+                let expected = try!(expected_error(&test));
+
+                match check_file(&test) {
+                    Err(e) => {
+                        let found = format!("{:?}", e);
+
+                        match expected {
+                            Some(ref kind) if error_kinds(&found).contains(kind) => {}
+                            _ => failed_differently.push((expected, found, test.to_owned())),
+                        }
+                    }
+                    Ok(_) => {
+                        should_of_failed.push(test.to_owned());
+                    }
                 }
-            },
-            Outcome::Pass => match result {
-                Err(e) => {
-                    let pair = (e, test.to_owned());
-                    should_of_passed.push(pair);
+            }
+            Outcome::Pass => {
+                let result = hubris::compile_file(
+                    &test,
+                    Some(PathBuf::from("/tmp/duh")));
+
+                match result {
+                    Err(e) => {
+                        let pair = (e, test.to_owned());
+                        should_of_passed.push(pair);
+                    }
+                    Ok(_) => {}
                 }
-                Ok(_) => {}
             }
         }
     }
@@ -54,6 +73,14 @@ fn run_tests_in_dir(path: &Path, expected_outcome: Outcome) -> io::Result<()> {
         println!("{}: should have failed", test.display());
     }
 
+    for (expected, found, test) in failed_differently {
+        match expected {
+            Some(kind) => println!("{}: should have failed with `{}`", test.display(), kind),
+            None => println!("{}: missing an `-- error: <kind>` line", test.display()),
+        }
+        println!("{}", found);
+    }
+
     for (e, test) in should_of_passed {
         println!("{}: should have passed", test.display());
         println!("{:?}", e);
@@ -61,3 +88,105 @@ fn run_tests_in_dir(path: &Path, expected_outcome: Outcome) -> io::Result<()> {
 
     Ok(())
 }
+
+/// Elaborate the module at `path`, without reporting the error it fails
+/// with, if any.
+fn check_file(path: &Path) -> Result<(), hubris::Error> {
+    let module_id = ModuleId(0);
+    let parser = try!(parser::from_file(path, module_id));
+    let module = try!(parser.parse());
+
+    let session = Session::from_root(path);
+    session.add_source_map_for(module_id, parser.source_map);
+
+    let mut ecx = try!(ElabCx::from_module(module, session));
+    try!(ecx.elaborate_module());
+
+    Ok(())
+}
+
+/// A failing test names the kind of error it should fail with on a line of
+/// the form `-- error: <kind>`, the kind is the name of the variant of the
+/// error which is reported, and must match it exactly.
+fn expected_error(path: &Path) -> io::Result<Option<String>> {
+    let mut contents = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut contents));
+
+    Ok(contents.lines()
+               .filter_map(|line| {
+                   if line.starts_with("-- error:") {
+                       Some(line["-- error:".len()..].trim().to_string())
+                   } else {
+                       None
+                   }
+               })
+               .next())
+}
+
+/// Variants which only wrap the errors of another part of the compiler.
+const WRAPPERS: &'static [&'static str] =
+    &["Elaborator", "TypeCk", "Solver", "Parser", "Term", "Io", "Many"];
+
+/// The names of the variants of the errors which are reported in `debug`,
+/// the `Debug` output of an error, looking through the variants which only
+/// wrap another error and the lists of errors reported together.
+fn error_kinds(debug: &str) -> Vec<String> {
+    let debug = debug.trim();
+    let name_len = debug.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                        .unwrap_or(debug.len());
+    let (name, rest) = debug.split_at(name_len);
+
+    if !WRAPPERS.contains(&name) || !rest.starts_with('(') {
+        return vec![name.to_string()];
+    }
+
+    let inner = rest[1..rest.len() - 1].trim();
+
+    if inner.starts_with('[') {
+        split_top_level(&inner[1..inner.len() - 1])
+            .into_iter()
+            .flat_map(|error| error_kinds(error))
+            .collect()
+    } else {
+        error_kinds(inner)
+    }
+}
+
+/// Split the `Debug` output of a list's elements at the commas which are
+/// not nested inside another value or a string.
+fn split_top_level(debug: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in debug.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&debug[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if debug[start..].trim().len() > 0 {
+        parts.push(&debug[start..]);
+    }
+
+    parts
+}
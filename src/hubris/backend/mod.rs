@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::fmt::{self, Debug, Formatter, Display};
 use std::fs::File;
 use std::path::{Path};
@@ -124,9 +125,15 @@ fn term_to_rust(term: &Term) -> Doc {
             to_object("|".pretty() + seperate(&args[..], &",".pretty()) + "|".pretty() +
                 block(term_to_rust(body)))
         }
+        &Term::Let(ref name, ref value, ref body) => {
+            block("let ".pretty() + name_to_rust(name) + " = ".pretty() +
+                  term_to_rust(value) + ";".pretty() + Doc::newline() +
+                  term_to_rust(body))
+        }
         &Term::Panic(ref msg) => {
             "panic!".pretty() + parens("\"".pretty() + msg.pretty() + "\"".pretty())
         }
+        &Term::Erased => to_object("()".pretty()),
         t => panic!("{:?}", t),
     }
 }
@@ -166,7 +173,10 @@ enum Term {
     Switch(Rc<Term>),
     Call(Rc<Term>, Vec<Term>),
     Lambda(Vec<core::Name>, Box<Term>),
+    Let(core::Name, Box<Term>, Box<Term>),
     Panic(String),
+    /// A proof, which has no computational content.
+    Erased,
 }
 
 impl Pretty for Term {
@@ -186,7 +196,11 @@ impl Pretty for Term {
                 f.pretty() + parens(seperate(&pargs[..], &",".pretty()))
             }
             &Lambda(_, ref body) => body.pretty(),
+            &Let(ref name, ref value, ref body) =>
+                "let ".pretty() + name.pretty() + " = ".pretty() + value.pretty() +
+                    " in ".pretty() + body.pretty(),
             &Panic(_) => "panic".pretty(),
+            &Erased => "erased".pretty(),
         }
     }
 }
@@ -200,13 +214,17 @@ impl Display for Term {
 /// This context is used to do type erasure, and lowering of `core::Term` to an
 /// untyped lambda calculus.
 struct ErasureCx<'tcx> {
-    ty_cx: &'tcx TyCtxt
+    ty_cx: &'tcx TyCtxt,
+    /// The locals bound to proofs, decided once from the type of their
+    /// binder.
+    erased: HashSet<core::Name>,
 }
 
 impl<'tcx> ErasureCx<'tcx> {
     pub fn new(ty_cx: &'tcx TyCtxt) -> ErasureCx<'tcx> {
         ErasureCx {
-            ty_cx: ty_cx
+            ty_cx: ty_cx,
+            erased: HashSet::new(),
         }
     }
 
@@ -226,19 +244,29 @@ impl<'tcx> ErasureCx<'tcx> {
     fn lower_def(&mut self, def: core::Definition) -> Definition {
         let core::Definition {
             name,
+            univ_params,
             args,
             ty,
             body,
             reduction,
+            coercion,
         } = def;
 
         println!("name: {}", name);
         println!("ty: {}", ty);
         println!("body: {}", body);
 
+        // Proofs can't influence the result of a computation, so we
+        // never need to compute them.
+        let body = if self.ty_cx.is_proposition(&ty) {
+            Term::Erased
+        } else {
+            self.lower_term(body)
+        };
+
         let def = Definition {
             name: name,
-            body: self.lower_term(body),
+            body: body,
         };
 
         println!("def: {}", def);
@@ -246,30 +274,83 @@ impl<'tcx> ErasureCx<'tcx> {
         def
     }
 
+    /// Open `binder` with a local, remembering whether it stands for a
+    /// proof.
+    fn open_binder(&mut self, binder: core::Binder) -> core::Name {
+        let is_proof = self.ty_cx.is_proposition(&binder.ty);
+        let local = self.ty_cx.local(binder);
+
+        if is_proof {
+            self.erased.insert(local.clone());
+        }
+
+        local
+    }
+
+    /// The type of the head of an application, read off the local or
+    /// global it refers to when possible.
+    fn head_type(&self, head: &core::Term) -> Option<core::Term> {
+        match head {
+            &core::Term::Var { name: core::Name::Local { ref ty, .. } } =>
+                Some(ty.clone().into_term()),
+            &core::Term::Var { ref name } =>
+                self.ty_cx.global_type(name).ok(),
+            _ => self.ty_cx.type_infer_term(head).ok().map(|(ty, _)| ty),
+        }
+    }
+
     fn lower_term(&mut self, term: core::Term) -> Term {
         match term {
             lam @ core::Term::Lambda { .. } => {
                 let mut final_body = lam;
                 let mut names = vec![];
                 while let core::Term::Lambda { binder, body, .. } = final_body {
-                    println!("binder: {} {}",
-                    binder.name, binder.ty);
-                    names.push(binder.name.clone());
-                    final_body = *body;
+                    // We open the binder with a local so we are able to
+                    // find the types of the subterms of the body.
+                    let local = self.open_binder(binder);
+                    final_body = body.instantiate(&local.to_term());
+                    names.push(local);
                 }
                 Term::Lambda(names, Box::new(self.lower_term(final_body)))
             }
+            core::Term::Let { binder, value, body, .. } => {
+                let local = self.open_binder(binder);
+                let body = body.instantiate(&local.to_term());
+                let value = if self.erased.contains(&local) {
+                    Term::Erased
+                } else {
+                    self.lower_term(value.into_term())
+                };
+                Term::Let(local, Box::new(value), Box::new(self.lower_term(body)))
+            }
             app @ core::Term::App { .. } => {
                 let (head, args) = app.uncurry();
-                println!("head: {}", head);
+                let mut fun_ty = self.head_type(&head);
                 let lhead = self.lower_term(head);
-                for arg in &args {
-                    println!("args: {}", arg);
+                let mut largs = vec![];
+
+                // An argument is a proof exactly when the binder it is
+                // passed for has a proposition as its type.
+                for arg in args {
+                    let is_proof = match fun_ty.take().map(|ty| self.ty_cx.whnf(&ty)) {
+                        Some(Ok((core::Term::Forall { binder, term, .. }, _))) => {
+                            fun_ty = Some(term.instantiate(&arg));
+                            self.ty_cx.is_proposition(&binder.ty)
+                        }
+                        _ => false,
+                    };
+
+                    if is_proof {
+                        largs.push(Term::Erased);
+                    } else {
+                        largs.push(self.lower_term(arg));
+                    }
                 }
-            Term::Call(Rc::new(lhead),
-                       args.into_iter()
-                           .map(|arg| self.lower_term(arg))
-                           .collect())
+
+                Term::Call(Rc::new(lhead), largs)
+            }
+            core::Term::Var { ref name } if self.erased.contains(name) => {
+                Term::Erased
             }
             core::Term::Var { name } => {
                 println!("name: {}", name);
@@ -143,7 +143,8 @@ fn lower_module(module: core::Module) -> Module {
             module.defs
                   .into_iter()
                   .filter_map(|i| match i {
-                      core::Item::Fn(d) => Some(lower_def(d)),
+                      core::Item::Fn(d) |
+                      core::Item::Instance(d) => Some(lower_def(d)),
                       _ => None,
                   })
                   .collect()
@@ -1,18 +1,23 @@
+use super::level::Level;
 use super::name::Name;
 use super::term::Term;
+use super::term_ref::TermRef;
 
 use super::super::pretty::*;
 
 #[derive(Debug, Copy, Clone, PartialEq, Hash, Eq)]
 pub enum BindingMode {
     Implicit,
-    Explicit
+    Explicit,
+    /// Bound to an instance of a class, which is found by instance
+    /// resolution.
+    InstImplicit,
 }
 
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub struct Binder {
     pub name: Name,
-    pub ty: Box<Term>,
+    pub ty: TermRef,
     pub mode: BindingMode,
 }
 
@@ -20,7 +25,7 @@ impl Binder {
     pub fn explicit(name: Name, ty: Term) -> Binder {
         Binder {
             name: name,
-            ty: Box::new(ty),
+            ty: TermRef::new(ty),
             mode: BindingMode::Explicit,
         }
 
@@ -29,7 +34,7 @@ impl Binder {
     pub fn implicit(name: Name, ty: Term) -> Binder {
         Binder {
             name: name,
-            ty: Box::new(ty),
+            ty: TermRef::new(ty),
             mode: BindingMode::Implicit,
         }
     }
@@ -38,7 +43,7 @@ impl Binder {
     pub fn with_mode(name: Name, ty: Term, mode: BindingMode) -> Binder {
         Binder {
             name: name,
-            ty: Box::new(ty),
+            ty: TermRef::new(ty),
             mode: mode,
         }
     }
@@ -46,7 +51,7 @@ impl Binder {
     pub fn abst(&self, index: usize, x: &Name) -> Binder {
         Binder {
             name: self.name.clone(),
-            ty: Box::new(self.ty.abst(index, x)),
+            ty: self.ty.abst(index, x),
             mode: self.mode.clone(),
         }
     }
@@ -54,23 +59,55 @@ impl Binder {
     pub fn replace(&self, index: usize, subst: &Term) -> Binder {
         Binder {
             name: self.name.clone(),
-            ty: Box::new(self.ty.replace(index, subst)),
+            ty: self.ty.replace(index, subst),
             mode: self.mode.clone(),
         }
     }
 
+    pub fn map_levels<F: Fn(&Level) -> Level>(&self, f: &F) -> Binder {
+        Binder {
+            name: self.name.clone(),
+            ty: TermRef::new(self.ty.map_levels(f)),
+            mode: self.mode.clone(),
+        }
+    }
+
+    pub fn map_names<F: Fn(&Name) -> Name>(&self, f: &F) -> Binder {
+        Binder {
+            name: self.name.clone(),
+            ty: TermRef::new(self.ty.map_names(f)),
+            mode: self.mode.clone(),
+        }
+    }
+
+    pub fn is_explicit(&self) -> bool {
+        match self.mode {
+            BindingMode::Explicit => true,
+            _ => false
+        }
+    }
+
     pub fn is_implicit(&self) -> bool {
         match self.mode {
             BindingMode::Implicit => true,
             _ => false
         }
     }
+
+    pub fn is_inst_implicit(&self) -> bool {
+        match self.mode {
+            BindingMode::InstImplicit => true,
+            _ => false
+        }
+    }
 }
 
 impl Pretty for Binder {
     fn pretty(&self) -> Doc {
         if self.is_implicit() {
             braces(self.name.pretty() + " : ".pretty() + self.ty.pretty())
+        } else if self.is_inst_implicit() {
+            "[".pretty() + self.name.pretty() + " : ".pretty() + self.ty.pretty() + "]".pretty()
         } else {
             parens(self.name.pretty() + " : ".pretty() + self.ty.pretty())
         }
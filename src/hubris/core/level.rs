@@ -1,9 +1,311 @@
-enum Level {
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use super::name::Name;
+
+use super::super::pretty::*;
+
+/// A universe level, every `Sort` is indexed by one of these, and the
+/// type of `Sort l` is `Sort (l + 1)`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Level {
     Zero,
-    Succ(Level),
+    Succ(Box<Level>),
     Max(Box<Level>, Box<Level>),
+    /// `imax l1 l2` is zero when `l2` is zero, and `max l1 l2` otherwise,
+    /// this is the level of a `forall` whose body lives in `l2`.
     IMax(Box<Level>, Box<Level>),
-    Param(Name), // There is no way to write this currently
-    Global(Name),
-    Meta(Name),
+    Param(Name),
+    Meta(usize),
+}
+
+impl Level {
+    pub fn one() -> Level {
+        Level::Zero.succ()
+    }
+
+    pub fn succ(self) -> Level {
+        Level::Succ(Box::new(self))
+    }
+
+    pub fn max(l1: Level, l2: Level) -> Level {
+        Level::Max(Box::new(l1), Box::new(l2))
+    }
+
+    pub fn imax(l1: Level, l2: Level) -> Level {
+        Level::IMax(Box::new(l1), Box::new(l2))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            &Level::Zero => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_meta(&self) -> bool {
+        match self {
+            &Level::Meta(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Strips all the successors off of a level, returning the base level
+    /// and the number of successors that were removed.
+    pub fn to_offset(&self) -> (&Level, usize) {
+        let mut cursor = self;
+        let mut offset = 0;
+
+        while let &Level::Succ(ref l) = cursor {
+            cursor = &**l;
+            offset += 1;
+        }
+
+        (cursor, offset)
+    }
+
+    /// The inverse of `to_offset`.
+    pub fn with_offset(self, offset: usize) -> Level {
+        let mut result = self;
+        for _ in 0..offset {
+            result = result.succ();
+        }
+        result
+    }
+
+    /// The level whose `k`th successor is this one, a `max` has one when all
+    /// of its arguments do.
+    pub fn without_offset(&self, k: usize) -> Option<Level> {
+        match self {
+            &Level::Max(ref l1, ref l2) =>
+                l1.without_offset(k).and_then(|l1| {
+                    l2.without_offset(k).map(|l2| Level::max(l1, l2))
+                }),
+            l => {
+                let (base, offset) = l.to_offset();
+
+                if offset >= k {
+                    Some(base.clone().with_offset(offset - k))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// A level that can never be instantiated to zero.
+    pub fn is_never_zero(&self) -> bool {
+        use self::Level::*;
+
+        match self {
+            &Zero | &Param(_) | &Meta(_) => false,
+            &Succ(_) => true,
+            &Max(ref l1, ref l2) => l1.is_never_zero() || l2.is_never_zero(),
+            &IMax(_, ref l2) => l2.is_never_zero(),
+        }
+    }
+
+    /// Put a level into a simpler form, this is not a complete decision
+    /// procedure but it handles all of the levels produced by the
+    /// type checker for closed terms.
+    ///
+    /// A `max` is flattened into its arguments, with the successors pushed
+    /// inside of it, and then put in a canonical order without duplicates,
+    /// so `max u v` and `max v u` have the same normal form.
+    pub fn normalize(&self) -> Level {
+        use self::Level::*;
+
+        match self {
+            &Zero | &Param(_) | &Meta(_) => self.clone(),
+            &Succ(ref l) => {
+                let args = l.normalize().max_args();
+                Level::max_of(args.into_iter().map(|(l, k)| (l, k + 1)).collect())
+            }
+            &Max(ref l1, ref l2) => {
+                let mut args = l1.normalize().max_args();
+                args.extend(l2.normalize().max_args().into_iter());
+                Level::max_of(args)
+            }
+            &IMax(ref l1, ref l2) => {
+                let l2 = l2.normalize();
+
+                if l2.is_zero() {
+                    Zero
+                } else if l2.is_never_zero() {
+                    Level::max((**l1).clone(), l2).normalize()
+                } else {
+                    let l1 = l1.normalize();
+                    // `imax 1 l` is `l`, whether or not `l` is zero.
+                    if l1 == l2 || l1.is_zero() || l1 == Level::one() {
+                        l2
+                    } else {
+                        Level::imax(l1, l2)
+                    }
+                }
+            }
+        }
+    }
+
+    /// The arguments of a normalized `max`, split into their base levels
+    /// and the number of successors applied to them.
+    fn max_args(&self) -> Vec<(Level, usize)> {
+        match self {
+            &Level::Max(ref l1, ref l2) => {
+                let mut args = l1.max_args();
+                args.extend(l2.max_args().into_iter());
+                args
+            }
+            l => {
+                let (base, offset) = l.to_offset();
+                vec![(base.clone(), offset)]
+            }
+        }
+    }
+
+    /// The `max` of `args` in canonical form. Only the largest offset of each
+    /// base level matters, and a constant is dropped when another argument
+    /// is at least as large.
+    fn max_of(args: Vec<(Level, usize)>) -> Level {
+        let mut canonical: Vec<(Level, usize)> = vec![];
+
+        for (base, offset) in args {
+            match canonical.iter().position(|a| a.0 == base) {
+                Some(i) => canonical[i].1 = ::std::cmp::max(canonical[i].1, offset),
+                None => canonical.push((base, offset)),
+            }
+        }
+
+        let constant = canonical.iter().find(|a| a.0.is_zero()).map(|a| a.1);
+
+        if let Some(k) = constant {
+            if canonical.iter().any(|a| !a.0.is_zero() && a.1 >= k) {
+                canonical.retain(|a| !a.0.is_zero());
+            }
+        }
+
+        canonical.sort_by_key(|a| (format!("{}", a.0), a.1));
+
+        let mut args = canonical.into_iter().rev().map(|(base, offset)| base.with_offset(offset));
+        let last = args.next().unwrap_or(Level::Zero);
+        args.fold(last, |max, arg| Level::max(arg, max))
+    }
+
+    /// Check whether two levels are equivalent for every instantiation
+    /// of their parameters.
+    pub fn is_equiv(&self, other: &Level) -> bool {
+        self == other || self.normalize() == other.normalize()
+    }
+
+    /// Check whether this level is at most `other` for every instantiation
+    /// of their parameters. Like `normalize` this is not complete, a level
+    /// we can't compare is never smaller than another.
+    pub fn leq(&self, other: &Level) -> bool {
+        let other = other.normalize().max_args();
+
+        self.normalize().max_args().into_iter().all(|arg| Level::arg_leq(arg, &other))
+    }
+
+    /// Whether `base + offset` is at most one of the arguments of a `max`.
+    fn arg_leq((base, offset): (Level, usize), max: &[(Level, usize)]) -> bool {
+        use self::Level::*;
+
+        match base {
+            Zero => offset == 0 || max.iter().any(|a| offset <= a.1),
+            // `imax l1 l2` is at most `max l1 l2`.
+            IMax(ref l1, ref l2) if !max.iter().any(|a| a.0 == base && offset <= a.1) =>
+                Level::max((**l1).clone(), (**l2).clone()).with_offset(offset)
+                    .normalize().max_args().into_iter()
+                    .all(|arg| Level::arg_leq(arg, max)),
+            _ => max.iter().any(|a| a.0 == base && offset <= a.1),
+        }
+    }
+
+    pub fn has_meta(&self) -> bool {
+        use self::Level::*;
+
+        match self {
+            &Zero | &Param(_) => false,
+            &Meta(_) => true,
+            &Succ(ref l) => l.has_meta(),
+            &Max(ref l1, ref l2) |
+            &IMax(ref l1, ref l2) => l1.has_meta() || l2.has_meta(),
+        }
+    }
+
+    pub fn occurs_meta(&self, meta: usize) -> bool {
+        use self::Level::*;
+
+        match self {
+            &Zero | &Param(_) => false,
+            &Meta(m) => m == meta,
+            &Succ(ref l) => l.occurs_meta(meta),
+            &Max(ref l1, ref l2) |
+            &IMax(ref l1, ref l2) => l1.occurs_meta(meta) || l2.occurs_meta(meta),
+        }
+    }
+
+    /// Apply `f` to every leaf of the level, replacing it with the result
+    /// when `f` returns `Some`.
+    pub fn replace<F: Fn(&Level) -> Option<Level>>(&self, f: &F) -> Level {
+        use self::Level::*;
+
+        if let Some(l) = f(self) {
+            return l;
+        }
+
+        match self {
+            &Zero | &Param(_) | &Meta(_) => self.clone(),
+            &Succ(ref l) => l.replace(f).succ(),
+            &Max(ref l1, ref l2) => Level::max(l1.replace(f), l2.replace(f)),
+            &IMax(ref l1, ref l2) => Level::imax(l1.replace(f), l2.replace(f)),
+        }
+    }
+
+    pub fn instantiate_params(&self, params: &[Name], levels: &[Level]) -> Level {
+        self.replace(&|l| match l {
+            &Level::Param(ref p) =>
+                params.iter()
+                      .position(|q| q == p)
+                      .and_then(|i| levels.get(i).cloned()),
+            _ => None,
+        })
+    }
+
+    pub fn instantiate_metas(&self, solutions: &HashMap<usize, Level>) -> Level {
+        self.replace(&|l| match l {
+            &Level::Meta(m) => solutions.get(&m).map(|s| s.instantiate_metas(solutions)),
+            _ => None,
+        })
+    }
+}
+
+impl Pretty for Level {
+    fn pretty(&self) -> Doc {
+        use self::Level::*;
+
+        let (base, offset) = self.to_offset();
+
+        let base_doc = match base {
+            &Zero => return Doc::text(format!("{}", offset)),
+            &Succ(_) => unreachable!(),
+            &Max(ref l1, ref l2) =>
+                parens("max ".pretty() + l1.pretty() + " ".pretty() + l2.pretty()),
+            &IMax(ref l1, ref l2) =>
+                parens("imax ".pretty() + l1.pretty() + " ".pretty() + l2.pretty()),
+            &Param(ref n) => n.pretty(),
+            &Meta(m) => Doc::text(format!("?u{}", m)),
+        };
+
+        if offset == 0 {
+            base_doc
+        } else {
+            base_doc + Doc::text(format!("+{}", offset))
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        format(self, formatter)
+    }
 }
@@ -7,13 +7,17 @@ use super::ast::Span;
 use super::pretty::*;
 
 pub mod binder;
+pub mod level;
 pub mod name;
 pub mod term;
+pub mod term_ref;
 // pub mod visit;
 // pub mod validate;
 pub use self::binder::*;
+pub use self::level::*;
 pub use self::name::*;
 pub use self::term::*;
+pub use self::term_ref::*;
 
 #[derive(Debug)]
 pub struct Module {
@@ -34,11 +38,34 @@ impl Module {
 pub struct Data {
     pub span: Span,
     pub name: Name,
+    pub univ_params: Vec<Name>,
     // Not sure if this is the best choice, should it be a binder?
     // we try to strip as much high level structure as possible.
     pub parameters: Vec<Name>,
     pub ty: Term,
     pub ctors: Vec<Constructor>,
+    /// The types declared together in a `mutual` block, including this
+    /// one, or empty if it was declared on its own.
+    pub mutual: Vec<Name>,
+    /// The names of the fields if this is a `structure`, each of which
+    /// has a projection `T.field`. Other types with a single non-recursive
+    /// constructor and no indices get the fields `_1`, `_2`, ... when they
+    /// are declared.
+    pub fields: Option<Vec<Name>>,
+    /// Whether this is a `class`, whose instances are found by instance
+    /// resolution.
+    pub class: bool,
+}
+
+impl Data {
+    /// The name of the projection for one of the fields of a structure.
+    pub fn projection(&self, field: &Name) -> Name {
+        match field {
+            &Name::Qual { ref components, .. } =>
+                self.name.in_scope(components.last().unwrap().clone()).unwrap(),
+            _ => panic!("the field {} is not a name", field),
+        }
+    }
 }
 
 pub type Constructor = (Name, Term);
@@ -60,7 +87,10 @@ pub struct Extern {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Item {
     Data(Data),
+    Mutual(Vec<Data>),
     Fn(Function),
+    /// A definition which is also an instance of a class.
+    Instance(Function),
     Axiom(Axiom),
     Extern(Extern),
 }
@@ -70,7 +100,8 @@ impl Display for Item {
         use self::Item::*;
 
         match self {
-            &Fn(ref fun) => write!(formatter, "{}", fun),
+            &Fn(ref fun) |
+            &Instance(ref fun) => write!(formatter, "{}", fun),
             d => write!(formatter, "{:?}", d),
         }
     }
@@ -81,10 +112,14 @@ pub type Function = Definition;
 #[derive(Debug, Clone, PartialEq)]
 pub struct Definition {
     pub name: Name,
+    pub univ_params: Vec<Name>,
     pub args: Vec<Name>,
     pub ty: Term,
     pub body: Term,
     pub reduction: DeltaReduction,
+    /// Whether the elaborator may insert the definition to coerce its first
+    /// explicit argument to its type.
+    pub coercion: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -98,12 +133,21 @@ impl Pretty for Function {
     fn pretty(&self) -> Doc {
         let &Function {
             ref name,
+            ref univ_params,
             ref ty,
             ref body,
             ..
         } = self;
+
+        let univ_params = if univ_params.len() == 0 {
+            "".pretty()
+        } else {
+            let ps: Vec<_> = univ_params.iter().map(|p| p.pretty()).collect();
+            ".{".pretty() + seperate(&ps[..], &" ".pretty()) + "}".pretty()
+        };
+
         // TODO: Would you fix this TK?
-        Pretty::pretty("def ") + Pretty::pretty(name) + Pretty::pretty(" : ") + Pretty::pretty(ty) +
+        Pretty::pretty("def ") + Pretty::pretty(name) + univ_params + Pretty::pretty(" : ") + Pretty::pretty(ty) +
         Pretty::pretty(" := \n") + Pretty::pretty(body) + Pretty::pretty("\nend")
     }
 }
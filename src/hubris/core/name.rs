@@ -3,8 +3,9 @@ use super::super::ast::{Span, HasSpan};
 use std::fmt::{self, Display, Formatter};
 use std::hash::{Hash, Hasher};
 
-use super::Term;
+use super::{Term, TermRef};
 use super::BindingMode;
+use super::Level;
 
 use super::super::pretty::*;
 
@@ -18,16 +19,22 @@ pub enum Name {
     Local {
         number: usize,
         repr: String,
-        ty: Box<Term>,
+        ty: TermRef,
         binding_info: BindingMode,
     },
+    /// A reference to a global, if the global is universe polymorphic
+    /// `levels` is the instantiation of its universe parameters. An empty
+    /// list of levels instantiates each parameter with itself, the type
+    /// checker spells these out so two references are only equal when
+    /// they are instantiated at the same levels.
     Qual {
         span: Span,
         components: Vec<String>,
+        levels: Vec<Level>,
     },
     Meta {
         number: usize,
-        ty: Box<Term>,
+        ty: TermRef,
     },
 }
 
@@ -36,6 +43,7 @@ impl Name {
         Name::Qual {
             span: Span::dummy(),
             components: vec![s.to_owned()],
+            levels: vec![],
         }
     }
 
@@ -84,24 +92,78 @@ impl Name {
         Name::Qual {
             span: Span::dummy(),
             components: components,
+            levels: vec![],
         }
     }
 
     pub fn in_scope(&self, component: String) -> Option<Name> {
         match self {
-            &Name::Qual { span, ref components } => {
+            &Name::Qual { span, ref components, .. } => {
                 let mut components = components.clone();
                 components.push(component);
 
                 Some(Name::Qual {
                     span: span,
                     components: components,
+                    levels: vec![],
                 })
             }
             _ => None,
         }
     }
 
+    /// The universe levels a global is instantiated with.
+    pub fn levels(&self) -> &[Level] {
+        match self {
+            &Name::Qual { ref levels, .. } => &levels[..],
+            _ => &[],
+        }
+    }
+
+    pub fn with_levels(mut self, new_levels: Vec<Level>) -> Name {
+        match &mut self {
+            &mut Name::Qual { ref mut levels, .. } =>
+                *levels = new_levels,
+            _ => panic!("only globals can be instantiated with universe levels"),
+        }
+
+        self
+    }
+
+    /// The global without the levels it is instantiated at, the tables of
+    /// the type checker are keyed by these.
+    pub fn without_levels(&self) -> Name {
+        match self {
+            &Name::Qual { span, ref components, .. } => Name::Qual {
+                span: span,
+                components: components.clone(),
+                levels: vec![],
+            },
+            n => n.clone(),
+        }
+    }
+
+    /// Whether two names refer to the same variable, even if they are
+    /// globals instantiated at different levels.
+    pub fn same_variable(&self, other: &Name) -> bool {
+        match (self, other) {
+            (&Name::Qual { components: ref components1, .. },
+             &Name::Qual { components: ref components2, .. }) => components1 == components2,
+            _ => self == other,
+        }
+    }
+
+    pub fn map_levels<F: Fn(&Level) -> Level>(&self, f: &F) -> Name {
+        match self {
+            &Name::Qual { span, ref components, ref levels } => Name::Qual {
+                span: span,
+                components: components.clone(),
+                levels: levels.iter().map(|l| f(l)).collect(),
+            },
+            n => n.clone(),
+        }
+    }
+
     pub fn is_implicit(&self) -> bool {
         match self {
             &Name::Local { binding_info: BindingMode::Implicit, .. } => true,
@@ -134,8 +196,9 @@ impl PartialEq for Name {
         match (self, other) {
             (&DeBruijn { index: ref index1, .. },
              &DeBruijn { index: ref index2, .. }) => index1 == index2,
-            (&Qual { components: ref components1, .. },
-             &Qual { components: ref components2, .. }) => components1 == components2,
+            (&Qual { components: ref components1, levels: ref levels1, .. },
+             &Qual { components: ref components2, levels: ref levels2, .. }) =>
+                components1 == components2 && levels1 == levels2,
             (&Local { number: ref n1, .. },
              &Local { number: ref n2, .. }) =>
                 n1 == n2,
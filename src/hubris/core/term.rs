@@ -1,9 +1,10 @@
 use super::super::ast::{Span, HasSpan};
 
+use std::cmp::max;
 use std::fmt::{self, Display, Formatter};
 use std::hash::{Hash, Hasher};
 
-use super::Name;
+use super::{Name, Level, TermRef};
 use super::{Binder, BindingMode, pretty_binders};
 
 use super::super::pretty::*;
@@ -15,23 +16,42 @@ pub enum Term {
     },
     App {
         span: Span,
-        fun: Box<Term>,
-        arg: Box<Term>,
+        fun: TermRef,
+        arg: TermRef,
     },
     Forall {
         span: Span,
         binder: Binder,
-        term: Box<Term>,
+        term: TermRef,
     },
     Lambda {
         span: Span,
         binder: Binder,
-        body: Box<Term>,
+        body: TermRef,
+    },
+    /// `let x : T := v in b`, the binder carries the declared type of `x`
+    /// and `body` refers to `x` as the bound variable 0.
+    Let {
+        span: Span,
+        binder: Binder,
+        value: TermRef,
+        body: TermRef,
+    },
+    Sort {
+        level: Level,
     },
-    Type,
 }
 
 impl Term {
+    pub fn sort(level: Level) -> Term {
+        Term::Sort { level: level }
+    }
+
+    /// The impredicative sort of propositions, `Sort 0`.
+    pub fn prop() -> Term {
+        Term::sort(Level::Zero)
+    }
+
     pub fn abstract_lambda(locals: Vec<Name>, t: Term) -> Term {
         let mut result = t;
         for local in locals.into_iter().rev() {
@@ -49,14 +69,37 @@ impl Term {
                         repr: repr,
                         span: Span::dummy(),
                     },
-                    *ty),
-                body: Box::new(body),
+                    ty.into_term()),
+                body: TermRef::new(body),
                 span: Span::dummy(),
             };
         }
         result
     }
 
+    /// Bind `local` to `value` in `t`.
+    pub fn abstract_let(local: Name, value: Term, t: Term) -> Term {
+        let body = t.abstr(&local);
+
+        let (repr, ty) = match local {
+            Name::Local { repr, ty, .. } => (repr, ty),
+            n => panic!("trying to abstract over {:?}", n),
+        };
+
+        Term::Let {
+            binder: Binder::explicit(
+                Name::DeBruijn {
+                    index: 0,
+                    repr: repr,
+                    span: Span::dummy(),
+                },
+                ty.into_term()),
+            value: TermRef::new(value),
+            body: TermRef::new(body),
+            span: Span::dummy(),
+        }
+    }
+
     pub fn abstract_pi(locals: Vec<Name>, t: Term) -> Term {
         Term::abstract_pi_internal(locals, t, None)
     }
@@ -97,7 +140,7 @@ impl Term {
 
             result = Term::Forall {
                 binder: binder,
-                term: Box::new(body),
+                term: TermRef::new(body),
                 span: Span::dummy(),
             };
         }
@@ -116,7 +159,9 @@ impl Term {
         use self::Term::*;
         use super::Name::*;
 
-        // debug!("subst: {} with {}", index, replacement);
+        if !self.has_locals() {
+            return self.clone();
+        }
 
         match self {
             &Var { name: ref vname } => {
@@ -139,26 +184,34 @@ impl Term {
             }
             &App { ref fun, ref arg, span } => {
                 App {
-                    fun: Box::new(fun.abst(index, x)),
-                    arg: Box::new(arg.abst(index, x)),
+                    fun: fun.abst(index, x),
+                    arg: arg.abst(index, x),
                     span: span,
                 }
             }
             &Forall { ref binder, ref term, span } => {
                 Forall {
                     binder: binder.abst(index, x),
-                    term: Box::new(term.abst(index + 1, x)),
+                    term: term.abst(index + 1, x),
                     span: span,
                 }
             }
             &Lambda {  ref binder, ref body, span } => {
                 Lambda {
                     binder: binder.abst(index, x),
-                    body: Box::new(body.abst(index + 1, x)),
+                    body: body.abst(index + 1, x),
+                    span: span,
+                }
+            }
+            &Let { ref binder, ref value, ref body, span } => {
+                Let {
+                    binder: binder.abst(index, x),
+                    value: value.abst(index, x),
+                    body: body.abst(index + 1, x),
                     span: span,
                 }
             }
-            &Type => Type,
+            s @ &Sort { .. } => s.clone(),
         }
     }
 
@@ -174,6 +227,10 @@ impl Term {
 
         debug!("replace: {} with {}", index, subst);
 
+        if self.loose_bvar_range() <= index {
+            return self.clone();
+        }
+
         match self {
             &Var { ref name } => {
                 match name {
@@ -189,26 +246,124 @@ impl Term {
             }
             &App { ref fun, ref arg, span } => {
                 App {
-                    fun: Box::new(fun.replace(index, subst)),
-                    arg: Box::new(arg.replace(index, subst)),
+                    fun: fun.replace(index, subst),
+                    arg: arg.replace(index, subst),
                     span: span,
                 }
             }
             &Forall { ref binder, ref term, span } => {
                 Forall {
                     binder: binder.replace(index, subst),
-                    term: Box::new(term.replace(index + 1, subst)),
+                    term: term.replace(index + 1, subst),
                     span: span,
                 }
             }
             &Lambda { ref binder, ref body, span } => {
                 Lambda {
                     binder: binder.replace(index, subst),
-                    body: Box::new(body.replace(index + 1, subst)),
+                    body: body.replace(index + 1, subst),
                     span: span,
                 }
             }
-            &Type => Type,
+            &Let { ref binder, ref value, ref body, span } => {
+                Let {
+                    binder: binder.replace(index, subst),
+                    value: value.replace(index, subst),
+                    body: body.replace(index + 1, subst),
+                    span: span,
+                }
+            }
+            s @ &Sort { .. } => s.clone(),
+        }
+    }
+
+    /// Checks whether `name` occurs anywhere in the term.
+    pub fn occurs(&self, name: &Name) -> bool {
+        use self::Term::*;
+
+        match self {
+            &Var { name: ref n } => n.same_variable(name),
+            &Sort { .. } => false,
+            &App { ref fun, ref arg, .. } => fun.occurs(name) || arg.occurs(name),
+            &Forall { ref binder, term: ref body, .. } |
+            &Lambda { ref binder, ref body, .. } =>
+                binder.ty.occurs(name) || body.occurs(name),
+            &Let { ref binder, ref value, ref body, .. } =>
+                binder.ty.occurs(name) || value.occurs(name) || body.occurs(name),
+        }
+    }
+
+    /// The first name in the term satisfying `pred`, if there is one.
+    pub fn find_name<F: Fn(&Name) -> bool>(&self, pred: &F) -> Option<&Name> {
+        use self::Term::*;
+
+        match self {
+            &Var { ref name } => if pred(name) { Some(name) } else { None },
+            &Sort { .. } => None,
+            &App { ref fun, ref arg, .. } =>
+                fun.find_name(pred).or_else(|| arg.find_name(pred)),
+            &Forall { ref binder, term: ref body, .. } |
+            &Lambda { ref binder, ref body, .. } =>
+                binder.ty.find_name(pred).or_else(|| body.find_name(pred)),
+            &Let { ref binder, ref value, ref body, .. } =>
+                binder.ty.find_name(pred)
+                         .or_else(|| value.find_name(pred))
+                         .or_else(|| body.find_name(pred)),
+        }
+    }
+
+    /// Checks whether the term mentions a bound variable that is not bound
+    /// inside the term itself.
+    pub fn has_loose_bvars(&self) -> bool {
+        self.loose_bvar_range() > 0
+    }
+
+    /// One more than the largest bound variable the term mentions without
+    /// binding it, so zero if the term is closed.
+    pub fn loose_bvar_range(&self) -> usize {
+        use self::Term::*;
+
+        match self {
+            &Var { name: Name::DeBruijn { index, .. } } => index + 1,
+            &Var { .. } | &Sort { .. } => 0,
+            &App { ref fun, ref arg, .. } =>
+                max(fun.loose_bvar_range(), arg.loose_bvar_range()),
+            &Forall { ref binder, term: ref body, .. } |
+            &Lambda { ref binder, ref body, .. } =>
+                max(binder.ty.loose_bvar_range(),
+                    body.loose_bvar_range().saturating_sub(1)),
+            &Let { ref binder, ref value, ref body, .. } =>
+                max(max(binder.ty.loose_bvar_range(), value.loose_bvar_range()),
+                    body.loose_bvar_range().saturating_sub(1)),
+        }
+    }
+
+    /// Checks whether a local constant occurs in the term, not counting the
+    /// types of the names it mentions.
+    pub fn has_locals(&self) -> bool {
+        self.any_child(&|t| t.has_locals(), &|n| n.is_local())
+    }
+
+    /// Checks whether a meta-variable occurs in the term, not counting the
+    /// types of the names it mentions.
+    pub fn has_metas(&self) -> bool {
+        self.any_child(&|t| t.has_metas(), &|n| n.is_meta())
+    }
+
+    fn any_child<F, G>(&self, child: &F, var: &G) -> bool
+        where F: Fn(&TermRef) -> bool, G: Fn(&Name) -> bool
+    {
+        use self::Term::*;
+
+        match self {
+            &Var { ref name } => var(name),
+            &Sort { .. } => false,
+            &App { ref fun, ref arg, .. } => child(fun) || child(arg),
+            &Forall { ref binder, term: ref body, .. } |
+            &Lambda { ref binder, ref body, .. } =>
+                child(&binder.ty) || child(body),
+            &Let { ref binder, ref value, ref body, .. } =>
+                child(&binder.ty) || child(value) || child(body),
         }
     }
 
@@ -233,8 +388,8 @@ impl Term {
 
     pub fn apply(t: Term, u: Term) -> Term {
         Term::App {
-            fun: Box::new(t),
-            arg: Box::new(u),
+            fun: TermRef::new(t),
+            arg: TermRef::new(u),
             span: Span::dummy(),
         }
     }
@@ -243,8 +398,8 @@ impl Term {
         let mut result = fun;
         for arg in args {
             result = Term::App {
-                fun: Box::new(result),
-                arg: Box::new(arg),
+                fun: TermRef::new(result),
+                arg: TermRef::new(arg),
                 span: Span::dummy(),
             };
         }
@@ -326,8 +481,9 @@ impl Term {
             }
             f @ &Forall { .. } => Some(f.clone()),
             l @ &Lambda { .. } => Some(l.clone()),
+            l @ &Let { .. } => Some(l.clone()),
             v @ &Var { .. } => Some(v.clone()),
-            &Type => Some(Type),
+            s @ &Sort { .. } => Some(s.clone()),
         }
     }
 
@@ -336,14 +492,14 @@ impl Term {
         match self {
             &App { ref fun, ref arg, ..} => {
                 let mut f = &**fun;
-                let mut result = vec![*arg.clone()];
+                let mut result = vec![(**arg).clone()];
                 while let &App { ref fun, ref arg, .. } = f {
                     f = &**fun;
-                    result.push(*arg.clone());
+                    result.push((**arg).clone());
                 }
                 Some(result.into_iter().rev().collect())
             }
-            &Var { .. } | &Type => Some(vec![]),
+            &Var { .. } | &Sort { .. } => Some(vec![]),
             _ => None,
         }
     }
@@ -354,10 +510,10 @@ impl Term {
         match self {
             &App { ref fun, ref arg, ..} => {
                 let mut f = &**fun;
-                let mut result = vec![*arg.clone()];
+                let mut result = vec![(**arg).clone()];
                 while let &App { ref fun, ref arg, .. } = f {
                     f = &**fun;
-                    result.push(*arg.clone());
+                    result.push((**arg).clone());
                 }
                 (f.clone(), result.into_iter().rev().collect())
             }
@@ -367,11 +523,112 @@ impl Term {
 
     pub fn is_sort(&self) -> bool {
         match self {
-            &Term::Type => true,
+            &Term::Sort { .. } => true,
             _ => false,
         }
     }
 
+    pub fn is_prop(&self) -> bool {
+        match self {
+            &Term::Sort { ref level } => level.normalize().is_zero(),
+            _ => false,
+        }
+    }
+
+    pub fn sort_level(&self) -> Option<&Level> {
+        match self {
+            &Term::Sort { ref level } => Some(level),
+            _ => None,
+        }
+    }
+
+    /// Instantiate the universe parameters `params` with `levels`.
+    pub fn instantiate_univ_params(&self, params: &[Name], levels: &[Level]) -> Term {
+        if params.len() == 0 {
+            return self.clone();
+        }
+
+        self.map_levels(&|l| l.instantiate_params(params, levels))
+    }
+
+    /// Rewrite every universe level occurring in the term.
+    pub fn map_levels<F: Fn(&Level) -> Level>(&self, f: &F) -> Term {
+        use self::Term::*;
+
+        match self {
+            &Var { ref name } => Var { name: name.map_levels(f) },
+            &App { ref fun, ref arg, span } => {
+                App {
+                    fun: TermRef::new(fun.map_levels(f)),
+                    arg: TermRef::new(arg.map_levels(f)),
+                    span: span,
+                }
+            }
+            &Forall { ref binder, ref term, span } => {
+                Forall {
+                    binder: binder.map_levels(f),
+                    term: TermRef::new(term.map_levels(f)),
+                    span: span,
+                }
+            }
+            &Lambda { ref binder, ref body, span } => {
+                Lambda {
+                    binder: binder.map_levels(f),
+                    body: TermRef::new(body.map_levels(f)),
+                    span: span,
+                }
+            }
+            &Let { ref binder, ref value, ref body, span } => {
+                Let {
+                    binder: binder.map_levels(f),
+                    value: TermRef::new(value.map_levels(f)),
+                    body: TermRef::new(body.map_levels(f)),
+                    span: span,
+                }
+            }
+            &Sort { ref level } => Sort { level: f(level) },
+        }
+    }
+
+    /// Rewrite every name occurring as a variable in the term.
+    pub fn map_names<F: Fn(&Name) -> Name>(&self, f: &F) -> Term {
+        use self::Term::*;
+
+        match self {
+            &Var { ref name } => Var { name: f(name) },
+            &App { ref fun, ref arg, span } => {
+                App {
+                    fun: TermRef::new(fun.map_names(f)),
+                    arg: TermRef::new(arg.map_names(f)),
+                    span: span,
+                }
+            }
+            &Forall { ref binder, ref term, span } => {
+                Forall {
+                    binder: binder.map_names(f),
+                    term: TermRef::new(term.map_names(f)),
+                    span: span,
+                }
+            }
+            &Lambda { ref binder, ref body, span } => {
+                Lambda {
+                    binder: binder.map_names(f),
+                    body: TermRef::new(body.map_names(f)),
+                    span: span,
+                }
+            }
+            &Let { ref binder, ref value, ref body, span } => {
+                Let {
+                    binder: binder.map_names(f),
+                    value: TermRef::new(value.map_names(f)),
+                    body: TermRef::new(body.map_names(f)),
+                    span: span,
+                }
+            }
+            s @ &Sort { .. } => s.clone(),
+        }
+    }
+
     pub fn is_forall(&self) -> bool {
         match self {
             &Term::Forall {..} => true,
@@ -379,6 +636,13 @@ impl Term {
         }
     }
 
+    pub fn is_lambda(&self) -> bool {
+        match self {
+            &Term::Lambda {..} => true,
+            _ => false,
+        }
+    }
+
     pub fn is_meta(&self) -> bool {
         match self {
             &Term::Var { ref name, .. } =>
@@ -394,6 +658,15 @@ impl Term {
         }
     }
 
+    /// Whether the term is the variable `name`, a global is the same
+    /// variable at whatever levels it is instantiated.
+    pub fn is_var(&self, name: &Name) -> bool {
+        match self {
+            &Term::Var { name: ref n } => n.same_variable(name),
+            _ => false,
+        }
+    }
+
     pub fn head_is_local(&self) -> bool {
         self.head().map(|h| match &h {
             &Term::Var { ref name, .. } => match name {
@@ -433,6 +706,10 @@ impl Term {
     }
 
     pub fn instantiate_meta(&self, meta: &Name, term: &Term) -> Term {
+        if !self.has_metas() {
+            return self.clone();
+        }
+
         let mut result = self.clone();
         result.replace_term(term, &|tm| tm == &meta.to_term());
         result
@@ -484,6 +761,11 @@ impl Term {
                     binder.ty.replace_term(&replacement, pred);
                     body.replace_term(&replacement, pred);
                 }
+                &mut Let { ref mut binder, ref mut value, ref mut body, .. } => {
+                    binder.ty.replace_term(&replacement, pred);
+                    value.replace_term(&replacement, pred);
+                    body.replace_term(&replacement, pred);
+                }
                 _ => {}
             }
         }
@@ -509,7 +791,12 @@ impl PartialEq for Term {
              &Lambda { binder: ref binder2, body: ref body2, ..}) => {
                 binder1 == binder2 && body1 == body2
             }
-            (&Type, &Type) => true,
+            (&Let { binder: ref binder1, value: ref value1, body: ref body1, .. },
+             &Let { binder: ref binder2, value: ref value2, body: ref body2, .. }) => {
+                binder1 == binder2 && value1 == value2 && body1 == body2
+            }
+            (&Sort { level: ref level1 }, &Sort { level: ref level2 }) =>
+                level1 == level2,
             _ => false,
         }
     }
@@ -520,7 +807,6 @@ impl Hash for Term {
         where H: Hasher
     {
         use self::Term::*;
-        debug!("hash: {}", self);
 
         match self {
             &Var { ref name, .. } => {
@@ -542,8 +828,15 @@ impl Hash for Term {
                 binder.hash(state);
                 body.hash(state);
             }
-            &Type => {
+            &Sort { ref level } => {
                 4.hash(state);
+                level.hash(state);
+            }
+            &Let { ref binder, ref value, ref body, .. } => {
+                5.hash(state);
+                binder.hash(state);
+                value.hash(state);
+                body.hash(state);
             }
         }
     }
@@ -557,7 +850,8 @@ impl Pretty for Term {
             &Var { ref name, .. } => name.pretty(),
             &App { ref fun, ref arg, .. } => {
                 let pretty_fun = match &**fun {
-                    complex @ &Term::Lambda { .. } =>
+                    complex @ &Term::Lambda { .. } |
+                    complex @ &Term::Let { .. } =>
                         parens(complex.pretty()),
                     t => t.pretty()
                 };
@@ -642,7 +936,17 @@ impl Pretty for Term {
 
                 "fun ".pretty() + pretty_binders(binders.as_slice()) + " => ".pretty() + cursor.pretty()
             }
-            &Type => Doc::text("Type"),
+            &Let { ref binder, ref value, ref body, .. } => {
+                "let ".pretty() + binder.name.pretty() + " : ".pretty() + binder.ty.pretty() +
+                    " := ".pretty() + value.pretty() + " in ".pretty() + body.pretty()
+            }
+            &Sort { ref level } => {
+                match level.to_offset() {
+                    (&Level::Zero, 0) => Doc::text("Prop"),
+                    (&Level::Zero, 1) => Doc::text("Type"),
+                    _ => "Sort ".pretty() + level.pretty(),
+                }
+            }
         }
     }
 }
@@ -671,7 +975,8 @@ impl HasSpan for Term {
             &App { span, .. } => span,
             &Forall { span, .. } => span,
             &Lambda { span, .. } => span,
-            &Type => Span::dummy(),
+            &Let { span, .. } => span,
+            &Sort { .. } => Span::dummy(),
         }
     }
 
@@ -683,7 +988,8 @@ impl HasSpan for Term {
             &mut App { ref mut span, .. } => *span = sp,
             &mut Forall { ref mut span, .. } => *span = sp,
             &mut Lambda { ref mut span, .. } => *span = sp,
-            &mut Type => {}
+            &mut Let { ref mut span, .. } => *span = sp,
+            &mut Sort { .. } => {}
         }
     }
 }
@@ -0,0 +1,133 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+use super::{Name, Term};
+
+/// A shared, immutable subterm.
+///
+/// Terms are built out of `TermRef`s so cloning a term only copies its
+/// outermost node. Each node caches its hash along with a summary of the
+/// variables it mentions, letting equality fail fast and letting
+/// substitution return subterms it can not affect without visiting them.
+#[derive(Clone)]
+pub struct TermRef {
+    node: Rc<Node>,
+}
+
+struct Node {
+    term: Term,
+    hash: u64,
+    /// One more than the largest bound variable not bound inside the term,
+    /// or zero if the term is closed.
+    loose_bvar_range: usize,
+    has_locals: bool,
+    has_metas: bool,
+}
+
+impl TermRef {
+    pub fn new(term: Term) -> TermRef {
+        let mut hasher = DefaultHasher::new();
+        term.hash(&mut hasher);
+
+        TermRef {
+            node: Rc::new(Node {
+                hash: hasher.finish(),
+                loose_bvar_range: term.loose_bvar_range(),
+                has_locals: term.has_locals(),
+                has_metas: term.has_metas(),
+                term: term,
+            }),
+        }
+    }
+
+    /// Take the term out, only copying the node if it is still shared.
+    pub fn into_term(self) -> Term {
+        match Rc::try_unwrap(self.node) {
+            Ok(node) => node.term,
+            Err(node) => node.term.clone(),
+        }
+    }
+
+    pub fn loose_bvar_range(&self) -> usize {
+        self.node.loose_bvar_range
+    }
+
+    pub fn has_locals(&self) -> bool {
+        self.node.has_locals
+    }
+
+    pub fn has_metas(&self) -> bool {
+        self.node.has_metas
+    }
+
+    /// Like `Term::abst`, sharing the subterm if it mentions no locals.
+    pub fn abst(&self, index: usize, x: &Name) -> TermRef {
+        if self.has_locals() {
+            TermRef::new(self.node.term.abst(index, x))
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Like `Term::replace`, sharing the subterm if it can not mention the
+    /// bound variable `index`.
+    pub fn replace(&self, index: usize, subst: &Term) -> TermRef {
+        if self.loose_bvar_range() > index {
+            TermRef::new(self.node.term.replace(index, subst))
+        } else {
+            self.clone()
+        }
+    }
+
+    pub fn replace_term<F: Fn(&Term) -> bool>(&mut self, replacement: &Term, pred: &F) {
+        let mut term = self.node.term.clone();
+        term.replace_term(replacement, pred);
+        *self = TermRef::new(term);
+    }
+}
+
+impl From<Term> for TermRef {
+    fn from(term: Term) -> TermRef {
+        TermRef::new(term)
+    }
+}
+
+impl Deref for TermRef {
+    type Target = Term;
+
+    fn deref(&self) -> &Term {
+        &self.node.term
+    }
+}
+
+impl PartialEq for TermRef {
+    fn eq(&self, other: &TermRef) -> bool {
+        Rc::ptr_eq(&self.node, &other.node) ||
+            (self.node.hash == other.node.hash && self.node.term == other.node.term)
+    }
+}
+
+impl Eq for TermRef {}
+
+impl Hash for TermRef {
+    fn hash<H>(&self, state: &mut H)
+        where H: Hasher
+    {
+        state.write_u64(self.node.hash)
+    }
+}
+
+impl Debug for TermRef {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        Debug::fmt(&self.node.term, formatter)
+    }
+}
+
+impl Display for TermRef {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        Display::fmt(&self.node.term, formatter)
+    }
+}
@@ -132,7 +132,7 @@ fn walk_term<'v, V: Visitor<'v>>(visitor: &mut V, term: &'v Term) {
             visitor.visit_span(span);
             panic!()
         }
-        &Type => {}
+        &Sort { .. } => {}
     }
 }
 
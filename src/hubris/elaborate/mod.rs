@@ -1,5 +1,7 @@
 mod pattern_matching;
+mod recursion;
 mod util;
+mod well_founded;
 
 use ast::{self, HasSpan};
 use core;
@@ -7,14 +9,67 @@ use typeck::{self, TyCtxt};
 use session::{HasSession, Session, Reportable};
 use self::util::to_qualified_name;
 use self::pattern_matching::elaborate_pattern_match;
+use self::recursion::compile_structural_recursion;
+use self::well_founded::{compile_well_founded_recursion, decreasing_proofs, recursive_call_ty};
 
 use std::io::{self};
 use std::collections::{HashMap, HashSet};
+use std::mem;
 
 #[derive(Debug)]
 pub enum Error {
     UnexpectedQualifiedName,
     UnknownVariable(ast::Name),
+    UnknownUniverse(ast::Name),
+    InvalidLevel(ast::Name),
+    NotStructurallyRecursive(core::Name, core::Term),
+    MissingDecreasingProof(core::Name, core::Term),
+    WellFoundedUnavailable(core::Name, core::Name),
+    /// A definition with a measure, and the type of the measure which has no
+    /// `WellFoundedRelation` instance.
+    NoWellFoundedRelation(core::Name, core::Term),
+    /// A definition, the number of recursive calls it makes and the number
+    /// of proofs its `decreasing_by` clause gives for them.
+    WrongNumberOfDecreasingProofs(ast::Name, usize, usize),
+    UnknownAttribute(ast::Name),
+    /// A qualified name given to a local by a binder or a `let`.
+    QualifiedLocal(ast::Name),
+    /// The span of a literal, which we can't elaborate yet.
+    UnsupportedLiteral(ast::Span),
+    /// The span and elaborated scrutinee of a match, along with its type
+    /// which is not an inductive type.
+    MatchOnNonInductive(ast::Span, core::Term, core::Term),
+    /// The span and elaborated scrutinee of a match on an inductive family,
+    /// whose type we need to know to refine the indices.
+    MatchNeedsType(ast::Span, core::Term),
+    /// The span of a match and the type of its scrutinee, whose indices we
+    /// can only refine with `Eq`, which is not in scope.
+    EqualityUnavailable(ast::Span, core::Term),
+    /// The span of a match and the type of its scrutinee, whose indices
+    /// have types which depend on each other.
+    UnsupportedIndices(ast::Span, core::Term),
+    /// The span of a match and two indices which we can not unify.
+    CantUnifyIndices(ast::Span, core::Term, core::Term),
+    /// The span of a match and a constructor which none of its arms cover.
+    MissingCase(ast::Span, core::Name),
+    /// The span of a match and examples of the values none of its arms
+    /// match.
+    NonExhaustive(ast::Span, Vec<String>),
+    /// The span of a match, one of the constructors of the type of its
+    /// scrutinee and that type, which the constructor doesn't build.
+    CtorDoesNotMatch(ast::Span, core::Name, core::Term),
+    /// A name used as a constructor in a pattern, and the type of the value
+    /// it matches which has no such constructor.
+    NotAConstructor(ast::Name, core::Term),
+    /// A constructor in a pattern, the number of arguments it takes and the
+    /// number the pattern gives it.
+    WrongNumberOfArguments(ast::Name, usize, usize),
+    /// The span of an arm, the number of patterns it has and the number of
+    /// scrutinees of its match.
+    WrongNumberOfPatterns(ast::Span, usize, usize),
+    /// The span of the term needing an instance of the class, for which
+    /// none could be found.
+    NoInstance(ast::Span, core::Term),
     TypeCk(typeck::Error),
     InvalidImport,
     Many(Vec<Error>),
@@ -36,6 +91,117 @@ impl Reportable for Error {
                 session.span_error(n.span,
                     format!("unresolved name `{}`", n))
             }
+            Error::UnknownUniverse(n) => {
+                session.span_error(n.span,
+                    format!("undeclared universe `{}`", n))
+            }
+            Error::InvalidLevel(n) => {
+                session.span_error(n.span,
+                    format!("`{}` is not a universe level operator, \
+                             expected `max` or `imax` applied to two levels", n))
+            }
+            Error::NotStructurallyRecursive(n, call) => {
+                session.span_error(n.get_span(),
+                    format!("`{}` is not structurally recursive, recursive calls \
+                             must be on something matched out of an argument in the \
+                             body, found `{}`", n, call))
+            }
+            Error::MissingDecreasingProof(n, call) => {
+                session.span_error(n.get_span(),
+                    format!("recursive call `{}` must be given each argument of `{}` \
+                             and a proof that the measure decreases", call, n))
+            }
+            Error::WellFoundedUnavailable(n, global) => {
+                session.span_error(n.get_span(),
+                    format!("`{}` uses well-founded recursion which requires `{}`, \
+                             try importing `WellFounded`", n, global))
+            }
+            Error::NoWellFoundedRelation(n, ty) => {
+                session.span_error(n.get_span(),
+                    format!("the measure of `{}` has type `{}`, which has no instance of \
+                             `WellFoundedRelation` to decrease along", n, ty))
+            }
+            Error::WrongNumberOfDecreasingProofs(n, calls, proofs) => {
+                session.span_error(n.span,
+                    format!("`{}` makes {} recursive calls but `decreasing_by` gives {} \
+                             proofs, give one for each call or one for all of them",
+                            n, calls, proofs))
+            }
+            Error::MatchOnNonInductive(span, scrutinee, ty) => {
+                session.span_error(span,
+                    format!("can not match on `{}`, its type `{}` is not an \
+                             inductive type", scrutinee, ty))
+            }
+            Error::MatchNeedsType(span, scrutinee) => {
+                session.span_error(span,
+                    format!("can not infer the type of this match on `{}`, it is \
+                             needed to refine the indices of the scrutinee", scrutinee))
+            }
+            Error::EqualityUnavailable(span, ty) => {
+                session.span_error(span,
+                    format!("matching on a value of type `{}` requires `Eq` to refine \
+                             its indices, try importing `Eq`", ty))
+            }
+            Error::UnsupportedIndices(span, ty) => {
+                session.span_error(span,
+                    format!("can not match on a value of type `{}`, the types of its \
+                             indices depend on each other", ty))
+            }
+            Error::CantUnifyIndices(span, t, u) => {
+                session.span_error(span,
+                    format!("can not unify the index `{}` with `{}` in this match", t, u))
+            }
+            Error::MissingCase(span, ctor) => {
+                session.span_error(span,
+                    format!("this match is missing a case for `{}`", ctor))
+            }
+            Error::NonExhaustive(span, examples) => {
+                let examples: Vec<_> =
+                    examples.iter().map(|e| format!("`{}`", e)).collect();
+
+                if examples.len() == 1 {
+                    session.span_error(span,
+                        format!("this match is missing a case for {}", examples[0]))
+                } else {
+                    session.span_error(span,
+                        format!("this match is missing cases for {}", examples.join(", ")))
+                }
+            }
+            Error::CtorDoesNotMatch(span, ctor, ty) => {
+                session.span_error(span,
+                    format!("the constructor `{}` does not build values of type `{}`", ctor, ty))
+            }
+            Error::NotAConstructor(n, ty) => {
+                session.span_error(n.span,
+                    format!("`{}` is not a constructor of `{}`", n, ty))
+            }
+            Error::WrongNumberOfArguments(n, expected, found) => {
+                session.span_error(n.span,
+                    format!("`{}` takes {} arguments, but this pattern gives it {}",
+                            n, expected, found))
+            }
+            Error::WrongNumberOfPatterns(span, found, expected) => {
+                session.span_error(span,
+                    format!("this arm has {} patterns, but the match has {} scrutinees",
+                            found, expected))
+            }
+            Error::NoInstance(span, class) => {
+                session.span_error(span,
+                    format!("could not find an instance of `{}`", class))
+            }
+            Error::UnknownAttribute(n) => {
+                session.span_error(n.span,
+                    format!("unknown attribute `{}`, expected `reducible`, \
+                             `semireducible`, `irreducible` or `coercion`", n))
+            }
+            Error::QualifiedLocal(n) => {
+                session.span_error(n.span,
+                    format!("can not bind the qualified name `{}`, the names of \
+                             locals must be unqualified", n))
+            }
+            Error::UnsupportedLiteral(span) => {
+                session.span_error(span, "literals are not supported yet".to_string())
+            }
             Error::Many(es) => {
                 for e in es {
                     try!(e.report(session))
@@ -58,9 +224,12 @@ pub struct ElabCx {
     constructors: HashSet<ast::Name>,
     /// Set of globally translated names.
     globals: HashMap<ast::Name, core::Name>,
-    /// A global counter for metavariable numbers, this should probably
-    /// be thread specific.
-    metavar_counter: usize,
+    /// The universe parameters of the declaration being elaborated.
+    univ_params: Vec<core::Name>,
+    /// The name of a definition with a `decreasing_by` clause, along with
+    /// the number of explicit arguments it takes and the proof to pass to
+    /// each of its recursive calls, by the span of the call.
+    decreasing_by: Option<(ast::Name, usize, HashMap<ast::Span, ast::Term>)>,
     /// Elaboration relies on type checking, the type checker produces
     /// an "inferred" type containing meta variables along with a set
     /// of constraints that must be solved, in order for type checking
@@ -75,21 +244,21 @@ impl HasSession for ElabCx {
 }
 
 impl ElabCx {
-    pub fn from_module(module: ast::Module, session: Session) -> ElabCx {
+    pub fn from_module(module: ast::Module, session: Session) -> Result<ElabCx, typeck::Error> {
         // Elaboration relies on type checking, so first we setup a typing context to use
         // while elaborating the program. We will run the type checker in inference mode
         // to setup constraints, and then we will solve the constraints, and check the
         // term again.
-        let mut ty_cx = TyCtxt::empty();
-        ty_cx.session = session;
+        let ty_cx = try!(TyCtxt::new(session));
 
-        ElabCx {
+        Ok(ElabCx {
             module: module,
             constructors: HashSet::new(),
             globals: HashMap::new(),
-            metavar_counter: 0,
+            univ_params: vec![],
+            decreasing_by: None,
             ty_cx: ty_cx,
-        }
+        })
     }
 
     pub fn elaborate_module(&mut self) -> Result<core::Module, Error> {
@@ -108,6 +277,14 @@ impl ElabCx {
                         self.constructors.insert(ctor.0.clone());
                     }
                 }
+                &ast::Item::Mutual(ref m) => {
+                    for ctor in m.inductives.iter().flat_map(|d| d.ctors.iter()) {
+                        self.constructors.insert(ctor.0.clone());
+                    }
+                }
+                &ast::Item::Structure(ref s) => {
+                    self.constructors.insert(structure_ctor(s));
+                }
                 &ast::Item::Import(ref n) =>
                     imports.push(try!(self.elaborate_import(n.clone()))),
                 _ => {}
@@ -150,18 +327,35 @@ impl ElabCx {
     pub fn elaborate_def(&mut self, def: ast::Item) -> Result<Option<core::Item>, Error> {
         debug!("elaborate_def: def={:?}", def);
 
+        self.univ_params = vec![];
+
         match def {
             ast::Item::Inductive(d) => {
                 let edata = try!(self.elaborate_data(d));
                 try!(self.ty_cx.declare_datatype(&edata));
                 Ok(Some(core::Item::Data(edata)))
             }
+            ast::Item::Mutual(m) => {
+                let edatas = try!(self.elaborate_mutual(m));
+                try!(self.ty_cx.declare_mutual(&edatas));
+                Ok(Some(core::Item::Mutual(edatas)))
+            }
+            ast::Item::Structure(s) => {
+                let edata = try!(self.elaborate_structure(s));
+                try!(self.ty_cx.declare_datatype(&edata));
+                Ok(Some(core::Item::Data(edata)))
+            }
             ast::Item::Def(def) => {
                 let edef = try!(self.elaborate_fn(def));
                 try!(self.ty_cx.declare_def(&edef));
                 debug!("elaborate_def: def={}", edef);
                 Ok(Some(core::Item::Fn(edef)))
             }
+            ast::Item::Instance(inst) => {
+                let edef = try!(self.elaborate_fn(inst));
+                try!(self.ty_cx.declare_instance(&edef));
+                Ok(Some(core::Item::Instance(edef)))
+            }
             ast::Item::Axiom(ax) => {
                 let eax = try!(self.elaborate_axiom(ax));
                 self.ty_cx.declare_axiom(&eax);
@@ -177,55 +371,215 @@ impl ElabCx {
     }
 
     fn elaborate_data(&mut self, data: ast::Inductive) -> Result<core::Data, Error> {
+        let ty = try!(self.declare_data_ty(&data));
+        self.elaborate_data_ctors(data, ty)
+    }
+
+    /// A structure is an inductive type with a single constructor taking
+    /// each of the fields, and returning the type applied to its parameters.
+    fn elaborate_structure(&mut self, structure: ast::Structure) -> Result<core::Data, Error> {
+        let ctor = structure_ctor(&structure);
+
+        let ast::Structure { span, name, univ_params, parameters, ty, fields, class, .. } = structure;
+
+        let mut result = ast::Term::Var { name: name.clone(), implicit: false };
+
+        for param in parameters.iter().flat_map(|b| b.names.iter().rev()) {
+            result = ast::Term::App {
+                span: span,
+                fun: Box::new(result),
+                arg: Box::new(ast::Term::Var { name: param.clone(), implicit: false }),
+            };
+        }
+
+        let mut field_names = vec![];
+
+        for field in fields.iter().flat_map(|b| b.names.iter().rev()) {
+            match to_qualified_name(field.clone()) {
+                None => return Err(Error::UnexpectedQualifiedName),
+                Some(f) => field_names.push(f),
+            }
+        }
+
+        let ctor_ty = if fields.is_empty() {
+            result
+        } else {
+            ast::Term::Forall { span: span, binders: fields, term: Box::new(result) }
+        };
+
+        let mut edata = try!(self.elaborate_data(ast::Inductive {
+            span: span,
+            name: name,
+            univ_params: univ_params,
+            parameters: parameters,
+            ty: ty,
+            ctors: vec![(ctor, ctor_ty)],
+        }));
+
+        edata.fields = Some(field_names);
+        edata.class = class;
+
+        Ok(edata)
+    }
+
+    /// Elaborate the types in a `mutual` block, each of them is declared
+    /// before any of the constructors are elaborated so they may refer to
+    /// one another.
+    fn elaborate_mutual(&mut self, mutual: ast::Mutual) -> Result<Vec<core::Data>, Error> {
+        let mut tys = vec![];
+
+        for data in &mutual.inductives {
+            tys.push(try!(self.declare_data_ty(data)));
+        }
+
+        let mut edatas = vec![];
+
+        for (data, ty) in mutual.inductives.into_iter().zip(tys.into_iter()) {
+            edatas.push(try!(self.elaborate_data_ctors(data, ty)));
+        }
+
+        let names: Vec<_> = edatas.iter().map(|d| d.name.clone()).collect();
+
+        // The types share their parameters, each was elaborated in its own
+        // scope so we switch them over to the first type's locals, this is
+        // fine as the types and constructors are closed terms.
+        let params = edatas[0].parameters.clone();
+        let first_ty = edatas[0].ty.clone();
+
+        for edata in &mut edatas {
+            if edata.parameters.len() == params.len() &&
+               same_parameters(&params, &first_ty, &edata.ty) {
+                edata.parameters = params.clone();
+            }
+
+            edata.mutual = names.clone();
+        }
+
+        Ok(edatas)
+    }
+
+    /// Elaborate the type of an inductive type, and make it available as a
+    /// constant so the constructors can refer to it.
+    fn declare_data_ty(&mut self, data: &ast::Inductive) -> Result<core::Term, Error> {
         let ast_rec_name = data.name.in_scope("rec".to_string()).unwrap();
-        let ty_name = try!(self.elaborate_global_name(data.name));
+        let ty_name = try!(self.elaborate_global_name(data.name.clone()));
+        try!(self.elaborate_univ_params(data.univ_params.clone()));
 
         // Pre-declare the recursor name for the time being.
         self.globals.insert(
             ast_rec_name,
             ty_name.in_scope("rec".to_string()).unwrap());
 
-        let mut lcx = LocalElabCx::from_elab_cx(self);
-
-        let data_ctors = data.ctors;
-        let data_ty = data.ty;
+        let data_ty = data.ty.clone();
 
-        let (ctors, ty, params) = try!(lcx.enter_scope(data.parameters.clone(),
+        let ty = try!(LocalElabCx::from_elab_cx(self).enter_scope(data.parameters.clone(),
         |lcx, params| {
             let ty = core::Term::abstract_pi(
                 params.clone(),
                 try!(lcx.elaborate_term(data_ty)));
 
-            // TODO: Fix this shouldn't expose so many details,
-            // but the elaborator has to interleave with the type
-            // checker better.
-            lcx.cx.ty_cx.axioms.insert(ty_name.clone(), typeck::Axiom::new(ty.clone()));
+            // Solve any universe meta-variables introduced by references
+            // to polymorphic types.
+            let (ty, _) = try!(lcx.cx.ty_cx.type_check_term(&ty, None));
+
+            Ok(ty)
+        }));
+
+        // TODO: Fix this shouldn't expose so many details,
+        // but the elaborator has to interleave with the type
+        // checker better.
+        self.ty_cx.axioms.insert(ty_name, typeck::Axiom::new(ty.clone()));
+
+        Ok(ty)
+    }
+
+    fn elaborate_data_ctors(&mut self, data: ast::Inductive, ty: core::Term) -> Result<core::Data, Error> {
+        let ty_name = try!(self.elaborate_global_name(data.name));
+        let univ_params = try!(self.elaborate_univ_params(data.univ_params));
+
+        let mut lcx = LocalElabCx::from_elab_cx(self);
 
+        let data_ctors = data.ctors;
+
+        let (ctors, params) = try!(lcx.enter_scope(data.parameters.clone(),
+        |lcx, params| {
             let mut ctors = Vec::new();
             for ctor in data_ctors.into_iter() {
                 let ector = try!(lcx.elaborate_ctor(&params, ctor));
                 ctors.push(ector);
             }
 
-            Ok((ctors, ty, params))
+            Ok((ctors, params))
         }));
 
         Ok(core::Data {
             span: data.span,
             name: ty_name,
+            univ_params: univ_params,
             parameters: params,
             ty: ty,
             ctors: ctors,
+            mutual: vec![],
+            fields: None,
+            class: false,
         })
     }
 
     fn elaborate_fn(&mut self, fun: ast::Def) -> Result<core::Function, Error> {
-        let mut lcx = LocalElabCx::from_elab_cx(self);
+        let univ_params = try!(self.elaborate_univ_params(fun.univ_params.clone()));
+        let reduction = try!(delta_reduction(&fun.attributes));
+
+        let explicit_args: usize =
+            fun.args.iter()
+                    .filter(|b| b.mode == ast::BindingMode::Explicit)
+                    .map(|b| b.names.len())
+                    .sum();
 
-        lcx.enter_scope(fun.args.clone(), move |lcx, args| {
+        self.decreasing_by = if fun.decreasing_by.len() == 0 {
+            None
+        } else {
+            Some((fun.name.clone(), explicit_args, try!(decreasing_proofs(&fun, explicit_args))))
+        };
+
+        let result = LocalElabCx::from_elab_cx(self).enter_scope(fun.args.clone(), move |lcx, args| {
             let name = try!(lcx.cx.elaborate_global_name(fun.name));
             let ty = try!(lcx.elaborate_term(fun.ty.clone()));
-            let ebody = try!(lcx.elaborate_term(fun.body));
+
+            let measure = match fun.termination_by {
+                None => None,
+                Some(m) => Some(try!(lcx.elaborate_term(m))),
+            };
+
+            // The definition may refer to itself, so we make it available as
+            // a constant while elaborating the body, and then compile away any
+            // recursive calls. With a measure each call must also be given a
+            // proof that the measure decreases.
+            let self_ty = match measure {
+                None => core::Term::abstract_pi(args.clone(), ty.clone()),
+                Some(ref m) => try!(recursive_call_ty(lcx, &name, &args, &ty, m)),
+            };
+
+            lcx.cx.ty_cx.axioms.insert(name.clone(), typeck::Axiom::new(self_ty));
+
+            lcx.postponing = true;
+            let ebody = lcx.elaborate_term_with_type(fun.body, Some(ty.clone())).and_then(|body| {
+                // A body such as `Refl` still needs its implicit arguments,
+                // unless the declared type binds them itself.
+                match ty {
+                    core::Term::Forall { ref binder, .. }
+                        if binder.is_implicit() || binder.is_inst_implicit() => Ok(body),
+                    _ => lcx.apply_implicit_args(body),
+                }
+            }).and_then(|body| lcx.coerce(body, &ty))
+              .and_then(|body| lcx.elaborate_postponed(body, &ty));
+            lcx.postponing = false;
+            lcx.cx.ty_cx.axioms.remove(&name);
+
+            let ebody = match measure {
+                None => try!(compile_structural_recursion(lcx, &name, &args, &ty, try!(ebody))),
+                Some(ref m) =>
+                    try!(compile_well_founded_recursion(lcx, &name, &args, &ty, m, try!(ebody))),
+            };
 
             debug!("elaborate_fn: ty={} body={}", ty, ebody);
 
@@ -237,6 +591,7 @@ impl ElabCx {
 
             Ok(core::Function {
                 name: name,
+                univ_params: univ_params,
                 args: args.clone(),
                 // We compute the full type of the function here
                 // by taking the return type and abstracting
@@ -245,9 +600,14 @@ impl ElabCx {
                 // We construct a lambda representing the body
                 // with all of the function's parameters abstracted.
                 body: body,
-                reduction: core::DeltaReduction::Reducible,
+                reduction: reduction,
+                coercion: is_coercion(&fun.attributes),
             })
-        })
+        });
+
+        self.decreasing_by = None;
+
+        result
     }
 
     fn elaborate_axiom(&mut self, ax: ast::Axiom) -> Result<core::Axiom, Error> {
@@ -268,12 +628,30 @@ impl ElabCx {
         })
     }
 
+    /// Elaborate the universe parameters of a declaration, bringing them
+    /// into scope for the rest of the declaration.
+    fn elaborate_univ_params(&mut self, params: Vec<ast::Name>) -> Result<Vec<core::Name>, Error> {
+        let mut univ_params = vec![];
+
+        for param in params {
+            match to_qualified_name(param) {
+                None => return Err(Error::UnexpectedQualifiedName),
+                Some(p) => univ_params.push(p),
+            }
+        }
+
+        self.univ_params = univ_params.clone();
+
+        Ok(univ_params)
+    }
+
     pub fn elaborate_global_name(&mut self, n: ast::Name) -> Result<core::Name, Error> {
         match n.repr.clone() {
             ast::NameKind::Qualified(components) => {
                 let qn = core::Name::Qual {
                     span: n.span,
                     components: components,
+                    levels: vec![],
                 };
 
                 self.globals.insert(n.clone(), qn.clone());
@@ -284,6 +662,7 @@ impl ElabCx {
                 let qn = core::Name::Qual {
                     span: n.span,
                     components: vec![name],
+                    levels: vec![],
                 };
 
                 self.globals.insert(n.clone(), qn.clone());
@@ -295,12 +674,105 @@ impl ElabCx {
     }
 }
 
+/// How eagerly a definition is unfolded, definitions are semireducible
+/// unless marked otherwise.
+fn delta_reduction(attributes: &[ast::Name]) -> Result<core::DeltaReduction, Error> {
+    let mut reduction = core::DeltaReduction::Semireducible;
+
+    for attribute in attributes {
+        reduction = match &attribute.repr {
+            &ast::NameKind::Unqualified(ref a) if a == "reducible" =>
+                core::DeltaReduction::Reducible,
+            &ast::NameKind::Unqualified(ref a) if a == "semireducible" =>
+                core::DeltaReduction::Semireducible,
+            &ast::NameKind::Unqualified(ref a) if a == "irreducible" =>
+                core::DeltaReduction::Irreducible,
+            &ast::NameKind::Unqualified(ref a) if a == "coercion" =>
+                reduction,
+            _ => return Err(Error::UnknownAttribute(attribute.clone())),
+        };
+    }
+
+    Ok(reduction)
+}
+
+/// Whether a definition is marked as a coercion with `@[coercion]`.
+fn is_coercion(attributes: &[ast::Name]) -> bool {
+    attributes.iter().any(|attribute| match &attribute.repr {
+        &ast::NameKind::Unqualified(ref a) => a == "coercion",
+        _ => false,
+    })
+}
+
+/// The constructor of a structure, `T.mk` unless it is named.
+fn structure_ctor(structure: &ast::Structure) -> ast::Name {
+    match structure.ctor {
+        Some(ref ctor) => ctor.clone(),
+        None => structure.name.in_scope("mk".to_string()).unwrap(),
+    }
+}
+
+/// Check that the parameters of the inductive type `ty` have the same types
+/// as `params`, the parameters of the first type in a `mutual` block.
+fn same_parameters(params: &[core::Name], first_ty: &core::Term, ty: &core::Term) -> bool {
+    let mut first_ty = first_ty.clone();
+    let mut ty = ty.clone();
+
+    for param in params {
+        match (first_ty, ty) {
+            (core::Term::Forall { binder: b1, term: t1, .. },
+             core::Term::Forall { binder: b2, term: t2, .. }) => {
+                if b1.ty != b2.ty {
+                    return false;
+                }
+
+                first_ty = t1.instantiate(&param.to_term());
+                ty = t2.instantiate(&param.to_term());
+            }
+            _ => return false,
+        }
+    }
+
+    true
+}
+
 pub struct LocalElabCx<'ecx> {
     cx: &'ecx mut ElabCx,
     locals: HashMap<ast::Name, core::Name>,
     // This is kind of a shitty hack to keep the HashMap above ordered, should probably
     // write a utility data strcture.
     locals_in_order: Vec<core::Name>,
+    /// The names standing for terms instead of locals, matching on an
+    /// inductive family replaces the locals it refines, see
+    /// `pattern_matching::dependent`.
+    refined: HashMap<ast::Name, core::Term>,
+    /// Whether subterms which need an expected type we do not know yet can
+    /// be postponed, see `elaborate_postponed`.
+    postponing: bool,
+    postponed: Vec<Postponed>,
+    /// The instances we are still to find, see `instance_argument`.
+    instances: Vec<PendingInstance>,
+}
+
+/// A subterm whose elaboration we put off until we know its expected type,
+/// in the meantime the meta-variable `hole` stands in for it.
+struct Postponed {
+    term: ast::Term,
+    hole: core::Term,
+    expected_ty: core::Term,
+    locals: HashMap<ast::Name, core::Name>,
+    locals_in_order: Vec<core::Name>,
+    refined: HashMap<ast::Name, core::Term>,
+}
+
+/// An instance-implicit argument whose class we did not know completely
+/// when we applied a function to it, the meta-variable `hole` stands in for
+/// it until we do.
+struct PendingInstance {
+    span: ast::Span,
+    hole: core::Term,
+    class: core::Term,
+    locals_in_order: Vec<core::Name>,
 }
 
 impl<'ecx> LocalElabCx<'ecx> {
@@ -309,6 +781,10 @@ impl<'ecx> LocalElabCx<'ecx> {
             cx: ecx,
             locals: HashMap::new(),
             locals_in_order: Vec::new(),
+            refined: HashMap::new(),
+            postponing: false,
+            postponed: vec![],
+            instances: vec![],
         }
     }
 
@@ -317,8 +793,22 @@ impl<'ecx> LocalElabCx<'ecx> {
                          body: F)
                          -> Result<R, Error>
         where F: FnOnce(&mut LocalElabCx, Vec<core::Name>) -> Result<R, Error>
+    {
+        self.enter_scope_with_type(binders, None, |lcx, locals, _| body(lcx, locals))
+    }
+
+    /// Like `enter_scope`, binders without a type take theirs from the
+    /// expected type `expected_ty` if it is a `forall`. The body is given
+    /// what remains of the expected type once the binders are introduced.
+    fn enter_scope_with_type<F, R>(&mut self,
+                                   binders: Vec<ast::Binder>,
+                                   expected_ty: Option<core::Term>,
+                                   body: F)
+                                   -> Result<R, Error>
+        where F: FnOnce(&mut LocalElabCx, Vec<core::Name>, Option<core::Term>) -> Result<R, Error>
     {
         let mut locals = vec![];
+        let mut expected_ty = expected_ty;
 
         let old_context = self.locals.clone();
         let old_locals_in_order = self.locals_in_order.clone();
@@ -332,27 +822,45 @@ impl<'ecx> LocalElabCx<'ecx> {
             let binder_ty = binder.ty;
             for name in binder.names.into_iter().rev() {
                 let repr = match name.clone().repr {
-                    ast::NameKind::Qualified(..) => panic!(),
+                    ast::NameKind::Qualified(..) => return Err(Error::QualifiedLocal(name)),
                     ast::NameKind::Unqualified(s) => s,
                     ast::NameKind::Placeholder => "_".to_string(),
                 };
 
-                let eterm = try!(self.elaborate_term(binder_ty.clone().unwrap()));
-
                 let binding_info = match binder.mode {
                     ast::BindingMode::Implicit => core::BindingMode::Implicit,
                     ast::BindingMode::Explicit => core::BindingMode::Explicit,
+                    ast::BindingMode::InstImplicit => core::BindingMode::InstImplicit,
+                };
+
+                // We give up on the expected type once the binders stop
+                // lining up with it.
+                let expected_pi =
+                    try!(self.expected_forall(expected_ty.take()))
+                        .and_then(|(b, codomain)| {
+                            if b.mode == binding_info { Some((b, codomain)) } else { None }
+                        });
+
+                let eterm = match (binder_ty.clone(), &expected_pi) {
+                    (Some(ty), _) => try!(self.elaborate_term(ty)),
+                    (None, &Some((ref b, _))) => b.ty.clone().into_term(),
+                    (None, &None) => {
+                        let sort = core::Term::sort(self.cx.ty_cx.fresh_level_meta());
+                        try!(self.meta_in_context(sort))
+                    }
                 };
 
                 let local = self.cx.ty_cx.local_with_repr_and_mode(repr, eterm, binding_info);
 
+                expected_ty = expected_pi.map(|(_, codomain)| codomain.instantiate(&local.to_term()));
+
                 self.locals.insert(name, local.clone());
                 self.locals_in_order.push(local.clone());
                 locals.push(local);
             }
         }
 
-        let result = try!(body(self, locals));
+        let result = try!(body(self, locals, expected_ty));
 
         // Restore the previous context.
         self.locals = old_context;
@@ -371,6 +879,7 @@ impl<'ecx> LocalElabCx<'ecx> {
         let ety = try!(self.elaborate_term(ctor.1));
         // TODO: Need to figure out if params are implicit for this ctor or not
         let ety = core::Term::abstract_pi_implicit(parameters.clone(), ety);
+        let (ety, _) = try!(self.cx.ty_cx.type_check_term(&ety, None));
 
         Ok((ename, ety))
     }
@@ -379,12 +888,16 @@ impl<'ecx> LocalElabCx<'ecx> {
         let mut fun_ty =
             try!(self.cx.ty_cx.type_infer_term(&term)).0;
 
+        let span = term.get_span();
         let mut result = term;
 
         while let core::Term::Forall { binder, term, .. } = fun_ty {
-            if binder.is_implicit() {
-                let implicit_arg =
-                    try!(self.implicit_argument(*binder.ty));
+            if binder.is_implicit() || binder.is_inst_implicit() {
+                let implicit_arg = if binder.is_inst_implicit() {
+                    try!(self.instance_argument(binder.ty.into_term(), span))
+                } else {
+                    try!(self.implicit_argument(binder.ty.into_term()))
+                };
                 // It is important any time we do an application to simulate it
                 // at the type level by instantiating the body of the type,
                 // if not this results in constraints that are not subst.
@@ -399,43 +912,131 @@ impl<'ecx> LocalElabCx<'ecx> {
         Ok(result)
     }
 
+    /// The binder and codomain of `expected_ty`, if it is a `forall` once
+    /// reduced to weak head normal form.
+    fn expected_forall(&self, expected_ty: Option<core::Term>)
+                       -> Result<Option<(core::Binder, core::TermRef)>, Error> {
+        let expected_ty = match expected_ty {
+            None => return Ok(None),
+            Some(ty) => if ty.is_forall() {
+                ty
+            } else {
+                try!(self.cx.ty_cx.whnf(&ty)).0
+            },
+        };
+
+        match expected_ty {
+            core::Term::Forall { binder, term, .. } => Ok(Some((binder, term))),
+            _ => Ok(None),
+        }
+    }
+
     pub fn elaborate_term(&mut self, term: ast::Term) -> Result<core::Term, Error> {
+        self.elaborate_term_with_type(term, None)
+    }
+
+    /// Elaborate `term`, which should have type `expected_ty` if we know it.
+    /// The expected type flows into the bodies of `fun`s, the arms of
+    /// `match`es, and from functions to their arguments, letting us leave
+    /// off the types of binders.
+    pub fn elaborate_term_with_type(&mut self,
+                                    term: ast::Term,
+                                    expected_ty: Option<core::Term>)
+                                    -> Result<core::Term, Error> {
         debug!("elaborate_term: term={:?}", term);
 
         match term {
-            ast::Term::Literal { span, lit } => {
-                panic!()
+            ast::Term::Literal { span, .. } => {
+                Err(Error::UnsupportedLiteral(span))
             }
             ast::Term::Var { name, .. } => {
                 self.elaborate_name(name)
             }
-            ast::Term::Match { scrutinee, cases, span } => {
-                elaborate_pattern_match(self, *scrutinee, cases)
+            ast::Term::Match { scrutinees, cases, span } => {
+                let result = elaborate_pattern_match(
+                    self, scrutinees.clone(), cases.clone(), expected_ty.clone());
+
+                // We may only learn the type of the scrutinee once we know
+                // more about the terms around the match.
+                match result {
+                    Err(Error::MatchOnNonInductive(..)) |
+                    Err(Error::MatchNeedsType(..)) if self.postponing => {
+                        let term = ast::Term::Match {
+                            scrutinees: scrutinees,
+                            cases: cases,
+                            span: span,
+                        };
+
+                        self.postpone(term, expected_ty)
+                    }
+                    result => result,
+                }
             }
             app @ ast::Term::App { .. } => {
                 let span = app.get_span();
-                let (head, args) = app.uncurry();
+                let (head, mut args) = app.uncurry();
+
+                if let Some(proof) = self.decreasing_proof(span, &head, args.len()) {
+                    args.push(proof);
+                }
 
                 let implicit = match &head {
                     &ast::Term::Var { implicit, .. } => implicit,
                     _ => true,
                 };
 
-                let efun = try!(self.elaborate_term(head));
-
-                let mut eargs = vec![];
+                // We need the type of the head to elaborate the arguments, so
+                // it can't be put off, instead its binders are given
+                // placeholder types.
+                let postponing = mem::replace(&mut self.postponing, false);
+                let efun = self.elaborate_term(head);
+                self.postponing = postponing;
+                let efun = try!(efun);
 
-                for arg in args {
-                    let earg = try!(self.elaborate_term(arg));
-                    eargs.push(try!(self.apply_implicit_args(earg)));
-                }
-
-                let efun = if implicit {
+                let mut efun = if implicit {
                     try!(self.apply_implicit_args(efun))
                 } else {
                     efun
                 };
 
+                // Each argument is expected to have the type of the binder
+                // it is applied to.
+                let mut fun_ty = self.cx.ty_cx.type_infer_term(&efun).ok().map(|(ty, _)| ty);
+
+                let mut eargs = vec![];
+
+                for arg in args {
+                    let mut expected_pi = try!(self.expected_forall(fun_ty.clone()));
+
+                    // What we have applied so far may not be a function, but
+                    // can still be coerced to one.
+                    if let (true, Some(ty)) = (expected_pi.is_none(), fun_ty.clone()) {
+                        let fun = core::Term::apply_all(efun.clone(), eargs.clone());
+
+                        let coerced = try!(self.cx.ty_cx.coerce_to_function(
+                            &self.locals_in_order, &fun, &ty));
+
+                        if let Some(coerced) = coerced {
+                            fun_ty = self.cx.ty_cx.type_infer_term(&coerced).ok().map(|(ty, _)| ty);
+                            expected_pi = try!(self.expected_forall(fun_ty.clone()));
+                            efun = coerced;
+                            eargs = vec![];
+                        }
+                    }
+
+                    let arg_ty = expected_pi.as_ref().map(|&(ref b, _)| b.ty.clone().into_term());
+
+                    let earg = try!(self.elaborate_term_with_type(arg, arg_ty.clone()));
+                    let earg = try!(self.apply_implicit_args(earg));
+                    let earg = match arg_ty {
+                        None => earg,
+                        Some(ref arg_ty) => try!(self.coerce(earg, arg_ty)),
+                    };
+
+                    fun_ty = expected_pi.map(|(_, codomain)| codomain.instantiate(&earg));
+                    eargs.push(earg);
+                }
+
                 let mut app = core::Term::apply_all(efun, eargs);
 
                 app.set_span(span);
@@ -448,38 +1049,143 @@ impl<'ecx> LocalElabCx<'ecx> {
                     Ok(core::Term::abstract_pi(locals, term))
                 })
             }
-            ast::Term::Lambda { args, body, .. } => {
-                self.enter_scope(args, move |lcx, locals| {
-                    let ebody = try!(lcx.elaborate_term(*body));
+            ast::Term::Lambda { args, body, ret_ty, span } => {
+                let unannotated = args.iter().any(|b| b.ty.is_none());
+
+                // Without the types of the binders, we wait until we know
+                // the type the function should have.
+                if unannotated && self.postponing &&
+                   try!(self.expected_forall(expected_ty.clone())).is_none() {
+                    let term = ast::Term::Lambda {
+                        args: args,
+                        body: body,
+                        ret_ty: ret_ty,
+                        span: span,
+                    };
+
+                    return self.postpone(term, expected_ty);
+                }
+
+                self.enter_scope_with_type(args, expected_ty, move |lcx, locals, body_ty| {
+                    let body_ty = match *ret_ty {
+                        None => body_ty,
+                        Some(ret_ty) => Some(try!(lcx.elaborate_term(ret_ty))),
+                    };
+
+                    let ebody = try!(lcx.elaborate_term_with_type(*body, body_ty));
                     Ok(core::Term::abstract_lambda(locals, ebody))
                 })
             }
-            ast::Term::Let { bindings, body, span } => {
-                // // Currently we elaborate let expressions by
-                // // constructing a lambda in which we bind each
-                // // name that occurs in the let binding, then
-                // // apply it to the terms bound to the names
-                // // in sequence.
-                // let mut binders = vec![];
-                // let mut terms = vec![];
-                // for (n, ty, term) in bindings {
-                //     binders.push((n, ty));
-                //     terms.push(term);
-                // }
-                //
-                // self.enter_scope(binders, move |lcx, locals| {
-                //     let ebody = try!(lcx.elaborate_term(*body));
-                //     let lambda = core::Term::abstract_lambda(locals, ebody);
-                //     Ok(core::Term::apply_all(lambda, terms))
-                // })
-                panic!("let bindings can not be elaborated")
+            ast::Term::Let { bindings, body, .. } => {
+                let old_context = self.locals.clone();
+                let old_locals_in_order = self.locals_in_order.clone();
+
+                // Each binding is in scope in the bindings after it, and in
+                // the body.
+                let mut lets = vec![];
+
+                for (binder, value) in bindings {
+                    let name = binder.names[0].clone();
+
+                    let repr = match name.clone().repr {
+                        ast::NameKind::Qualified(..) => return Err(Error::QualifiedLocal(name)),
+                        ast::NameKind::Unqualified(s) => s,
+                        ast::NameKind::Placeholder => "_".to_string(),
+                    };
+
+                    let ety = try!(self.elaborate_term(binder.ty.unwrap()));
+                    let evalue = try!(self.elaborate_term_with_type(value, Some(ety.clone())));
+                    let evalue = try!(self.coerce(evalue, &ety));
+
+                    let local = self.cx.ty_cx.local_with_repr(repr, ety);
+
+                    self.locals.insert(name, local.clone());
+                    self.locals_in_order.push(local.clone());
+                    lets.push((local, evalue));
+                }
+
+                let mut result = try!(self.elaborate_term_with_type(*body, expected_ty));
+
+                // Restore the previous context.
+                self.locals = old_context;
+                self.locals_in_order = old_locals_in_order;
+
+                for (local, value) in lets.into_iter().rev() {
+                    result = core::Term::abstract_let(local, value, result);
+                }
+
+                Ok(result)
             },
-            ast::Term::Type => Ok(core::Term::Type),
+            ast::Term::Prop => Ok(core::Term::prop()),
+            ast::Term::Type => Ok(core::Term::sort(core::Level::one())),
+            ast::Term::Sort { level, .. } =>
+                Ok(core::Term::sort(try!(self.elaborate_level(level)))),
+        }
+    }
+
+    fn elaborate_level(&mut self, level: ast::Level) -> Result<core::Level, Error> {
+        match level {
+            ast::Level::Name(n) => {
+                match to_qualified_name(n.clone()) {
+                    Some(ref p) if self.cx.univ_params.contains(p) =>
+                        Ok(core::Level::Param(p.clone())),
+                    _ => Err(Error::UnknownUniverse(n)),
+                }
+            }
+            ast::Level::Num(k) => Ok(core::Level::Zero.with_offset(k)),
+            ast::Level::Plus(l, k) => Ok(try!(self.elaborate_level(*l)).with_offset(k)),
+            ast::Level::App(op, args) => {
+                let mut eargs = vec![];
+                for arg in args {
+                    eargs.push(try!(self.elaborate_level(arg)));
+                }
+
+                let is_op = |s: &str| match &op.repr {
+                    &ast::NameKind::Unqualified(ref o) => o == s,
+                    _ => false,
+                };
+
+                if eargs.len() != 2 {
+                    return Err(Error::InvalidLevel(op.clone()));
+                }
+
+                let l2 = eargs.pop().unwrap();
+                let l1 = eargs.pop().unwrap();
+
+                if is_op("max") {
+                    Ok(core::Level::max(l1, l2))
+                } else if is_op("imax") {
+                    Ok(core::Level::imax(l1, l2))
+                } else {
+                    Err(Error::InvalidLevel(op.clone()))
+                }
+            }
+        }
+    }
+
+    /// References to universe polymorphic globals are instantiated with a
+    /// fresh universe meta-variable for each of their parameters.
+    pub fn instantiate_global(&mut self, name: core::Name) -> core::Name {
+        let arity = self.cx.ty_cx.univ_params_of(&name).len();
+
+        if arity == 0 {
+            name
+        } else {
+            let levels = (0..arity).map(|_| self.cx.ty_cx.fresh_level_meta()).collect();
+            name.with_levels(levels)
         }
     }
 
-    fn elaborate_literal(&self, lit: ast::Literal) -> core::Term {
-        panic!()
+    /// The proof to supply to the recursive call `head args` at `span` of a
+    /// definition with a `decreasing_by` clause.
+    fn decreasing_proof(&self, span: ast::Span, head: &ast::Term, num_args: usize) -> Option<ast::Term> {
+        match (head, &self.cx.decreasing_by) {
+            (&ast::Term::Var { ref name, .. }, &Some((ref f, arity, ref proofs)))
+                if name == f && num_args == arity && !self.locals.contains_key(name) &&
+                   !self.refined.contains_key(name) =>
+                proofs.get(&span).cloned(),
+            _ => None,
+        }
     }
 
     fn elaborate_name(&mut self, name: ast::Name) -> Result<core::Term, Error> {
@@ -491,28 +1197,34 @@ impl<'ecx> LocalElabCx<'ecx> {
             _ => None,
         };
 
-        // It is most likely to be a local
-        let mut core_name = match self.locals.get(&name) {
+        // It is most likely to be a local, or a local we have refined.
+        let local = self.locals
+                        .get(&name)
+                        .map(core::Name::to_term)
+                        .or_else(|| self.refined.get(&name).cloned());
+        let global = self.cx.globals.get(&name).cloned();
+
+        let mut core_name = match local {
             // A global in the current module
             None => {
-                match self.cx.globals.get(&name) {
+                match global {
                     // If it isn't a global we are going to see if the name has already been
                     // loading into the type context, if not this is an error.
                     None => {
                         match to_qualified_name(name.clone()) {
                             None => placeholder.unwrap(),
                             Some(ref core_name) if self.cx.ty_cx.in_scope(core_name) => {
-                                core_name.to_term()
+                                self.instantiate_global(core_name.clone()).to_term()
                             }
                             Some(_) => {
                                 return Err(Error::UnknownVariable(name.clone()))
                             }
                         }
                     }
-                    Some(nn) => nn.to_term(),
+                    Some(nn) => self.instantiate_global(nn).to_term(),
                 }
             }
-            Some(local) => local.to_term(),
+            Some(local) => local,
         };
 
         // IMPORTANT!: Make sure we update the span here for the precise name being elaborated
@@ -523,26 +1235,232 @@ impl<'ecx> LocalElabCx<'ecx> {
         Ok(core_name)
     }
 
+    /// Put off elaborating `term`, returning a meta-variable to stand in for
+    /// it until `elaborate_postponed` gets to it.
+    fn postpone(&mut self, term: ast::Term, expected_ty: Option<core::Term>) -> Result<core::Term, Error> {
+        let expected_ty = match expected_ty {
+            Some(ty) => ty,
+            None => {
+                let sort = core::Term::sort(self.cx.ty_cx.fresh_level_meta());
+                try!(self.meta_in_context(sort))
+            }
+        };
+
+        let hole = try!(self.meta_in_context(expected_ty.clone()));
+
+        self.postponed.push(Postponed {
+            term: term,
+            hole: hole.clone(),
+            expected_ty: expected_ty,
+            locals: self.locals.clone(),
+            locals_in_order: self.locals_in_order.clone(),
+            refined: self.refined.clone(),
+        });
+
+        Ok(hole)
+    }
+
+    /// Elaborate the subterms postponed while elaborating `term` of type
+    /// `ty`, which must be in the same context we elaborated `term` in, and
+    /// find its pending instances.
+    ///
+    /// Each pass solves the constraints of `term` as it stands, and uses the
+    /// solutions to find the expected types of the postponed subterms and
+    /// the classes of the instances. Once a pass makes no progress we
+    /// elaborate the remaining subterms without postponing, and look for
+    /// instances of whatever classes we have, reporting any errors.
+    pub fn elaborate_postponed(&mut self, term: core::Term, ty: &core::Term) -> Result<core::Term, Error> {
+        let mut term = term;
+        let mut stuck = false;
+
+        while self.postponed.len() > 0 || self.instances.len() > 0 {
+            // The constraints may not have a solution until every subterm
+            // has been elaborated, in that case we learn nothing.
+            let solutions = match self.cx.ty_cx.solve_term(&term, Some(ty)) {
+                Ok((_, solutions, _)) => solutions,
+                Err(_) => HashMap::new(),
+            };
+
+            let postponed = mem::replace(&mut self.postponed, vec![]);
+            let mut progress = false;
+
+            self.postponing = !stuck;
+
+            for p in postponed {
+                let (hole, value, postponed_again) = try!(self.resume(p, &solutions));
+                progress = progress || !postponed_again;
+                term = term.instantiate_meta(&hole, &value);
+            }
+
+            let instances = mem::replace(&mut self.instances, vec![]);
+
+            for pending in instances {
+                match try!(self.find_instance(pending, &solutions, stuck)) {
+                    Ok((hole, instance)) => {
+                        progress = true;
+                        term = term.instantiate_meta(&hole, &instance);
+                    }
+                    Err(pending) => self.instances.push(pending),
+                }
+            }
+
+            self.postponing = true;
+            stuck = !progress;
+        }
+
+        Ok(term)
+    }
+
+    /// Elaborate a postponed subterm in the context it was postponed in,
+    /// returning its hole and the solution for it. We also report whether
+    /// the subterm was postponed once more.
+    fn resume(&mut self,
+              postponed: Postponed,
+              solutions: &typeck::Solutions)
+              -> Result<(core::Name, core::Term, bool), Error> {
+        let Postponed { term, hole, expected_ty, locals, locals_in_order, refined } = postponed;
+
+        // The types of the locals may have been solved as well.
+        let instantiate = |local: &core::Name| match local {
+            &core::Name::Local { number, ref ty, ref repr, binding_info } => core::Name::Local {
+                number: number,
+                ty: core::TermRef::new(typeck::instantiate_solutions((**ty).clone(), solutions)),
+                repr: repr.clone(),
+                binding_info: binding_info,
+            },
+            other => other.clone(),
+        };
+
+        let old_context = mem::replace(
+            &mut self.locals,
+            locals.iter().map(|(n, l)| (n.clone(), instantiate(l))).collect());
+
+        let old_locals_in_order = mem::replace(
+            &mut self.locals_in_order,
+            locals_in_order.iter().map(&instantiate).collect());
+
+        let old_refined = mem::replace(
+            &mut self.refined,
+            refined.into_iter()
+                   .map(|(n, t)| (n, typeck::instantiate_solutions(t, solutions)))
+                   .collect());
+
+        let expected_ty = typeck::instantiate_solutions(expected_ty, solutions);
+        let num_postponed = self.postponed.len();
+
+        let result = self.elaborate_term_with_type(term, Some(expected_ty));
+
+        let postponed_again =
+            self.postponed.len() > num_postponed &&
+            result.as_ref().ok() == Some(&self.postponed[num_postponed].hole);
+
+        // The hole is applied to the locals in scope, which may since have
+        // been abstracted over.
+        let locals_in_order = mem::replace(&mut self.locals_in_order, old_locals_in_order);
+        self.locals = old_context;
+        self.refined = old_refined;
+
+        let value = core::Term::abstract_lambda(locals_in_order, try!(result));
+
+        let hole = match hole.uncurry().0 {
+            core::Term::Var { name } => name,
+            _ => panic!("elaboration bug: a hole must be a meta-variable"),
+        };
+
+        Ok((hole, value, postponed_again))
+    }
+
+    /// Look for an instance once its class no longer mentions any
+    /// meta-variables, or if we are `stuck` and it never will. Gives back the
+    /// pending instance if it should wait for another pass.
+    fn find_instance(&mut self,
+                     pending: PendingInstance,
+                     solutions: &typeck::Solutions,
+                     stuck: bool)
+                     -> Result<Result<(core::Name, core::Term), PendingInstance>, Error> {
+        let class = typeck::instantiate_solutions(pending.class.clone(), solutions);
+
+        if class.has_metas() && !stuck {
+            return Ok(Err(pending));
+        }
+
+        let PendingInstance { span, hole, locals_in_order, .. } = pending;
+
+        let locals_in_order: Vec<_> = locals_in_order.into_iter().map(|local| match local {
+            core::Name::Local { number, ty, repr, binding_info } => core::Name::Local {
+                number: number,
+                ty: core::TermRef::new(typeck::instantiate_solutions(ty.into_term(), solutions)),
+                repr: repr,
+                binding_info: binding_info,
+            },
+            other => other,
+        }).collect();
+
+        let instance = match try!(self.cx.ty_cx.resolve_instance(&locals_in_order, &class)) {
+            None => return Err(Error::NoInstance(span, class)),
+            Some(instance) => instance,
+        };
+
+        let hole = match hole.uncurry().0 {
+            core::Term::Var { name } => name,
+            _ => panic!("elaboration bug: a hole must be a meta-variable"),
+        };
+
+        Ok(Ok((hole, core::Term::abstract_lambda(locals_in_order, instance))))
+    }
+
+    /// Insert a coercion if `term` does not have the type `expected_ty` but
+    /// can be coerced to it.
+    fn coerce(&mut self, term: core::Term, expected_ty: &core::Term) -> Result<core::Term, Error> {
+        let ty = match self.cx.ty_cx.type_infer_term(&term) {
+            Ok((ty, _)) => ty,
+            Err(_) => return Ok(term),
+        };
+
+        match try!(self.cx.ty_cx.coerce(&self.locals_in_order, &term, &ty, expected_ty)) {
+            None => Ok(term),
+            Some(coerced) => Ok(coerced),
+        }
+    }
+
     fn implicit_argument(&mut self, ty: core::Term) -> Result<core::Term, Error> {
         self.meta_in_context(ty)
     }
 
-    fn make_placeholder(&mut self) -> Result<core::Term, Error> {
-        let meta_no = self.cx.metavar_counter;
+    /// An instance of `class` for an instance-implicit argument. The class
+    /// often mentions implicit arguments which we have yet to solve, so in
+    /// the body of a definition we leave a meta-variable in its place and
+    /// look for the instance in `elaborate_postponed`.
+    fn instance_argument(&mut self, class: core::Term, span: ast::Span) -> Result<core::Term, Error> {
+        if !self.postponing {
+            return match try!(self.cx.ty_cx.resolve_instance(&self.locals_in_order, &class)) {
+                None => Err(Error::NoInstance(span, class)),
+                Some(instance) => Ok(instance),
+            };
+        }
 
-        let meta_ty = core::Name::Meta {
-            number: meta_no,
-            ty: Box::new(core::Term::Type),
-        };
+        let hole = try!(self.meta_in_context(class.clone()));
 
-        self.cx.metavar_counter += 1;
+        self.instances.push(PendingInstance {
+            span: span,
+            hole: hole.clone(),
+            class: class,
+            locals_in_order: self.locals_in_order.clone(),
+        });
 
-        self.meta_in_context(meta_ty.to_term())
+        Ok(hole)
     }
 
-    fn meta_in_context(&mut self, ty: core::Term) -> Result<core::Term, Error> {
-        let meta_no = self.cx.metavar_counter;
+    fn make_placeholder(&mut self) -> Result<core::Term, Error> {
+        // The type may mention the locals in scope as well, so it needs
+        // to be in context too.
+        let sort = core::Term::sort(self.cx.ty_cx.fresh_level_meta());
+        let meta_ty = try!(self.meta_in_context(sort));
 
+        self.meta_in_context(meta_ty)
+    }
+
+    fn meta_in_context(&mut self, ty: core::Term) -> Result<core::Term, Error> {
         let ty =
             core::Term::abstract_pi(self.locals_in_order.clone(), ty);
 
@@ -552,12 +1470,7 @@ impl<'ecx> LocalElabCx<'ecx> {
                 .map(core::Name::to_term)
                 .collect();
 
-        let meta = core::Name::Meta {
-            number: meta_no,
-            ty: Box::new(ty),
-        };
-
-        self.cx.metavar_counter += 1;
+        let meta = self.cx.ty_cx.fresh_meta(ty);
 
         Ok(core::Term::apply_all(meta.to_term(), args))
     }
@@ -0,0 +1,268 @@
+//! Checking that a match covers every value of its scrutinee, and that each
+//! of its arms can be reached.
+//!
+//! We ask the two questions of Maranget's "Warnings for pattern matching"
+//! of the rows of patterns the arms of a match have, one pattern for each
+//! scrutinee: which values does no row match, and which rows match only
+//! values an earlier row already matches.
+//!
+//! A constructor of an inductive family whose indices can't be those of the
+//! scrutinee needs no arm, so we leave out those which build values whose
+//! indices can't unify with the scrutinee's.
+
+use super::super::super::ast;
+use super::super::super::core::{self, Term};
+use super::super::Error;
+use super::simplify::{self, Row};
+use super::PatternMatchCx;
+
+use std::fmt::{self, Display, Formatter};
+
+use pretty::*;
+
+/// A pattern a row matches a value against, we only need to know the
+/// constructors it mentions.
+#[derive(Debug, Clone)]
+enum Pattern {
+    Wildcard,
+    Constructor(core::Name, Vec<Pattern>),
+}
+
+impl Pretty for Pattern {
+    fn pretty(&self) -> Doc {
+        use self::Pattern::*;
+
+        match self {
+            &Wildcard => "_".pretty(),
+            &Constructor(ref ctor, ref args) => {
+                let name = match ctor {
+                    &core::Name::Qual { ref components, .. } =>
+                        Doc::text(components.last().cloned().unwrap_or(String::new())),
+                    n => n.pretty(),
+                };
+
+                let args: Vec<_> = args.iter().map(|arg| match arg {
+                    &Constructor(_, ref args) if args.len() > 0 => parens(arg.pretty()),
+                    _ => arg.pretty(),
+                }).collect();
+
+                if args.len() == 0 {
+                    name
+                } else {
+                    name + " ".pretty() + seperate(&args[..], &" ".pretty())
+                }
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a simplify::Pattern> for Pattern {
+    fn from(pattern: &'a simplify::Pattern) -> Pattern {
+        match pattern {
+            &simplify::Pattern::Constructor(_, ref ctor, ref args) =>
+                Pattern::Constructor(ctor.clone(), args.iter().map(Pattern::from).collect()),
+            _ => Pattern::Wildcard,
+        }
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        format(self, formatter)
+    }
+}
+
+impl<'ecx, 'cx: 'ecx> PatternMatchCx<'ecx, 'cx> {
+    /// Check that the `rows` of the match at `span`, on values of the types
+    /// `tys`, cover all of them. Returns the `spans` of the rows which can
+    /// never be reached.
+    pub fn check_coverage(&self,
+                          span: ast::Span,
+                          rows: &[Row],
+                          spans: &[ast::Span],
+                          tys: &[Term]) -> Result<Vec<ast::Span>, Error> {
+        let rows: Vec<Vec<Pattern>> =
+            rows.iter().map(|row| row.patterns.iter().map(Pattern::from).collect()).collect();
+
+        let missing = try!(self.missing(&rows, tys));
+
+        if missing.len() > 0 {
+            return Err(Error::NonExhaustive(span, missing.iter().map(|example| {
+                let patterns: Vec<_> = example.iter().map(|p| format!("{}", p)).collect();
+                patterns.join(", ")
+            }).collect()));
+        }
+
+        let mut unreachable = vec![];
+
+        for i in 0..rows.len() {
+            if !try!(self.useful(&rows[..i], &rows[i], tys)) {
+                unreachable.push(spans[i]);
+            }
+        }
+
+        Ok(unreachable)
+    }
+
+    /// The constructors whose arguments we need patterns for to cover the
+    /// values of type `ty`, when it is an inductive type. Those of a family
+    /// are the ones which can build a value with the indices of `ty`.
+    fn signature(&self, ty: &Term) -> Result<Option<Vec<core::Name>>, Error> {
+        let dt = match self.datatype(ty) {
+            None => return Ok(None),
+            Some(dt) => dt,
+        };
+
+        let (family, args) = ty.uncurry();
+
+        if args.len() <= dt.parameters.len() {
+            return Ok(Some(dt.ctors.iter().map(|c| c.0.clone()).collect()));
+        }
+
+        let levels = match &family {
+            &Term::Var { ref name } => name.levels().to_vec(),
+            _ => vec![],
+        };
+
+        let (params, indices) = args.split_at(dt.parameters.len());
+        let mut ctors = vec![];
+
+        'ctors: for &(ref ctor, _) in &dt.ctors {
+            let (_, ctor_indices, _) = try!(self.ctor_instance(ctor, &levels, params));
+
+            for (lhs, rhs) in ctor_indices.iter().zip(indices.iter()) {
+                if !try!(self.may_unify_indices(lhs, rhs, true)) {
+                    continue 'ctors;
+                }
+            }
+
+            ctors.push(ctor.clone());
+        }
+
+        Ok(Some(ctors))
+    }
+
+    /// Examples of the values with types `tys` which none of `rows` match.
+    fn missing(&self, rows: &[Vec<Pattern>], tys: &[Term]) -> Result<Vec<Vec<Pattern>>, Error> {
+        if tys.len() == 0 {
+            return Ok(if rows.len() == 0 { vec![vec![]] } else { vec![] });
+        }
+
+        let heads = heads(rows);
+
+        // When no row mentions a constructor we only need to look inside
+        // the value if there are none to build it with, and so no values.
+        match try!(self.signature(&tys[0])) {
+            Some(ref ctors) if heads.len() > 0 || ctors.len() == 0 => {
+                let mut missing = vec![];
+
+                for ctor in ctors {
+                    let fields = self.fields(ctor, &tys[0]);
+                    let arity = fields.len();
+                    let tys = fields.into_iter().chain(tys[1..].iter().cloned()).collect::<Vec<_>>();
+
+                    for mut example in try!(self.missing(&specialize(rows, ctor, arity), &tys)) {
+                        let rest = example.split_off(arity);
+                        let mut example = vec![Pattern::Constructor(ctor.clone(), example)];
+                        example.extend(rest.into_iter());
+                        missing.push(example);
+                    }
+                }
+
+                Ok(missing)
+            }
+            _ => {
+                Ok(try!(self.missing(&default(rows), &tys[1..])).into_iter().map(|example| {
+                    Some(Pattern::Wildcard).into_iter().chain(example.into_iter()).collect()
+                }).collect())
+            }
+        }
+    }
+
+    /// Whether the patterns `row` for values with types `tys` match a value
+    /// none of `rows` do.
+    fn useful(&self, rows: &[Vec<Pattern>], row: &[Pattern], tys: &[Term]) -> Result<bool, Error> {
+        if row.len() == 0 {
+            return Ok(rows.len() == 0);
+        }
+
+        let signature = try!(self.signature(&tys[0]));
+
+        match &row[0] {
+            &Pattern::Constructor(ref ctor, ref args) => {
+                // A constructor which can't build a value of this type
+                // matches nothing.
+                match signature {
+                    Some(ref ctors) if !ctors.contains(ctor) => return Ok(false),
+                    _ => {}
+                }
+
+                let tys: Vec<_> = self.fields(ctor, &tys[0]).into_iter()
+                                      .chain(tys[1..].iter().cloned()).collect();
+                let row: Vec<_> = args.iter().chain(row[1..].iter()).cloned().collect();
+
+                self.useful(&specialize(rows, ctor, args.len()), &row, &tys)
+            }
+            &Pattern::Wildcard => {
+                let heads = heads(rows);
+
+                match signature {
+                    Some(ref ctors) if ctors.iter().all(|c| heads.contains(c)) => {
+                        for ctor in ctors {
+                            let fields = self.fields(ctor, &tys[0]);
+                            let arity = fields.len();
+                            let tys: Vec<_> = fields.into_iter().chain(tys[1..].iter().cloned()).collect();
+                            let row: Vec<_> = (0..arity).map(|_| Pattern::Wildcard)
+                                                        .chain(row[1..].iter().cloned()).collect();
+
+                            if try!(self.useful(&specialize(rows, ctor, arity), &row, &tys)) {
+                                return Ok(true);
+                            }
+                        }
+
+                        Ok(false)
+                    }
+                    _ => self.useful(&default(rows), &row[1..], &tys[1..]),
+                }
+            }
+        }
+    }
+}
+
+/// The constructors at the head of the first pattern of `rows`, in the
+/// order they first appear.
+fn heads(rows: &[Vec<Pattern>]) -> Vec<core::Name> {
+    let mut heads = vec![];
+
+    for row in rows {
+        if let Pattern::Constructor(ref ctor, _) = row[0] {
+            if !heads.contains(ctor) {
+                heads.push(ctor.clone());
+            }
+        }
+    }
+
+    heads
+}
+
+/// The rows which match a value built with `ctor`, with the patterns for
+/// its `arity` arguments in place of the first pattern.
+fn specialize(rows: &[Vec<Pattern>], ctor: &core::Name, arity: usize) -> Vec<Vec<Pattern>> {
+    rows.iter().filter_map(|row| {
+        let args = match &row[0] {
+            &Pattern::Constructor(ref c, ref args) if c == ctor => args.clone(),
+            &Pattern::Constructor(..) => return None,
+            &Pattern::Wildcard => (0..arity).map(|_| Pattern::Wildcard).collect(),
+        };
+
+        Some(args.into_iter().chain(row[1..].iter().cloned()).collect())
+    }).collect()
+}
+
+/// The rows which match any value, without their first pattern.
+fn default(rows: &[Vec<Pattern>]) -> Vec<Vec<Pattern>> {
+    rows.iter().filter_map(|row| match &row[0] {
+        &Pattern::Wildcard => Some(row[1..].to_vec()),
+        _ => None,
+    }).collect()
+}
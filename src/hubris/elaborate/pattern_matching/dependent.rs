@@ -0,0 +1,694 @@
+//! Matching on a value of an inductive family, like `Vec A n` or `Eq A x y`.
+//!
+//! The constructors of a family fix its indices, a `Cons` has the type
+//! `Vec A (S m)`, so in each arm we learn something about the indices of the
+//! scrutinee. We compile the match to `T.cases_on` with a motive abstracted
+//! over the indices, in the arm for each constructor:
+//!
+//! - when the indices are distinct locals we generalize over them, along
+//!   with the scrutinee and the locals whose types mention them, and they
+//!   become the indices of the constructor.
+//!
+//! - otherwise the motive takes an equation between each index of the
+//!   scrutinee and the corresponding index of the constructor, which we
+//!   solve in the arm by rewriting with `Eq.rec`, and by telling
+//!   constructors apart with their `no_confusion` lemmas. A constructor
+//!   whose indices can't be equal to the scrutinee's needs no arm.
+//!
+//! The names in scope which stood for a local we replace stand for the term
+//! it was replaced with in the arm, see `LocalElabCx::refined`.
+
+use super::super::super::ast;
+use super::super::super::core::{self, Term};
+use super::super::Error;
+use super::simplify::*;
+use super::PatternMatchCx;
+
+use std::collections::HashMap;
+
+/// The names in scope, so we can restore them once we leave an arm.
+pub struct Scope {
+    locals: HashMap<ast::Name, core::Name>,
+    locals_in_order: Vec<core::Name>,
+    refined: HashMap<ast::Name, Term>,
+}
+
+impl<'ecx, 'cx: 'ecx> PatternMatchCx<'ecx, 'cx> {
+    /// Elaborate a match on `scrutinee` of type `scrutinee_ty`, an instance
+    /// of the inductive family `datatype`, to `T.cases_on`. We need to know
+    /// the type of the match, which the motive abstracts.
+    pub fn elaborate_dependent_match(&mut self,
+                                     span: ast::Span,
+                                     scrutinee: Term,
+                                     scrutinee_ty: Term,
+                                     datatype: core::Data,
+                                     cases: Vec<SimpleCase>,
+                                     expected_ty: Option<Term>)
+                                     -> Result<Term, Error> {
+        let goal = match expected_ty {
+            None => return Err(Error::MatchNeedsType(span, scrutinee)),
+            Some(goal) => goal,
+        };
+
+        let (family, args) = scrutinee_ty.uncurry();
+        let num_params = datatype.parameters.len();
+        let params = args[..num_params].to_vec();
+        let indices = args[num_params..].to_vec();
+
+        let levels = match &family {
+            &Term::Var { ref name } => name.levels().to_vec(),
+            _ => vec![],
+        };
+
+        let cases_on = datatype.name.in_scope("cases_on".to_string()).unwrap();
+        let cases_on = self.elab_cx.instantiate_global(cases_on);
+
+        // We can generalize over indices which are distinct locals, which
+        // don't appear in the parameters.
+        let generalized: Vec<_> = indices.iter().enumerate().filter_map(|(k, index)| match index {
+            &Term::Var { ref name }
+                if name.is_local() && !params.iter().chain(&indices[..k]).any(|t| t.occurs(name)) =>
+                Some(name.clone()),
+            _ => None,
+        }).collect();
+
+        let mut args = params.clone();
+        let mut minor_premises = vec![];
+        let extra_args: Vec<_>;
+
+        if generalized.len() == indices.len() {
+            let x = match &scrutinee {
+                &Term::Var { ref name } if name.is_local() => name.clone(),
+                _ => self.elab_cx.cx.ty_cx.local_with_repr("x".to_string(), scrutinee_ty.clone()),
+            };
+
+            let mut generalized = generalized;
+            generalized.push(x.clone());
+
+            let reverted = self.dependents(&generalized);
+            let motive_body = Term::abstract_pi(reverted.clone(), goal);
+
+            args.push(Term::abstract_lambda(generalized.clone(), motive_body.clone()));
+
+            for &(ref ctor, _) in &datatype.ctors {
+                let (ctor_args, ctor_indices, value) =
+                    try!(self.ctor_instance(ctor, &levels, &params));
+
+                let mut refinements: Vec<_> =
+                    generalized.iter().cloned().zip(ctor_indices.into_iter()).collect();
+
+                refinements.push((x.clone(), value.clone()));
+
+                let goal = refinements.iter().fold(motive_body.clone(), |goal, &(ref local, ref value)| {
+                    replace(&goal, local, value)
+                });
+
+                minor_premises.push(try!(self.elaborate_arm(
+                    span, ctor, ctor_args, refinements,
+                    &reverted, goal, 0, &cases)));
+            }
+
+            extra_args = reverted.iter().map(core::Name::to_term).collect();
+        } else {
+            let (eq, refl) = try!(self.equality(span, &scrutinee_ty));
+
+            // The motive takes the indices, along with a value of the
+            // family at those indices.
+            let mut family_ty = try!(self.elab_cx.cx.ty_cx.global_type(&match &family {
+                &Term::Var { ref name } => name.clone(),
+                _ => unreachable!(),
+            }));
+
+            for param in &params {
+                family_ty = match family_ty {
+                    Term::Forall { term, .. } => term.instantiate(param),
+                    t => panic!("elaboration bug: {} takes too few parameters", t),
+                };
+            }
+
+            let mut motive_args = vec![];
+            let mut index_tys = vec![];
+
+            while let Term::Forall { binder, term, .. } = family_ty {
+                let index_ty = binder.ty.into_term();
+                let local = self.elab_cx.cx.ty_cx.local_with_repr("j".to_string(), index_ty.clone());
+
+                if index_tys.len() < indices.len() && term.instantiate(&local.to_term()).occurs(&local) {
+                    return Err(Error::UnsupportedIndices(span, scrutinee_ty.clone()));
+                }
+
+                family_ty = term.instantiate(&local.to_term());
+                motive_args.push(local);
+                index_tys.push(index_ty);
+            }
+
+            let y = self.elab_cx.cx.ty_cx.local_with_repr(
+                "y".to_string(),
+                Term::apply_all(family.clone(), params.iter().cloned().chain(
+                    motive_args.iter().map(core::Name::to_term)).collect()));
+
+            let equations = |pcx: &mut PatternMatchCx, rhs: &[Term]| -> Vec<core::Name> {
+                index_tys.iter().zip(indices.iter()).zip(rhs.iter()).map(|((ty, lhs), rhs)| {
+                    pcx.elab_cx.cx.ty_cx.local_with_repr(
+                        "h".to_string(),
+                        Term::apply_all(eq.to_term(), vec![ty.clone(), lhs.clone(), rhs.clone()]))
+                }).collect()
+            };
+
+            let motive_indices: Vec<_> = motive_args.iter().map(core::Name::to_term).collect();
+            let motive_equations = equations(self, &motive_indices);
+            motive_args.push(y);
+
+            args.push(Term::abstract_lambda(
+                motive_args,
+                Term::abstract_pi(motive_equations, goal.clone())));
+
+            for &(ref ctor, _) in &datatype.ctors {
+                let (ctor_args, ctor_indices, _) =
+                    try!(self.ctor_instance(ctor, &levels, &params));
+
+                let goal = Term::abstract_pi(equations(self, &ctor_indices), goal.clone());
+
+                minor_premises.push(try!(self.elaborate_arm(
+                    span, ctor, ctor_args, vec![],
+                    &[], goal, indices.len(), &cases)));
+            }
+
+            extra_args = index_tys.iter().zip(indices.iter()).map(|(ty, index)| {
+                Term::apply_all(refl.to_term(), vec![ty.clone(), index.clone()])
+            }).collect();
+        }
+
+        args.extend(indices.into_iter());
+        args.push(scrutinee);
+        args.extend(minor_premises.into_iter());
+        args.extend(extra_args);
+
+        let result = Term::apply_all(cases_on.to_term(), args);
+
+        debug!("elaborate_dependent_match: {}", result);
+
+        Ok(result)
+    }
+
+    /// The minor premise for `ctor`, applied to the locals `ctor_args`. The
+    /// arm for it replaces each local in `refinements` with its term and
+    /// introduces a local for each of the `reverted` locals, it then has the
+    /// type `goal` which starts with `equations` equations to solve.
+    fn elaborate_arm(&mut self,
+                     span: ast::Span,
+                     ctor: &core::Name,
+                     ctor_args: Vec<core::Name>,
+                     refinements: Vec<(core::Name, Term)>,
+                     reverted: &[core::Name],
+                     goal: Term,
+                     equations: usize,
+                     cases: &[SimpleCase]) -> Result<Term, Error> {
+        let scope = self.enter_arm();
+
+        let result = self.bind_pattern(ctor, &ctor_args, cases).and_then(|rhs| {
+            self.elab_cx.locals_in_order.extend(ctor_args.iter().cloned());
+
+            for &(ref local, ref value) in &refinements {
+                self.replace_local(local, value);
+            }
+
+            let (introduced, goal) = self.introduce(reverted, goal);
+
+            self.solve_equations(span, ctor, goal, equations, rhs)
+                .map(|body| Term::abstract_lambda(introduced, body))
+        });
+
+        self.leave_arm(scope);
+
+        Ok(Term::abstract_lambda(ctor_args, try!(result)))
+    }
+
+    /// Bind the names of the arguments in the first case which matches
+    /// `ctor` to `ctor_args`, returning its right hand side.
+    fn bind_pattern(&mut self,
+                    ctor: &core::Name,
+                    ctor_args: &[core::Name],
+                    cases: &[SimpleCase]) -> Result<Option<SimpleMatchArm>, Error> {
+        let case = match try!(self.case_for(ctor, cases)) {
+            None => return Ok(None),
+            Some(case) => case,
+        };
+
+        if let SimplePattern::Constructor(_, ref names) = case.pattern {
+            for (name, local) in names.iter().zip(ctor_args.iter()) {
+                self.elab_cx.locals.insert(name.clone(), local.clone());
+            }
+        }
+
+        Ok(Some(case.rhs.clone()))
+    }
+
+    /// Solve the first `equations` equations `goal` starts with, and then
+    /// elaborate `rhs` with the type that remains.
+    fn solve_equations(&mut self,
+                       span: ast::Span,
+                       ctor: &core::Name,
+                       goal: Term,
+                       equations: usize,
+                       rhs: Option<SimpleMatchArm>) -> Result<Term, Error> {
+        if equations == 0 {
+            return match rhs {
+                None => Err(Error::MissingCase(span, ctor.clone())),
+                Some(arm) => self.elaborate_simple_arm(arm, Some(goal)),
+            };
+        }
+
+        let (equation, rest) = match goal {
+            Term::Forall { binder, term, .. } => (binder.ty.into_term(), term),
+            goal => panic!("elaboration bug: expected an equation in {}", goal),
+        };
+
+        let h = self.elab_cx.cx.ty_cx.local_with_repr("h".to_string(), equation.clone());
+        let rest = rest.instantiate(&h.to_term());
+
+        let mut sides = equation.uncurry().1;
+
+        if sides.len() != 3 {
+            panic!("elaboration bug: {} is not an equation", equation);
+        }
+
+        let rhs_index = sides.pop().unwrap();
+        let lhs = sides.pop().unwrap();
+        let ty = sides.pop().unwrap();
+
+        let body = try!(self.unify_indices(
+            span, ctor, &h, ty, lhs, rhs_index, rest, equations - 1, rhs, true));
+
+        Ok(Term::abstract_lambda(vec![h], body))
+    }
+
+    /// Solve the equation `h : Eq ty lhs rhs`, along with the `equations`
+    /// equations `rest` starts with. If we can't we try again once both
+    /// sides are in weak head normal form.
+    fn unify_indices(&mut self,
+                     span: ast::Span,
+                     ctor: &core::Name,
+                     h: &core::Name,
+                     ty: Term,
+                     lhs: Term,
+                     rhs: Term,
+                     rest: Term,
+                     equations: usize,
+                     arm: Option<SimpleMatchArm>,
+                     reduce: bool) -> Result<Term, Error> {
+        let def_eq = match self.elab_cx.cx.ty_cx.def_eq(span, &lhs, &rhs) {
+            Ok((_, constraints)) => constraints.len() == 0,
+            Err(_) => false,
+        };
+
+        if def_eq {
+            return self.solve_equations(span, ctor, rest, equations, arm);
+        }
+
+        match (&lhs, &rhs) {
+            (_, &Term::Var { ref name }) if name.is_local() && !lhs.occurs(name) =>
+                return self.eliminate(span, ctor, h, ty, &lhs, &rhs, name, &lhs, rest, equations, arm),
+            (&Term::Var { ref name }, _) if name.is_local() && !rhs.occurs(name) =>
+                return self.eliminate(span, ctor, h, ty, &lhs, &rhs, name, &rhs, rest, equations, arm),
+            _ => {}
+        }
+
+        if let (Some(lhs_ctor), Some(rhs_ctor)) = (self.ctor_application(&lhs), self.ctor_application(&rhs)) {
+            return self.no_confusion(span, ctor, h, lhs, rhs, lhs_ctor, rhs_ctor, rest, equations, arm);
+        }
+
+        if reduce {
+            let lhs = try!(self.elab_cx.cx.ty_cx.whnf(&lhs)).0;
+            let rhs = try!(self.elab_cx.cx.ty_cx.whnf(&rhs)).0;
+
+            return self.unify_indices(span, ctor, h, ty, lhs, rhs, rest, equations, arm, false);
+        }
+
+        Err(Error::CantUnifyIndices(span, lhs, rhs))
+    }
+
+    /// Whether `unify_indices` could solve an equation between `lhs` and
+    /// `rhs`, which it can't only when they are built with different
+    /// constructors. When we can't tell we assume it could.
+    pub fn may_unify_indices(&self, lhs: &Term, rhs: &Term, reduce: bool) -> Result<bool, Error> {
+        let def_eq = match self.elab_cx.cx.ty_cx.def_eq(ast::Span::dummy(), lhs, rhs) {
+            Ok((_, constraints)) => constraints.len() == 0,
+            Err(_) => false,
+        };
+
+        if def_eq {
+            return Ok(true);
+        }
+
+        match (lhs, rhs) {
+            (&Term::Var { ref name }, _) |
+            (_, &Term::Var { ref name }) if name.is_local() => return Ok(true),
+            _ => {}
+        }
+
+        if let (Some(lhs_ctor), Some(rhs_ctor)) = (self.full_ctor_application(lhs),
+                                                   self.full_ctor_application(rhs)) {
+            let (datatype, lhs_name, lhs_args) = lhs_ctor;
+            let (_, rhs_name, rhs_args) = rhs_ctor;
+
+            if !lhs_name.same_variable(&rhs_name) {
+                return Ok(false);
+            }
+
+            let num_params = datatype.parameters.len();
+
+            for (lhs_arg, rhs_arg) in lhs_args[num_params..].iter().zip(rhs_args[num_params..].iter()) {
+                if !try!(self.may_unify_indices(lhs_arg, rhs_arg, true)) {
+                    return Ok(false);
+                }
+            }
+
+            return Ok(true);
+        }
+
+        if reduce {
+            let lhs = try!(self.elab_cx.cx.ty_cx.whnf(lhs)).0;
+            let rhs = try!(self.elab_cx.cx.ty_cx.whnf(rhs)).0;
+
+            return self.may_unify_indices(&lhs, &rhs, false);
+        }
+
+        Ok(true)
+    }
+
+    /// Solve `h : Eq ty lhs rhs` where one side is the local `local`, by
+    /// replacing it with `value`, the other side. We rewrite the rest of the
+    /// goal with `Eq.rec`, along with the locals whose types mention `local`.
+    fn eliminate(&mut self,
+                 span: ast::Span,
+                 ctor: &core::Name,
+                 h: &core::Name,
+                 ty: Term,
+                 lhs: &Term,
+                 rhs: &Term,
+                 local: &core::Name,
+                 value: &Term,
+                 rest: Term,
+                 equations: usize,
+                 arm: Option<SimpleMatchArm>) -> Result<Term, Error> {
+        let (eq, _) = try!(self.equality(span, &ty));
+
+        let reverted = self.dependents(&[local.clone()]);
+        let motive_body = Term::abstract_pi(reverted.clone(), rest);
+
+        let ty_cx = &self.elab_cx.cx.ty_cx;
+
+        let x = ty_cx.local_with_repr("x".to_string(), ty.clone());
+        let y = ty_cx.local_with_repr("y".to_string(), ty.clone());
+        let hxy = ty_cx.local_with_repr("h".to_string(), Term::apply_all(
+            eq.to_term(), vec![ty.clone(), x.to_term(), y.to_term()]));
+
+        // We rewrite from the side with `value` to the side with `local`.
+        let (from, to) = if value == lhs { (&x, &y) } else { (&y, &x) };
+
+        let from_goal = ty_cx.local_with_repr(
+            "g".to_string(), replace(&motive_body, local, &from.to_term()));
+
+        let motive = Term::abstract_lambda(
+            vec![x.clone(), y.clone(), hxy],
+            Term::abstract_pi(vec![from_goal], replace(&motive_body, local, &to.to_term())));
+
+        let z = ty_cx.local_with_repr("z".to_string(), ty.clone());
+        let z_goal = ty_cx.local_with_repr("g".to_string(), replace(&motive_body, local, &z.to_term()));
+        let minor_premise = Term::abstract_lambda(vec![z, z_goal.clone()], z_goal.to_term());
+
+        let scope = self.enter_arm();
+
+        self.replace_local(local, value);
+
+        let (introduced, goal) = self.introduce(&reverted, replace(&motive_body, local, value));
+        let result = self.solve_equations(span, ctor, goal, equations, arm);
+
+        self.leave_arm(scope);
+
+        let rewritten = Term::abstract_lambda(introduced, try!(result));
+
+        let rec = eq.in_scope("rec".to_string()).unwrap();
+        let rec = self.elab_cx.instantiate_global(rec);
+
+        let rewrite = Term::apply_all(rec.to_term(), vec![
+            ty,
+            motive,
+            minor_premise,
+            lhs.clone(),
+            rhs.clone(),
+            h.to_term(),
+            rewritten]);
+
+        Ok(Term::apply_all(rewrite, reverted.iter().map(core::Name::to_term).collect()))
+    }
+
+    /// Solve `h : Eq ty lhs rhs` where both sides are constructors applied
+    /// to arguments. Different constructors can't be equal so we are done,
+    /// otherwise their arguments must be equal, which we solve in turn.
+    fn no_confusion(&mut self,
+                    span: ast::Span,
+                    ctor: &core::Name,
+                    h: &core::Name,
+                    lhs: Term,
+                    rhs: Term,
+                    lhs_ctor: (core::Data, core::Name, Vec<Term>),
+                    rhs_ctor: (core::Data, core::Name, Vec<Term>),
+                    rest: Term,
+                    equations: usize,
+                    arm: Option<SimpleMatchArm>) -> Result<Term, Error> {
+        let (datatype, lhs_name, lhs_args) = lhs_ctor;
+        let (_, rhs_name, rhs_args) = rhs_ctor;
+
+        let num_params = datatype.parameters.len();
+
+        let no_confusion = datatype.name.in_scope("no_confusion".to_string()).unwrap();
+        let no_confusion = self.elab_cx.instantiate_global(no_confusion);
+
+        let mut args = lhs_args[..num_params].to_vec();
+        args.push(rest.clone());
+        args.push(lhs);
+        args.push(rhs);
+        args.push(h.to_term());
+
+        let no_confusion = Term::apply_all(no_confusion.to_term(), args);
+
+        if !lhs_name.same_variable(&rhs_name) {
+            return Ok(no_confusion);
+        }
+
+        let (eq, _) = try!(self.equality(span, &rest));
+        let mut ctor_ty = try!(self.elab_cx.cx.ty_cx.global_type(&lhs_name));
+        let mut arg_equations = vec![];
+
+        for (i, (lhs_arg, rhs_arg)) in lhs_args.iter().zip(rhs_args.iter()).enumerate() {
+            let (binder, term) = match ctor_ty {
+                Term::Forall { binder, term, .. } => (binder, term),
+                t => panic!("elaboration bug: {} takes too few arguments", t),
+            };
+
+            if i >= num_params {
+                arg_equations.push(self.elab_cx.cx.ty_cx.local_with_repr(
+                    "h".to_string(),
+                    Term::apply_all(eq.to_term(), vec![
+                        binder.ty.into_term(),
+                        lhs_arg.clone(),
+                        rhs_arg.clone()])));
+            }
+
+            ctor_ty = term.instantiate(lhs_arg);
+        }
+
+        let num_equations = arg_equations.len();
+        let goal = Term::abstract_pi(arg_equations, rest);
+        let solved = try!(self.solve_equations(span, ctor, goal, equations + num_equations, arm));
+
+        Ok(Term::apply(no_confusion, solved))
+    }
+
+    /// The inductive type, constructor and arguments of `term`, if it is a
+    /// constructor of a type with a `no_confusion` lemma applied to all of
+    /// its arguments.
+    fn ctor_application(&self, term: &Term) -> Option<(core::Data, core::Name, Vec<Term>)> {
+        let ty_cx = &self.elab_cx.cx.ty_cx;
+
+        self.full_ctor_application(term).and_then(|(dt, name, args)| {
+            if ty_cx.in_scope(&dt.name.in_scope("no_confusion".to_string()).unwrap()) {
+                Some((dt, name, args))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The inductive type, constructor and arguments of `term`, if it is a
+    /// constructor applied to all of its arguments.
+    fn full_ctor_application(&self, term: &Term) -> Option<(core::Data, core::Name, Vec<Term>)> {
+        let (head, args) = term.uncurry();
+
+        let name = match head {
+            Term::Var { name } => name,
+            _ => return None,
+        };
+
+        self.elab_cx.cx.ty_cx.types.values().find(|dt| {
+            dt.ctors.iter().any(|c| c.0.same_variable(&name) && count_binders(&c.1) == args.len())
+        }).map(|dt| (dt.clone(), name, args))
+    }
+
+    /// `Eq` and its constructor, which we need to match on a value of type
+    /// `ty`.
+    fn equality(&self, span: ast::Span, ty: &Term) -> Result<(core::Name, core::Name), Error> {
+        match self.elab_cx.cx.ty_cx.equality() {
+            None => Err(Error::EqualityUnavailable(span, ty.clone())),
+            Some(eq) => Ok(eq),
+        }
+    }
+
+    /// The locals for the arguments of `ctor` with the parameters `params`,
+    /// along with the indices of its type and its application to them.
+    pub fn ctor_instance(&self,
+                         ctor: &core::Name,
+                         levels: &[core::Level],
+                         params: &[Term]) -> Result<(Vec<core::Name>, Vec<Term>, Term), Error> {
+        let ctor = ctor.clone().with_levels(levels.to_vec());
+        let mut ctor_ty = try!(self.elab_cx.cx.ty_cx.global_type(&ctor));
+
+        for param in params {
+            ctor_ty = match ctor_ty {
+                Term::Forall { term, .. } => term.instantiate(param),
+                t => panic!("elaboration bug: {} takes too few parameters", t),
+            };
+        }
+
+        let mut ctor_args = vec![];
+
+        while let Term::Forall { binder, term, .. } = ctor_ty {
+            let local = self.elab_cx.cx.ty_cx.local(binder);
+            ctor_ty = term.instantiate(&local.to_term());
+            ctor_args.push(local);
+        }
+
+        let indices = ctor_ty.uncurry().1[params.len()..].to_vec();
+
+        let value = Term::apply_all(
+            ctor.to_term(),
+            params.iter().cloned().chain(ctor_args.iter().map(core::Name::to_term)).collect());
+
+        Ok((ctor_args, indices, value))
+    }
+
+    /// The locals in scope whose types mention `locals`, or a local we have
+    /// already found, in the order they were introduced.
+    fn dependents(&self, locals: &[core::Name]) -> Vec<core::Name> {
+        let mut found = locals.to_vec();
+        let mut dependents = vec![];
+
+        for local in &self.elab_cx.locals_in_order {
+            if found.contains(local) {
+                continue;
+            }
+
+            if let &core::Name::Local { ref ty, .. } = local {
+                if found.iter().any(|l| ty.occurs(l)) {
+                    found.push(local.clone());
+                    dependents.push(local.clone());
+                }
+            }
+        }
+
+        dependents
+    }
+
+    /// Replace `local` with `value`, the names which stood for `local` now
+    /// stand for `value`.
+    fn replace_local(&mut self, local: &core::Name, value: &Term) {
+        let names: Vec<_> =
+            self.elab_cx
+                .locals
+                .iter()
+                .filter(|&(_, l)| l == local)
+                .map(|(n, _)| n.clone())
+                .collect();
+
+        for name in names {
+            match value {
+                &Term::Var { name: ref value } if value.is_local() => {
+                    self.elab_cx.locals.insert(name, value.clone());
+                }
+                _ => {
+                    self.elab_cx.locals.remove(&name);
+                    self.elab_cx.refined.insert(name, value.clone());
+                }
+            }
+        }
+
+        for refined in self.elab_cx.refined.values_mut() {
+            *refined = replace(refined, local, value);
+        }
+
+        self.elab_cx.locals_in_order.retain(|l| l != local);
+    }
+
+    /// Introduce a local for each of the `reverted` locals, which `goal`
+    /// starts by binding, returning them and the rest of the goal.
+    fn introduce(&mut self, reverted: &[core::Name], goal: Term) -> (Vec<core::Name>, Term) {
+        let mut goal = goal;
+        let mut introduced = vec![];
+
+        for old_local in reverted {
+            let (binder, rest) = match goal {
+                Term::Forall { binder, term, .. } => (binder, term),
+                goal => panic!("elaboration bug: expected {} in {}", old_local, goal),
+            };
+
+            let repr = match old_local {
+                &core::Name::Local { ref repr, .. } => repr.clone(),
+                _ => "_".to_string(),
+            };
+
+            let local = self.elab_cx.cx.ty_cx.local_with_repr(repr, binder.ty.into_term());
+
+            goal = rest.instantiate(&local.to_term());
+
+            self.replace_local(old_local, &local.to_term());
+            self.elab_cx.locals_in_order.push(local.clone());
+            introduced.push(local);
+        }
+
+        (introduced, goal)
+    }
+
+    pub fn enter_arm(&self) -> Scope {
+        Scope {
+            locals: self.elab_cx.locals.clone(),
+            locals_in_order: self.elab_cx.locals_in_order.clone(),
+            refined: self.elab_cx.refined.clone(),
+        }
+    }
+
+    pub fn leave_arm(&mut self, scope: Scope) {
+        self.elab_cx.locals = scope.locals;
+        self.elab_cx.locals_in_order = scope.locals_in_order;
+        self.elab_cx.refined = scope.refined;
+    }
+}
+
+/// Replace the local `local` with `value` in `term`.
+fn replace(term: &Term, local: &core::Name, value: &Term) -> Term {
+    term.abstr(local).instantiate(value)
+}
+
+/// The number of arguments a constructor takes, including the parameters.
+pub fn count_binders(ty: &Term) -> usize {
+    let mut count = 0;
+    let mut ty = ty;
+
+    while let &Term::Forall { ref term, .. } = ty {
+        count += 1;
+        ty = term;
+    }
+
+    count
+}
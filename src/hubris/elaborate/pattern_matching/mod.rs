@@ -1,14 +1,18 @@
-use super::super::ast::{self};
+use super::super::ast::{self, HasSpan};
 use super::super::core::{self, Term};
+use super::super::session::HasSession;
+use super::super::typeck;
 use super::{LocalElabCx, Error};
-
-use std::collections::HashMap;
+use super::util::to_qualified_name;
 
 struct PatternMatchCx<'ecx, 'cx: 'ecx> {
     elab_cx: &'ecx mut LocalElabCx<'cx>,
+    /// The spans of the arms of the match which can never be reached.
+    unreachable: Vec<ast::Span>,
 }
 
-mod renamer;
+mod coverage;
+mod dependent;
 mod simplify;
 
 use self::simplify::*;
@@ -17,6 +21,7 @@ impl<'ecx, 'cx: 'ecx>PatternMatchCx<'ecx, 'cx> {
     fn new(elab_cx: &'ecx mut LocalElabCx<'cx>) -> PatternMatchCx<'ecx, 'cx> {
         PatternMatchCx {
             elab_cx: elab_cx,
+            unreachable: vec![],
         }
     }
 
@@ -27,19 +32,25 @@ impl<'ecx, 'cx: 'ecx>PatternMatchCx<'ecx, 'cx> {
                                  -> Result<R, Error>
         where F: FnOnce(&mut PatternMatchCx, Vec<core::Name>) -> Result<R, Error>
     {
-        let mut locals = vec![];
+        let scope = self.enter_arm();
+
+        let result = self.bind_pattern_locals(name_and_type).and_then(|locals| body(self, locals));
 
-        let old_context = self.elab_cx.locals.clone();
-        let old_locals_in_order = self.elab_cx.locals_in_order.clone();
+        // Restore the previous context, whether or not the body failed.
+        self.leave_arm(scope);
+
+        result
+    }
 
-        // A binder can contain multiple names like so:
-        // (A B C : T) will result in a binder with
-        // 3 names to bind, so we then do an inner
-        // loop.
+    /// Introduce a local for each of the names a pattern binds.
+    fn bind_pattern_locals(&mut self,
+                           name_and_type: Vec<(ast::Name, core::Term)>)
+                           -> Result<Vec<core::Name>, Error> {
+        let mut locals = vec![];
 
         for (name, ty) in name_and_type {
             let repr = match name.clone().repr {
-                ast::NameKind::Qualified(..) => panic!(),
+                ast::NameKind::Qualified(..) => return Err(Error::QualifiedLocal(name)),
                 ast::NameKind::Unqualified(s) => s,
                 ast::NameKind::Placeholder => "_".to_string(),
             };
@@ -52,162 +63,494 @@ impl<'ecx, 'cx: 'ecx>PatternMatchCx<'ecx, 'cx> {
             locals.push(local);
         }
 
-        let result = try!(body(self, locals));
+        Ok(locals)
+    }
 
-        // Restore the previous context.
-        self.elab_cx.locals = old_context;
-        self.elab_cx.locals_in_order = old_locals_in_order;
+    /// Elaborate a match on `scrutinees`. We elaborate each of the scrutinees
+    /// once, a made up name stands for it in the decision tree we compile the
+    /// arms to.
+    fn elaborate_match(&mut self,
+                       scrutinees: Vec<ast::Term>,
+                       cases: Vec<ast::Case>,
+                       expected_ty: Option<core::Term>) -> Result<core::Term, Error> {
+        let mut names = vec![];
+        let mut tys = vec![];
+
+        for (i, scrutinee) in scrutinees.into_iter().enumerate() {
+            let scrutinee_span = scrutinee.get_span();
+            let escrutinee = try!(self.elab_cx.elaborate_term(scrutinee));
+            let scrutinee_ty = try!(self.scrutinee_type(&escrutinee));
+
+            if self.datatype(&scrutinee_ty).is_none() {
+                return Err(Error::MatchOnNonInductive(scrutinee_span, escrutinee, scrutinee_ty));
+            }
 
-        Ok(result)
+            let name = ast::Name {
+                span: scrutinee_span,
+                repr: ast::NameKind::Unqualified(format!("_s{}", i)),
+            };
+
+            self.elab_cx.refined.insert(name.clone(), escrutinee);
+            names.push(ast::Term::Var { name: name, implicit: false });
+            tys.push(scrutinee_ty);
+        }
+
+        let scrutinees_span = ast::Span {
+            hi: names[names.len() - 1].get_span().hi,
+            .. names[0].get_span()
+        };
+
+        let mut rows = vec![];
+        let mut spans = vec![];
+
+        for case in cases {
+            let ast::Case { span: case_span, patterns, rhs } = case;
+
+            if patterns.len() != tys.len() {
+                return Err(Error::WrongNumberOfPatterns(case_span, patterns.len(), tys.len()));
+            }
+
+            spans.push(patterns_span(&patterns, case_span));
+
+            let mut resolved = vec![];
+
+            for (pattern, ty) in patterns.into_iter().zip(tys.iter()) {
+                resolved.push(try!(self.resolve_pattern(pattern, ty)));
+            }
+
+            rows.push(Row {
+                patterns: resolved,
+                bindings: vec![],
+                rhs: rhs,
+            });
+        }
+
+        self.unreachable = try!(self.check_coverage(scrutinees_span, &rows, &spans, &tys));
+
+        // A match with no arms is still a match on the first scrutinee, one
+        // with no cases.
+        let tree = compile(names.clone(), rows, &mut 0).unwrap_or_else(|| {
+            SimpleMatchArm::Match(SimpleMatch {
+                scrutinee: names[0].clone(),
+                cases: vec![],
+            })
+        });
+
+        debug!("elaborate_match: decision tree {}", tree);
+
+        self.elaborate_simple_arm(tree, expected_ty)
     }
 
+    /// The type of the scrutinee `escrutinee`. It may still contain
+    /// meta-variables we only solve later, in which case we settle for its
+    /// inferred type. A type defined as an inductive type is unfolded to it.
+    fn scrutinee_type(&mut self, escrutinee: &core::Term) -> Result<core::Term, Error> {
+        let ty = match self.elab_cx.cx.ty_cx.type_check_term(escrutinee, None) {
+            Ok((_, ty)) => ty,
+            Err(e) => {
+                let ty = try!(self.elab_cx.cx.ty_cx.type_infer_term(escrutinee)).0;
+
+                if !ty.has_metas() {
+                    return Err(Error::TypeCk(e));
+                }
+
+                ty
+            }
+        };
+
+        if self.datatype(&ty).is_some() {
+            return Ok(ty);
+        }
+
+        let whnf = try!(self.elab_cx.cx.ty_cx.whnf(&ty)).0;
+
+        Ok(if self.datatype(&whnf).is_some() { whnf } else { ty })
+    }
 
-    fn elaborate_simple_match(&mut self, simple_match: SimpleMatch) -> Result<core::Term, Error> {
+    /// Resolve the names in `pattern`, which matches a value of type `ty`, to
+    /// the constructors of its type or to variables.
+    fn resolve_pattern(&mut self, pattern: ast::Pattern, ty: &core::Term) -> Result<Pattern, Error> {
+        let (name, args) = match pattern {
+            ast::Pattern::Placeholder => return Ok(Pattern::Wildcard),
+            ast::Pattern::Name(name) => (name, vec![]),
+            ast::Pattern::Constructor(name, args) => (name, args),
+        };
+
+        if name.repr == ast::NameKind::Placeholder && args.len() == 0 {
+            return Ok(Pattern::Wildcard);
+        }
+
+        // Until it resolves to a constructor the name may be a variable, so
+        // it is not recorded as a global yet.
+        let elab_name = match to_qualified_name(name.clone()) {
+            Some(elab_name) => elab_name,
+            None => return Err(Error::UnexpectedQualifiedName),
+        };
+
+        let is_ctor_of_ty = self.datatype(ty).map(|dt| {
+            dt.ctors.iter().any(|c| c.0.same_variable(&elab_name))
+        }).unwrap_or(false);
+
+        if !is_ctor_of_ty {
+            return if args.len() == 0 && !self.is_ctor(&elab_name) {
+                match name.repr {
+                    ast::NameKind::Qualified(..) => Err(Error::QualifiedLocal(name)),
+                    _ => Ok(Pattern::Var(name)),
+                }
+            } else {
+                Err(Error::NotAConstructor(name, ty.clone()))
+            };
+        }
+
+        self.elab_cx.cx.globals.insert(name.clone(), elab_name.clone());
+
+        let fields = self.fields(&elab_name, ty);
+
+        if fields.len() != args.len() {
+            return Err(Error::WrongNumberOfArguments(name, fields.len(), args.len()));
+        }
+
+        let mut resolved = vec![];
+
+        for (arg, field_ty) in args.into_iter().zip(fields.iter()) {
+            resolved.push(try!(self.resolve_pattern(arg, field_ty)));
+        }
+
+        Ok(Pattern::Constructor(name, elab_name, resolved))
+    }
+
+    /// The inductive type `ty` is an instance of.
+    fn datatype(&self, ty: &core::Term) -> Option<core::Data> {
+        match ty.uncurry().0 {
+            Term::Var { ref name } => self.elab_cx.cx.ty_cx.types.get(&name.without_levels()).cloned(),
+            _ => None,
+        }
+    }
+
+    fn is_ctor(&self, name: &core::Name) -> bool {
+        self.elab_cx.cx.ty_cx.types.values().any(|dt| dt.ctors.iter().any(|c| c.0.same_variable(name)))
+    }
+
+    /// The types of the arguments of `ctor` for a value of type `ty`, those
+    /// which depend on earlier arguments mention a local standing for them.
+    /// The locals the indices of `ty` determine are replaced by them.
+    fn fields(&self, ctor: &core::Name, ty: &core::Term) -> Vec<core::Term> {
+        let dt = self.datatype(ty).unwrap();
+        let params = ty.uncurry().1;
+        let mut ctor_ty = dt.ctors.iter().find(|c| &c.0 == ctor).unwrap().1.clone();
+        let mut fields = vec![];
+        let mut locals = vec![];
+
+        while let Term::Forall { binder, term, .. } = ctor_ty {
+            let binder_ty = binder.ty.into_term();
+
+            let arg = if fields.len() < dt.parameters.len() && fields.len() < params.len() {
+                params[fields.len()].clone()
+            } else {
+                let local = self.elab_cx.cx.ty_cx.local_with_repr("x".to_string(), binder_ty.clone());
+                locals.push(local.clone());
+                local.to_term()
+            };
+
+            fields.push(binder_ty);
+            ctor_ty = term.instantiate(&arg);
+        }
+
+        let mut fields = fields.split_off(dt.parameters.len());
+        let mut solved = vec![];
+
+        for (index, expected) in ctor_ty.uncurry().1.iter().zip(params.iter()).skip(dt.parameters.len()) {
+            self.match_index(index, expected, &locals, &mut solved);
+        }
+
+        for (local, value) in solved {
+            for field in &mut fields {
+                *field = field.abstr(&local).instantiate(&value);
+            }
+        }
+
+        fields
+    }
+
+    /// Find the values of `locals` which make `index` the same as `expected`,
+    /// when they are built with the same constructors.
+    fn match_index(&self,
+                   index: &core::Term,
+                   expected: &core::Term,
+                   locals: &[core::Name],
+                   solved: &mut Vec<(core::Name, core::Term)>) {
+        if let &Term::Var { ref name } = index {
+            if locals.contains(name) && !solved.iter().any(|s| &s.0 == name) {
+                solved.push((name.clone(), expected.clone()));
+                return;
+            }
+        }
+
+        let (index_head, index_args) = index.uncurry();
+        let (expected_head, expected_args) = expected.uncurry();
+
+        match (&index_head, &expected_head) {
+            (&Term::Var { name: ref index_ctor }, &Term::Var { name: ref expected_ctor })
+                if index_ctor == expected_ctor && self.is_ctor(index_ctor) &&
+                   index_args.len() == expected_args.len() => {
+                for (index_arg, expected_arg) in index_args.iter().zip(expected_args.iter()) {
+                    self.match_index(index_arg, expected_arg, locals, solved);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Elaborate an arm of the decision tree, binding the names the patterns
+    /// of a right hand side bind before we elaborate it.
+    fn elaborate_simple_arm(&mut self,
+                            arm: SimpleMatchArm,
+                            expected_ty: Option<core::Term>) -> Result<core::Term, Error> {
+        match arm {
+            SimpleMatchArm::Match(simple_match) =>
+                self.elaborate_simple_match(simple_match, expected_ty),
+            SimpleMatchArm::Term(bindings, rhs) => {
+                let scope = self.enter_arm();
+
+                let result = self.bind_names(bindings).and_then(|_| {
+                    self.elab_cx.elaborate_term_with_type(rhs, expected_ty)
+                });
+
+                self.leave_arm(scope);
+
+                result
+            }
+        }
+    }
+
+    /// Bind each name to the value of its term, which is why we can only
+    /// refer to the values a pattern matched in the right hand side of its
+    /// arm.
+    fn bind_names(&mut self, bindings: Vec<(ast::Name, ast::Term)>) -> Result<(), Error> {
+        for (name, term) in bindings {
+            let value = try!(self.elab_cx.elaborate_term(term));
+
+            match value {
+                Term::Var { name: ref local } if local.is_local() => {
+                    self.elab_cx.locals.insert(name, local.clone());
+                }
+                value => {
+                    self.elab_cx.locals.remove(&name);
+                    self.elab_cx.refined.insert(name, value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn elaborate_simple_match(&mut self,
+                              simple_match: SimpleMatch,
+                              expected_ty: Option<core::Term>) -> Result<core::Term, Error> {
         let SimpleMatch {
             scrutinee,
             cases,
-            pattern_type,
         } = simple_match;
 
-        let escrutinee = try!(self.elab_cx.elaborate_term(scrutinee));
-
-        let scrutinee_ty =
-            try!(self.elab_cx.cx.ty_cx.type_check_term(&escrutinee, None)).1;
+        let span = scrutinee.get_span();
+        let escrutinee = try!(self.elab_cx.elaborate_term(scrutinee.clone()));
+        let scrutinee_ty = try!(self.scrutinee_type(&escrutinee));
 
         let (inductive_ty, args) = scrutinee_ty.uncurry();
 
-        let inductive_ty = match inductive_ty {
-            Term::Var { name } => name,
-            other => panic!("{}", other),
+        let (inductive_ty, datatype) = match (inductive_ty, self.datatype(&scrutinee_ty)) {
+            (Term::Var { name }, Some(dt)) => (name, dt),
+            _ => return Err(Error::MatchOnNonInductive(span, escrutinee, scrutinee_ty)),
         };
 
-        let datatype = match self.elab_cx.cx.ty_cx.types.get(&inductive_ty) {
-            None => panic!("can't fine dt decl"),
-            Some(dt) => dt.clone(),
+        // The constructors of an inductive family refine its indices, and
+        // each constructor refines the type of the match when it mentions
+        // the scrutinee.
+        let refines_expected = match (&escrutinee, &expected_ty) {
+            (&Term::Var { ref name }, &Some(ref ty)) => name.is_local() && ty.occurs(name),
+            _ => false,
         };
 
-        let ctor_map : HashMap<_, _> =
-            datatype.ctors
-                    .clone()
-                    .into_iter()
-                    .collect();
-
-        let cases : Vec<_> =
-            try!(cases.into_iter()
-                      .map(|c| self.elaborate_simple_case(c, &scrutinee_ty, &ctor_map))
-                      .collect());
-
-        // let cases : Vec<_> =
-        //     cases.into_iter()
-        //          .map(|c| { debug!("core case: {}", c); Term::apply_all(c, args.clone()) })
-        //          .collect();
-
-        // debug!("core case: {}", case);
-
-         match pattern_type  {
-             PatternType::Cases => {
-                let cases_on = inductive_ty.in_scope("cases_on".to_string()).unwrap();
-                let head = try!(self.elab_cx.apply_implicit_args(cases_on.to_term()));
-                let mut args = vec![escrutinee];
-                args.extend(cases.into_iter());
-                let result = Term::apply_all(head, args);
-                debug!("elaborated_match: {}", result);
-                Ok(result)
-             }
-         }
+        if args.len() > datatype.parameters.len() || refines_expected {
+            return self.elaborate_dependent_match(
+                span, escrutinee, scrutinee_ty, datatype, cases, expected_ty);
+        }
+
+        // Each arm has the type of the whole match, unless that type depends
+        // on the scrutinee.
+        let depends_on_scrutinee = |ty: &core::Term| {
+            args.iter().chain(Some(&escrutinee))
+                .any(|a| match a {
+                    &Term::Var { ref name } if name.is_local() => ty.occurs(name),
+                    _ => true,
+                })
+        };
+
+        let expected_ty = expected_ty.and_then(|ty| {
+            if depends_on_scrutinee(&ty) { None } else { Some(ty) }
+        });
+
+        // `cases_on` takes a case for each constructor, in the order they
+        // were declared in.
+        let mut ecases = vec![];
+
+        for &(ref ctor, ref ctor_ty) in &datatype.ctors {
+            let case = match try!(self.case_for(ctor, &cases)) {
+                None => return Err(Error::MissingCase(span, ctor.clone())),
+                Some(case) => case,
+            };
+
+            let ctor = ctor.clone().with_levels(inductive_ty.levels().to_vec());
+
+            ecases.push(try!(self.elaborate_simple_case(
+                case, (&scrutinee, &escrutinee), &scrutinee_ty, &ctor, ctor_ty, expected_ty.clone())));
+        }
+
+        let cases_on = inductive_ty.in_scope("cases_on".to_string()).unwrap();
+        let cases_on = self.elab_cx.instantiate_global(cases_on);
+        let head = try!(self.elab_cx.apply_implicit_args(cases_on.to_term()));
+        let mut args = vec![escrutinee];
+        args.extend(ecases.into_iter());
+        let result = Term::apply_all(head, args);
+        debug!("elaborated_match: {}", result);
+        Ok(result)
+    }
+
+    /// The first of `cases` which matches a value built with `ctor`.
+    fn case_for<'c>(&mut self,
+                    ctor: &core::Name,
+                    cases: &'c [SimpleCase]) -> Result<Option<&'c SimpleCase>, Error> {
+        for case in cases {
+            match &case.pattern {
+                &SimplePattern::Constructor(ref name, _) => {
+                    if to_qualified_name(name.clone()).as_ref() == Some(ctor) {
+                        return Ok(Some(case));
+                    }
+                }
+                &SimplePattern::Wildcard => return Ok(Some(case)),
+            }
+        }
+
+        Ok(None)
     }
 
+    /// The names a case for the constructor `ctor` of type `ctor_ty` binds,
+    /// along with their types, when it matches a value of type
+    /// `scrutinee_ty` in the match at `span`.
     fn simple_pattern_binders(&mut self,
-                              simple_pattern: SimplePattern,
+                              span: ast::Span,
+                              simple_pattern: &SimplePattern,
                               scrutinee_ty: &core::Term,
-                              ctor_map: &HashMap<core::Name, core::Term>) -> Result<Vec<(ast::Name, core::Term)>, Error> {
+                              ctor: &core::Name,
+                              ctor_ty: &core::Term) -> Result<Vec<(ast::Name, core::Term)>, Error> {
+        let (_, i_args) = scrutinee_ty.uncurry();
+        let mut ctor_ty = ctor_ty.clone();
+
+        for arg in i_args {
+            ctor_ty = match ctor_ty {
+                Term::Forall { term, .. } => term.instantiate(&arg),
+                _ => return Err(Error::CtorDoesNotMatch(span, ctor.clone(), scrutinee_ty.clone())),
+            };
+        }
+
+        debug!("ctor_ty {}", ctor_ty);
+
+        let binders = ctor_ty.binders().unwrap_or(vec![]);
 
         match simple_pattern {
-            SimplePattern::Name(n) => {
-                let elab_name = try!(self.elab_cx.cx.elaborate_global_name(n.clone()));
-
-                match ctor_map.get(&elab_name) {
-                    None => return Ok(vec![(n, scrutinee_ty.clone())]),
-                    Some(ctor_ty) => {
-                        // Need to do error checking here
-                        return Ok(vec![]);
-                    }
-                }
-            }
-            SimplePattern::Constructor(ctor, args) => {
-                let elab_name = try!(self.elab_cx.cx.elaborate_global_name(ctor.clone()));
-
-                match ctor_map.get(&elab_name) {
-                    None => panic!("not the right"), // return Ok(vec![(ctor.clone(), scrutinee_ty.clone())]),
-                    Some(ctor_ty) => {
-                        debug!("{:?}", ctor_ty.binders());
-                        let (inductive_ty, i_args) = scrutinee_ty.uncurry();
-                        let mut ctor_ty = ctor_ty.clone();
-
-                        for arg in i_args {
-                            match ctor_ty {
-                                Term::Forall { term, .. } => {
-                                    debug!("arg {}", arg);
-                                    ctor_ty = term.instantiate(&arg);
-                                }
-                                _ => panic!()
-                            }
-                        }
-
-                        debug!("ctor_ty {}", ctor_ty);
-                        let binders = ctor_ty.binders()
-                                             .unwrap_or(vec![])
-                                             .iter()
-                                             .cloned()
-                                             .zip(args.into_iter())
-                                             .map(|(t, n)| {
-                                                 (n, t.clone())
-                                             }).collect();
-
-                        return Ok(binders);
-                    }
+            &SimplePattern::Constructor(ref name, ref args) => {
+                if args.len() != binders.len() {
+                    return Err(Error::WrongNumberOfArguments(name.clone(), binders.len(), args.len()));
                 }
+
+                Ok(binders.into_iter().zip(args.iter()).map(|(t, n)| (n.clone(), t.clone())).collect())
             }
+            // The wildcard case binds nothing, the arguments still need names.
+            &SimplePattern::Wildcard =>
+                Ok(binders.into_iter().map(|t| (ast::Name::from_str("_"), t.clone())).collect()),
         }
     }
 
+    /// Elaborate the case for `ctor` of a match on `scrutinee`, the name
+    /// standing for it in the decision tree along with its value.
     fn elaborate_simple_case(&mut self,
-                             simple_case: SimpleCase,
+                             simple_case: &SimpleCase,
+                             scrutinee: (&ast::Term, &core::Term),
                              scrutinee_ty: &core::Term,
-                             ctor_map: &HashMap<core::Name, core::Term>) -> Result<core::Term, Error> {
-        let SimpleCase {
-            pattern,
-            rhs,
+                             ctor: &core::Name,
+                             ctor_ty: &core::Term,
+                             expected_ty: Option<core::Term>) -> Result<core::Term, Error> {
+        let &SimpleCase {
+            ref pattern,
+            ref rhs,
         } = simple_case;
 
         debug!("pattern: {} rhs: {}", pattern, rhs);
 
         let binders = try!(self.simple_pattern_binders(
-            pattern,
-            scrutinee_ty,
-            ctor_map));
+            scrutinee.0.get_span(), pattern, scrutinee_ty, ctor, ctor_ty));
 
         for &(ref n, ref ty) in &binders {
             debug!("{} {}", n, ty);
         }
 
+        let rhs = rhs.clone();
+        let params = scrutinee_ty.uncurry().1;
+
         self.enter_pattern_scope(binders, move |pat_cx, names| {
-            match rhs {
-                SimpleMatchArm::Term(rhs) =>
-                    Ok(Term::abstract_lambda(names, try!(pat_cx.elab_cx.elaborate_term(rhs)))),
-                SimpleMatchArm::Match(mat) =>
-                    pat_cx.elaborate_simple_match(mat)
-            }
+            let value = Term::apply_all(
+                ctor.to_term(),
+                params.into_iter().chain(names.iter().map(core::Name::to_term)).collect());
+
+            let scope = pat_cx.enter_arm();
+            pat_cx.refine_scrutinee(scrutinee, value);
+            let result = pat_cx.elaborate_simple_arm(rhs, expected_ty);
+            pat_cx.leave_arm(scope);
+
+            Ok(Term::abstract_lambda(names, try!(result)))
         })
     }
+
+    /// Inside a case the scrutinee is the constructor applied to the names
+    /// the case binds, so the names the patterns bind to it, or to a value
+    /// built from it, refer to the arguments of the case rather than to the
+    /// scrutinee. Otherwise they would still refer to the argument of a
+    /// structurally recursive definition once its recursive calls no longer
+    /// pass it.
+    fn refine_scrutinee(&mut self, scrutinee: (&ast::Term, &core::Term), value: Term) {
+        if let &Term::Var { name: ref local } = scrutinee.1 {
+            if local.is_local() {
+                for refined in self.elab_cx.refined.values_mut() {
+                    *refined = refined.abstr(local).instantiate(&value);
+                }
+            }
+        }
+
+        if let &ast::Term::Var { ref name, .. } = scrutinee.0 {
+            self.elab_cx.locals.remove(name);
+            self.elab_cx.refined.insert(name.clone(), value);
+        }
+    }
 }
 
 pub fn elaborate_pattern_match<'ecx>(
         elab_cx: &mut LocalElabCx<'ecx>,
-        scrutinee: ast::Term,
-        cases: Vec<ast::Case>) -> Result<Term, Error> {
+        scrutinees: Vec<ast::Term>,
+        cases: Vec<ast::Case>,
+        expected_ty: Option<Term>) -> Result<Term, Error> {
     let mut pmcx = PatternMatchCx::new(elab_cx);
-    let simplified_match = simplify_match(scrutinee, cases);
-    debug!("simplified_match: {}", simplified_match);
-    pmcx.elaborate_simple_match(simplified_match)
+
+    // The names standing for the scrutinees are only in scope in the match.
+    let scope = pmcx.enter_arm();
+    let result = pmcx.elaborate_match(scrutinees, cases, expected_ty);
+    pmcx.leave_arm(scope);
+    let result = try!(result);
+
+    for span in pmcx.unreachable {
+        try!(pmcx.elab_cx.cx.session()
+                 .span_warning(span, format!("unreachable pattern"))
+                 .map_err(typeck::Error::Io));
+    }
+
+    Ok(result)
 }
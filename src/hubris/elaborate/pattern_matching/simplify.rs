@@ -1,36 +1,55 @@
-use super::super::super::ast::{self};
-use super::renamer::{rename_term, RenameMap};
+//! Compiling the arms of a match to a decision tree of simple matches, each
+//! of which matches a single value against the constructors of its type,
+//! and so becomes a single `cases_on`.
+//!
+//! We use the clause matrix algorithm, each arm is a row of patterns, one
+//! for each of the values we have still to match. To compile the rows we
+//! pick a value the first row matches against a constructor, and for each
+//! constructor the rows mention we compile the rows which can match a value
+//! built with it, in which the patterns for its arguments replace the
+//! pattern for the value. The rows whose pattern for the value matches any
+//! value also become the case for the constructors no row mentions. Once
+//! the first row only has patterns which match any value, its right hand
+//! side is the result.
+//!
+//! A name in a pattern matches any value, we bind it to the value it matched
+//! once we reach the right hand side of its arm, so it doesn't force the
+//! value to be matched on, or its arm to be copied for every constructor.
+
+use super::super::super::ast::{self, HasSpan};
+use super::super::super::core;
 
-use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
 
 use pretty::*;
 
-#[derive(Debug, Copy, Clone)]
-pub enum PatternType {
-    Cases,
+/// A pattern whose names have been resolved to constructors or variables.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// `_`, which matches any value.
+    Wildcard,
+    /// A name, which matches any value and is bound to it.
+    Var(ast::Name),
+    /// A constructor, as it was written and what it resolved to, applied to
+    /// a pattern for each of its arguments.
+    Constructor(ast::Name, core::Name, Vec<Pattern>),
 }
 
+/// An arm of a match, a pattern for each of the values left to match along
+/// with the names the patterns we have already matched bind.
 #[derive(Debug, Clone)]
-pub enum SimpleMatchArm {
-    Match(SimpleMatch),
-    Term(ast::Term),
+pub struct Row {
+    pub patterns: Vec<Pattern>,
+    pub bindings: Vec<(ast::Name, ast::Term)>,
+    pub rhs: ast::Term,
 }
 
-impl SimpleMatchArm {
-    fn rename(self, rename_map: &RenameMap) -> SimpleMatchArm {
-        use self::SimpleMatchArm::*;
-
-        match self {
-            Match(m) => {
-                Match(m.rename(rename_map))
-            }
-            Term(mut t) => {
-                rename_term(rename_map.clone(), &mut t);
-                Term(t)
-            }
-        }
-    }
+#[derive(Debug, Clone)]
+pub enum SimpleMatchArm {
+    Match(SimpleMatch),
+    /// The right hand side of an arm, along with the names its patterns
+    /// bind and the terms they stand for.
+    Term(Vec<(ast::Name, ast::Term)>, ast::Term),
 }
 
 impl Pretty for SimpleMatchArm {
@@ -39,7 +58,7 @@ impl Pretty for SimpleMatchArm {
 
         match self {
             &Match(ref m) => m.pretty(),
-            &Term(ref t) => t.pretty()
+            &Term(_, ref t) => t.pretty()
         }
     }
 }
@@ -55,21 +74,6 @@ impl Display for SimpleMatchArm {
 pub struct SimpleMatch {
     pub scrutinee: ast::Term,
     pub cases: Vec<SimpleCase>,
-    pub pattern_type: PatternType,
-}
-
-impl SimpleMatch {
-    fn rename(self, rename_map: &RenameMap) -> SimpleMatch {
-        let mut scrutinee = self.scrutinee;
-        rename_term(rename_map.clone(), &mut scrutinee);
-        let cases = self.cases.into_iter().map(|c| c.rename(rename_map)).collect();
-
-        SimpleMatch {
-            scrutinee: scrutinee,
-            cases: cases,
-            pattern_type: self.pattern_type,
-        }
-    }
 }
 
 impl Pretty for SimpleMatch {
@@ -92,12 +96,6 @@ pub struct SimpleCase {
     pub rhs: SimpleMatchArm,
 }
 
-impl SimpleCase {
-    fn rename(self, rename_map: &RenameMap) -> SimpleCase {
-        panic!()
-    }
-}
-
 impl Pretty for SimpleCase {
     fn pretty(&self) -> Doc {
         "| ".pretty() + self.pattern.pretty() + " => ".pretty() + self.rhs.pretty()
@@ -112,8 +110,10 @@ impl Display for SimpleCase {
 
 #[derive(Debug, Clone)]
 pub enum SimplePattern {
+    /// A constructor applied to the names of its arguments.
     Constructor(ast::Name, Vec<ast::Name>),
-    Name(ast::Name),
+    /// The constructors none of the cases before it are for.
+    Wildcard,
 }
 
 impl Pretty for SimplePattern {
@@ -125,7 +125,7 @@ impl Pretty for SimplePattern {
                 let ns: Vec<_> = ns.iter().map(|x| parens(x.pretty())).collect();
                 n.pretty() + seperate(&ns[..], &" ".pretty())
             },
-            &Name(ref n) => n.pretty()
+            &Wildcard => "_".pretty()
         }
     }
 }
@@ -136,108 +136,138 @@ impl Display for SimplePattern {
     }
 }
 
-pub fn simplify_match(scrutinee: ast::Term, cases: Vec<ast::Case>) -> SimpleMatch {
-    let mut simple_cases = vec![];
+/// Compile `rows`, which match the values `scrutinees`, to a decision tree,
+/// or `None` if there are no rows to compile. `fresh` counts the names we
+/// have made up for the arguments of constructors.
+pub fn compile(scrutinees: Vec<ast::Term>,
+               mut rows: Vec<Row>,
+               fresh: &mut usize) -> Option<SimpleMatchArm> {
+    if rows.len() == 0 {
+        return None;
+    }
+
+    // A name matches any value, we bind it to its value and from then on
+    // treat it as a wildcard.
+    for row in &mut rows {
+        let &mut Row { ref mut patterns, ref mut bindings, .. } = row;
+
+        for (pattern, scrutinee) in patterns.iter_mut().zip(scrutinees.iter()) {
+            let name = match pattern {
+                &mut Pattern::Var(ref name) => name.clone(),
+                _ => continue,
+            };
 
-    for case in cases {
-        let rhs = SimpleMatchArm::Term(case.rhs);
-        simple_cases.push(simplify_pattern(case.pattern, rhs));
+            bindings.push((name, scrutinee.clone()));
+            *pattern = Pattern::Wildcard;
+        }
     }
 
-    let simple_match = SimpleMatch {
-        scrutinee: scrutinee,
-        cases: simple_cases,
-        pattern_type: PatternType::Cases,
+    let column = match rows[0].patterns.iter().position(is_constructor) {
+        Some(column) => column,
+        None => {
+            let Row { bindings, rhs, .. } = rows.swap_remove(0);
+            return Some(SimpleMatchArm::Term(bindings, rhs));
+        }
     };
 
-    let simple_match = match condense(SimpleMatchArm::Match(simple_match)) {
-        SimpleMatchArm::Match(m) => m,
-        _ => panic!("condensing a match should result in at least one match")
-    };
+    let scrutinee = scrutinees[column].clone();
+    let mut rest = scrutinees;
+    rest.remove(column);
+
+    let mut cases = vec![];
+
+    for (ctor, elab_ctor, arity) in heads(&rows, column) {
+        let args: Vec<_> = (0..arity).map(|_| {
+            *fresh += 1;
 
-    simple_match
-}
-
-pub fn condense(simple_match: SimpleMatchArm) -> SimpleMatchArm {
-    match simple_match {
-        SimpleMatchArm::Term(rhs) => SimpleMatchArm::Term(rhs),
-        SimpleMatchArm::Match(simple_match) => {
-            let SimpleMatch {
-                scrutinee,
-                cases,
-                pattern_type,
-            } = simple_match;
-
-            // This handles the case in which the simplification pass has generated
-            // a simple match like `match a with | b => rhs`, we just simplify to
-            // rhs.
-            if cases.len() == 1 {
-                let case = cases[0].clone();
-                match case.pattern {
-                    SimplePattern::Name(n) => {
-                        let mut name_map = HashMap::new();
-                        name_map.insert(n, scrutinee.clone());
-                        condense(case.rhs).rename(&name_map)
-                    }
-                    _ => {
-                        let cases =
-                            cases.into_iter()
-                                 .map(|mut case| {
-                                     case.rhs = condense(case.rhs);
-                                     case
-                                 })
-                                 .collect();
-
-                        SimpleMatchArm::Match(SimpleMatch {
-                            scrutinee: scrutinee,
-                            cases: cases,
-                            pattern_type: pattern_type,
-                        })
-                    }
-                }
-            } else {
-                let new_cases = vec![];
-                SimpleMatchArm::Match(SimpleMatch {
-                    scrutinee: scrutinee,
-                    cases: new_cases,
-                    pattern_type: PatternType::Cases,
-                })
+            ast::Name {
+                span: ctor.span,
+                repr: ast::NameKind::Unqualified(format!("_a{}", fresh)),
             }
+        }).collect();
+
+        let scrutinees =
+            args.iter()
+                .map(|n| ast::Term::Var { name: n.clone(), implicit: false })
+                .chain(rest.iter().cloned())
+                .collect();
+
+        let rows = rows.iter().filter_map(|row| {
+            let mut patterns = row.patterns.clone();
+
+            let args = match patterns.remove(column) {
+                Pattern::Constructor(_, ref c, ref args) if c == &elab_ctor => args.clone(),
+                Pattern::Constructor(..) => return None,
+                _ => (0..arity).map(|_| Pattern::Wildcard).collect(),
+            };
+
+            Some(Row {
+                patterns: args.into_iter().chain(patterns.into_iter()).collect(),
+                bindings: row.bindings.clone(),
+                rhs: row.rhs.clone(),
+            })
+        }).collect();
+
+        if let Some(arm) = compile(scrutinees, rows, fresh) {
+            cases.push(SimpleCase {
+                pattern: SimplePattern::Constructor(ctor, args),
+                rhs: arm,
+            });
         }
     }
+
+    // The rows which match any value cover the constructors none of the
+    // cases are for.
+    let default = rows.into_iter().filter_map(|mut row| {
+        match row.patterns.remove(column) {
+            Pattern::Constructor(..) => None,
+            _ => Some(row),
+        }
+    }).collect();
+
+    if let Some(arm) = compile(rest, default, fresh) {
+        cases.push(SimpleCase {
+            pattern: SimplePattern::Wildcard,
+            rhs: arm,
+        });
+    }
+
+    Some(SimpleMatchArm::Match(SimpleMatch {
+        scrutinee: scrutinee,
+        cases: cases,
+    }))
 }
 
-pub fn simplify_pattern(pattern: ast::Pattern, mut rhs: SimpleMatchArm) -> SimpleCase {
+fn is_constructor(pattern: &Pattern) -> bool {
     match pattern {
-        ast::Pattern::Placeholder => {
-            panic!()
-        }
-        ast::Pattern::Name(n) => {
-            SimpleCase {
-                pattern: SimplePattern::Name(n),
-                rhs: rhs,
-            }
-        }
-        ast::Pattern::Constructor(pat_head, pat_args) => {
-            println!("pattern_head: {}", pat_head);
-
-            let mut arg_names = vec![];
-
-            for (i, pat_arg) in pat_args.into_iter().enumerate().rev() {
-                println!("pattern_arg {}", pat_arg);
-                let arg_name = ast::Name::from_str(&format!("a{}", i)[..]);
-                arg_names.push(arg_name.clone());
-                rhs = SimpleMatchArm::Match(SimpleMatch {
-                    scrutinee: ast::Term::Var { name: arg_name, implicit: false },
-                    cases: vec![simplify_pattern(pat_arg, rhs)],
-                    pattern_type: PatternType::Cases,
-                })
-            }
+        &Pattern::Constructor(..) => true,
+        _ => false,
+    }
+}
 
-            SimpleCase {
-                pattern: SimplePattern::Constructor(pat_head, arg_names.into_iter().rev().collect()),
-                rhs: rhs,
+/// The constructors the patterns in `column` of `rows` match against, in
+/// the order they first appear, along with the number of arguments each
+/// takes.
+fn heads(rows: &[Row], column: usize) -> Vec<(ast::Name, core::Name, usize)> {
+    let mut heads: Vec<(ast::Name, core::Name, usize)> = vec![];
+
+    for row in rows {
+        if let Pattern::Constructor(ref ctor, ref elab_ctor, ref args) = row.patterns[column] {
+            if !heads.iter().any(|h| &h.1 == elab_ctor) {
+                heads.push((ctor.clone(), elab_ctor.clone(), args.len()));
             }
         }
     }
+
+    heads
+}
+
+/// The span of the first of `patterns`, which we point at when talking
+/// about its arm.
+pub fn patterns_span(patterns: &[ast::Pattern], default: ast::Span) -> ast::Span {
+    match patterns.first() {
+        Some(&ast::Pattern::Name(ref n)) |
+        Some(&ast::Pattern::Constructor(ref n, _)) => n.get_span(),
+        _ => default,
+    }
 }
@@ -0,0 +1,592 @@
+use core::{Binder, Data, Level, Name, Term, TermRef};
+use super::{LocalElabCx, Error};
+
+/// Compile a structurally recursive definition into an application of
+/// `brec_on`.
+///
+/// A definition `f (a_1 ... a_n) : R` which recurs on `a_k : T ps is`, where
+/// the indices `is` are arguments before `a_k`, becomes
+///
+/// `T.brec_on ps C is a_k (fun is a_k (b : T.below ps C is a_k) =>
+///                           fun a_k+1 ... a_n => body) a_k+1 ... a_n`
+///
+/// with the motive `C = fun is a_k => forall a_k+1 ... a_n, R`. The value `b`
+/// holds the results of `f` on everything smaller than `a_k`. Each match on
+/// `a_k`, or on a part of it, is generalized over its `below` so every case
+/// sees the `below` of the constructor it matched, and a recursive call
+/// `f a_1 ... a_k-1 y b_k+1 ... b_n` on an argument `y` of that constructor,
+/// or on something matched out of `y`, takes its result from there.
+///
+/// We try to recur on each of the arguments in turn.
+pub fn compile_structural_recursion(lcx: &mut LocalElabCx,
+                                    name: &Name,
+                                    args: &[Name],
+                                    ty: &Term,
+                                    body: Term) -> Result<Term, Error> {
+    if !body.occurs(name) {
+        return Ok(body);
+    }
+
+    let mut error = None;
+
+    for k in 0..args.len() {
+        match recursion_on(lcx, name, args, ty, &body, k) {
+            Ok(Some(term)) => return Ok(term),
+            Ok(None) => {}
+            Err(e) => if error.is_none() { error = Some(e) },
+        }
+    }
+
+    Err(error.unwrap_or_else(|| not_structural(name, &body)))
+}
+
+/// Compile the recursion in `body` as recursion on the `k`th argument, if
+/// it is of an inductive type with `brec_on`.
+fn recursion_on(lcx: &mut LocalElabCx,
+                name: &Name,
+                args: &[Name],
+                ty: &Term,
+                body: &Term,
+                k: usize) -> Result<Option<Term>, Error> {
+    let rec_arg = &args[k];
+
+    let (arg_ty, _) = try!(lcx.cx.ty_cx.whnf(&local_ty(rec_arg)));
+    let (head, ty_args) = arg_ty.uncurry();
+
+    let data = match head {
+        Term::Var { ref name } => match lcx.cx.ty_cx.types.get(&name.without_levels()) {
+            None => return Ok(None),
+            Some(data) => data.clone(),
+        },
+        _ => return Ok(None),
+    };
+
+    let brec_on = data.name.in_scope("brec_on".to_string()).unwrap();
+
+    if !lcx.cx.ty_cx.in_scope(&brec_on) || ty_args.len() < data.parameters.len() {
+        return Ok(None);
+    }
+
+    let (params, indices) = ty_args.split_at(data.parameters.len());
+
+    // The motive abstracts over the indices, so they must be distinct
+    // arguments, and the other arguments before `a_k` may not depend on them.
+    let mut index_args = vec![];
+
+    for index in indices {
+        match args[..k].iter().find(|a| &a.to_term() == index) {
+            Some(a) if !index_args.contains(a) => index_args.push(a.clone()),
+            _ => return Ok(None),
+        }
+    }
+
+    let fixed: Vec<_> =
+        args[..k].iter()
+                 .enumerate()
+                 .filter(|&(_, a)| !index_args.contains(a))
+                 .map(|(i, a)| (i, a.clone()))
+                 .collect();
+
+    if fixed.iter().any(|&(_, ref a)| index_args.iter().any(|i| local_ty(a).occurs(i))) {
+        return Ok(None);
+    }
+
+    let later = &args[k + 1..];
+
+    let mut major = index_args.clone();
+    major.push(rec_arg.clone());
+
+    let motive = Term::abstract_lambda(major.clone(), Term::abstract_pi(later.to_vec(), ty.clone()));
+
+    let brec_on = lcx.instantiate_global(brec_on);
+    let levels = brec_on.levels().to_vec();
+
+    let below_ty = below_app(&data, &levels, params, &motive, &index_args.iter().map(|i| i.to_term()).collect::<Vec<_>>(), rec_arg.to_term());
+    let below = lcx.cx.ty_cx.local_with_repr("below".to_string(), below_ty.clone());
+
+    let body = {
+        let mut rcx = RecursionCx {
+            lcx: &mut *lcx,
+            name: name,
+            data: &data,
+            levels: &levels,
+            motive: &motive,
+            k: k,
+            fixed: &fixed,
+            results: vec![],
+            belows: vec![(rec_arg.to_term(), below.to_term(), below_ty)],
+        };
+
+        try!(rcx.compile(body))
+    };
+
+    if let Some(call) = find_call(name, &body) {
+        return Err(not_structural(name, &call));
+    }
+
+    let mut binders = major.clone();
+    binders.push(below);
+
+    let functional = Term::abstract_lambda(binders, Term::abstract_lambda(later.to_vec(), body));
+
+    let mut brec_on_args = params.to_vec();
+    brec_on_args.push(motive);
+    brec_on_args.extend(major.iter().map(|a| a.to_term()));
+    brec_on_args.push(functional);
+    brec_on_args.extend(later.iter().map(|a| a.to_term()));
+
+    Ok(Some(Term::apply_all(brec_on.to_term(), brec_on_args)))
+}
+
+struct RecursionCx<'a, 'ecx: 'a> {
+    lcx: &'a mut LocalElabCx<'ecx>,
+    name: &'a Name,
+    data: &'a Data,
+    /// The levels `brec_on` and `below` are instantiated at.
+    levels: &'a [Level],
+    motive: &'a Term,
+    /// The position of the argument we recur on.
+    k: usize,
+    /// The arguments before it which must be passed unchanged.
+    fixed: &'a [(usize, Name)],
+    /// The values we have the results of recursive calls for, each along with
+    /// the value holding them and its type. For a recursive argument of type
+    /// `forall ys, T ps js` this is a function of `ys`.
+    results: Vec<(Term, Term, Term)>,
+    /// The values we have the `below` for, along with it and its type.
+    belows: Vec<(Term, Term, Term)>,
+}
+
+impl<'a, 'ecx: 'a> RecursionCx<'a, 'ecx> {
+    fn compile(&mut self, term: &Term) -> Result<Term, Error> {
+        let (head, args) = term.uncurry();
+
+        // A dependent match abstracts over the locals it refines and applies
+        // the result to them, we substitute them back so we still know what
+        // was matched out of the argument we recur on.
+        if head.is_lambda() && args.len() > 0 && is_local(&args[0]) {
+            let mut head = head;
+            let mut reduced = 0;
+
+            while head.is_lambda() && reduced < args.len() && is_local(&args[reduced]) {
+                head = match head {
+                    Term::Lambda { body, .. } => body.instantiate(&args[reduced]),
+                    _ => unreachable!(),
+                };
+                reduced += 1;
+            }
+
+            return self.compile(&Term::apply_all(head, args[reduced..].to_vec()));
+        }
+
+        if head.is_var(self.name) && args.len() > self.k {
+            return self.recursive_call(term, args);
+        }
+
+        let cases_on = self.data.name.in_scope("cases_on".to_string()).unwrap();
+        let num_params = self.data.parameters.len();
+        let num_indices = arity(&self.data.ty) - num_params;
+        let scrut = num_params + 1 + num_indices;
+
+        if head.is_var(&cases_on) && args.len() >= scrut + 1 + self.data.ctors.len() {
+            if let Some(below) = find(&self.belows, &args[scrut]) {
+                return self.cases_on(head, args, below);
+            }
+        }
+
+        match term {
+            &Term::App { ref fun, ref arg, span } => Ok(Term::App {
+                fun: TermRef::new(try!(self.compile(fun))),
+                arg: TermRef::new(try!(self.compile(arg))),
+                span: span,
+            }),
+            &Term::Forall { ref binder, ref term, span } => Ok(Term::Forall {
+                binder: try!(self.compile_binder(binder)),
+                term: TermRef::new(try!(self.compile(term))),
+                span: span,
+            }),
+            &Term::Lambda { ref binder, ref body, span } => Ok(Term::Lambda {
+                binder: try!(self.compile_binder(binder)),
+                body: TermRef::new(try!(self.compile(body))),
+                span: span,
+            }),
+            &Term::Let { ref binder, ref value, ref body, span } => Ok(Term::Let {
+                binder: try!(self.compile_binder(binder)),
+                value: TermRef::new(try!(self.compile(value))),
+                body: TermRef::new(try!(self.compile(body))),
+                span: span,
+            }),
+            t => Ok(t.clone()),
+        }
+    }
+
+    fn compile_binder(&mut self, binder: &Binder) -> Result<Binder, Error> {
+        Ok(Binder {
+            ty: TermRef::new(try!(self.compile(&binder.ty))),
+            ..binder.clone()
+        })
+    }
+
+    /// Replace the call `f args` with the result we have for the argument
+    /// we recur on.
+    fn recursive_call(&mut self, call: &Term, args: Vec<Term>) -> Result<Term, Error> {
+        // Arguments before the one we recur on must be passed unchanged,
+        // implicit ones will have been elaborated to meta-variables. The
+        // indices are determined by the argument we recur on.
+        let fixed_args_unchanged =
+            self.fixed.iter().all(|&(i, ref a)| {
+                args[i] == a.to_term() || args[i].head().map(|h| h.is_meta()).unwrap_or(false)
+            });
+
+        let result = self.results.iter().rev().filter_map(|&(ref key, ref value, ref ty)| {
+            applied_to(&args[self.k], key).map(|ys| (value.clone(), ty.clone(), ys))
+        }).next();
+
+        match (fixed_args_unchanged, result) {
+            (true, Some((value, ty, ys))) => {
+                let (value, ty) = try!(self.apply(value, ty, ys));
+                let (result, _) = try!(self.project(value, ty, true));
+
+                let mut rest = vec![];
+
+                for arg in &args[self.k + 1..] {
+                    rest.push(try!(self.compile(arg)));
+                }
+
+                Ok(Term::apply_all(result, rest))
+            }
+            _ => Err(not_structural(self.name, call)),
+        }
+    }
+
+    /// Generalize `T.cases_on ps M is x ms rest` over the `below` of `x`,
+    ///
+    /// `T.cases_on ps (fun is x => T.below ps C is x -> M is x) is x ms' below rest`
+    ///
+    /// where each case `fun as => m` becomes `fun as b => m`, in which we have
+    /// the results for the recursive arguments in `as`.
+    fn cases_on(&mut self, head: Term, args: Vec<Term>, below: Term) -> Result<Term, Error> {
+        let num_params = self.data.parameters.len();
+        let num_indices = arity(&self.data.ty) - num_params;
+        let num_ctors = self.data.ctors.len();
+
+        let minors = num_params + 2 + num_indices;
+
+        // The cases are compiled once we know what they bind.
+        let mut compiled = vec![];
+
+        for (i, arg) in args.iter().enumerate() {
+            if i < minors || i >= minors + num_ctors {
+                compiled.push(try!(self.compile(arg)));
+            } else {
+                compiled.push(arg.clone());
+            }
+        }
+
+        let params = compiled[..num_params].to_vec();
+        let motive = compiled[num_params].clone();
+        let indices = compiled[num_params + 1..num_params + 1 + num_indices].to_vec();
+        let scrut = args[num_params + 1 + num_indices].clone();
+        let rest = compiled[num_params + 2 + num_indices + num_ctors..].to_vec();
+
+        // The motive now returns a function of the `below`, which may live in
+        // a larger universe.
+        let (cases_on, ty_levels) = match head {
+            Term::Var { name } => {
+                let mut levels = name.levels().to_vec();
+                levels.pop();
+                let ty_levels = levels.clone();
+                levels.push(self.lcx.cx.ty_cx.fresh_level_meta());
+                (name.with_levels(levels), ty_levels)
+            }
+            _ => unreachable!(),
+        };
+
+        let mut ty = instantiate_params(self.data.ty.clone(), &params);
+        let mut is = vec![];
+
+        while let Term::Forall { binder, term, .. } = ty {
+            let local = self.lcx.cx.ty_cx.local(binder);
+            ty = term.instantiate(&local.to_term());
+            is.push(local);
+        }
+
+        let x = self.lcx.cx.ty_cx.local_with_repr(
+            "x".to_string(),
+            Term::apply_all(
+                self.data.name.clone().with_levels(ty_levels.clone()).to_term(),
+                params.iter().cloned().chain(is.iter().map(|i| i.to_term())).collect()));
+
+        let is_terms: Vec<_> = is.iter().map(|i| i.to_term()).collect();
+        let b = self.lcx.cx.ty_cx.local_with_repr(
+            "b".to_string(),
+            below_app(self.data, self.levels, &params, self.motive, &is_terms, x.to_term()));
+
+        let mut motive_args = is_terms.clone();
+        motive_args.push(x.to_term());
+
+        let mut major = is;
+        major.push(x);
+
+        let new_motive = Term::abstract_lambda(
+            major,
+            Term::abstract_pi(vec![b], Term::apply_all(motive, motive_args)));
+
+        let mut new_args = params.clone();
+        new_args.push(new_motive);
+        new_args.extend(indices.into_iter());
+        new_args.push(scrut.clone());
+
+        for (i, ctor) in self.data.ctors.iter().enumerate() {
+            let minor = args[num_params + 2 + num_indices + i].clone();
+            new_args.push(try!(self.minor_premise(ctor, &ty_levels, &params, &scrut, minor)));
+        }
+
+        new_args.push(below);
+        new_args.extend(rest.into_iter());
+
+        Ok(Term::apply_all(cases_on.to_term(), new_args))
+    }
+
+    /// The case `fun as => m` for `ctor` becomes `fun as b => m`, where
+    /// `b : T.below ps C is (ctor ps as)`.
+    fn minor_premise(&mut self,
+                     ctor: &(Name, Term),
+                     ty_levels: &[Level],
+                     params: &[Term],
+                     scrut: &Term,
+                     minor: Term) -> Result<Term, Error> {
+        let mut ctor_ty = instantiate_params(ctor.1.clone(), params);
+        let mut body = minor;
+        let mut fields = vec![];
+        let mut recursive = vec![];
+
+        while let Term::Forall { binder, term, .. } = ctor_ty {
+            let field = match body {
+                Term::Lambda { binder, body: inner, .. } => {
+                    let field = self.lcx.cx.ty_cx.local(binder);
+                    body = inner.instantiate(&field.to_term());
+                    field
+                }
+                other => {
+                    let field = self.lcx.cx.ty_cx.local_with_repr(
+                        "a".to_string(), binder.ty.clone().into_term());
+                    body = Term::apply(other, field.to_term());
+                    field
+                }
+            };
+
+            if is_recursive(&self.data.name, &binder.ty) {
+                recursive.push(field.clone());
+            }
+
+            ctor_ty = term.instantiate(&field.to_term());
+            fields.push(field);
+        }
+
+        let num_params = self.data.parameters.len();
+        let indices: Vec<_> = ctor_ty.args().unwrap_or(vec![]).into_iter().skip(num_params).collect();
+
+        let ctor_app = Term::apply_all(
+            ctor.0.clone().with_levels(ty_levels.to_vec()).to_term(),
+            params.iter().cloned().chain(fields.iter().map(|f| f.to_term())).collect());
+
+        let below_ty = below_app(self.data, self.levels, params, self.motive, &indices, ctor_app.clone());
+        let b = self.lcx.cx.ty_cx.local_with_repr("b".to_string(), below_ty.clone());
+
+        let (num_results, num_belows) = (self.results.len(), self.belows.len());
+
+        // In this case the scrutinee is the constructor applied to its
+        // arguments, so we know the same about both.
+        let aliases: Vec<_> =
+            self.results.iter()
+                        .filter(|&&(ref key, _, _)| key == scrut)
+                        .map(|&(_, ref v, ref t)| (ctor_app.clone(), v.clone(), t.clone()))
+                        .collect();
+        self.results.extend(aliases.into_iter());
+
+        let (mut value, mut ty) = (b.to_term(), below_ty);
+
+        for field in recursive {
+            let (component, component_ty) = try!(self.project(value.clone(), ty.clone(), true));
+
+            if !local_ty(&field).is_forall() {
+                let below = try!(self.project(component.clone(), component_ty.clone(), false));
+                self.belows.push((field.to_term(), below.0, below.1));
+            }
+
+            self.results.push((field.to_term(), component, component_ty));
+
+            let rest = try!(self.project(value, ty, false));
+            value = rest.0;
+            ty = rest.1;
+        }
+
+        let body = try!(self.compile(&body));
+
+        self.results.truncate(num_results);
+        self.belows.truncate(num_belows);
+
+        fields.push(b);
+
+        Ok(Term::abstract_lambda(fields, body))
+    }
+
+    /// Apply `value : ty` to `args`, returning the result and its type.
+    fn apply(&self, value: Term, ty: Term, args: Vec<Term>) -> Result<(Term, Term), Error> {
+        let mut ty = ty;
+
+        for arg in &args {
+            ty = match try!(self.lcx.cx.ty_cx.whnf(&ty)).0 {
+                Term::Forall { term, .. } => term.instantiate(arg),
+                _ => return Err(not_structural(self.name, &value)),
+            };
+        }
+
+        Ok((Term::apply_all(value, args), ty))
+    }
+
+    /// Project the first or second component out of `value : ty`, where `ty`
+    /// reduces to `PProd A B`.
+    fn project(&self, value: Term, ty: Term, first: bool) -> Result<(Term, Term), Error> {
+        let (ty, _) = try!(self.lcx.cx.ty_cx.whnf(&ty));
+        let (head, args) = ty.uncurry();
+
+        let pprod = Name::from_str("PProd");
+
+        let levels = match head {
+            Term::Var { ref name } if name.same_variable(&pprod) && args.len() == 2 => name.levels().to_vec(),
+            _ => return Err(not_structural(self.name, &value)),
+        };
+
+        let (field, component_ty) = if first {
+            ("fst", args[0].clone())
+        } else {
+            ("snd", args[1].clone())
+        };
+
+        let projection = pprod.in_scope(field.to_string()).unwrap().with_levels(levels);
+        let mut projection_args = args;
+        projection_args.push(value);
+
+        Ok((Term::apply_all(projection.to_term(), projection_args), component_ty))
+    }
+}
+
+/// `T.below ps C is x`.
+fn below_app(data: &Data, levels: &[Level], params: &[Term], motive: &Term, indices: &[Term], x: Term) -> Term {
+    let below = data.name.in_scope("below".to_string()).unwrap().with_levels(levels.to_vec());
+
+    let mut args = params.to_vec();
+    args.push(motive.clone());
+    args.extend(indices.iter().cloned());
+    args.push(x);
+
+    Term::apply_all(below.to_term(), args)
+}
+
+/// The arguments `ys` if `term` is `key ys`.
+fn applied_to(term: &Term, key: &Term) -> Option<Vec<Term>> {
+    if term == key {
+        return Some(vec![]);
+    }
+
+    let (head, args) = term.uncurry();
+
+    if &head == key {
+        return Some(args);
+    }
+
+    // A constructor applied to the same arguments, its parameters may have
+    // been elaborated differently.
+    let (key_head, key_args) = key.uncurry();
+
+    if head == key_head && args.len() <= key_args.len() && args.len() > 0 &&
+       key_args.ends_with(&args[..]) {
+        return Some(vec![]);
+    }
+
+    None
+}
+
+fn find(entries: &[(Term, Term, Term)], term: &Term) -> Option<Term> {
+    entries.iter()
+           .rev()
+           .find(|&&(ref key, _, _)| applied_to(term, key) == Some(vec![]))
+           .map(|&(_, ref value, _)| value.clone())
+}
+
+/// Whether the type of an argument of a constructor of `T` is
+/// `forall ys, T ps js`.
+fn is_recursive(ty_name: &Name, ty: &Term) -> bool {
+    let mut ty = ty;
+
+    while let &Term::Forall { ref term, .. } = ty {
+        ty = term;
+    }
+
+    ty.head().map(|h| h.is_var(ty_name)).unwrap_or(false)
+}
+
+fn is_local(term: &Term) -> bool {
+    match term {
+        &Term::Var { ref name } => name.is_local(),
+        _ => false,
+    }
+}
+
+fn instantiate_params(ty: Term, params: &[Term]) -> Term {
+    let mut ty = ty;
+
+    for param in params {
+        ty = match ty {
+            Term::Forall { term, .. } => term.instantiate(param),
+            t => t,
+        };
+    }
+
+    ty
+}
+
+fn local_ty(local: &Name) -> Term {
+    match local {
+        &Name::Local { ref ty, .. } => ty.clone().into_term(),
+        _ => panic!("elaboration bug: {} is not a local", local),
+    }
+}
+
+/// Find an application of `name` in `term`.
+fn find_call(name: &Name, term: &Term) -> Option<Term> {
+    if term.head().map(|h| h.is_var(name)).unwrap_or(false) {
+        return Some(term.clone());
+    }
+
+    match term {
+        &Term::App { ref fun, ref arg, .. } =>
+            find_call(name, fun).or_else(|| find_call(name, arg)),
+        &Term::Forall { ref binder, term: ref body, .. } |
+        &Term::Lambda { ref binder, ref body, .. } =>
+            find_call(name, &binder.ty).or_else(|| find_call(name, body)),
+        &Term::Let { ref binder, ref value, ref body, .. } =>
+            find_call(name, &binder.ty)
+                .or_else(|| find_call(name, value))
+                .or_else(|| find_call(name, body)),
+        &Term::Var { .. } | &Term::Sort { .. } => None,
+    }
+}
+
+fn arity(ty: &Term) -> usize {
+    let mut arity = 0;
+    let mut ty = ty;
+
+    while let &Term::Forall { ref term, .. } = ty {
+        arity += 1;
+        ty = term;
+    }
+
+    arity
+}
+
+fn not_structural(name: &Name, term: &Term) -> Error {
+    Error::NotStructurallyRecursive(name.clone(), term.clone())
+}
@@ -11,5 +11,6 @@ pub fn to_qualified_name(name: ast::Name) -> Option<core::Name> {
     Some(core::Name::Qual {
         components: components,
         span: name.span,
+        levels: vec![],
     })
 }
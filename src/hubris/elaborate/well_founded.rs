@@ -0,0 +1,417 @@
+use ast::{self, HasSpan};
+use core::{Binder, Name, Term, TermRef};
+use syntax::visit::{self, Visitor};
+use super::{LocalElabCx, Error};
+
+use std::collections::HashMap;
+
+/// Pair each recursive call `f c_1 ... c_n` in the body of a definition `f`
+/// taking `n` explicit arguments with the proof from its `decreasing_by`
+/// clause that the measure decreases, by the span of the call. There is
+/// either a proof for each call in the order they appear, or a single proof
+/// for all of them.
+pub fn decreasing_proofs(def: &ast::Def, n: usize) -> Result<HashMap<ast::Span, ast::Term>, Error> {
+    let mut calls = RecursiveCalls {
+        name: &def.name,
+        arity: n,
+        spans: vec![],
+    };
+
+    calls.visit_term(&def.body);
+
+    let mut spans = calls.spans;
+    spans.sort();
+
+    let proofs = &def.decreasing_by;
+
+    if proofs.len() == 1 {
+        Ok(spans.into_iter().map(|span| (span, proofs[0].clone())).collect())
+    } else if proofs.len() == spans.len() {
+        Ok(spans.into_iter().zip(proofs.iter().cloned()).collect())
+    } else {
+        Err(Error::WrongNumberOfDecreasingProofs(def.name.clone(), spans.len(), proofs.len()))
+    }
+}
+
+/// The spans of the applications of `name` to `arity` arguments.
+struct RecursiveCalls<'a> {
+    name: &'a ast::Name,
+    arity: usize,
+    spans: Vec<ast::Span>,
+}
+
+impl<'a, 'v> Visitor<'v> for RecursiveCalls<'a> {
+    fn visit_term(&mut self, term: &'v ast::Term) {
+        if let &ast::Term::App { .. } = term {
+            let mut head = term;
+            let mut args = vec![];
+
+            while let &ast::Term::App { ref fun, ref arg, .. } = head {
+                head = fun;
+                args.push(&**arg);
+            }
+
+            match head {
+                &ast::Term::Var { ref name, .. } if name == self.name && args.len() == self.arity =>
+                    self.spans.push(term.get_span()),
+                _ => {}
+            }
+
+            self.visit_term(head);
+
+            for arg in args.into_iter().rev() {
+                self.visit_term(arg);
+            }
+        } else {
+            visit::walk_term(self, term)
+        }
+    }
+}
+
+/// The relation the measure of a definition decreases along, found by
+/// resolving an instance of `WellFoundedRelation A` for the type `A` of the
+/// measure, along with the proof that it is well-founded.
+struct Relation {
+    ty: Term,
+    rel: Term,
+    wf: Term,
+}
+
+fn relation(lcx: &mut LocalElabCx, name: &Name, measure: &Term) -> Result<Relation, Error> {
+    let ty = try!(lcx.cx.ty_cx.type_infer_term(measure)).0;
+    let class = try!(global(lcx, name, "WellFoundedRelation"));
+
+    let instance = match try!(lcx.cx.ty_cx.resolve_instance(
+            &lcx.locals_in_order, &Term::apply(class, ty.clone()))) {
+        None => return Err(Error::NoWellFoundedRelation(name.clone(), ty)),
+        Some(instance) => instance,
+    };
+
+    let rel = try!(global(lcx, name, "WellFoundedRelation.rel"));
+    let wf = try!(global(lcx, name, "WellFoundedRelation.wf"));
+
+    Ok(Relation {
+        rel: Term::apply_all(rel, vec![ty.clone(), instance.clone()]),
+        wf: Term::apply_all(wf, vec![ty.clone(), instance]),
+        ty: ty,
+    })
+}
+
+/// The type `f` has while elaborating the body of a definition
+/// `f (a_1 ... a_n) : R termination_by m`, each recursive call must
+/// supply a proof that the measure decreases along the relation `<`:
+///
+/// `forall (b_1 ... b_n), m[b] < m[a] -> R[b]`
+pub fn recursive_call_ty(lcx: &mut LocalElabCx,
+                         name: &Name,
+                         args: &[Name],
+                         ty: &Term,
+                         measure: &Term) -> Result<Term, Error> {
+    let rel = try!(relation(lcx, name, measure)).rel;
+    let (locals, result_ty) = open_args(lcx, args, ty);
+
+    let values: Vec<_> = locals.iter().map(|l| l.to_term()).collect();
+
+    let decreases = Term::apply_all(rel, vec![
+        subst_args(args, &values, measure),
+        measure.clone()]);
+
+    let mut binders = locals;
+    binders.push(lcx.cx.ty_cx.local_with_repr("h".to_string(), decreases));
+
+    Ok(Term::abstract_pi(binders, result_ty))
+}
+
+/// Compile a definition `f (a_1 ... a_n) : R termination_by m` into an
+/// application of the recursor for `Acc`.
+///
+/// We recur on the accessibility of the measure, of type `A`, with respect
+/// to the relation `<` of its `WellFoundedRelation` instance. The motive
+/// quantifies over the arguments along with a proof that their measure is
+/// the value we are recurring on:
+///
+/// `C := fun k _ => forall b_1 ... b_n, Eq A m[b] k -> R[b]`
+///
+/// The body becomes the minor premise, and each recursive call
+/// `f c_1 ... c_n p` becomes `ih m[c] p' c_1 ... c_n (Refl A m[c])`, where
+/// `p'` is `p` rewritten by the equation for the current measure. We then
+/// start the recursion with the proof that `<` is well-founded.
+pub fn compile_well_founded_recursion(lcx: &mut LocalElabCx,
+                                      name: &Name,
+                                      args: &[Name],
+                                      ty: &Term,
+                                      measure: &Term,
+                                      body: Term) -> Result<Term, Error> {
+    let Relation { ty: measure_ty, rel, wf } = try!(relation(lcx, name, measure));
+
+    let mut wcx = WellFoundedCx {
+        measure_ty: measure_ty,
+        acc: try!(global(lcx, name, "Acc")),
+        rel: rel,
+        eq: try!(global(lcx, name, "Eq")),
+        refl: try!(global(lcx, name, "Refl")),
+        lcx: lcx,
+        name: name,
+        args: args,
+        measure: measure,
+    };
+
+    let motive = wcx.motive(ty);
+    let minor_premise = try!(wcx.minor_premise(&motive, body));
+
+    let acc_rec = try!(global(wcx.lcx, name, "Acc.rec"));
+
+    let mut rec_args = vec![
+        wcx.measure_ty.clone(),
+        wcx.rel.clone(),
+        motive,
+        minor_premise,
+        measure.clone(),
+        Term::apply(wf, measure.clone())];
+
+    rec_args.extend(args.iter().map(|a| a.to_term()));
+    rec_args.push(Term::apply_all(wcx.refl.clone(), vec![wcx.measure_ty.clone(), measure.clone()]));
+
+    Ok(Term::apply_all(acc_rec, rec_args))
+}
+
+struct WellFoundedCx<'a, 'ecx: 'a> {
+    lcx: &'a mut LocalElabCx<'ecx>,
+    name: &'a Name,
+    args: &'a [Name],
+    measure: &'a Term,
+    /// The type of the measure.
+    measure_ty: Term,
+    acc: Term,
+    /// The well-founded relation the measure decreases along.
+    rel: Term,
+    eq: Term,
+    refl: Term,
+}
+
+impl<'a, 'ecx: 'a> WellFoundedCx<'a, 'ecx> {
+    fn motive(&mut self, ty: &Term) -> Term {
+        let k = self.lcx.cx.ty_cx.local_with_repr("k".to_string(), self.measure_ty.clone());
+        let acc_k = self.lcx.cx.ty_cx.local_with_repr(
+            "acc".to_string(),
+            Term::apply_all(self.acc.clone(), vec![self.measure_ty.clone(), self.rel.clone(), k.to_term()]));
+
+        let (mut locals, result_ty) = open_args(self.lcx, self.args, ty);
+        let values: Vec<_> = locals.iter().map(|l| l.to_term()).collect();
+
+        let measure_eq = self.lcx.cx.ty_cx.local_with_repr(
+            "e".to_string(),
+            self.eq_measure(subst_args(self.args, &values, self.measure), k.to_term()));
+
+        locals.push(measure_eq);
+
+        Term::abstract_lambda(vec![k, acc_k], Term::abstract_pi(locals, result_ty))
+    }
+
+    /// The minor premise for `AccIntro`, it binds the accessible value `x`,
+    /// the proof that everything below it is accessible and the inductive
+    /// hypothesis, followed by the arguments and the equation for their
+    /// measure.
+    fn minor_premise(&mut self, motive: &Term, body: Term) -> Result<Term, Error> {
+        let measure_ty = self.measure_ty.clone();
+        let x = self.lcx.cx.ty_cx.local_with_repr("x".to_string(), measure_ty.clone());
+
+        let y = self.lcx.cx.ty_cx.local_with_repr("y".to_string(), measure_ty.clone());
+        let y_lt_x = self.lcx.cx.ty_cx.local_with_repr(
+            "r".to_string(),
+            Term::apply_all(self.rel.clone(), vec![y.to_term(), x.to_term()]));
+
+        let acc_y = Term::apply_all(self.acc.clone(), vec![measure_ty, self.rel.clone(), y.to_term()]);
+        let h = self.lcx.cx.ty_cx.local_with_repr(
+            "h".to_string(),
+            Term::abstract_pi(vec![y.clone(), y_lt_x.clone()], acc_y));
+
+        let h_y = Term::apply_all(h.to_term(), vec![y.to_term(), y_lt_x.to_term()]);
+        let ih = self.lcx.cx.ty_cx.local_with_repr(
+            "ih".to_string(),
+            Term::abstract_pi(
+                vec![y.clone(), y_lt_x],
+                Term::apply_all(motive.clone(), vec![y.to_term(), h_y])));
+
+        let measure_eq = self.lcx.cx.ty_cx.local_with_repr(
+            "e".to_string(),
+            self.eq_measure(self.measure.clone(), x.to_term()));
+
+        let body = try!(self.replace_recursive_calls(&x, &ih, &measure_eq, &body));
+
+        let mut binders = self.args.to_vec();
+        binders.push(measure_eq);
+
+        Ok(Term::abstract_lambda(
+            vec![x, h, ih],
+            Term::abstract_lambda(binders, body)))
+    }
+
+    fn replace_recursive_calls(&mut self,
+                               x: &Name,
+                               ih: &Name,
+                               measure_eq: &Name,
+                               term: &Term) -> Result<Term, Error> {
+        let (head, args) = term.uncurry();
+        let n = self.args.len();
+
+        if head.is_var(self.name) {
+            // Every call must be given all of the arguments along with the
+            // proof that the measure decreases.
+            if args.len() <= n {
+                return Err(Error::MissingDecreasingProof(self.name.clone(), term.clone()));
+            }
+
+            let mut args: Vec<_> = try!(args.iter()
+                .map(|a| self.replace_recursive_calls(x, ih, measure_eq, a))
+                .collect());
+
+            let rest = args.split_off(n + 1);
+            let proof = args.pop().unwrap();
+
+            let measure = subst_args(self.args, &args, self.measure);
+            let proof = try!(self.cast_decreasing(x, measure_eq, &measure, proof));
+            let measure_refl =
+                Term::apply_all(self.refl.clone(), vec![self.measure_ty.clone(), measure.clone()]);
+
+            let mut call_args = vec![measure, proof];
+            call_args.extend(args.into_iter());
+            call_args.push(measure_refl);
+            call_args.extend(rest.into_iter());
+
+            return Ok(Term::apply_all(ih.to_term(), call_args));
+        }
+
+        Ok(match term {
+            &Term::App { ref fun, ref arg, span } => Term::App {
+                fun: TermRef::new(try!(self.replace_recursive_calls(x, ih, measure_eq, fun))),
+                arg: TermRef::new(try!(self.replace_recursive_calls(x, ih, measure_eq, arg))),
+                span: span,
+            },
+            &Term::Forall { ref binder, ref term, span } => Term::Forall {
+                binder: try!(self.replace_in_binder(x, ih, measure_eq, binder)),
+                term: TermRef::new(try!(self.replace_recursive_calls(x, ih, measure_eq, term))),
+                span: span,
+            },
+            &Term::Lambda { ref binder, ref body, span } => Term::Lambda {
+                binder: try!(self.replace_in_binder(x, ih, measure_eq, binder)),
+                body: TermRef::new(try!(self.replace_recursive_calls(x, ih, measure_eq, body))),
+                span: span,
+            },
+            &Term::Let { ref binder, ref value, ref body, span } => Term::Let {
+                binder: try!(self.replace_in_binder(x, ih, measure_eq, binder)),
+                value: TermRef::new(try!(self.replace_recursive_calls(x, ih, measure_eq, value))),
+                body: TermRef::new(try!(self.replace_recursive_calls(x, ih, measure_eq, body))),
+                span: span,
+            },
+            t => t.clone(),
+        })
+    }
+
+    fn replace_in_binder(&mut self,
+                         x: &Name,
+                         ih: &Name,
+                         measure_eq: &Name,
+                         binder: &Binder) -> Result<Binder, Error> {
+        Ok(Binder {
+            ty: TermRef::new(try!(self.replace_recursive_calls(x, ih, measure_eq, &binder.ty))),
+            ..binder.clone()
+        })
+    }
+
+    /// Turn a proof of `m[c] < m[a]` into a proof of `m[c] < x` by
+    /// rewriting with the equation `Eq A m[a] x`.
+    fn cast_decreasing(&mut self,
+                       x: &Name,
+                       measure_eq: &Name,
+                       measure: &Term,
+                       proof: Term) -> Result<Term, Error> {
+        let measure_ty = self.measure_ty.clone();
+        let rel = self.rel.clone();
+        let lt_measure = |i: &Name| Term::apply_all(rel.clone(), vec![measure.clone(), i.to_term()]);
+
+        let i1 = self.lcx.cx.ty_cx.local_with_repr("i".to_string(), measure_ty.clone());
+        let i2 = self.lcx.cx.ty_cx.local_with_repr("j".to_string(), measure_ty.clone());
+        let eq = self.lcx.cx.ty_cx.local_with_repr(
+            "eq".to_string(),
+            self.eq_measure(i1.to_term(), i2.to_term()));
+        let lt_i1 = self.lcx.cx.ty_cx.local_with_repr("".to_string(), lt_measure(&i1));
+
+        let rewrite_motive = Term::abstract_lambda(
+            vec![i1, i2.clone(), eq],
+            Term::abstract_pi(vec![lt_i1], lt_measure(&i2)));
+
+        let z = self.lcx.cx.ty_cx.local_with_repr("z".to_string(), measure_ty.clone());
+        let q = self.lcx.cx.ty_cx.local_with_repr("q".to_string(), lt_measure(&z));
+        let rewrite_refl = Term::abstract_lambda(vec![z, q.clone()], q.to_term());
+
+        let eq_rec = try!(global(self.lcx, self.name, "Eq.rec"));
+
+        Ok(Term::apply_all(eq_rec, vec![
+            measure_ty,
+            rewrite_motive,
+            rewrite_refl,
+            self.measure.clone(),
+            x.to_term(),
+            measure_eq.to_term(),
+            proof]))
+    }
+
+    fn eq_measure(&self, lhs: Term, rhs: Term) -> Term {
+        Term::apply_all(self.eq.clone(), vec![self.measure_ty.clone(), lhs, rhs])
+    }
+}
+
+/// Open the binders of `forall (a_1 ... a_n), R` with fresh locals.
+fn open_args(lcx: &mut LocalElabCx, args: &[Name], ty: &Term) -> (Vec<Name>, Term) {
+    let mut pi = Term::abstract_pi(args.to_vec(), ty.clone());
+    let mut locals = vec![];
+
+    while locals.len() < args.len() {
+        pi = match pi {
+            Term::Forall { binder, term, .. } => {
+                let local = lcx.cx.ty_cx.local(binder);
+                let term = term.instantiate(&local.to_term());
+                locals.push(local);
+                term
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    (locals, pi)
+}
+
+/// Simultaneously substitute `values` for `args` in `term`.
+fn subst_args(args: &[Name], values: &[Term], term: &Term) -> Term {
+    let mut term = Term::abstract_lambda(args.to_vec(), term.clone());
+
+    for value in values {
+        term = match term {
+            Term::Lambda { body, .. } => body.instantiate(value),
+            _ => unreachable!(),
+        };
+    }
+
+    term
+}
+
+/// Look up one of the definitions from `WellFounded` that well-founded
+/// recursion is compiled to.
+fn global(lcx: &mut LocalElabCx, def: &Name, name: &str) -> Result<Term, Error> {
+    let mut components = name.split('.');
+    let mut global = Name::from_str(components.next().unwrap());
+
+    for component in components {
+        global = global.in_scope(component.to_string()).unwrap();
+    }
+
+    let in_scope = lcx.cx.ty_cx.definitions.contains_key(&global) ||
+                   lcx.cx.ty_cx.axioms.contains_key(&global);
+
+    if in_scope {
+        Ok(lcx.instantiate_global(global).to_term())
+    } else {
+        Err(Error::WellFoundedUnavailable(def.clone(), global))
+    }
+}
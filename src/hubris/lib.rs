@@ -102,9 +102,9 @@ pub fn compile_file<T: AsRef<Path>>(path: T, output: Option<PathBuf>) -> Result<
         parser.source_map);
 
     let mut ecx =
-        elaborate::ElabCx::from_module(
+        try!(elaborate::ElabCx::from_module(
             module,
-            session);
+            session));
 
     let core_module = ecx.elaborate_module();
 
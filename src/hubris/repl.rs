@@ -115,13 +115,13 @@ impl Repl {
 
             session.add_source_map_for(id, parser.source_map);
 
-            ElabCx::from_module(
+            try!(ElabCx::from_module(
                 module,
-                session.clone())
+                session.clone()))
         } else {
-            ElabCx::from_module(
+            try!(ElabCx::from_module(
                 ast::Module::empty(),
-                session.clone())
+                session.clone()))
         };
 
         // Ensure that if a type error occurs here we report it, ideally
@@ -57,6 +57,9 @@ pub struct SessionData {
     /// A global counter used to track how many module ids
     /// we have handed out.
     module_id_counter: usize,
+    /// A global counter for the numbers of locals, declarations from
+    /// every module end up in one context so their locals must not clash.
+    local_counter: usize,
     /// The set of things that have been imported.
     imported_files: HashSet<PathBuf>,
     /// An index from module id to source map.
@@ -91,6 +94,7 @@ impl Session   {
             data: Rc::new(RefCell::new(SessionData {
                 terminal: term::stdout().unwrap(), // Not sure about this, we can revisit it later.
                 module_id_counter: 0,
+                local_counter: 0,
                 imported_files: HashSet::new(),
                 source_maps: HashMap::new(),
                 load_paths: vec![home.join(".hubris/lib")],
@@ -112,6 +116,7 @@ impl Session   {
             data: Rc::new(RefCell::new(SessionData {
                 terminal: term::stdout().unwrap(), // Not sure about this, we can revisit it later.
                 module_id_counter: 0,
+                local_counter: 0,
                 imported_files: HashSet::new(),
                 source_maps: HashMap::new(),
                 load_paths: vec![home.join(".hubris/lib")],
@@ -138,6 +143,12 @@ impl Session   {
         ModuleId(id)
     }
 
+    pub fn next_local_number(&self) -> usize {
+        let number = self.data.borrow().local_counter;
+        self.data.borrow_mut().local_counter += 1;
+        number
+    }
+
     pub fn add_source_map_for(&self, id: ModuleId, source_map: SourceMap) {
         let mut data = self.data.borrow_mut();
         data.source_maps.insert(id, source_map);
@@ -147,6 +158,22 @@ impl Session   {
     pub fn span_error(&self,
                       span: Span,
                       message: String) -> io::Result<()> {
+        self.span_message(span, "error", color::RED, message)
+    }
+
+    /// Reports a warning at a given location, like `span_error` it
+    /// underlines the Span.
+    pub fn span_warning(&self,
+                        span: Span,
+                        message: String) -> io::Result<()> {
+        self.span_message(span, "warning", color::YELLOW, message)
+    }
+
+    fn span_message(&self,
+                    span: Span,
+                    kind: &str,
+                    colour: color::Color,
+                    message: String) -> io::Result<()> {
 
         let mut session_data = self.data.borrow_mut();
         let &mut SessionData {
@@ -175,8 +202,8 @@ impl Session   {
 
         try!(write!(terminal, "{}", filename_str));
 
-        try!(terminal.fg(color::RED));
-        try!(write!(terminal, "error: "));
+        try!(terminal.fg(colour));
+        try!(write!(terminal, "{}: ", kind));
         try!(terminal.reset());
         try!(writeln!(terminal, "{}", message));
 
@@ -194,7 +221,7 @@ impl Session   {
         }
 
         try!(write!(terminal, "{}", marker_padding));
-        try!(terminal.fg(color::RED));
+        try!(terminal.fg(colour));
         try!(writeln!(terminal, "{}", marker));
         try!(terminal.reset());
         try!(terminal.flush());
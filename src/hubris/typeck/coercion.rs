@@ -0,0 +1,158 @@
+use super::{TyCtxt, Error, instantiate_solutions};
+use super::constraint::*;
+use super::solver::Solver;
+use super::super::core::*;
+
+impl TyCtxt {
+    /// Coerce `term` of type `ty` to `expected_ty` using one of the declared
+    /// coercions from `ty`. Returns `None` when `ty` already unifies with
+    /// `expected_ty`, or when no coercion applies.
+    ///
+    /// `locals` are the locals in scope, which the types may mention.
+    pub fn coerce(&mut self, locals: &[Name], term: &Term, ty: &Term, expected_ty: &Term)
+                  -> Result<Option<Term>, Error> {
+        debug!("coerce: term={} ty={} expected_ty={}", term, ty, expected_ty);
+
+        let candidates = try!(self.coercions_from(ty));
+
+        if candidates.len() == 0 || self.unifies(ty, expected_ty) {
+            return Ok(None);
+        }
+
+        for coercion in candidates {
+            if let Some(coerced) = try!(self.try_coercion(locals, &coercion, term, ty, Some(expected_ty))) {
+                return Ok(Some(coerced));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Coerce `term` of type `ty` to a function, so it can be applied.
+    /// Returns `None` when `ty` is already a function type, or when no
+    /// coercion applies.
+    pub fn coerce_to_function(&mut self, locals: &[Name], term: &Term, ty: &Term)
+                              -> Result<Option<Term>, Error> {
+        debug!("coerce_to_function: term={} ty={}", term, ty);
+
+        for coercion in try!(self.coercions_from(ty)) {
+            if let Some(coerced) = try!(self.try_coercion(locals, &coercion, term, ty, None)) {
+                return Ok(Some(coerced));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The coercions from `ty`, the most recently declared first.
+    fn coercions_from(&self, ty: &Term) -> Result<Vec<Name>, Error> {
+        let ty = if ty.head_is_global() {
+            ty.clone()
+        } else {
+            try!(self.whnf(ty)).0
+        };
+
+        let coercions = match ty.uncurry().0 {
+            Term::Var { ref name } if !name.is_meta() && !name.is_local() =>
+                self.coercions.get(&name.without_levels()).cloned().unwrap_or(vec![]),
+            _ => vec![],
+        };
+
+        Ok(coercions.into_iter().rev().collect())
+    }
+
+    /// Whether `t` and `u` can be made equal, any meta-variables they
+    /// mention stay unsolved.
+    fn unifies(&mut self, t: &Term, u: &Term) -> bool {
+        let just = Justification::Asserted(AssertedBy::ExpectedFound(u.clone(), t.clone()));
+        let constraints = vec![Constraint::Unification(t.clone(), u.clone(), just)];
+
+        Solver::new(self, constraints).and_then(|solver| solver.solve()).is_ok()
+    }
+
+    /// Apply `coercion` to fresh meta-variables up to its first explicit
+    /// argument, which is `term`, and solve for them so the result has type
+    /// `expected_ty`, or is a function when we don't expect a type.
+    fn try_coercion(&mut self,
+                    locals: &[Name],
+                    coercion: &Name,
+                    term: &Term,
+                    ty: &Term,
+                    expected_ty: Option<&Term>)
+                    -> Result<Option<Term>, Error> {
+        let mut coercion_ty = try!(self.global_type(coercion));
+        let mut args = vec![];
+        let mut subgoals = vec![];
+        let mut constraints = vec![];
+
+        while let Term::Forall { binder, term: codomain, .. } = coercion_ty {
+            if binder.is_explicit() {
+                let just = Justification::Asserted(AssertedBy::ExpectedFound(binder.ty.clone().into_term(), ty.clone()));
+                constraints.push(Constraint::Unification(binder.ty.into_term(), ty.clone(), just));
+
+                coercion_ty = codomain.instantiate(term);
+                args.push(term.clone());
+                break;
+            }
+
+            let arg = self.meta_in_context(locals, binder.ty.clone().into_term());
+
+            if binder.is_inst_implicit() {
+                subgoals.push((args.len(), binder.ty.into_term()));
+            }
+
+            coercion_ty = codomain.instantiate(&arg);
+            args.push(arg);
+        }
+
+        if let Some(expected_ty) = expected_ty {
+            let just = Justification::Asserted(AssertedBy::ExpectedFound(expected_ty.clone(), coercion_ty.clone()));
+            constraints.push(Constraint::Unification(coercion_ty.clone(), expected_ty.clone(), just));
+        }
+
+        let solutions = match Solver::new(self, constraints).and_then(|solver| solver.solve()) {
+            Ok((solutions, _)) => solutions,
+            Err(_) => return Ok(None),
+        };
+
+        // Coercing to a function only makes sense if we end up with one.
+        if expected_ty.is_none() {
+            let result_ty = instantiate_solutions(coercion_ty, &solutions);
+
+            if !try!(self.whnf(&result_ty)).0.is_forall() {
+                return Ok(None);
+            }
+        }
+
+        for (i, class) in subgoals {
+            let class = instantiate_solutions(class, &solutions);
+
+            if class.has_metas() {
+                return Ok(None);
+            }
+
+            match try!(self.resolve_instance(locals, &class)) {
+                None => return Ok(None),
+                Some(instance) => args[i] = instance,
+            }
+        }
+
+        let args = args.into_iter().map(|a| instantiate_solutions(a, &solutions)).collect();
+        let coerced = Term::apply_all(coercion.to_term(), args);
+
+        if coerced.has_metas() {
+            Ok(None)
+        } else {
+            Ok(Some(coerced))
+        }
+    }
+
+    /// A fresh meta-variable which may depend on `locals`.
+    fn meta_in_context(&self, locals: &[Name], ty: Term) -> Term {
+        let meta = self.fresh_meta(Term::abstract_pi(locals.to_vec(), ty));
+
+        Term::apply_all(
+            meta.to_term(),
+            locals.iter().map(Name::to_term).collect())
+    }
+}
@@ -3,18 +3,24 @@
 
 
 use std::cmp::{PartialOrd, Ordering};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 use std::fmt::{self, Debug, Formatter, Display};
 use std::rc::Rc;
 
-use core::{Term, Name};
-use hubris_syntax::ast::Span;
+use core::{Term, Level};
+use hubris_syntax::ast::{Span, HasSpan};
+use super::{TyCtxt, Error};
 
 pub type ConstraintSeq = Vec<Constraint>;
 
 #[derive(Debug, Clone)]
 pub enum Constraint {
     Unification(Term, Term, Justification),
+    Universe(Level, Level, Justification),
+    /// A choice constraint `Choice(t, ty, f, j)` is solved by one of the sets
+    /// of constraints `f` produces for the term `t` of type `ty`, once the
+    /// solutions known so far have been substituted into them. The solver
+    /// tries each alternative in turn, backtracking if one fails.
     Choice(Term, Term, ChoiceProcedure, Justification),
 }
 
@@ -26,6 +32,9 @@ impl PartialEq for Constraint {
             (&Unification(ref t1, ref u1, ref j1), &Unification(ref t2, ref u2, ref j2)) => {
                 t1 == t2 && u1 == u2 && j1 == j2
             },
+            (&Universe(ref l1, ref k1, ref j1), &Universe(ref l2, ref k2, ref j2)) => {
+                l1 == l2 && k1 == k2 && j1 == j2
+            },
             (_, _) => false,
         }
     }
@@ -34,11 +43,11 @@ impl PartialEq for Constraint {
 impl Eq for Constraint {}
 
 #[derive(Clone)]
-struct ChoiceProcedure(Rc<Fn(Term, Term, HashMap<Name, (Term, Justification)>) -> ()>);
+pub struct ChoiceProcedure(pub Rc<Fn(&TyCtxt, &Term, &Term) -> Result<Vec<ConstraintSeq>, Error>>);
 
 impl Debug for ChoiceProcedure {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        panic!()
+        write!(fmt, "<choice procedure>")
     }
 }
 
@@ -110,7 +119,18 @@ impl Constraint {
                     panic!("not sure how to categorize constraint")
                 }
             }
-            Choice(..)=> panic!(),
+            // Universe constraints are handed straight to the universe solver,
+            // so they do not need a meaningful category.
+            c @ Universe(..) => CategorizedConstraint {
+                constraint: c,
+                category: Ready,
+            },
+            // We wait for as many meta-variables to be solved as possible
+            // before making a choice.
+            c @ Choice(..) => CategorizedConstraint {
+                constraint: c,
+                category: Postponed,
+            },
         }
     }
 }
@@ -120,7 +140,10 @@ impl Display for Constraint {
         match self {
             &Constraint::Unification(ref t, ref u, ref j) =>
                 write!(formatter, "{} = {} by {:?}", t, u, j),
-            _ => panic!()
+            &Constraint::Universe(ref l1, ref l2, ref j) =>
+                write!(formatter, "{} = {} by {:?}", l1, l2, j),
+            &Constraint::Choice(ref t, ref ty, _, ref j) =>
+                write!(formatter, "choice {} : {} by {:?}", t, ty, j),
         }
     }
 }
@@ -132,6 +155,22 @@ pub enum Justification {
     Join(Rc<Justification>, Rc<Justification>)
 }
 
+impl Justification {
+    /// The location in the source that is responsible for this justification,
+    /// if there is one.
+    pub fn span(&self) -> Option<Span> {
+        use self::Justification::*;
+
+        match self {
+            &Asserted(AssertedBy::Application(span, _, _)) => Some(span),
+            &Asserted(AssertedBy::ExpectedFound(_, ref ty)) => Some(ty.get_span()),
+            &Asserted(AssertedBy::Checked(span, _, _)) => Some(span),
+            &Assumption => None,
+            &Join(ref j1, ref j2) => j1.span().or(j2.span()),
+        }
+    }
+}
+
 // impl Display for Justification {
 //     fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
 //         use self::Justification::*;
@@ -150,6 +189,9 @@ pub enum Justification {
 pub enum AssertedBy {
     Application(Span, Term, Term),
     ExpectedFound(Term, Term),
+    /// The term at the span has the first type, and was checked against
+    /// the second.
+    Checked(Span, Term, Term),
 }
 
 impl Display for AssertedBy {
@@ -161,6 +203,8 @@ impl Display for AssertedBy {
                 write!(formatter, "applied {} to {}", u, t),
             &ExpectedFound(ref ty, ref infer_ty) =>
                 write!(formatter, "expected {} found {}", ty, infer_ty),
+            &Checked(_, ref infer_ty, ref ty) =>
+                write!(formatter, "expected {} found {}", ty, infer_ty),
         }
     }
 }
@@ -1,8 +1,9 @@
 use super::super::ast::{Span, HasSpan};
-use super::super::core::{Term, Name};
+use super::super::core::{Term, Name, Level};
 use super::super::session::{Reportable, HasSession, Session};
 use parser;
 use super::solver;
+use super::instance;
 
 use std::io;
 use term;
@@ -10,9 +11,36 @@ use term;
 #[derive(Debug)]
 pub enum Error {
     ExpectedFunction(Span, Term),
+    /// A term applied to arguments whose type is stuck on a meta-variable,
+    /// so we can't tell whether it is a function yet.
+    UnknownFunctionType(Span, Term),
+    ExpectedSort(Span, Term),
     ApplicationMismatch(Span, Term, Term, Term, Term),
     DefUnequal(Span, Term, Term, Vec<(Term, Term)>),
     UnknownVariable(Name),
+    /// The type being declared occurs to the left of an arrow in one of
+    /// the arguments of a constructor.
+    NonPositiveOccurrence(Span, Name, Name, Term),
+    /// A constructor returns something other than the type being declared.
+    CtorReturnType(Span, Name, Name, Term),
+    /// A constructor doesn't apply the type to the declared parameters.
+    CtorParameters(Span, Name, Name, Term),
+    /// An argument of a constructor lives in a universe larger than the
+    /// one the type being declared lives in.
+    CtorUniverse(Span, Name, Term, Level, Level),
+    /// A type in a `mutual` block takes different parameters to the first.
+    MutualParameters(Span, Name, Name),
+    /// An instance whose type is not a class.
+    NotAClass(Name, Term),
+    /// Instance resolution went deeper than `instance::MAX_DEPTH` while
+    /// looking for an instance of the class.
+    InstanceDepth(Term),
+    /// The name and type of a coercion whose first explicit argument does
+    /// not have a type we can coerce from.
+    NotACoercion(Name, Term),
+    /// A value which is not a function, applied to an argument while
+    /// evaluating.
+    NotAFunction(Term, Term),
     NameExists(Name),
     NoMain,
     Many(Vec<Error>),
@@ -85,6 +113,83 @@ impl Reportable for Error {
 
                 cx.span_error(span, msg)
             }
+            Error::UnknownFunctionType(span, ty) => {
+                let msg = format!(
+                    "can not apply a term whose type `{}` is not known yet, \
+                     try giving it a type annotation", ty);
+
+                cx.span_error(span, msg)
+            }
+            Error::ExpectedSort(span, t) => {
+                let msg = format!(
+                    "expected a type, found `{}` which is not a sort", t);
+
+                cx.span_error(span, msg)
+            }
+            Error::NonPositiveOccurrence(span, ctor, ty_name, arg) => {
+                let msg = format!(
+                    "`{}` occurs in a non-positive position in the argument \
+                     `{}` of the constructor `{}`", ty_name, arg, ctor);
+
+                cx.span_error(span, msg)
+            }
+            Error::CtorReturnType(span, ctor, ty_name, found) => {
+                let msg = format!(
+                    "the constructor `{}` must return `{}`, found `{}`",
+                    ctor, ty_name, found);
+
+                cx.span_error(span, msg)
+            }
+            Error::CtorParameters(span, ctor, ty_name, found) => {
+                let msg = format!(
+                    "the constructor `{}` must apply `{}` to its declared \
+                     parameters, found `{}`", ctor, ty_name, found);
+
+                cx.span_error(span, msg)
+            }
+            Error::CtorUniverse(span, ctor, arg, arg_level, level) => {
+                let msg = format!(
+                    "the argument `{}` of the constructor `{}` lives in universe \
+                     `{}`, which is larger than the universe `{}` of its type",
+                    arg, ctor, arg_level, level);
+
+                cx.span_error(span, msg)
+            }
+            Error::MutualParameters(span, ty_name, first) => {
+                let msg = format!(
+                    "`{}` must take the same parameters as `{}`, the types in \
+                     a `mutual` block share their parameters", ty_name, first);
+
+                cx.span_error(span, msg)
+            }
+            Error::NotAClass(inst, ty) => {
+                let msg = format!(
+                    "the instance `{}` must have a class as its type, \
+                     found `{}`", inst, ty);
+
+                cx.span_error(inst.get_span(), msg)
+            }
+            Error::InstanceDepth(goal) => {
+                let msg = format!(
+                    "gave up looking for an instance of `{}` after searching \
+                     {} instances deep", goal, instance::MAX_DEPTH);
+
+                cx.error(msg)
+            }
+            Error::NotACoercion(coercion, ty) => {
+                let msg = format!(
+                    "the coercion `{}` must take an explicit argument whose \
+                     type is an inductive type or a constant, found `{}`", coercion, ty);
+
+                cx.span_error(coercion.get_span(), msg)
+            }
+            Error::NotAFunction(fun, arg) => {
+                let msg = format!(
+                    "can not apply `{}` to `{}`, it does not evaluate to \
+                     a function", fun, arg);
+
+                cx.error(msg)
+            }
             Error::Many(errs) => {
                 for err in errs {
                     try!(cx.report(err));
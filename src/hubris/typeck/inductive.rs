@@ -1,4 +1,8 @@
 use super::{TyCtxt, ComputationRule, Error};
+use super::constraint::ConstraintSeq;
+use super::nested::Group;
+use super::solver::Solver;
+use super::super::ast::{HasSpan, Span};
 use super::super::core::*;
 //use super::name_generator::*;
 
@@ -6,7 +10,19 @@ use super::super::core::*;
 pub struct InductiveCx<'i, 'tcx> {
     ty_cx: &'tcx mut TyCtxt,
     inductive_ty: &'i Data,
+    /// The types declared together with `inductive_ty`, including itself,
+    /// and the auxiliary types for any nested occurrences.
+    mutual: &'i [Data],
+    /// The constructor each of the constructors in `mutual` stands for,
+    /// along with the number of parameters it takes.
+    ctor_origins: &'i [(Name, usize)],
     ind_hyp: Name,
+    /// The motive for each of the types in `mutual`, `ind_hyp` is the one
+    /// for `inductive_ty`.
+    motives: Vec<Name>,
+    /// The universe the motive of the recursor eliminates into, or `None`
+    /// if the type can only be eliminated into `Prop`.
+    elim_level: Option<Name>,
 }
 
 /// `Recursor` describes the recursor for a inductive type, each field
@@ -18,30 +34,57 @@ struct Recursor {
     major_premise: Term,
     ty: Term,
     name: Name,
+    univ_params: Vec<Name>,
     computation_rule: ComputationRule,
 }
 
 impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
     ///
-    fn new(ty_cx: &'tcx mut TyCtxt, inductive_ty: &'i Data) -> InductiveCx<'i, 'tcx> {
+    fn new(ty_cx: &'tcx mut TyCtxt,
+           group: &'i Group,
+           inductive_ty: &'i Data) -> InductiveCx<'i, 'tcx> {
+        let mutual = &group.types[..];
 
         let mut rcx = InductiveCx {
             ty_cx: ty_cx,
             inductive_ty: inductive_ty,
+            mutual: mutual,
+            ctor_origins: &group.ctors[..],
             ind_hyp: inductive_ty.name.clone(),
+            motives: vec![],
+            elim_level: None,
         };
 
-        let ty = rcx.make_ind_hyp_ty();
+        if !rcx.eliminates_only_into_prop() {
+            rcx.elim_level = Some(fresh_univ_param(&rcx.mutual_univ_params()));
+        }
+
+        for (i, data_type) in mutual.iter().enumerate() {
+            let ty = rcx.make_ind_hyp_ty(data_type);
+
+            let repr = if mutual.len() == 1 {
+                "C".to_string()
+            } else {
+                format!("C{}", i)
+            };
+
+            let motive = rcx.ty_cx.local_with_repr(repr, ty);
+
+            if data_type.name == inductive_ty.name {
+                rcx.ind_hyp = motive.clone();
+            }
+
+            rcx.motives.push(motive);
+        }
 
-        rcx.ind_hyp = rcx.ty_cx.local_with_repr("C".to_string(), ty);
         rcx
     }
 
-    fn make_ind_hyp_ty(&self) -> Term {
+    fn make_ind_hyp_ty(&self, data_type: &Data) -> Term {
         let mut pi =
-            self.with_params(self.inductive_ty.ty.clone());
+            self.with_params(data_type.ty.clone());
         let  result =
-            self.with_params(self.inductive_ty.name.to_term());
+            self.with_params(data_type.name.to_term());
 
         let mut i = 0;
         let mut locals = vec![];
@@ -51,10 +94,10 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
                 self.ty_cx
                     .local_with_repr(
                         format!("x{}", i),
-                        *binder.ty);
+                        binder.ty.into_term());
 
             locals.push(local);
-            pi = *term;
+            pi = term.into_term();
             i += 1;
         }
 
@@ -68,7 +111,91 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
 
         Term::abstract_pi(
             locals.clone(),
-            Term::Type)
+            Term::sort(self.motive_level()))
+    }
+
+    fn motive_level(&self) -> Level {
+        match &self.elim_level {
+            &Some(ref l) => Level::Param(l.clone()),
+            &None => Level::Zero,
+        }
+    }
+
+    /// An inductive proposition may only be eliminated into `Prop`, unless it
+    /// has a single constructor whose arguments are all proofs or appear in
+    /// the indices of its type, like `And` or `Eq`. Large elimination for
+    /// any other proposition would let us observe which proof was used.
+    ///
+    /// The types in a `mutual` block share a recursor, so they may only be
+    /// eliminated into `Prop` if any of them can.
+    fn eliminates_only_into_prop(&self) -> bool {
+        self.mutual.iter().any(|d| self.data_eliminates_only_into_prop(d))
+    }
+
+    fn data_eliminates_only_into_prop(&self, data_type: &Data) -> bool {
+        let mut ty = &data_type.ty;
+
+        while let &Term::Forall { ref term, .. } = ty {
+            ty = term;
+        }
+
+        if !ty.is_prop() {
+            return false;
+        }
+
+        let ctors = &data_type.ctors;
+
+        match ctors.len() {
+            0 => false,
+            1 => !self.is_subsingleton_ctor(&ctors[0]),
+            _ => true,
+        }
+    }
+
+    fn is_subsingleton_ctor(&self, ctor: &(Name, Term)) -> bool {
+        let mut pi = self.with_params(ctor.1.clone());
+        let mut args = vec![];
+
+        while let Term::Forall { binder, term, .. } = pi {
+            let local = self.ty_cx.local(binder);
+            pi = term.instantiate(&local.to_term());
+            args.push(local);
+        }
+
+        let indices = pi.args().unwrap_or(vec![]);
+
+        args.iter().all(|arg| {
+            let is_index = indices.iter().any(|i| i == &arg.to_term());
+
+            let is_proof = match arg {
+                &Name::Local { ref ty, .. } => self.ty_cx.is_proposition(ty),
+                _ => false,
+            };
+
+            is_index || is_proof
+        })
+    }
+
+    /// The recursor and the definitions built on top of it are polymorphic
+    /// in the inductive type's universes and the level of the motive.
+    fn univ_params(&self) -> Vec<Name> {
+        let mut univ_params = self.mutual_univ_params();
+        univ_params.extend(self.elim_level.clone().into_iter());
+        univ_params
+    }
+
+    fn mutual_univ_params(&self) -> Vec<Name> {
+        let mut univ_params: Vec<Name> = vec![];
+
+        for data_type in self.mutual {
+            for param in &data_type.univ_params {
+                if !univ_params.contains(param) {
+                    univ_params.push(param.clone());
+                }
+            }
+        }
+
+        univ_params
     }
 
     // A helper for applying parameters to different types of terms.
@@ -105,15 +232,18 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
         }
     }
 
-    fn is_recursive_arg(&self, term: &Term) -> bool {
-        debug!("is_recursive_arg: term={}", term);
-        match term.head() {
-            None => false,
-            Some(h) => h == self.inductive_ty.name.to_term(),
-        }
+    fn recursive_arg(&self, ty: &Term) -> Option<(usize, Vec<Name>, Vec<Term>)> {
+        debug!("recursive_arg: ty={}", ty);
+        recursive_arg(self.ty_cx, self.mutual, ty)
     }
 
-    pub fn minor_premise_for(&mut self, ind_hyp: &Name, ctor: &(Name, Term)) -> Result<Term, Error> {
+    /// Construct the type of the minor premise for `ctor`, if `ind_hyps` is
+    /// set the premise also takes an inductive hypothesis for each recursive
+    /// argument, using the motive for the type of that argument.
+    pub fn minor_premise_for(&mut self,
+                             ind_hyp: &Name,
+                             ctor: &(Name, Term),
+                             ind_hyps: bool) -> Result<Term, Error> {
         debug!("minor_premise_for: ind_hyp={} ctor=({}, {})", ind_hyp, ctor.0, ctor.1);
         // Apply the constructor name to the parameters.
         let ctor_with_params =
@@ -138,7 +268,7 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
             let arg_local =
                 self.ty_cx.local_with_repr(
                     format!("a{}", i),
-                    *ty.clone());
+                    (*ty).clone());
 
             // Add this to the list of binders (a0 : A) (a1 : List A)
             binders.push(arg_local.clone());
@@ -146,31 +276,29 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
 
             // If this is a recursive argument we all need to generate a piece of proof
             // for that case for example `C a1`.
-            if self.is_recursive_arg(&*ty) {
-                let num_params = self.inductive_ty.parameters.len();
-                let mut indicies = match ty.args() {
-                    None => vec![],
-                    Some(is) =>
-                        is.iter()
-                          .skip(num_params)
-                          .map(Clone::clone)
-                          .collect(),
-                };
+            let recursive_arg =
+                if ind_hyps { self.recursive_arg(&*ty) } else { None };
 
-                // debug!("type to get indicies from: {}", ty);
+            if let Some((j, locals, mut indicies)) = recursive_arg {
+                // Add the argument applied to the locals to the end of the list and
+                // build the type `forall ys, C indicies (a ys)`.
+                let applied_arg =
+                    Term::apply_all(
+                        arg_local.to_term(),
+                        locals.iter().map(|l| l.to_term()).collect());
 
-                // Add the ctor to the end of the list and we are going to build an
-                // application of the form C indicies (Ctor args)
-                indicies.push(arg_local.to_term());
+                indicies.push(applied_arg);
 
                 let local_x =
                     self.ty_cx.local_with_repr(
                         "".to_string(),
-                        Term::apply_all(
-                            ind_hyp.to_term(),
-                            indicies));
+                        Term::abstract_pi(
+                            locals,
+                            Term::apply_all(
+                                self.motives[j].to_term(),
+                                indicies)));
 
-                 arguments.push(local_x);
+                arguments.push(local_x);
             }
 
             pi = term.instantiate(&arg_local.to_term());
@@ -220,7 +348,6 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
                                 minor_premises: Vec<Name>,
                                 major_premise_args: Vec<Name>,
                                 major_premise: Term) -> Term {
-        let ind_hyp = self.ind_hyp.clone();
         let params =
             self.inductive_ty
                 .parameters
@@ -229,7 +356,7 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
         let recursor_ty =
             Term::abstract_pi_implicit(
                 params.clone(),
-                Term::abstract_pi(vec![ind_hyp.clone()],
+                Term::abstract_pi(self.motives.clone(),
                     Term::abstract_pi(minor_premises.clone(),
                         Term::abstract_pi(major_premise_args.clone(),
                             major_premise))));
@@ -256,8 +383,8 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
 
         let mut arguments = Vec::new();
         while let Term::Forall { binder, term,.. } = data_type_ty {
-            arguments.push(*binder.ty);
-            data_type_ty = *term;
+            arguments.push(binder.ty.into_term());
+            data_type_ty = term.into_term();
         }
 
         let mut arguments: Vec<_> = arguments.into_iter()
@@ -289,112 +416,114 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
 
 
     pub fn construct_computation_rule(&self) -> Result<ComputationRule, Error> {
-        Ok(Box::new(|cx: &TyCtxt, term: Term| {
-            // BUG WARNING: this code IS NOT general enough
-            debug!("term {}", term);
+        // The rule works with the group as we built the recursor from it, in
+        // particular the constructors of the declared types refer to the
+        // auxiliary types in place of the nested occurrences.
+        let mutual = self.mutual.to_vec();
+        let ctor_origins = self.ctor_origins.to_vec();
+
+        Ok(Box::new(move |cx: &TyCtxt, term: Term| {
+            debug!("computation_rule: term={}", term);
             let (head, args) = term.uncurry();
-            let ty_name = match &head {
-                &Term::Var { ref name } => match name {
-                    &Name::Qual { ref components, span } => Name::Qual {
-                        components: vec![components[0].clone()],
-                        span: span,
-                    },
-                    _ => panic!()
-                },
-                _ => panic!()
+
+            let (ty_name, levels) = match eliminated_type(&head) {
+                None => panic!("type checking bug: {} is not a recursor", head),
+                Some(eliminated) => eliminated,
             };
 
             debug!("ty_name: {}", ty_name);
-            let scrutinee = &args[args.len() - 1];
-            let scrutinee = try!(cx.eval(scrutinee));
+
+            let j = match mutual.iter().position(|d| d.name.same_variable(&ty_name)) {
+                None => panic!("type checking bug: can not find inductive type {}", ty_name),
+                Some(j) => j,
+            };
+
+            let dt = &mutual[j];
+
+            let num_params = dt.parameters.len();
+            let num_motives = mutual.len();
+            let num_minors = mutual.iter().map(|d| d.ctors.len()).sum::<usize>();
+            let num_indices = arity(&dt.ty) - num_params;
+
+            // The recursor takes the parameters, a motive for each type in the
+            // `mutual` block, a minor premise for each of their constructors,
+            // the indices and finally the major premise.
+            let num_args = num_params + num_motives + num_minors + num_indices + 1;
+
+            if args.len() < num_args {
+                return Ok(term);
+            }
+
+            let scrutinee = &args[num_args - 1];
             debug!("scrutinee: {}", scrutinee);
             let (scrut_ctor, scrut_args) = scrutinee.uncurry();
 
-            match cx.types.get(&ty_name) {
-                None => panic!("type checking bug: can not find inductive type {}", ty_name),
-                Some(dt) => {
-                    let params = &dt.parameters;
+            // We can only reduce when the major premise is a constructor, the
+            // minor premises are in the order of the constructors of each type.
+            // The constructors of an auxiliary type stand for those of the
+            // nested type, which is what the major premise is built from.
+            let offset = mutual[..j].iter().map(|d| d.ctors.len()).sum::<usize>();
+
+            let (i, num_ctor_params) = match &scrut_ctor {
+                &Term::Var { ref name } => {
+                    let ctors = &ctor_origins[offset..offset + dt.ctors.len()];
+                    match ctors.iter().position(|c| c.0.same_variable(name)) {
+                        Some(i) => (i, ctors[i].1),
+                        None => return Ok(term),
+                    }
+                }
+                _ => return Ok(term),
+            };
 
-                    let motif_and_premise : Vec<_> =
-                        args.iter()
-                            .skip(params.len())
-                            .cloned()
-                            .collect();
+            let rec_args = &args[..num_params + num_motives + num_minors];
+            let premise = args[num_params + num_motives + offset + i].clone();
 
-                    let motif = motif_and_premise[0].clone();
+            // Walk the constructor's type to find the recursive arguments,
+            // remembering to instantiate the parameters first.
+            let mut ctor_ty = dt.ctors[i].1.clone();
 
-                    let premises : Vec<_> =
-                        motif_and_premise.iter()
-                                         .skip(1)
-                                         .cloned()
-                                         .collect();
+            for param in args.iter().take(num_params) {
+                ctor_ty = match ctor_ty {
+                    Term::Forall { term, .. } => term.instantiate(param),
+                    _ => return Ok(term),
+                };
+            }
+
+            let ctor_args: Vec<_> =
+                scrut_args.iter()
+                          .skip(num_ctor_params)
+                          .cloned()
+                          .collect();
 
-                    for (i, ctor) in dt.ctors.iter().enumerate() {
-                        let name = &ctor.0;
-                        let ctor_ty = &ctor.1;
-
-                        if scrut_ctor == name.to_term() {
-                            let premise = premises[i].clone();
-
-                            let is_recursive =
-                                cx.is_recursive_ctor(&ty_name, ctor_ty);
-
-                            if !is_recursive {
-                                // Remember to remove the parameters, since
-                                // the premise is not parametrized by them.
-                                let args : Vec<_> =
-                                    scrut_args.iter()
-                                              .skip(dt.parameters.len())
-                                              .cloned()
-                                              .collect();
-
-                                return cx.eval(&Term::apply_all(premise, args));
-                            } else {
-                                panic!()
-                                // let args: Vec<_> =
-                                //     scrutinee.args()
-                                //              .unwrap();
-                            //
-                            // // Need to skip the parameters
-                            // let args =
-                            //     args.iter()
-                            //         .skip(dt.parameters.len());
-                            //
-                            // let tys =
-                            //     premise.binders()
-                            //            .unwrap();
-                            //
-                            // debug!("premise: {}", premise);
-                            // debug!("scurtinee: {}", scrutinee);
-                            //
-                            // let mut term_args = vec![];
-                            // let mut recursor_args = vec![];
-                            //
-                            // for (arg, ty) in args.zip(tys.into_iter()) {
-                            //     debug!("arg : {}", arg);
-                            //     debug!("ty : {}", ty);
-                            //     if ty.head().unwrap() == ty_name.to_term() {
-                            //         let rec =
-                            //         Recursor(
-                            //             ty_name.clone(),
-                            //             premises.clone(),
-                            //             Box::new(arg.clone()));
-                            //             recursor_args.push(rec);
-                            //     }
-                            //
-                            //     term_args.push(arg.clone());
-                            // }
-                            //
-                            // let mut args = term_args;
-                            // args.extend(recursor_args.into_iter());
-                            //
-                            // return self.eval(&Term::apply_all(premise.clone(), args));
-                            }
+            let mut ind_hyps = vec![];
+
+            for arg in &ctor_args {
+                ctor_ty = match ctor_ty {
+                    Term::Forall { binder, term, .. } => {
+                        if let Some(ih) = recursive_call(cx, &mutual, &levels, rec_args, arg, &binder.ty) {
+                            ind_hyps.push(ih);
                         }
+
+                        term.instantiate(arg)
                     }
-                }
+                    _ => return Ok(term),
+                };
+            }
+
+            // The constructor is only partially applied.
+            if ctor_ty.is_forall() {
+                return Ok(term);
             }
-            panic!("this shouldn't happen")
+
+            let mut premise_args = ctor_args;
+            premise_args.extend(ind_hyps.into_iter());
+
+            let result =
+                Term::apply_all(
+                    Term::apply_all(premise, premise_args),
+                    args[num_args..].to_vec());
+
+            Ok(result)
         }))
     }
 
@@ -406,16 +535,17 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
                 .parameters
                 .clone();
 
-        let minor_premises: Result<_, Error> =
-            self.inductive_ty.ctors
-                             .iter()
-                             .map(|ctor| {
-                                 let p = try!(self.minor_premise_for(&motif, ctor));
-                                 Ok(self.ty_cx.local_with_repr("".to_string(), p))
-                             })
-                             .collect();
+        // The recursor takes a minor premise for every constructor of every
+        // type in the `mutual` block, each concluding with its type's motive.
+        let mutual = self.mutual;
+        let mut minor_premises = vec![];
 
-        let minor_premises: Vec<_> = try!(minor_premises);
+        for (motive, data_type) in self.motives.clone().iter().zip(mutual.iter()) {
+            for ctor in &data_type.ctors {
+                let p = try!(self.minor_premise_for(motive, ctor, true));
+                minor_premises.push(self.ty_cx.local_with_repr("".to_string(), p));
+            }
+        }
 
         let (tys, major_premise) =
             self.major_premise();
@@ -438,61 +568,11 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
             major_premise: major_premise,
             ty: recursor_ty,
             name: recursor_name,
+            univ_params: self.univ_params(),
             computation_rule: computation_rule,
         })
     }
 
-    pub fn make_below(&mut self) -> Result<(), Error> {
-        let name = self.inductive_ty
-                       .name
-                       .in_scope("below".to_string())
-                       .unwrap();
-
-        let params : Vec<_> = self.inductive_ty
-                         .parameters
-                         .clone();
-
-        let params_as_terms : Vec<_> =
-            params.clone()
-                  .iter()
-                  .map(|p| p.to_term())
-                  .collect();
-
-        let ty =
-            Term::abstract_pi_implicit(
-                params.clone(),
-                Term::apply_all(
-                    self.inductive_ty.name.to_term(),
-                    params_as_terms.clone()));
-
-        let rec =
-            self.inductive_ty
-                .name
-                .in_scope("rec".to_string())
-                .unwrap();
-
-        let body =
-            Term::abstract_lambda(
-                params.clone(),
-                Term::apply_all(
-                    rec.to_term(),
-                    params_as_terms));
-
-        let def = Function {
-            name: name,
-            args: vec![],
-            ty: ty,
-            body: body,
-            reduction: DeltaReduction::Reducible,
-        };
-
-        debug!("{}", def);
-
-        try!(self.ty_cx.declare_def(&def));
-
-        Ok(())
-    }
-
     pub fn make_cases_on(&mut self) -> Result<(), Error> {
             let name = self.inductive_ty
                            .name
@@ -511,13 +591,13 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
 
             let inductive_ty = self.inductive_ty;
             let ind_hyp = self.ind_hyp.clone();
-            debug!("ind_hyp = {}", Term::abstract_pi(vec![ind_hyp.clone()], Term::Type));
+            debug!("ind_hyp = {}", ind_hyp);
 
             let minor_premises: Result<_, Error> =
                 inductive_ty.ctors
                    .iter()
                    .map(|ctor| {
-                       let p = try!(self.minor_premise_for(&ind_hyp , ctor));
+                       let p = try!(self.minor_premise_for(&ind_hyp, ctor, false));
                        debug!("{}", p);
                        Ok(self.ty_cx.local_with_repr("".to_string(), p))
                    })
@@ -525,25 +605,38 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
 
             let minor_premises : Vec<Name> = try!(minor_premises);
 
-            let scrut =
-                self.ty_cx.local_with_repr(
-                    "n".to_string(),
-                    Term::apply_all(
-                        self.inductive_ty.name.to_term(),
-                       params_as_terms.clone()));
+            // The indices of the scrutinee are implicit, they can always be
+            // read off of its type.
+            let (mut indices, premise) = self.major_premise();
+            let scrut = indices.pop().unwrap();
 
             let ty =
                 Term::abstract_pi_implicit(
                     params.clone(),
                     Term::abstract_pi_implicit(
                         vec![self.ind_hyp.clone()],
-                        Term::abstract_pi(
-                            vec![scrut.clone()],
+                        Term::abstract_pi_implicit(
+                            indices.clone(),
                             Term::abstract_pi(
-                                minor_premises.clone(),
-                                Term::apply_all(
-                                    self.ind_hyp.to_term(),
-                                    vec![scrut.to_term()])))));
+                                vec![scrut.clone()],
+                                Term::abstract_pi(
+                                    minor_premises.clone(),
+                                    premise)))));
+
+            // The recursor for a type in a `mutual` block needs a motive for
+            // each of the other types too, so instead of defining `cases_on`
+            // with it we make `cases_on` a constant with its own computation
+            // rule.
+            if self.mutual.len() > 1 {
+                self.ty_cx.axioms.insert(name, super::Axiom {
+                    ty: ty,
+                    univ_params: self.univ_params(),
+                    computation_rule: Some(cases_on_computation_rule()),
+                    major_premise: Some(self.inductive_ty.parameters.len() + 1 + indices.len()),
+                });
+
+                return Ok(());
+            }
 
             let rec =
                 self.inductive_ty
@@ -551,69 +644,1347 @@ impl<'i, 'tcx> InductiveCx<'i, 'tcx> {
                     .in_scope("rec".to_string())
                     .unwrap();
 
+            // The recursor's minor premises also take the inductive hypotheses,
+            // which `cases_on` doesn't, so we wrap each premise in a function
+            // that discards them.
             let mut rec_args = vec![];
-            for premise in minor_premises.clone() {
-                let ty = try!(self.ty_cx.type_infer_term(&premise.to_term()));
-                let mut ty = ty.0;
-                // debug!("premise={} ty={}", premise, ty);
+            for (premise, ctor) in minor_premises.iter().zip(inductive_ty.ctors.iter()) {
+                let num_args = match premise {
+                    &Name::Local { ref ty, .. } => arity(ty),
+                    _ => unreachable!(),
+                };
+
+                let mut rec_premise_ty = try!(self.minor_premise_for(&ind_hyp, ctor, true));
                 let mut locals = vec![];
-                while let Term::Forall { binder, term, .. } = ty {
-                    locals.push(self.ty_cx.local(binder).with_repr("f".to_string()));
-                    ty = *term;
-                }
 
-                for local in locals.iter().rev() {
-                    // debug!("{}", local);
+                while let Term::Forall { binder, term, .. } = rec_premise_ty {
+                    let local = self.ty_cx.local(binder);
+                    rec_premise_ty = term.instantiate(&local.to_term());
+                    locals.push(local);
                 }
-                // If this argument is recursive we should skip it
-                // debug!("transformed: {}", Term::apply_all(premise.to_term(), vec![]));
-                rec_args.push(premise.with_repr("f".to_string()));
+
+                let args = locals.iter()
+                                 .take(num_args)
+                                 .map(|l| l.to_term())
+                                 .collect();
+
+                rec_args.push(
+                    Term::abstract_lambda(
+                        locals,
+                        Term::apply_all(premise.to_term(), args)));
             }
 
             let mut recursor_args = params_as_terms.clone();
             recursor_args.push(self.ind_hyp.to_term());
-            recursor_args.extend(rec_args.clone().into_iter().map(|x| x.to_term()));
+            recursor_args.extend(rec_args.into_iter());
+            recursor_args.extend(indices.iter().map(|i| i.to_term()));
             recursor_args.push(scrut.to_term());
 
+            let mut motive_and_major = vec![self.ind_hyp.clone()];
+            motive_and_major.extend(indices.into_iter());
+            motive_and_major.push(scrut);
+
             let body =
                 Term::abstract_lambda(
                     params.clone(),
                     Term::abstract_lambda(
-                        vec![self.ind_hyp.clone(), scrut],
+                        motive_and_major,
                         Term::abstract_lambda(
-                            rec_args.clone(),
+                            minor_premises,
                             Term::apply_all(
                                 rec.to_term(),
                                 recursor_args))));
 
             let def = Function {
                 name: name,
+                univ_params: self.univ_params(),
                 args: vec![],
                 ty: ty,
                 body: body,
-                reduction: DeltaReduction::Reducible
+                reduction: DeltaReduction::Reducible,
+                coercion: false,
             };
 
             // debug!("{}", def);
             self.ty_cx.declare_def(&def)
         }
+
+    /// A structure `T` gets a projection `T.field` for each of its fields,
+    /// defined by cases on the structure. The type of a field may mention
+    /// the fields before it, which become projections of the structure.
+    pub fn make_projections(&mut self, fields: &[Name]) -> Result<(), Error> {
+        let inductive_ty = self.inductive_ty;
+        let params = inductive_ty.parameters.clone();
+        let params_as_terms: Vec<_> = params.iter().map(|p| p.to_term()).collect();
+
+        // The projections of a class find the structure by instance
+        // resolution.
+        let scrut_mode = if inductive_ty.class {
+            BindingMode::InstImplicit
+        } else {
+            BindingMode::Explicit
+        };
+
+        let scrut = self.ty_cx.local_with_repr_and_mode(
+            "s".to_string(),
+            self.with_params(inductive_ty.name.to_term()),
+            scrut_mode);
+
+        // The motive of each `cases_on` lives in the universe of the field.
+        let mut levels: Vec<_> = self.mutual_univ_params()
+                                     .into_iter()
+                                     .map(Level::Param)
+                                     .collect();
+
+        if self.elim_level.is_some() {
+            levels.push(self.ty_cx.fresh_level_meta());
+        }
+
+        let cases_on = inductive_ty.name
+                                   .in_scope("cases_on".to_string())
+                                   .unwrap()
+                                   .with_levels(levels);
+
+        let mut field_pi = self.with_params(inductive_ty.ctors[0].1.clone());
+
+        for (i, field) in fields.iter().enumerate() {
+            let (field_ty, rest) = match field_pi {
+                Term::Forall { binder, term, .. } => (binder.ty.into_term(), term),
+                t => panic!("type checking bug: {} has too few fields", t),
+            };
+
+            let name = inductive_ty.projection(field);
+
+            // The minor premise binds all of the fields, returning the one
+            // we are projecting.
+            let mut ctor_pi = self.with_params(inductive_ty.ctors[0].1.clone());
+            let mut ctor_args = vec![];
+
+            while let Term::Forall { binder, term, .. } = ctor_pi {
+                let local = self.ty_cx.local(binder);
+                ctor_pi = term.instantiate(&local.to_term());
+                ctor_args.push(local);
+            }
+
+            let minor_premise = Term::abstract_lambda(
+                ctor_args.clone(),
+                ctor_args[i].to_term());
+
+            let motive = Term::abstract_lambda(vec![scrut.clone()], field_ty.clone());
+
+            let mut cases_on_args = params_as_terms.clone();
+            cases_on_args.push(motive);
+            cases_on_args.push(scrut.to_term());
+            cases_on_args.push(minor_premise);
+
+            let body = Term::abstract_lambda(
+                params.clone(),
+                Term::abstract_lambda(
+                    vec![scrut.clone()],
+                    Term::apply_all(cases_on.to_term(), cases_on_args)));
+
+            let ty = Term::abstract_pi_implicit(
+                params.clone(),
+                Term::abstract_pi(vec![scrut.clone()], field_ty));
+
+            let mut projection_args = params_as_terms.clone();
+            projection_args.push(scrut.to_term());
+
+            field_pi = rest.instantiate(&Term::apply_all(name.to_term(), projection_args));
+
+            try!(self.ty_cx.declare_def(&Function {
+                name: name,
+                univ_params: self.mutual_univ_params(),
+                args: vec![],
+                ty: ty,
+                body: body,
+                reduction: DeltaReduction::Reducible,
+                coercion: false,
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the type gets `below` and `brec_on`, it must have a recursive
+    /// argument to recur on, eliminate into any universe, and not be part
+    /// of a `mutual` block or contain nested occurrences.
+    fn has_below(&self) -> bool {
+        self.mutual.len() == 1 &&
+        self.elim_level.is_some() &&
+        self.inductive_ty.ctors.iter().any(|ctor| {
+            let mut pi = self.with_params(ctor.1.clone());
+
+            while let Term::Forall { binder, term, .. } = pi {
+                if self.recursive_arg(&binder.ty).is_some() {
+                    return true;
+                }
+
+                pi = term.instantiate(&self.ty_cx.local(binder).to_term());
+            }
+
+            false
+        })
+    }
+
+    /// Open the arguments of `ctor`, along with the arguments `ys` and the
+    /// indices `js` of the type `forall ys, T ps js` of each recursive one.
+    fn open_ctor(&self, ctor: &(Name, Term)) -> (Vec<Name>, Vec<(Name, Vec<Name>, Vec<Term>)>) {
+        let mut pi = self.with_params(ctor.1.clone());
+        let mut args = vec![];
+        let mut recursive = vec![];
+
+        while let Term::Forall { binder, term, .. } = pi {
+            let arg = self.ty_cx.local_with_repr(format!("a{}", args.len()), binder.ty.clone().into_term());
+
+            if let Some((_, ys, js)) = self.recursive_arg(&binder.ty) {
+                recursive.push((arg.clone(), ys, js));
+            }
+
+            pi = term.instantiate(&arg.to_term());
+            args.push(arg);
+        }
+
+        (args, recursive)
+    }
+
+    /// The indices of `ctor` applied to the parameters and `args`.
+    fn ctor_indices(&self, ctor: &(Name, Term), args: &[Name]) -> Result<Vec<Term>, Error> {
+        let ctor_app = Term::apply_all(
+            self.with_params(ctor.0.to_term()),
+            args.iter().map(|a| a.to_term()).collect());
+
+        let (ty, _) = try!(self.ty_cx.type_infer_term(&ctor_app));
+        let num_params = self.inductive_ty.parameters.len();
+
+        Ok(ty.args().unwrap_or(vec![]).into_iter().skip(num_params).collect())
+    }
+
+    /// The level of the sort the type `ty` lives in.
+    fn sort_level(&self, ty: &Term) -> Result<Level, Error> {
+        let (sort, _) = try!(self.ty_cx.type_infer_term(ty));
+
+        match try!(self.ty_cx.whnf(&sort)).0 {
+            Term::Sort { level } => Ok(level),
+            t => panic!("type checking bug: {} is not a sort", t),
+        }
+    }
+
+    /// `below` and `brec_on` live in `Sort (max 1 l s)`, where the motive
+    /// eliminates into `Sort l` and the type lives in `Sort s`, so the
+    /// recursive arguments fit as well.
+    fn below_level(&self) -> Level {
+        Level::max(Level::one(), Level::max(self.motive_level(), result_level(&self.inductive_ty.ty)))
+    }
+
+    /// The levels of the recursor, eliminating into `level`.
+    fn rec_levels(&self, level: Level) -> Vec<Level> {
+        let mut levels: Vec<_> = self.mutual_univ_params().into_iter().map(Level::Param).collect();
+        levels.push(level);
+        levels
+    }
+
+    /// `T.below ps C is t`.
+    fn below_app(&self, indices: Vec<Term>, scrut: Term) -> Term {
+        let below = self.inductive_ty.name.in_scope("below".to_string()).unwrap();
+        let mut args = self.with_params_as_args();
+        args.push(self.ind_hyp.to_term());
+        args.extend(indices.into_iter());
+        args.push(scrut);
+        Term::apply_all(below.to_term(), args)
+    }
+
+    fn with_params_as_args(&self) -> Vec<Term> {
+        self.inductive_ty.parameters.iter().map(|p| p.to_term()).collect()
+    }
+
+    /// The component for a recursive argument `a` of type `forall ys, T ps js`
+    /// in `below`, `forall ys, PProd (C js (a ys)) (b ys)` where `b ys` is
+    /// the `below` of `a ys`, along with its level.
+    fn below_component<F>(&self, arg: &Name, ys: &[Name], js: &[Term], below: F) -> Result<(Term, Level), Error>
+        where F: Fn(Vec<Term>, Term) -> Term {
+        let applied = Term::apply_all(arg.to_term(), ys.iter().map(|y| y.to_term()).collect());
+
+        let mut motive_args = js.to_vec();
+        motive_args.push(applied.clone());
+
+        let pair = pprod(
+            self.motive_level(),
+            self.below_level(),
+            Term::apply_all(self.ind_hyp.to_term(), motive_args),
+            below(js.to_vec(), applied));
+
+        let mut level = pprod_level(self.motive_level(), self.below_level());
+
+        for y in ys.iter().rev() {
+            let y_ty = match y {
+                &Name::Local { ref ty, .. } => ty.clone().into_term(),
+                _ => unreachable!(),
+            };
+
+            level = Level::imax(try!(self.sort_level(&y_ty)), level);
+        }
+
+        Ok((Term::abstract_pi(ys.to_vec(), pair), level))
+    }
+
+    /// For a type `T ps : forall is, Sort s` we define
+    ///
+    /// `T.below : forall {ps} (C : forall is, T ps is -> Sort l) is (t : T ps is),
+    ///            Sort (max 1 l s)`
+    ///
+    /// by recursion on `t`, for `c as` it is the product of
+    /// `forall ys, PProd (C js (a ys)) (T.below ps C js (a ys))` for each
+    /// recursive argument `a : forall ys, T ps js`. These are the results
+    /// of the recursive calls a definition by course-of-values recursion on
+    /// `c as` may make.
+    pub fn make_below(&mut self) -> Result<(), Error> {
+        let level = self.below_level();
+        let sort = Term::sort(level.clone());
+
+        let (major, _) = self.major_premise();
+        let motive = Term::abstract_lambda(major, sort.clone());
+
+        let mut minor_premises = vec![];
+
+        for ctor in &self.inductive_ty.ctors {
+            let (args, recursive) = self.open_ctor(ctor);
+            let mut ind_hyps = vec![];
+            let mut components = vec![];
+
+            for (arg, ys, js) in recursive {
+                let ih = self.ty_cx.local_with_repr(
+                    "ih".to_string(),
+                    Term::abstract_pi(ys.clone(), sort.clone()));
+
+                components.push(try!(self.below_component(&arg, &ys, &js, |_, _| {
+                    Term::apply_all(ih.to_term(), ys.iter().map(|y| y.to_term()).collect())
+                })));
+
+                ind_hyps.push(ih);
+            }
+
+            let mut binders = args;
+            binders.extend(ind_hyps.into_iter());
+
+            minor_premises.push(Term::abstract_lambda(binders, product(&level, components).0));
+        }
+
+        let (major, _) = self.major_premise();
+        let rec = self.inductive_ty
+                      .name
+                      .in_scope("rec".to_string())
+                      .unwrap()
+                      .with_levels(self.rec_levels(level.succ()));
+
+        let mut rec_args = self.with_params_as_args();
+        rec_args.push(motive);
+        rec_args.extend(minor_premises.into_iter());
+        rec_args.extend(major.iter().map(|a| a.to_term()));
+
+        let mut locals = vec![self.ind_hyp.clone()];
+        locals.extend(major.into_iter());
+
+        let params = self.inductive_ty.parameters.clone();
+
+        self.ty_cx.declare_def(&Function {
+            name: self.inductive_ty.name.in_scope("below".to_string()).unwrap(),
+            univ_params: self.univ_params(),
+            args: vec![],
+            ty: Term::abstract_pi_implicit(
+                params.clone(),
+                Term::abstract_pi(locals.clone(), sort)),
+            body: Term::abstract_lambda(
+                params,
+                Term::abstract_lambda(locals, Term::apply_all(rec.to_term(), rec_args))),
+            reduction: DeltaReduction::Reducible,
+            coercion: false,
+        })
+    }
+
+    /// Course-of-values recursion on `T`,
+    ///
+    /// `T.brec_on : forall {ps} (C : forall is, T ps is -> Sort l) is (t : T ps is)
+    ///              (F : forall is (t : T ps is), T.below ps C is t -> C is t),
+    ///              C is t`
+    ///
+    /// computes `PProd (C is t) (T.below ps C is t)` by recursion on `t`,
+    /// applying `F` to the `below` built from the recursive arguments.
+    pub fn make_brec_on(&mut self) -> Result<(), Error> {
+        let level = self.below_level();
+        let motive_level = self.motive_level();
+        let pair_level = pprod_level(motive_level.clone(), level.clone());
+
+        let (f_major, f_result) = self.major_premise();
+        let f_below = self.ty_cx.local_with_repr("b".to_string(), {
+            let (indices, scrut) = split_major(&f_major);
+            self.below_app(indices, scrut)
+        });
+
+        let f = self.ty_cx.local_with_repr(
+            "F".to_string(),
+            Term::abstract_pi(f_major, Term::abstract_pi(vec![f_below], f_result)));
+
+        let (major, _) = self.major_premise();
+        let motive = {
+            let (indices, scrut) = split_major(&major);
+            let mut motive_args = indices.clone();
+            motive_args.push(scrut.clone());
+
+            Term::abstract_lambda(major.clone(), pprod(
+                motive_level.clone(),
+                level.clone(),
+                Term::apply_all(self.ind_hyp.to_term(), motive_args),
+                self.below_app(indices, scrut)))
+        };
+
+        let mut minor_premises = vec![];
+
+        for ctor in &self.inductive_ty.ctors {
+            let (args, recursive) = self.open_ctor(ctor);
+            let mut ind_hyps = vec![];
+            let mut components = vec![];
+
+            for (arg, ys, js) in recursive {
+                let (component, component_level) =
+                    try!(self.below_component(&arg, &ys, &js, |js, applied| self.below_app(js, applied)));
+
+                ind_hyps.push(self.ty_cx.local_with_repr("ih".to_string(), component.clone()));
+                components.push((component, component_level));
+            }
+
+            // The `below` of `c as` is the tuple of the hypotheses.
+            let mut below = (punit_name().in_scope("star".to_string()).unwrap()
+                                         .with_levels(vec![level.clone()])
+                                         .to_term(),
+                             punit_name().with_levels(vec![level.clone()]).to_term(),
+                             level.clone());
+
+            for ((component, component_level), ih) in components.into_iter().zip(ind_hyps.iter()).rev() {
+                let (rest, rest_ty, rest_level) = below;
+
+                below = (Term::apply_all(
+                             pprod_name().in_scope("mk".to_string()).unwrap()
+                                         .with_levels(vec![component_level.clone(), rest_level.clone()])
+                                         .to_term(),
+                             vec![component.clone(), rest_ty.clone(), ih.to_term(), rest]),
+                         pprod(component_level.clone(), rest_level.clone(), component, rest_ty),
+                         pprod_level(component_level, rest_level));
+            }
+
+            let below = below.0;
+
+            let ctor_app = Term::apply_all(
+                self.with_params(ctor.0.to_term()),
+                args.iter().map(|a| a.to_term()).collect());
+
+            let indices = try!(self.ctor_indices(ctor, &args));
+
+            let mut f_args = indices.clone();
+            f_args.push(ctor_app.clone());
+            f_args.push(below.clone());
+
+            let mut motive_args = indices.clone();
+            motive_args.push(ctor_app.clone());
+
+            let result = Term::apply_all(
+                pprod_name().in_scope("mk".to_string()).unwrap()
+                            .with_levels(vec![motive_level.clone(), level.clone()])
+                            .to_term(),
+                vec![Term::apply_all(self.ind_hyp.to_term(), motive_args),
+                     self.below_app(indices, ctor_app),
+                     Term::apply_all(f.to_term(), f_args),
+                     below]);
+
+            let mut binders = args;
+            binders.extend(ind_hyps.into_iter());
+
+            minor_premises.push(Term::abstract_lambda(binders, result));
+        }
+
+        let rec = self.inductive_ty
+                      .name
+                      .in_scope("rec".to_string())
+                      .unwrap()
+                      .with_levels(self.rec_levels(pair_level));
+
+        let mut rec_args = self.with_params_as_args();
+        rec_args.push(motive);
+        rec_args.extend(minor_premises.into_iter());
+        rec_args.extend(major.iter().map(|a| a.to_term()));
+
+        let (indices, scrut) = split_major(&major);
+        let mut motive_args = indices.clone();
+        motive_args.push(scrut.clone());
+        let result_ty = Term::apply_all(self.ind_hyp.to_term(), motive_args);
+
+        let fst = pprod_name().in_scope("fst".to_string()).unwrap()
+                              .with_levels(vec![motive_level, level]);
+
+        let body = Term::apply_all(fst.to_term(), vec![
+            result_ty.clone(),
+            self.below_app(indices, scrut),
+            Term::apply_all(rec.to_term(), rec_args)]);
+
+        let mut locals = vec![self.ind_hyp.clone()];
+        locals.extend(major.into_iter());
+        locals.push(f);
+
+        let params = self.inductive_ty.parameters.clone();
+
+        self.ty_cx.declare_def(&Function {
+            name: self.inductive_ty.name.in_scope("brec_on".to_string()).unwrap(),
+            univ_params: self.univ_params(),
+            args: vec![],
+            ty: Term::abstract_pi_implicit(
+                params.clone(),
+                Term::abstract_pi(locals.clone(), result_ty)),
+            body: Term::abstract_lambda(params, Term::abstract_lambda(locals, body)),
+            reduction: DeltaReduction::Reducible,
+            coercion: false,
+        })
+    }
+}
+
+fn punit_name() -> Name {
+    Name::from_str("PUnit")
+}
+
+fn pprod_name() -> Name {
+    Name::from_str("PProd")
+}
+
+/// `PProd.{a b} ty_a ty_b`.
+fn pprod(a: Level, b: Level, ty_a: Term, ty_b: Term) -> Term {
+    Term::apply_all(pprod_name().with_levels(vec![a, b]).to_term(), vec![ty_a, ty_b])
+}
+
+/// The level of `PProd.{a b}`.
+fn pprod_level(a: Level, b: Level) -> Level {
+    Level::max(Level::one(), Level::max(a, b))
+}
+
+/// The nested product of `components` and `PUnit.{level}`, with its level.
+fn product(level: &Level, components: Vec<(Term, Level)>) -> (Term, Level) {
+    let unit = punit_name().with_levels(vec![level.clone()]).to_term();
+
+    components.into_iter().rev().fold((unit, level.clone()), |(rest, rest_level), (ty, ty_level)| {
+        (pprod(ty_level.clone(), rest_level.clone(), ty, rest), pprod_level(ty_level, rest_level))
+    })
+}
+
+/// Split the locals `is t` of a major premise into the indices and the
+/// scrutinee.
+fn split_major(major: &[Name]) -> (Vec<Term>, Term) {
+    let (scrut, indices) = major.split_last().unwrap();
+    (indices.iter().map(|i| i.to_term()).collect(), scrut.to_term())
+}
+
+/// `T.cases_on ps C is (c as) ms` reduces to the minor premise for `c`
+/// applied to `as`.
+fn cases_on_computation_rule() -> ComputationRule {
+    Box::new(|cx: &TyCtxt, term: Term| {
+        debug!("cases_on_computation_rule: term={}", term);
+        let (head, args) = term.uncurry();
+
+        let ty_name = match eliminated_type(&head) {
+            None => return Ok(term),
+            Some((ty_name, _)) => ty_name,
+        };
+
+        let dt = match cx.types.get(&ty_name) {
+            None => return Err(Error::UnknownVariable(ty_name)),
+            Some(dt) => dt,
+        };
+
+        let num_params = dt.parameters.len();
+        let num_indices = arity(&dt.ty) - num_params;
+        let major = num_params + 1 + num_indices;
+        let num_args = major + 1 + dt.ctors.len();
+
+        if args.len() < num_args {
+            return Ok(term);
+        }
+
+        let (scrut_ctor, scrut_args) = args[major].uncurry();
+
+        let i = match &scrut_ctor {
+            &Term::Var { ref name } => match dt.ctors.iter().position(|c| c.0.same_variable(name)) {
+                Some(i) => i,
+                None => return Ok(term),
+            },
+            _ => return Ok(term),
+        };
+
+        let ctor_args: Vec<_> =
+            scrut_args.iter()
+                      .skip(num_params)
+                      .cloned()
+                      .collect();
+
+        // The constructor is only partially applied.
+        if num_params + ctor_args.len() != arity(&dt.ctors[i].1) {
+            return Ok(term);
+        }
+
+        let result =
+            Term::apply_all(
+                Term::apply_all(args[major + 1 + i].clone(), ctor_args),
+                args[num_args..].to_vec());
+
+        Ok(result)
+    })
+}
+
+/// The recursor and `cases_on` for `T` are named `T.rec` and `T.cases_on`,
+/// we drop the last component to find the inductive type, returning it
+/// along with the levels the eliminator was instantiated with.
+fn eliminated_type(head: &Term) -> Option<(Name, Vec<Level>)> {
+    match head {
+        &Term::Var { name: Name::Qual { ref components, span, ref levels } } => Some((Name::Qual {
+            components: components[..components.len() - 1].to_vec(),
+            span: span,
+            levels: vec![],
+        }, levels.clone())),
+        _ => None,
+    }
+}
+
+/// The number of arguments a type former takes.
+fn arity(ty: &Term) -> usize {
+    let mut arity = 0;
+    let mut ty = ty;
+
+    while let &Term::Forall { ref term, .. } = ty {
+        arity += 1;
+        ty = term;
+    }
+
+    arity
+}
+
+/// If `ty` is the type of a recursive argument of a constructor,
+/// `forall (ys : Bs), T ps is` where `T` is one of the `mutual` types, open
+/// its binders returning the position of `T`, the locals `ys` and the
+/// indices `is`.
+fn recursive_arg(ty_cx: &TyCtxt, mutual: &[Data], ty: &Term) -> Option<(usize, Vec<Name>, Vec<Term>)> {
+    if !mutual.iter().any(|d| ty.occurs(&d.name)) {
+        return None;
+    }
+
+    let mut pi = ty.clone();
+    let mut locals = vec![];
+
+    while let Term::Forall { binder, term, .. } = pi {
+        let local = ty_cx.local(binder);
+        pi = term.instantiate(&local.to_term());
+        locals.push(local);
+    }
+
+    let (head, args) = pi.uncurry();
+
+    mutual.iter().position(|d| head.is_var(&d.name)).map(|j| {
+        let indices = args.into_iter().skip(mutual[j].parameters.len()).collect();
+        (j, locals, indices)
+    })
+}
+
+/// Build the recursive call for the argument `arg` of type `ty`, for a
+/// recursive argument of type `forall (ys : Bs), T ps is` this is
+/// `fun (ys : Bs) => T.rec ps Cs ms is (arg ys)`.
+fn recursive_call(ty_cx: &TyCtxt,
+                  mutual: &[Data],
+                  levels: &[Level],
+                  rec_args: &[Term],
+                  arg: &Term,
+                  ty: &Term) -> Option<Term> {
+    recursive_arg(ty_cx, mutual, ty).map(|(j, locals, indices)| {
+        let rec = mutual[j].name
+                           .in_scope("rec".to_string())
+                           .unwrap()
+                           .with_levels(levels.to_vec());
+
+        let mut call_args = rec_args.to_vec();
+        call_args.extend(indices.into_iter());
+        call_args.push(Term::apply_all(
+            arg.clone(),
+            locals.iter().map(|l| l.to_term()).collect()));
+
+        Term::abstract_lambda(locals, Term::apply_all(rec.to_term(), call_args))
+    })
+}
+
+/// Check that the types declared together in `mutual` are valid inductive
+/// declarations. They must all take the same parameters, every constructor
+/// must return the type being declared applied to its parameters, the
+/// types may only occur strictly positively in the constructor's arguments,
+/// and those arguments must live in a universe no larger than the type's.
+pub fn check_inductive(ty_cx: &mut TyCtxt, mutual: &[Data]) -> Result<(), Error> {
+    let mut universes = Universes {
+        args: vec![],
+        constraints: vec![],
+    };
+
+    for data_type in mutual {
+        if data_type.parameters != mutual[0].parameters {
+            return Err(Error::MutualParameters(
+                data_type.name.get_span(), data_type.name.clone(), mutual[0].name.clone()));
+        }
+
+        for ctor in &data_type.ctors {
+            try!(check_ctor(ty_cx, mutual, data_type, ctor, &mut universes));
+        }
+    }
+
+    check_universes(ty_cx, universes)
+}
+
+/// The levels of the arguments of the constructors of an inductive
+/// declaration, which we can only compare with the levels of their types
+/// once we solve the constraints we found inferring them.
+struct Universes {
+    /// The constructor, the type of its argument, the level of that type
+    /// and the level of the type the constructor builds.
+    args: Vec<(Name, Term, Level, Level)>,
+    constraints: ConstraintSeq,
+}
+
+fn check_ctor(ty_cx: &mut TyCtxt,
+              mutual: &[Data],
+              data_type: &Data,
+              ctor: &(Name, Term),
+              universes: &mut Universes) -> Result<(), Error> {
+    let &(ref ctor_name, ref ctor_ty) = ctor;
+    let ty_name = &data_type.name;
+    let span = ctor_name.get_span();
+
+    let params_error = |found: &Term| {
+        Error::CtorParameters(span, ctor_name.clone(), ty_name.clone(), found.clone())
+    };
+
+    // The constructor's type is abstracted over the parameters, we open
+    // those binders with the parameters themselves.
+    let mut pi = ctor_ty.clone();
+    for param in &data_type.parameters {
+        pi = match pi {
+            Term::Forall { term, .. } => term.instantiate(&param.to_term()),
+            _ => return Err(params_error(ctor_ty)),
+        };
+    }
+
+    let level = result_level(&data_type.ty);
+
+    while let Term::Forall { binder, term, .. } = pi {
+        try!(check_positivity(ty_cx, mutual, ctor_name, &*binder.ty));
+        try!(arg_universe(ty_cx, ctor_name, &*binder.ty, &level, universes));
+        let local = ty_cx.local(binder);
+        pi = term.instantiate(&local.to_term());
+    }
+
+    let (head, args) = pi.uncurry();
+
+    if !head.is_var(ty_name) || args.len() != arity(&data_type.ty) {
+        return Err(Error::CtorReturnType(span, ctor_name.clone(), ty_name.clone(), pi.clone()));
+    }
+
+    try!(check_params(data_type, &args, &pi).map_err(|t| params_error(&t)));
+
+    // The types can't appear in their own indices, `C : T (T Unit)` is
+    // rejected for example.
+    let num_params = data_type.parameters.len();
+    if let Some(occurs) = args.iter().skip(num_params).filter_map(|i| occurring(mutual, i)).next() {
+        return Err(Error::NonPositiveOccurrence(
+            span, ctor_name.clone(), occurs.clone(), pi.clone()));
+    }
+
+    Ok(())
+}
+
+/// The level of the sort an inductive type of type `ty` lives in.
+fn result_level(ty: &Term) -> Level {
+    let mut ty = ty;
+
+    while let &Term::Forall { ref term, .. } = ty {
+        ty = term;
+    }
+
+    match ty {
+        &Term::Sort { ref level } => level.clone(),
+        _ => Level::Zero,
+    }
+}
+
+/// Record the level of an argument of type `arg_ty`, which must fit in a
+/// type whose sort has level `level`, `Type` can't contain a `Type` for
+/// example. A proposition may have arguments from any universe, the level
+/// of a `forall` whose body is a proposition is zero whatever the level of
+/// its domain.
+fn arg_universe(ty_cx: &mut TyCtxt,
+                ctor_name: &Name,
+                arg_ty: &Term,
+                level: &Level,
+                universes: &mut Universes) -> Result<(), Error> {
+    if level.is_zero() {
+        return Ok(());
+    }
+
+    let (sort, constraints) = try!(ty_cx.type_infer_term(arg_ty));
+    let (arg_level, sort_cs) = try!(ty_cx.ensure_sort(sort));
+
+    universes.constraints.extend(constraints.into_iter());
+    universes.constraints.extend(sort_cs.into_iter());
+    universes.args.push((ctor_name.clone(), arg_ty.clone(), arg_level, level.clone()));
+
+    Ok(())
 }
 
-/// Construct a recursor for `data_type`.
-pub fn make_recursor(ty_cx: &mut TyCtxt, data_type: &Data) -> Result<(), Error> {
-    let mut rcx = InductiveCx::new(ty_cx, data_type);
-    let recursor = try!(rcx.recursor());
+/// Check that the arguments of the constructors fit in the universes of
+/// their types, solving the constraints on their levels together.
+fn check_universes(ty_cx: &mut TyCtxt, universes: Universes) -> Result<(), Error> {
+    let Universes { args, constraints } = universes;
+
+    if args.len() == 0 {
+        return Ok(());
+    }
 
-    // Add an axiom with the recursor type, and the associated computation rule.
-    rcx.ty_cx
-       .axioms
-       .insert(recursor.name, super::Axiom {
-           ty: recursor.ty,
-           computation_rule: Some(recursor.computation_rule),
-       });
+    let solver = try!(Solver::new(ty_cx, constraints));
+    let (_, levels) = try!(solver.solve());
 
-    // Now setup all the automatically generated constructs.
-    try!(rcx.make_cases_on());
+    for (ctor_name, arg_ty, arg_level, level) in args {
+        let arg_level = arg_level.instantiate_metas(&levels);
+
+        if !arg_level.leq(&level) {
+            return Err(Error::CtorUniverse(
+                ctor_name.get_span(), ctor_name, arg_ty, arg_level, level));
+        }
+    }
 
     Ok(())
 }
+
+/// Check that the first arguments of an occurrence of the inductive type
+/// are exactly its parameters, returning the offending term if not.
+fn check_params(data_type: &Data, args: &[Term], occurrence: &Term) -> Result<(), Term> {
+    let params = &data_type.parameters;
+
+    if args.len() < params.len() ||
+       args.iter().zip(params.iter()).any(|(a, p)| a != &p.to_term()) {
+        Err(occurrence.clone())
+    } else {
+        Ok(())
+    }
+}
+
+/// An argument type is strictly positive when none of the types being
+/// declared occur in it, or it is of the form `forall (xs : Bs), T ps is`
+/// where `T` is one of them and they do not occur in any of the `Bs` or the
+/// indices `is`.
+fn check_positivity(ty_cx: &TyCtxt, mutual: &[Data], ctor_name: &Name, arg_ty: &Term) -> Result<(), Error> {
+    let ty_name = match occurring(mutual, arg_ty) {
+        None => return Ok(()),
+        Some(ty_name) => ty_name,
+    };
+
+    let non_positive = |ty_name: &Name| {
+        Error::NonPositiveOccurrence(
+            ctor_name.get_span(), ctor_name.clone(), ty_name.clone(), arg_ty.clone())
+    };
+
+    match try!(ty_cx.eval(arg_ty)) {
+        Term::Forall { binder, term, .. } => {
+            if let Some(ty_name) = occurring(mutual, &binder.ty) {
+                return Err(non_positive(ty_name));
+            }
+
+            let local = ty_cx.local(binder);
+            check_positivity(ty_cx, mutual, ctor_name, &term.instantiate(&local.to_term()))
+        }
+        t => {
+            let (head, args) = t.uncurry();
+
+            let data_type = match mutual.iter().find(|d| head.is_var(&d.name)) {
+                None => return Err(non_positive(ty_name)),
+                Some(data_type) => data_type,
+            };
+
+            let num_params = data_type.parameters.len();
+
+            if let Some(ty_name) = args.iter().skip(num_params).filter_map(|i| occurring(mutual, i)).next() {
+                return Err(non_positive(ty_name));
+            }
+
+            check_params(data_type, &args, &t).map_err(|t| {
+                Error::CtorParameters(
+                    ctor_name.get_span(), ctor_name.clone(), data_type.name.clone(), t)
+            })
+        }
+    }
+}
+
+/// The first of the `mutual` types which occurs in `term`.
+fn occurring<'a>(mutual: &'a [Data], term: &Term) -> Option<&'a Name> {
+    mutual.iter()
+          .map(|d| &d.name)
+          .find(|n| term.occurs(n))
+}
+
+/// Construct a recursor for each of the types in `group`, the auxiliary
+/// types for nested occurrences only get a recursor.
+pub fn make_recursors(ty_cx: &mut TyCtxt, group: &Group) -> Result<(), Error> {
+    for (i, data_type) in group.types.iter().enumerate() {
+        let mut rcx = InductiveCx::new(ty_cx, group, data_type);
+        let recursor = try!(rcx.recursor());
+
+        // The major premise comes after every other argument.
+        let major_premise = arity(&recursor.ty) - 1;
+
+        // Add an axiom with the recursor type, and the associated computation rule.
+        rcx.ty_cx
+           .axioms
+           .insert(recursor.name, super::Axiom {
+               ty: recursor.ty,
+               univ_params: recursor.univ_params,
+               computation_rule: Some(recursor.computation_rule),
+               major_premise: Some(major_premise),
+           });
+
+        // Now setup all the automatically generated constructs.
+        if i < group.num_declared {
+            try!(rcx.make_cases_on());
+        }
+
+        // Only structures get projections, and with them eta.
+        if let Some(ref fields) = data_type.fields {
+            try!(rcx.make_projections(fields));
+        }
+
+        if i < group.num_declared && rcx.has_below() {
+            try!(rcx.make_below());
+            try!(rcx.make_brec_on());
+        }
+    }
+
+    Ok(())
+}
+
+/// Declare the types `below` is built from, which are available in every
+/// module,
+///
+/// `inductive PUnit.{u} : Sort u | PUnit.star : PUnit`
+///
+/// `structure PProd.{u v} (A : Sort u) (B : Sort v) : Sort (max 1 u v) :=
+///    (fst : A) (snd : B)`
+pub fn declare_products(ty_cx: &mut TyCtxt) -> Result<(), Error> {
+    let u = Name::from_str("u");
+    let v = Name::from_str("v");
+
+    try!(ty_cx.declare_datatype(&Data {
+        span: Span::dummy(),
+        name: punit_name(),
+        univ_params: vec![u.clone()],
+        parameters: vec![],
+        ty: Term::sort(Level::Param(u.clone())),
+        ctors: vec![(product_name("PUnit", "star"), punit_name().to_term())],
+        mutual: vec![],
+        fields: None,
+        class: false,
+    }));
+
+    let a = ty_cx.local_with_repr("A".to_string(), Term::sort(Level::Param(u.clone())));
+    let b = ty_cx.local_with_repr("B".to_string(), Term::sort(Level::Param(v.clone())));
+    let fst = ty_cx.local_with_repr("fst".to_string(), a.to_term());
+    let snd = ty_cx.local_with_repr("snd".to_string(), b.to_term());
+    let params = vec![a.clone(), b.clone()];
+
+    let ctor_ty = Term::abstract_pi_implicit(
+        params.clone(),
+        Term::abstract_pi(
+            vec![fst, snd],
+            Term::apply_all(pprod_name().to_term(), vec![a.to_term(), b.to_term()])));
+
+    ty_cx.declare_datatype(&Data {
+        span: Span::dummy(),
+        name: pprod_name(),
+        univ_params: vec![u.clone(), v.clone()],
+        parameters: params.clone(),
+        ty: Term::abstract_pi(params, Term::sort(pprod_level(Level::Param(u), Level::Param(v)))),
+        ctors: vec![(product_name("PProd", "mk"), ctor_ty)],
+        mutual: vec![],
+        fields: Some(vec![Name::from_str("fst"), Name::from_str("snd")]),
+        class: false,
+    })
+}
+
+/// The name of the constant `component` that comes with one of the types
+/// declared by `declare_products`.
+fn product_name(ty: &str, component: &str) -> Name {
+    Name::qualified(vec![ty.to_string(), component.to_string()])
+}
+
+/// Whether `name` is one of the types declared by `declare_products`, or
+/// one of the constants that come with them.
+pub fn is_product(name: &Name) -> bool {
+    match name {
+        &Name::Qual { ref components, .. } =>
+            components.first() == Some(&"PUnit".to_string()) ||
+            components.first() == Some(&"PProd".to_string()),
+        _ => false,
+    }
+}
+
+/// Once `Eq` is in scope each inductive type whose constructors can be told
+/// apart by an equation gets a `no_confusion` lemma, see
+/// `make_no_confusion`. Types declared before `Eq` get theirs as soon as it
+/// is declared or imported. We only look at each type once.
+pub fn make_no_confusions(ty_cx: &mut TyCtxt) -> Result<(), Error> {
+    let (eq, refl) = match equality(ty_cx) {
+        None => return Ok(()),
+        Some(eq) => eq,
+    };
+
+    let mut names: Vec<_> =
+        ty_cx.types.keys().filter(|n| !ty_cx.no_confusions.contains(n)).cloned().collect();
+    names.sort_by_key(|n| n.to_string());
+
+    for name in names {
+        ty_cx.no_confusions.insert(name.clone());
+
+        let no_confusion = name.in_scope("no_confusion".to_string()).unwrap();
+
+        if ty_cx.in_scope(&no_confusion) {
+            continue;
+        }
+
+        let data_type = ty_cx.types[&name].clone();
+
+        if let Some(ctor_args) = try!(no_confusion_args(ty_cx, &data_type)) {
+            try!(make_no_confusion(ty_cx, &data_type, &eq, &refl, &ctor_args));
+        }
+    }
+
+    Ok(())
+}
+
+/// The names of `Eq` and its constructor, if it has the shape we expect,
+/// `Eq (A : Type) : A -> A -> Type` with the constructor
+/// `Refl : forall (x : A), Eq A x x`.
+pub fn equality(ty_cx: &TyCtxt) -> Option<(Name, Name)> {
+    let eq = Name::from_str("Eq");
+
+    let data_type = match ty_cx.types.get(&eq) {
+        None => return None,
+        Some(data_type) => data_type,
+    };
+
+    let rec = eq.in_scope("rec".to_string()).unwrap();
+
+    if data_type.univ_params.len() == 0 &&
+       data_type.parameters.len() == 1 &&
+       arity(&data_type.ty) == 3 &&
+       data_type.ctors.len() == 1 &&
+       arity(&data_type.ctors[0].1) == 2 &&
+       ty_cx.univ_params_of(&rec).len() == 1 {
+        Some((eq.clone(), data_type.ctors[0].0.clone()))
+    } else {
+        None
+    }
+}
+
+/// The types of the arguments of each constructor of `data_type`, in terms
+/// of its parameters, if it gets a `no_confusion`. It must be a `Type`
+/// without indices which eliminates into any universe, and its constructors
+/// must take non-dependent arguments in `Type`, so we can state an equation
+/// between each pair of them.
+fn no_confusion_args(ty_cx: &TyCtxt, data_type: &Data) -> Result<Option<Vec<Vec<Term>>>, Error> {
+    let cases_on = data_type.name.in_scope("cases_on".to_string()).unwrap();
+    let params = &data_type.parameters;
+
+    if arity(&data_type.ty) != params.len() ||
+       !ty_cx.in_scope(&cases_on) ||
+       ty_cx.univ_params_of(&cases_on).len() != data_type.univ_params.len() + 1 {
+        return Ok(None);
+    }
+
+    if !is_type(&with_params(params, data_type.ty.clone())) {
+        return Ok(None);
+    }
+
+    let mut ctor_args = vec![];
+
+    for &(_, ref ctor_ty) in &data_type.ctors {
+        let mut ctor_ty = with_params(params, ctor_ty.clone());
+        let mut args = vec![];
+
+        while let Term::Forall { binder, term, .. } = ctor_ty {
+            let arg_ty = binder.ty.into_term();
+            let local = ty_cx.local_with_repr("a".to_string(), arg_ty.clone());
+
+            ctor_ty = term.instantiate(&local.to_term());
+
+            if ctor_ty.occurs(&local) || !is_type(&try!(ty_cx.type_infer_term(&arg_ty)).0) {
+                return Ok(None);
+            }
+
+            args.push(arg_ty);
+        }
+
+        ctor_args.push(args);
+    }
+
+    Ok(Some(ctor_args))
+}
+
+/// For a type `T ps` with constructors whose arguments have the types
+/// `ctor_args` we define
+///
+/// `T.no_confusion_type : forall {ps} (P : Sort u) (v1 v2 : T ps), Sort u`
+///
+/// which is `P` for different constructors, and
+/// `(forall (h1 : Eq A1 a1 b1) ..., P) -> P` for `c as` and `c bs`, along with
+///
+/// `T.no_confusion : forall {ps} {P v1 v2} (h : Eq (T ps) v1 v2),
+///                   T.no_confusion_type ps P v1 v2`
+///
+/// which rewrites with `h` so we only need to handle `v1` against itself, where
+/// each equation holds by `Refl`.
+fn make_no_confusion(ty_cx: &mut TyCtxt,
+                     data_type: &Data,
+                     eq: &Name,
+                     refl: &Name,
+                     ctor_args: &[Vec<Term>]) -> Result<(), Error> {
+    let u = fresh_univ_param(&data_type.univ_params);
+    let sort = Term::sort(Level::Param(u.clone()));
+
+    let mut univ_params = data_type.univ_params.clone();
+    univ_params.push(u.clone());
+
+    let levels: Vec<_> = data_type.univ_params.iter().cloned().map(Level::Param).collect();
+
+    let params = data_type.parameters.clone();
+    let params_as_terms: Vec<_> = params.iter().map(|p| p.to_term()).collect();
+
+    let ty = Term::apply_all(
+        data_type.name.clone().with_levels(levels.clone()).to_term(),
+        params_as_terms.clone());
+
+    let cases_on = |level: Level, motive: Term, scrut: Term, minors: Vec<Term>| {
+        let mut levels = levels.clone();
+        levels.push(level);
+
+        let name = data_type.name
+                            .in_scope("cases_on".to_string())
+                            .unwrap()
+                            .with_levels(levels);
+
+        let mut args = params_as_terms.clone();
+        args.push(motive);
+        args.push(scrut);
+        args.extend(minors.into_iter());
+
+        Term::apply_all(name.to_term(), args)
+    };
+
+    let equation = |ty: &Term, a: &Name, b: &Name| {
+        Term::apply_all(eq.to_term(), vec![ty.clone(), a.to_term(), b.to_term()])
+    };
+
+    let p = ty_cx.local_with_repr("P".to_string(), sort.clone());
+    let v1 = ty_cx.local_with_repr("v1".to_string(), ty.clone());
+    let v2 = ty_cx.local_with_repr("v2".to_string(), ty.clone());
+
+    // The type of the equations between the arguments, this is eliminated
+    // by cases on both values.
+    let type_motive = Term::abstract_lambda(
+        vec![ty_cx.local_with_repr("v".to_string(), ty.clone())],
+        sort.clone());
+
+    // The arguments of the constructors, shared by every pair of them.
+    let ass: Vec<_> = ctor_args.iter().map(|args| ctor_locals(ty_cx, "a", args)).collect();
+    let bss: Vec<_> = ctor_args.iter().map(|args| ctor_locals(ty_cx, "b", args)).collect();
+
+    let mut minor_premises = vec![];
+
+    for (i, args_i) in ctor_args.iter().enumerate() {
+        let as_ = ass[i].clone();
+        let mut inner_premises = vec![];
+
+        for j in 0..ctor_args.len() {
+            let bs = bss[j].clone();
+
+            let result = if i == j {
+                let hs: Vec<_> =
+                    as_.iter()
+                       .zip(bs.iter())
+                       .zip(args_i.iter())
+                       .map(|((a, b), arg_ty)| {
+                           ty_cx.local_with_repr("h".to_string(), equation(arg_ty, a, b))
+                       })
+                       .collect();
+
+                let k = ty_cx.local_with_repr("k".to_string(), Term::abstract_pi(hs, p.to_term()));
+
+                Term::abstract_pi(vec![k], p.to_term())
+            } else {
+                p.to_term()
+            };
+
+            inner_premises.push(Term::abstract_lambda(bs, result));
+        }
+
+        minor_premises.push(Term::abstract_lambda(
+            as_,
+            cases_on(Level::Param(u.clone()).succ(), type_motive.clone(), v2.to_term(), inner_premises)));
+    }
+
+    let no_confusion_type = data_type.name.in_scope("no_confusion_type".to_string()).unwrap();
+
+    let body = cases_on(Level::Param(u.clone()).succ(), type_motive, v1.to_term(), minor_premises);
+
+    try!(ty_cx.declare_def(&Function {
+        name: no_confusion_type.clone(),
+        univ_params: univ_params.clone(),
+        args: vec![],
+        ty: Term::abstract_pi_implicit(
+            params.clone(),
+            Term::abstract_pi(vec![p.clone(), v1.clone(), v2.clone()], sort.clone())),
+        body: Term::abstract_lambda(
+            params.clone(),
+            Term::abstract_lambda(vec![p.clone(), v1.clone(), v2.clone()], body)),
+        reduction: DeltaReduction::Reducible,
+        coercion: false,
+    }));
+
+    let mut all_levels = levels.clone();
+    all_levels.push(Level::Param(u.clone()));
+
+    let no_confusion_ty = |x: &Name, y: &Name| {
+        let mut args = params_as_terms.clone();
+        args.push(p.to_term());
+        args.push(x.to_term());
+        args.push(y.to_term());
+
+        Term::apply_all(no_confusion_type.clone().with_levels(all_levels.clone()).to_term(), args)
+    };
+
+    // When `v1` is `c as` the type is `(forall (h1 : Eq A1 a1 a1) ..., P) -> P`.
+    let mut diagonal_premises = vec![];
+
+    for args in ctor_args {
+        let as_ = ctor_locals(ty_cx, "a", args);
+
+        let hs: Vec<_> =
+            as_.iter()
+               .zip(args.iter())
+               .map(|(a, arg_ty)| ty_cx.local_with_repr("h".to_string(), equation(arg_ty, a, a)))
+               .collect();
+
+        let k = ty_cx.local_with_repr("k".to_string(), Term::abstract_pi(hs, p.to_term()));
+
+        let refls = as_.iter()
+                       .zip(args.iter())
+                       .map(|(a, arg_ty)| Term::apply_all(refl.to_term(), vec![arg_ty.clone(), a.to_term()]))
+                       .collect();
+
+        let mut locals = as_;
+        locals.push(k.clone());
+
+        diagonal_premises.push(Term::abstract_lambda(locals, Term::apply_all(k.to_term(), refls)));
+    }
+
+    let v = ty_cx.local_with_repr("v".to_string(), ty.clone());
+
+    let diagonal = cases_on(
+        Level::Param(u.clone()),
+        Term::abstract_lambda(vec![v.clone()], no_confusion_ty(&v, &v)),
+        v1.to_term(),
+        diagonal_premises);
+
+    // We rewrite `T.no_confusion_type ps P v1 v1` to the type we want.
+    let x = ty_cx.local_with_repr("x".to_string(), ty.clone());
+    let y = ty_cx.local_with_repr("y".to_string(), ty.clone());
+    let hxy = ty_cx.local_with_repr("h".to_string(), equation(&ty, &x, &y));
+    let nxx = ty_cx.local_with_repr("n".to_string(), no_confusion_ty(&x, &x));
+
+    let motive = Term::abstract_lambda(
+        vec![x.clone(), y.clone(), hxy],
+        Term::abstract_pi(vec![nxx], no_confusion_ty(&x, &y)));
+
+    let z = ty_cx.local_with_repr("z".to_string(), ty.clone());
+    let nzz = ty_cx.local_with_repr("n".to_string(), no_confusion_ty(&z, &z));
+    let minor_premise = Term::abstract_lambda(vec![z, nzz.clone()], nzz.to_term());
+
+    let h = ty_cx.local_with_repr("h".to_string(), equation(&ty, &v1, &v2));
+
+    let rec = eq.in_scope("rec".to_string()).unwrap().with_levels(vec![Level::Param(u.clone())]);
+
+    let body = Term::apply_all(rec.to_term(), vec![
+        ty.clone(),
+        motive,
+        minor_premise,
+        v1.to_term(),
+        v2.to_term(),
+        h.to_term(),
+        diagonal]);
+
+    let mut locals = params.clone();
+    locals.extend(vec![p.clone(), v1.clone(), v2.clone()].into_iter());
+
+    try!(ty_cx.declare_def(&Function {
+        name: data_type.name.in_scope("no_confusion".to_string()).unwrap(),
+        univ_params: univ_params,
+        args: vec![],
+        ty: Term::abstract_pi_implicit(
+            locals.clone(),
+            Term::abstract_pi(vec![h.clone()], no_confusion_ty(&v1, &v2))),
+        body: Term::abstract_lambda(
+            locals,
+            Term::abstract_lambda(vec![h], body)),
+        reduction: DeltaReduction::Reducible,
+        coercion: false,
+    }));
+
+    Ok(())
+}
+
+/// A local for each of the arguments of a constructor, with the types
+/// `args`.
+fn ctor_locals(ty_cx: &TyCtxt, repr: &str, args: &[Term]) -> Vec<Name> {
+    args.iter()
+        .enumerate()
+        .map(|(i, ty)| ty_cx.local_with_repr(format!("{}{}", repr, i), ty.clone()))
+        .collect()
+}
+
+/// Instantiate the leading binders of `ty` with `params`.
+fn with_params(params: &[Name], ty: Term) -> Term {
+    let mut ty = ty;
+
+    for param in params {
+        ty = match ty {
+            Term::Forall { term, .. } => term.instantiate(&param.to_term()),
+            t => panic!("type checking bug: {} takes too few parameters", t),
+        };
+    }
+
+    ty
+}
+
+/// Whether `ty` is `Type`, that is `Sort 1`.
+fn is_type(ty: &Term) -> bool {
+    match ty {
+        &Term::Sort { ref level } => level.normalize() == Level::one(),
+        _ => false,
+    }
+}
+
+/// Pick a name for the motive's universe that doesn't clash with the
+/// universe parameters of the inductive type.
+fn fresh_univ_param(univ_params: &[Name]) -> Name {
+    let mut repr = "l".to_string();
+
+    while univ_params.iter().any(|p| p == &Name::from_str(&repr)) {
+        repr.push('\'');
+    }
+
+    Name::from_str(&repr)
+}
@@ -0,0 +1,155 @@
+use super::{TyCtxt, Error, instantiate_solutions};
+use super::constraint::*;
+use super::solver::Solver;
+use super::super::core::*;
+
+use std::collections::HashMap;
+
+/// How many instances deep we look for the instances other instances need
+/// before giving up.
+pub const MAX_DEPTH: usize = 32;
+
+impl TyCtxt {
+    /// Find an instance of the class `goal`, either one of `locals` bound
+    /// by an instance-implicit binder, or a declared instance whose own
+    /// instance-implicit arguments can be found in turn.
+    ///
+    /// `locals` are the locals in scope, `goal` may mention them and so may
+    /// the instance we find.
+    pub fn resolve_instance(&mut self, locals: &[Name], goal: &Term) -> Result<Option<Term>, Error> {
+        debug!("resolve_instance: goal={}", goal);
+
+        let mut resolver = Resolver {
+            ty_cx: self,
+            locals: locals,
+            table: HashMap::new(),
+        };
+
+        resolver.resolve(goal, 0)
+    }
+}
+
+struct Resolver<'tcx> {
+    ty_cx: &'tcx mut TyCtxt,
+    locals: &'tcx [Name],
+    /// The answer for each goal we have looked at, a goal we are still
+    /// working on has no answer yet so an instance which (indirectly) needs
+    /// itself fails instead of looping.
+    table: HashMap<Term, Option<Term>>,
+}
+
+impl<'tcx> Resolver<'tcx> {
+    fn resolve(&mut self, goal: &Term, depth: usize) -> Result<Option<Term>, Error> {
+        if depth > MAX_DEPTH {
+            return Err(Error::InstanceDepth(goal.clone()));
+        }
+
+        if let Some(answer) = self.table.get(goal) {
+            return Ok(answer.clone());
+        }
+
+        self.table.insert(goal.clone(), None);
+
+        let answer = try!(self.search(goal, depth));
+
+        debug!("Resolver::resolve: goal={} answer={:?}", goal, answer);
+
+        self.table.insert(goal.clone(), answer.clone());
+
+        Ok(answer)
+    }
+
+    /// Try the instances in scope, innermost first, and then the declared
+    /// instances of the class, the most recently declared first.
+    fn search(&mut self, goal: &Term, depth: usize) -> Result<Option<Term>, Error> {
+        let class = match goal.uncurry().0 {
+            Term::Var { name } => name.without_levels(),
+            _ => return Ok(None),
+        };
+
+        let mut candidates = vec![];
+
+        for local in self.locals.iter().rev() {
+            if let &Name::Local { binding_info: BindingMode::InstImplicit, ref ty, .. } = local {
+                candidates.push((local.to_term(), (**ty).clone()));
+            }
+        }
+
+        if let Some(instances) = self.ty_cx.instances.get(&class) {
+            for instance in instances.iter().rev() {
+                let ty = try!(self.ty_cx.global_type(instance));
+                candidates.push((instance.to_term(), ty));
+            }
+        }
+
+        for (candidate, ty) in candidates {
+            if let Some(answer) = try!(self.try_candidate(goal, candidate, ty, depth)) {
+                return Ok(Some(answer));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Apply `candidate` of type `ty` to fresh meta-variables until its
+    /// type matches `goal`, the meta-variables standing for instances are
+    /// then resolved as goals of their own.
+    fn try_candidate(&mut self, goal: &Term, candidate: Term, ty: Term, depth: usize)
+                     -> Result<Option<Term>, Error> {
+        let mut ty = ty;
+        let mut args = vec![];
+        let mut subgoals = vec![];
+
+        while let Term::Forall { binder, term, .. } = ty {
+            let arg = self.meta_in_context(binder.ty.clone().into_term());
+
+            if binder.is_inst_implicit() {
+                subgoals.push((args.len(), binder.ty.into_term()));
+            }
+
+            ty = term.instantiate(&arg);
+            args.push(arg);
+        }
+
+        let just = Justification::Asserted(AssertedBy::ExpectedFound(ty.clone(), goal.clone()));
+
+        let solutions = match Solver::new(self.ty_cx, vec![Constraint::Unification(ty, goal.clone(), just)])
+                                     .and_then(|solver| solver.solve()) {
+            Ok((solutions, _)) => solutions,
+            Err(_) => return Ok(None),
+        };
+
+        for (i, subgoal) in subgoals {
+            let subgoal = instantiate_solutions(subgoal, &solutions);
+
+            // We don't guess at the arguments of a class, this includes
+            // those which depend on other instances.
+            if subgoal.has_metas() {
+                return Ok(None);
+            }
+
+            match try!(self.resolve(&subgoal, depth + 1)) {
+                None => return Ok(None),
+                Some(instance) => args[i] = instance,
+            }
+        }
+
+        let args = args.into_iter().map(|a| instantiate_solutions(a, &solutions)).collect();
+        let answer = Term::apply_all(candidate, args);
+
+        if answer.has_metas() {
+            Ok(None)
+        } else {
+            Ok(Some(answer))
+        }
+    }
+
+    /// A fresh meta-variable which may depend on the locals in scope.
+    fn meta_in_context(&self, ty: Term) -> Term {
+        let meta = self.ty_cx.fresh_meta(Term::abstract_pi(self.locals.to_vec(), ty));
+
+        Term::apply_all(
+            meta.to_term(),
+            self.locals.iter().map(Name::to_term).collect())
+    }
+}
@@ -1,39 +1,68 @@
+mod coercion;
 mod constraint;
 mod error;
 mod inductive;
+mod instance;
+mod nbe;
+mod nested;
 mod solver;
+mod universe;
 
 use core::{
     self, Name,
-    Term, Binder, Item, Definition, Data,
-    Module, Extern, BindingMode, DeltaReduction};
+    Term, TermRef, Binder, Item, Definition, Data,
+    Module, Extern, BindingMode, DeltaReduction, Level};
 use super::ast::{Span, HasSpan};
 use super::parser;
 use super::session::{HasSession, Session, Reportable};
 use super::elaborate::{self};
 pub use self::error::Error;
 use self::constraint::*;
-use self::solver::replace_metavars;
+use self::nbe::{Evaluator, Env};
+use self::solver::{replace_metavars, replace_metavars_with_err, replace_level_metas};
 use term::{stdout, StdoutTerminal};
 
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::path::{PathBuf, Path};
 
+/// Reduces an application of an eliminator whose major premise is in weak
+/// head normal form, giving back the application itself when it is stuck.
 pub type ComputationRule = Box<Fn(&TyCtxt, Term) -> Result<Term, Error>>;
 
+/// The solutions the solver found for meta-variables, along with why.
+pub type Solutions = HashMap<Name, (Term, Justification)>;
+
+/// Replace the meta-variables in `term` which have a solution, leaving the
+/// rest in place.
+pub fn instantiate_solutions(term: Term, solutions: &Solutions) -> Term {
+    replace_metavars_with_err(term, solutions, &mut vec![])
+}
+
 /// An axiom
 pub struct Axiom {
     pub ty: Term,
+    /// The universe parameters `ty` is polymorphic over.
+    pub univ_params: Vec<Name>,
     /// Adds a computation rule to the axiom
     pub computation_rule: Option<ComputationRule>,
+    /// The argument the computation rule inspects, the evaluator only
+    /// applies the rule once this argument has been supplied.
+    pub major_premise: Option<usize>,
 }
 
 impl Axiom {
     pub fn new(ty: Term) -> Axiom {
+        Axiom::with_univ_params(ty, vec![])
+    }
+
+    pub fn with_univ_params(ty: Term, univ_params: Vec<Name>) -> Axiom {
         Axiom {
             ty: ty,
+            univ_params: univ_params,
             computation_rule: None,
+            major_premise: None,
         }
     }
 }
@@ -45,8 +74,19 @@ pub struct TyCtxt {
     pub types: HashMap<Name, Data>,
     pub axioms: HashMap<Name, Axiom>,
     pub definitions: HashMap<Name, Definition>,
+    /// The instances of each class, in the order they were declared.
+    pub instances: HashMap<Name, Vec<Name>>,
+    /// The coercions from each type, keyed by the constant at the head of
+    /// the type, in the order they were declared.
+    pub coercions: HashMap<Name, Vec<Name>>,
+    /// The height of each definition, see `definition_height`.
+    heights: HashMap<Name, usize>,
+    /// The types we have already tried to give a `no_confusion`, see
+    /// `inductive::make_no_confusions`.
+    no_confusions: HashSet<Name>,
     pub session: Session,
-    local_counter: RefCell<usize>,
+    meta_counter: RefCell<usize>,
+    level_meta_counter: RefCell<usize>,
     pub terminal: Box<StdoutTerminal>,
 }
 
@@ -58,16 +98,31 @@ impl TyCtxt {
             types: HashMap::new(),
             axioms: HashMap::new(),
             definitions: HashMap::new(),
+            instances: HashMap::new(),
+            coercions: HashMap::new(),
+            heights: HashMap::new(),
+            no_confusions: HashSet::new(),
             session: Session::empty(),
-            local_counter: RefCell::new(0),
+            meta_counter: RefCell::new(0),
+            level_meta_counter: RefCell::new(0),
             terminal: stdout().unwrap(),
         }
     }
 
-    pub fn from_module(module: &Module, session: Session) -> Result<TyCtxt, Error> {
+    /// A context with the types every module may use, see
+    /// `inductive::declare_products`.
+    pub fn new(session: Session) -> Result<TyCtxt, Error> {
         let mut ty_cx = TyCtxt::empty();
         ty_cx.session = session;
 
+        try!(inductive::declare_products(&mut ty_cx));
+
+        Ok(ty_cx)
+    }
+
+    pub fn from_module(module: &Module, session: Session) -> Result<TyCtxt, Error> {
+        let mut ty_cx = try!(TyCtxt::new(session));
+
         try!(ty_cx.type_check_module(module));
 
         Ok(ty_cx)
@@ -85,7 +140,9 @@ impl TyCtxt {
         for def in &module.defs {
             match def {
                 &Item::Data(ref d) => try!(self.declare_datatype(d)),
+                &Item::Mutual(ref ds) => try!(self.declare_mutual(ds)),
                 &Item::Fn(ref f) => try!(self.declare_def(f)),
+                &Item::Instance(ref f) => try!(self.declare_instance(f)),
                 &Item::Extern(ref e) => self.declare_extern(e),
                 &Item::Axiom(ref ax) => self.declare_axiom(ax),
             }
@@ -98,7 +155,8 @@ impl TyCtxt {
     }
 
     pub fn in_scope(&self, name: &Name) -> bool {
-        self.axioms.contains_key(name) || self.definitions.contains_key(name)
+        let name = name.without_levels();
+        self.axioms.contains_key(&name) || self.definitions.contains_key(&name)
     }
 
     pub fn load_import(&mut self, name: &Name) -> Result<(), Error> {
@@ -125,9 +183,9 @@ impl TyCtxt {
             self.session.add_source_map_for(id, parser.source_map);
 
             // Construct a new elaboration context for this module.
-            let mut ecx = elaborate::ElabCx::from_module(
+            let mut ecx = try!(elaborate::ElabCx::from_module(
                 module,
-                self.session.clone());
+                self.session.clone()));
 
             // Elaborate the module
             let emodule =
@@ -155,11 +213,20 @@ impl TyCtxt {
             types,
             axioms,
             definitions,
+            instances,
+            coercions,
+            heights,
+            no_confusions,
             ..
         } = ty_cx;
 
         let mut errors = vec![];
 
+        // Every context starts out with the products, see `TyCtxt::new`.
+        let types = types.into_iter().filter(|&(ref n, _)| !inductive::is_product(n));
+        let axioms = axioms.into_iter().filter(|&(ref n, _)| !inductive::is_product(n));
+        let definitions = definitions.into_iter().filter(|&(ref n, _)| !inductive::is_product(n));
+
         for (n, ty) in types {
             if let Some(_) = self.types.insert(n.clone(), ty) {
                 errors.push(Error::NameExists(n))
@@ -179,10 +246,21 @@ impl TyCtxt {
             }
         }
 
+        for (class, insts) in instances {
+            self.instances.entry(class).or_insert(vec![]).extend(insts.into_iter());
+        }
+
+        for (source, coes) in coercions {
+            self.coercions.entry(source).or_insert(vec![]).extend(coes.into_iter());
+        }
+
+        self.heights.extend(heights.into_iter());
+        self.no_confusions.extend(no_confusions.into_iter());
+
         if errors.len() != 0 {
             Err(Error::Many(errors))
         } else {
-            Ok(())
+            inductive::make_no_confusions(self)
         }
     }
 
@@ -194,41 +272,198 @@ impl TyCtxt {
     }
 
     pub fn declare_datatype(&mut self, data_type: &Data) -> Result<(), Error> {
-        // Currently we use types/functions for metadata, do we need them?
-        self.types.insert(data_type.name.clone(), data_type.clone());
+        self.declare_mutual(&[data_type.clone()])
+    }
+
+    /// Declare a group of mutually inductive types, they are checked
+    /// together and share a recursor with a motive for each type.
+    ///
+    /// Occurrences of other inductive types nested in the constructors, like
+    /// `List (Rose A)`, are treated as further types in the group, see
+    /// `nested::Group`.
+    pub fn declare_mutual(&mut self, mutual: &[Data]) -> Result<(), Error> {
+        let group = try!(nested::nested_group(self, mutual));
+
+        // The module is checked again once it has been elaborated, while we
+        // check the group its auxiliary types must not unfold to the
+        // occurrences they were defined as the last time.
+        for def in &group.aux_defs {
+            self.definitions.remove(&def.name);
+        }
+
+        // The type is just a constant with the type `ty`, the arguments of
+        // the constructors may mention it, we need its type to find the
+        // universes they live in.
+        for data_type in &group.types {
+            self.axioms.insert(data_type.name.clone(),
+                               Axiom::with_univ_params(data_type.ty.clone(),
+                                                       data_type.univ_params.clone()));
+        }
+
+        try!(inductive::check_inductive(self, &group.types));
+
+        let names: Vec<_> = group.types.iter().map(|d| d.name.clone()).collect();
+
+        for (i, data_type) in group.types.iter().enumerate() {
+            // Currently we use types/functions for metadata, do we need them?
+            if i < group.num_declared {
+                let mut declared = mutual[i].clone();
 
-        // The type is just a constant with the type `ty`
-        self.axioms.insert(data_type.name.clone(), Axiom::new(data_type.ty.clone()));
+                if names.len() > 1 {
+                    declared.mutual = names.clone();
+                }
+
+                self.types.insert(data_type.name.clone(), declared);
+            }
 
-        // Each constructor also becomes a constant with type ascribed
-        // in its definition.
-        for ctor in &data_type.ctors {
-            let name = ctor.0.clone();
-            let ty = ctor.1.clone();
-            let axiom = Axiom::new(ty);
-            self.axioms.insert(name, axiom);
+            // Each constructor also becomes a constant, while we build the
+            // recursors they take the types with the nested occurrences
+            // replaced.
+            for ctor in &data_type.ctors {
+                let name = ctor.0.clone();
+                let ty = ctor.1.clone();
+                let axiom = Axiom::with_univ_params(ty, data_type.univ_params.clone());
+                self.axioms.insert(name, axiom);
+            }
         }
 
-        inductive::make_recursor(self, data_type)
+        try!(inductive::make_recursors(self, &group));
+
+        // The constructors now get the types ascribed in their definitions,
+        // which are equal to the ones above once the auxiliary types unfold
+        // to the occurrences they stand for.
+        for data_type in mutual {
+            for ctor in &data_type.ctors {
+                let axiom = Axiom::with_univ_params(ctor.1.clone(), data_type.univ_params.clone());
+                self.axioms.insert(ctor.0.clone(), axiom);
+            }
+        }
+
+        for def in &group.aux_defs {
+            self.axioms.remove(&def.name);
+            self.insert_definition(def.clone());
+        }
 
-        // let mut generated_definitions = vec![];
-        // try!(inductive::make_recursor(self, data_type, &mut generated_definitions))
-        //
-        // for def in generated_definitions {}
+        inductive::make_no_confusions(self)
     }
 
     pub fn declare_def(&mut self, def: &Definition) -> Result<(), Error> {
-        let (term, ty) = try!(self.type_check_term(&def.body, Some(def.ty.clone())));
+        let body = self.spell_out_levels(&def.body);
+        let ty = self.spell_out_levels(&def.ty);
+        let (term, ty) = try!(self.type_check_term(&body, Some(ty)));
 
         let mut def = def.clone();
         def.body = term;
         def.ty = ty;
 
-        self.definitions.insert(def.name.clone(), def);
+        let coercion = def.coercion;
+        let name = def.name.clone();
+        let ty = def.ty.clone();
+
+        self.insert_definition(def);
+
+        if coercion {
+            try!(self.declare_coercion(&name, &ty));
+        }
+
+        Ok(())
+    }
+
+    /// Declare the definition `name` of type `ty` as a coercion from the type
+    /// of its first explicit argument, the binders before it must be
+    /// implicit so the elaborator can fill them in.
+    fn declare_coercion(&mut self, name: &Name, ty: &Term) -> Result<(), Error> {
+        let mut fun_ty = ty.clone();
+        let mut source = None;
+
+        while let Term::Forall { binder, term, .. } = fun_ty {
+            if binder.is_explicit() {
+                source = Some(binder.ty.into_term());
+                break;
+            }
+
+            fun_ty = term.instantiate(&self.local(binder).to_term());
+        }
+
+        let source = match source.map(|s| s.uncurry().0) {
+            Some(Term::Var { name: ref head }) if !head.is_meta() && !head.is_local() => head.without_levels(),
+            _ => return Err(Error::NotACoercion(name.clone(), ty.clone())),
+        };
+
+        let coercions = self.coercions.entry(source).or_insert(vec![]);
+
+        // Declarations are checked again once the module is elaborated.
+        if !coercions.contains(name) {
+            coercions.push(name.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Declare `def` as an instance of the class its type returns, it will
+    /// be found by instance resolution from then on.
+    pub fn declare_instance(&mut self, def: &Definition) -> Result<(), Error> {
+        try!(self.declare_def(def));
+
+        let mut ty = def.ty.clone();
+
+        while let Term::Forall { binder, term, .. } = ty {
+            ty = term.instantiate(&self.local(binder).to_term());
+        }
+
+        let class = match ty.uncurry().0 {
+            Term::Var { ref name } if self.is_class(name) => name.without_levels(),
+            _ => return Err(Error::NotAClass(def.name.clone(), def.ty.clone())),
+        };
+
+        let instances = self.instances.entry(class).or_insert(vec![]);
+
+        // Declarations are checked again once the module is elaborated.
+        if !instances.contains(&def.name) {
+            instances.push(def.name.clone());
+        }
 
         Ok(())
     }
 
+    pub fn is_class(&self, name: &Name) -> bool {
+        self.types.get(&name.without_levels()).map(|d| d.class).unwrap_or(false)
+    }
+
+    /// The names of `Eq` and its constructor, if `Eq` is in scope and has
+    /// the shape its `no_confusion` lemmas and pattern matching rely on.
+    pub fn equality(&self) -> Option<(Name, Name)> {
+        inductive::equality(self)
+    }
+
+    fn insert_definition(&mut self, def: Definition) {
+        let height = self.definition_height(&def.body);
+        self.heights.insert(def.name.clone(), height);
+        self.definitions.insert(def.name.clone(), def);
+    }
+
+    /// The height of a definition is one more than the greatest height of
+    /// the definitions its body refers to, unfolding a definition can only
+    /// produce definitions of a smaller height.
+    fn definition_height(&self, body: &Term) -> usize {
+        fn max_height(heights: &HashMap<Name, usize>, term: &Term) -> usize {
+            match term {
+                &Term::Var { ref name } => heights.get(&name.without_levels()).cloned().unwrap_or(0),
+                &Term::App { ref fun, ref arg, .. } =>
+                    cmp::max(max_height(heights, fun), max_height(heights, arg)),
+                &Term::Forall { ref binder, term: ref body, .. } |
+                &Term::Lambda { ref binder, ref body, .. } =>
+                    cmp::max(max_height(heights, &binder.ty), max_height(heights, body)),
+                &Term::Let { ref binder, ref value, ref body, .. } =>
+                    cmp::max(max_height(heights, &binder.ty),
+                             cmp::max(max_height(heights, value), max_height(heights, body))),
+                &Term::Sort { .. } => 0,
+            }
+        }
+
+        1 + max_height(&self.heights, body)
+    }
+
     /// Declaring an external function creates an axiom in the type checker
     /// with the appropriate type.
     ///
@@ -247,7 +482,8 @@ impl TyCtxt {
     pub fn type_check_def(&mut self, def: &Item) -> Result<(), Error> {
         debug!("type_check_def: def={}", def);
         match def {
-            &Item::Fn(ref fun) => {
+            &Item::Fn(ref fun) |
+            &Item::Instance(ref fun) => {
                 let &Definition {
                     ref ty,
                     ref body, ..
@@ -261,9 +497,11 @@ impl TyCtxt {
     }
 
     pub fn lookup_global(&self, name: &Name) -> Result<&Term, Error> {
-        match self.definitions.get(name) {
+        let global = name.without_levels();
+
+        match self.definitions.get(&global) {
             None => {
-                match self.axioms.get(name) {
+                match self.axioms.get(&global) {
                     None => {
                         for (ref n, _) in &self.definitions {
                             println!("name: {}", n);
@@ -277,41 +515,109 @@ impl TyCtxt {
         }
     }
 
+    /// The universe parameters of a global, non-polymorphic and unknown
+    /// names have none.
+    pub fn univ_params_of(&self, name: &Name) -> &[Name] {
+        let global = name.without_levels();
+
+        match self.definitions.get(&global) {
+            Some(def) => &def.univ_params[..],
+            None => {
+                match self.axioms.get(&global) {
+                    Some(ax) => &ax.univ_params[..],
+                    None => &[],
+                }
+            }
+        }
+    }
+
+    /// Instantiate the universe parameters of the global `name` in `term`
+    /// with the levels carried by `name`, a reference with no levels is
+    /// instantiated with the parameters themselves.
+    fn instantiate_univ_params(&self, name: &Name, term: &Term) -> Term {
+        let term = self.spell_out_levels(term);
+        let levels = name.levels();
+
+        if levels.len() == 0 {
+            return term;
+        }
+
+        term.instantiate_univ_params(self.univ_params_of(name), levels)
+    }
+
+    /// A global mentioned without levels, as an inductive type is in its
+    /// own constructors and recursor, stands for its instantiation with its
+    /// own parameters. We spell these out so references to a global are
+    /// only equal when they are instantiated at the same levels.
+    pub fn spell_out_levels(&self, term: &Term) -> Term {
+        term.map_names(&|n| {
+            let params = self.univ_params_of(n);
+
+            if n.is_qual() && n.levels().len() == 0 && params.len() > 0 {
+                n.clone().with_levels(params.iter().cloned().map(Level::Param).collect())
+            } else {
+                n.clone()
+            }
+        })
+    }
+
+    /// Look up the type of a global instantiated at the levels `name`
+    /// carries.
+    pub fn global_type(&self, name: &Name) -> Result<Term, Error> {
+        let ty = try!(self.lookup_global(name));
+        Ok(self.instantiate_univ_params(name, ty))
+    }
+
     pub fn local(&self, binder: Binder) -> Name {
         let repr = match &binder.name {
             &Name::DeBruijn { ref repr, .. } => repr,
             _ => panic!("creating local {:?}", binder.name),
         };
 
-        self.local_with_repr_and_mode(repr.clone(), *binder.ty, binder.mode)
+        self.local_with_repr_and_mode(repr.clone(), binder.ty.into_term(), binder.mode)
     }
 
     pub fn local_with_repr(&self, repr: String, ty: Term) -> Name {
         let new_local = Name::Local {
-            number: *self.local_counter.borrow(),
-            ty: Box::new(ty),
+            number: self.session.next_local_number(),
+            ty: TermRef::new(ty),
             repr: repr.clone(),
             binding_info: BindingMode::Explicit,
         };
 
-        *self.local_counter.borrow_mut() += 1;
-
         new_local
     }
 
     pub fn local_with_repr_and_mode(&self, repr: String, ty: Term, mode: BindingMode) -> Name {
         let new_local = Name::Local {
-            number: *self.local_counter.borrow(),
-            ty: Box::new(ty),
+            number: self.session.next_local_number(),
+            ty: TermRef::new(ty),
             repr: repr.clone(),
             binding_info: mode,
         };
 
-        *self.local_counter.borrow_mut() += 1;
-
         new_local
     }
 
+    /// Create a fresh meta-variable of type `ty`.
+    pub fn fresh_meta(&self, ty: Term) -> Name {
+        let meta = Name::Meta {
+            number: *self.meta_counter.borrow(),
+            ty: TermRef::new(ty),
+        };
+
+        *self.meta_counter.borrow_mut() += 1;
+
+        meta
+    }
+
+    /// Create a fresh universe meta-variable.
+    pub fn fresh_level_meta(&self) -> Level {
+        let meta = Level::Meta(*self.level_meta_counter.borrow());
+        *self.level_meta_counter.borrow_mut() += 1;
+        meta
+    }
+
     /// Will try to unfold a name if it is unfoldable
     pub fn unfold_name(&self, n: &Name) -> Result<Term, Error> {
         use core::Name::*;
@@ -319,11 +625,11 @@ impl TyCtxt {
         match n {
             q @ &Qual { .. } => {
                 // TODO: also check axioms and report an error about unfolding axioms
-                // TODO: we actually need to know whether a name is Opaque or not
-                // Or we can't implement this
-                match self.definitions.get(q) {
+                // Irreducible definitions are opaque to the type checker.
+                match self.definitions.get(&q.without_levels()) {
                     None => Ok(n.to_term()), // panic!("failed to lookup name {}", q),
-                    Some(t) => Ok(t.body.clone()),
+                    Some(t) if t.reduction == DeltaReduction::Irreducible => Ok(n.to_term()),
+                    Some(t) => Ok(self.instantiate_univ_params(n, &t.body)),
                 }
             }
             &DeBruijn { .. } |
@@ -332,58 +638,61 @@ impl TyCtxt {
         }
     }
 
+    /// Unfold the occurrences of the definition `n` in `t`. A term is only
+    /// an occurrence when it is a reference to `n` which is convertible to
+    /// it without solving any constraints, the universe levels it is
+    /// instantiated with may only be equal under some of them.
     pub fn unfold(&self, mut t: Term, n: &Name) -> Result<Term, Error> {
         let def_rhs = try!(self.unfold_name(n));
         let nt = n.to_term();
 
-        t.replace_term(&def_rhs,
-                       &|term| self.def_eq(Span::dummy(), term, &nt).is_err());
-
-        Ok(t)
-    }
-
-    /// Checks whether a constructor's type is recursive
-    pub fn is_recursive_ctor(&self, ty_name: &Name, mut ctor_ty: &Term) -> bool {
-        let mut is_rec = false;
+        t.replace_term(&def_rhs, &|term| {
+            let refers_to_n = match (term, n) {
+                (&Term::Var { name: Name::Qual { components: ref components1, .. } },
+                 &Name::Qual { components: ref components2, .. }) => components1 == components2,
+                _ => false,
+            };
 
-        while let &Term::Forall { ref binder, ref term, .. } = ctor_ty {
-            match binder.ty.head() {
-                None => is_rec = is_rec || false,
-                Some(head) =>
-                    if head == ty_name.to_term() {
-                        return true;
-                    }
+            refers_to_n && match self.def_eq(Span::dummy(), term, &nt) {
+                Ok((_, constraints)) => constraints.len() == 0,
+                Err(_) => false,
             }
-            ctor_ty = term;
-        }
+        });
 
-        return is_rec;
+        Ok(t)
     }
 
+    /// Reduce `term` to weak head normal form, performing beta, iota, zeta
+    /// and delta reduction at the head.
     pub fn whnf(&self, term: &Term) -> CkResult {
         debug!("whnf: {}", term);
-        match term {
-            &Term::App { ref fun, ref arg, span } => {
-                let efun = try!(self.whnf(fun)).0;
-                // This is call by value
-                let earg = try!(self.whnf(arg)).0;
 
-                match efun {
-                    Term::Lambda { ref body, .. } => {
-                        self.whnf(&body.instantiate(&earg))
-                    }
-                    f => Ok((Term::App {
-                        fun: Box::new(f),
-                        arg: Box::new(earg),
-                        span: span,
-                    }, vec![]))
-                }
-            }
-            _ => Ok((term.clone(), vec![]))
-        }
+        let ev = Evaluator::new(self, false);
+        let value = try!(ev.whnf(try!(ev.eval(&Env::empty(), term))));
+
+        Ok((ev.quote_head(&value), vec![]))
     }
 
+    /// Evaluate `term`, unfolding every definition which isn't irreducible.
     pub fn eval(&self, term: &Term) -> Result<Term, Error> {
+        debug!("eval: {}", term);
+
+        let ev = Evaluator::new(self, true);
+        let value = try!(ev.eval(&Env::empty(), term));
+        let result = try!(ev.quote(0, &value));
+
+        debug!("eval: result={}", result);
+
+        Ok(result)
+    }
+
+    /// Evaluate `term` by substitution, this is how `eval` used to work and
+    /// we keep it around to benchmark against.
+    pub fn eval_by_substitution(&self, term: &Term) -> Result<Term, Error> {
+        self.reduce(term)
+    }
+
+    fn reduce(&self, term: &Term) -> Result<Term, Error> {
         use core::Term::*;
 
         debug!("eval: {}", term);
@@ -394,11 +703,11 @@ impl TyCtxt {
                 let span = app.get_span();
                 let (head, args) = app.uncurry();
 
-                let efun = try!(self.eval(&head));
+                let efun = try!(self.reduce(&head));
 
                 let mut eargs = vec![];
                 for arg in args {
-                    eargs.push(try!(self.eval(&arg)));
+                    eargs.push(try!(self.reduce(&arg)));
                 }
 
                 match efun {
@@ -412,12 +721,20 @@ impl TyCtxt {
                                 _ => panic!("evaluation error")
                             }
                         }
-                        Ok(try!(self.eval(&lambda)))
+                        Ok(try!(self.reduce(&lambda)))
                     }
                     Term::Var { ref name } => {
                         if let Some(comp_rule) = self.computation_rule(name) {
                             let t = Term::apply_all(Term::Var { name: name.clone() }, eargs);
-                            comp_rule(self, t)
+                            let reduced = try!(comp_rule(self, t.clone()));
+
+                            // Computation rules only reduce to weak head
+                            // normal form, so keep evaluating the result.
+                            if reduced == t {
+                                Ok(t)
+                            } else {
+                                self.reduce(&reduced)
+                            }
                         } else {
                             let mut t = Term::apply_all(Term::Var { name: name.clone() }, eargs);
                             t.set_span(span);
@@ -428,27 +745,37 @@ impl TyCtxt {
                 }
             }
             &Term::Forall { ref binder, ref term, span } => {
-                let ety = try!(self.eval(&*binder.ty));
-                let eterm = try!(self.eval(term));
+                let ety = try!(self.reduce(&*binder.ty));
+                let eterm = try!(self.reduce(term));
 
                 Ok(Forall {
                     binder: Binder::with_mode(binder.name.clone(), ety, binder.mode.clone()),
-                    term: Box::new(eterm),
+                    term: TermRef::new(eterm),
                     span: span,
                 })
             }
             &Term::Lambda { ref binder, ref body, span } => {
-                let ety = try!(self.eval(&*binder.ty));
-                let eterm = try!(self.eval(body));
+                let ety = try!(self.reduce(&*binder.ty));
+                let eterm = try!(self.reduce(body));
 
                 Ok(Lambda {
                     binder: Binder::with_mode(binder.name.clone(), ety, binder.mode.clone()),
-                    body: Box::new(eterm),
+                    body: TermRef::new(eterm),
                     span: span,
                 })
             }
-            &Term::Var { ref name } => self.unfold_name(name),
-            &Term::Type => Ok(Term::Type)
+            &Term::Let { ref value, ref body, .. } =>
+                self.reduce(&body.instantiate(value)),
+            &Term::Var { ref name } => {
+                let unfolded = try!(self.unfold_name(name));
+
+                if &unfolded == term {
+                    Ok(unfolded)
+                } else {
+                    self.reduce(&unfolded)
+                }
+            }
+            &Term::Sort { ref level } => Ok(Term::sort(level.normalize())),
         };
 
         let result = try!(result);
@@ -458,11 +785,13 @@ impl TyCtxt {
         Ok(result)
     }
 
-    /// Check whether a term is beta/iota reducible.
+    /// Check whether a term is beta/iota/zeta reducible.
     pub fn is_bi_reducible(&self, term: &Term) -> bool {
         debug!("is_bi_reducible: term={}", term);
         let (head, args) = term.uncurry();
-        if args.len() > 0 {
+        if let Term::Let { .. } = head {
+            true
+        } else if args.len() > 0 {
             !head.is_meta() && !head.is_constant() && term.is_app()
         } else {
             false
@@ -470,7 +799,7 @@ impl TyCtxt {
     }
 
     pub fn computation_rule(&self, name: &Name) -> Option<&ComputationRule> {
-        self.axioms.get(name).and_then(|x| x.computation_rule.as_ref())
+        self.axioms.get(&name.without_levels()).and_then(|x| x.computation_rule.as_ref())
     }
 
     // TODO: currently this reports that two terms are not equal, we should probably
@@ -478,75 +807,189 @@ impl TyCtxt {
     // U, is not equal to specified type W.
     pub fn def_eq(&self, span: Span, t: &Term, u: &Term) -> CkResult {
         debug!("def_eq: {} {}", t, u);
-        let t = try!(self.eval(t));
-        let u = try!(self.eval(u));
 
+        let ev = Evaluator::new(self, false);
         let mut constraints = vec![];
 
-        let is_def_eq = def_eq_modulo(
-            &t,
-            &u,
-            &mut constraints);
+        let t_value = try!(ev.eval(&Env::empty(), t));
+        let u_value = try!(ev.eval(&Env::empty(), u));
 
-        if is_def_eq {
+        if try!(ev.is_def_eq(t_value, u_value, &mut constraints)) {
             Ok((t.clone(), constraints))
         } else {
-            // Should
             Err(Error::DefUnequal(span, t.clone(), u.clone(), vec![]))
         }
     }
 
-    pub fn type_check_term(&mut self,
-                           term: &Term,
-                           expected_ty: Option<Term>) -> Result<(Term, Term), Error> {
-        debug!("type_check_term: term={}", term);
+    /// The height of `name` if it is a definition which can be unfolded.
+    fn delta_height(&self, name: &Name) -> Option<usize> {
+        let global = name.without_levels();
 
-        let (infer_ty, mut infer_cs) = try!(self.type_infer_term(term));
+        match self.definitions.get(&global) {
+            Some(def) if def.reduction != DeltaReduction::Irreducible =>
+                self.heights.get(&global).cloned(),
+            _ => None,
+        }
+    }
 
-        match &expected_ty {
-            &None => {}
-            &Some(ref ty) => {
-                let just =
-                    Justification::Asserted(
-                        AssertedBy::ExpectedFound(
-                            infer_ty.clone(),
-                            ty.clone()));
+    /// The structure `ctor` is the constructor of, if any.
+    pub fn structure_of_ctor(&self, ctor: &Name) -> Option<&Data> {
+        self.types.values().find(|d| {
+            d.fields.is_some() && d.ctors.len() == 1 && &d.ctors[0].0 == ctor
+        })
+    }
 
-                debug!("inferred {} expected {}", infer_ty, ty);
+    /// Eta for structures, `t` is equal to `MkT ps as` when each of its
+    /// projections is equal to the corresponding field in `as`. Returns
+    /// the pairs of projections and fields when `u` is a fully applied
+    /// structure constructor.
+    pub fn structure_eta(&self, t: &Term, u: &Term) -> Option<Vec<(Term, Term)>> {
+        let (head, args) = u.uncurry();
 
-                infer_cs.push(
-                    Constraint::Unification(
-                        infer_ty.clone(),
-                        ty.clone(),
-                        just));
-            }
+        let ctor = match head {
+            Term::Var { name } => name,
+            _ => return None,
+        };
+
+        let data_type = match self.structure_of_ctor(&ctor) {
+            None => return None,
+            Some(data_type) => data_type,
+        };
+
+        let fields = data_type.fields.as_ref().unwrap();
+        let num_params = data_type.parameters.len();
+
+        if args.len() != num_params + fields.len() {
+            return None;
         }
 
-        let solver = try!(solver::Solver::new(self, infer_cs));
+        let pairs = fields.iter().zip(args[num_params..].iter()).map(|(field, arg)| {
+            let projection = data_type.projection(field).with_levels(ctor.levels().to_vec());
 
-        let solutions = try!(solver.solve());
+            let mut projection_args = args[..num_params].to_vec();
+            projection_args.push(t.clone());
 
-        for (meta, sol) in &solutions {
-            debug!("solutions: meta={} {}", meta, sol.0);
+            (Term::apply_all(projection.to_term(), projection_args), arg.clone())
+        });
+
+        Some(pairs.collect())
+    }
+
+    /// Checks whether `ty` is a proposition, i.e. its type is `Prop`.
+    pub fn is_proposition(&self, ty: &Term) -> bool {
+        if ty.has_loose_bvars() {
+            return false;
+        }
+
+        match self.type_infer_term(ty).and_then(|(sort, _)| self.whnf(&sort)) {
+            Ok((sort, _)) => sort.is_prop(),
+            Err(_) => false,
         }
+    }
+
+    /// If `term` is a proof, returns the proposition it proves.
+    pub fn proof_of(&self, term: &Term) -> Option<Term> {
+        if term.has_loose_bvars() {
+            return None;
+        }
+
+        match self.type_infer_term(term) {
+            Ok((ty, _)) => if self.is_proposition(&ty) { Some(ty) } else { None },
+            Err(_) => None,
+        }
+    }
+
+    pub fn type_check_term(&mut self,
+                           term: &Term,
+                           expected_ty: Option<Term>) -> Result<(Term, Term), Error> {
+        debug!("type_check_term: term={}", term);
+
+        let (infer_ty, solutions, levels) =
+            try!(self.solve_term(term, expected_ty.as_ref()));
 
         // Finally use the solutions given to us by the solver or
         // throw an error if there is not a solution for a meta-var
         // occurring in them
         let new_term = try!(replace_metavars(term.clone(), &solutions));
+        let new_term = replace_level_metas(new_term, &levels);
 
         debug!("term={}\nnew_term={}", term, new_term);
 
         let infer_ty = try!(replace_metavars(infer_ty.clone(), &solutions));
+        let infer_ty = replace_level_metas(infer_ty, &levels);
+
+        let ty = match expected_ty {
+            None => infer_ty,
+            Some(ty) => replace_level_metas(ty, &levels),
+        };
 
-        Ok((new_term, expected_ty.unwrap_or(infer_ty)))
+        Ok((new_term, ty))
     }
 
-    pub fn ensure_sort(&self, term: Term) -> CkResult {
-        if term.is_sort() {
-            return Ok(constrain(term, vec![]));
-        } else {
-            panic!("ensure sort {}", term);
+    /// Infer the type of `term`, checking it against `expected_ty` if there
+    /// is one, and solve the resulting constraints. Unlike `type_check_term`
+    /// the meta-variables in `term` do not all need to be solved, so the
+    /// elaborator can use this to learn about a partially elaborated term.
+    pub fn solve_term(&mut self,
+                      term: &Term,
+                      expected_ty: Option<&Term>)
+                      -> Result<(Term, Solutions, HashMap<usize, Level>), Error> {
+        let (ty, constraints) = match expected_ty {
+            None => try!(self.type_infer_term(term)),
+            Some(ty) => {
+                // Checking the expected type solves the levels of any
+                // polymorphic globals it mentions.
+                let (sort, mut constraints) = try!(self.type_infer_term(ty));
+                let (_, sort_cs) = try!(self.ensure_sort(sort));
+
+                constraints.extend(sort_cs.into_iter());
+                constraints.extend(try!(self.check(term, ty)).into_iter());
+
+                (ty.clone(), constraints)
+            }
+        };
+
+        let solver = try!(solver::Solver::new(self, constraints));
+
+        let (solutions, levels) = try!(solver.solve());
+
+        for (meta, sol) in &solutions {
+            debug!("solutions: meta={} {}", meta, sol.0);
+        }
+
+        Ok((ty, solutions, levels))
+    }
+
+    /// Ensure that `term` is a sort, returning its level.
+    pub fn ensure_sort(&self, term: Term) -> Result<(Level, ConstraintSeq), Error> {
+        if let &Term::Sort { ref level } = &term {
+            return Ok(constrain(level.clone(), vec![]));
+        }
+
+        let (tp, mut cs) = try!(self.whnf(&term));
+
+        match tp {
+            Term::Sort { level } => Ok(constrain(level, cs)),
+            tp => if tp.is_stuck().is_some() {
+                // We don't know the sort yet, so we introduce a fresh universe
+                // meta-variable and ask the solver to figure it out.
+                let sort = Term::sort(self.fresh_level_meta());
+
+                let just =
+                    Justification::Asserted(
+                        AssertedBy::ExpectedFound(
+                            tp.clone(),
+                            sort.clone()));
+
+                cs.push(Constraint::Unification(tp, sort.clone(), just));
+
+                match sort {
+                    Term::Sort { level } => Ok(constrain(level, cs)),
+                    _ => unreachable!(),
+                }
+            } else {
+                Err(Error::ExpectedSort(term.get_span(), term))
+            }
         }
     }
 
@@ -560,25 +1003,25 @@ impl TyCtxt {
         if tp.is_forall() {
             Ok((tp, cs))
         } else if let Some(_) = tp.is_stuck() {
-            panic!()
+            Err(Error::UnknownFunctionType(sp, tp))
         } else {
             Err(Error::ExpectedFunction(sp, term))
         }
     }
 
-    pub fn type_infer_term(&mut self, term: &Term) -> CkResult {
+    pub fn type_infer_term(&self, term: &Term) -> CkResult {
         debug!("type_infer_term: term={}", term);
         let result = match term {
             &Term::Var { ref name, .. } => {
                 match name {
                     &Name::Local { ref ty, .. } =>
-                        Ok(constrain(*ty.clone(), vec![])),
+                        Ok(constrain((**ty).clone(), vec![])),
                     q @ &Name::Qual { .. } => {
-                        let global_ty = try!(self.lookup_global(q).map(Clone::clone));
+                        let global_ty = try!(self.global_type(q));
                         Ok(constrain(global_ty, vec![]))
                     }
                     &Name::Meta { ref ty, .. } => {
-                        Ok(constrain(*ty.clone(), vec![]))
+                        Ok(constrain((**ty).clone(), vec![]))
                     }
                     _ => {
                         panic!("internal error: all variable occurences must be free when type \
@@ -620,7 +1063,7 @@ impl TyCtxt {
                         constraints.push(
                             Constraint::Unification(
                                 arg_ty,
-                                *binder.ty,
+                                binder.ty.into_term(),
                                 just));
 
                         // TODO: add type checking obliation here
@@ -628,10 +1071,10 @@ impl TyCtxt {
                     }
                     t => Err(Error::ApplicationMismatch(
                         span,
-                        *fun.clone(),
-                        *arg.clone(),
+                        (**fun).clone(),
+                        (**arg).clone(),
                         t,
-                        Term::Type))
+                        Term::sort(Level::one())))
                 }
             }
             &Term::Forall { ref binder, ref term, .. } => {
@@ -642,17 +1085,21 @@ impl TyCtxt {
                 let term = term.instantiate(&local.to_term());
 
                 let (sort, ty_cs) = try!(self.type_infer_term(&*ty));
-                let (_, sort_cs) = try!(self.ensure_sort(sort));
-                
+                let (arg_level, sort_cs) = try!(self.ensure_sort(sort));
+
                 constraints.extend(ty_cs.into_iter());
                 constraints.extend(sort_cs.into_iter());
 
                 let (sort, ty_cs) = try!(self.type_infer_term(&term));
-                let (_, sort_cs) = try!(self.ensure_sort(sort));
+                let (body_level, sort_cs) = try!(self.ensure_sort(sort));
                 constraints.extend(ty_cs.into_iter());
                 constraints.extend(sort_cs.into_iter());
 
-                Ok(constrain(Term::Type, constraints))
+                // The level of a function type is the `imax` of its domain and
+                // codomain, this ensures that `Sort 0` is impredicative.
+                let level = Level::imax(arg_level, body_level).normalize();
+
+                Ok(constrain(Term::sort(level), constraints))
             }
             &Term::Lambda { ref binder, ref body, span, } => {
                 let ty = &binder.ty;
@@ -676,13 +1123,46 @@ impl TyCtxt {
                 let forall = Term::Forall {
                     span: span,
                     binder: binder.clone(),
-                    term: Box::new(pi_body.abstr(&local)),
+                    term: TermRef::new(pi_body.abstr(&local)),
                 };
 
                 Ok(constrain(forall, constraints))
             }
-            &Term::Type =>
-                Ok(constrain(Term::Type, vec![])),
+            &Term::Let { ref binder, ref value, ref body, .. } => {
+                let ty = &binder.ty;
+
+                let mut constraints = vec![];
+
+                let (sort, ty_cs) = try!(self.type_infer_term(ty));
+                let (_, sort_cs) = try!(self.ensure_sort(sort));
+
+                constraints.extend(ty_cs.into_iter());
+                constraints.extend(sort_cs.into_iter());
+
+                // The value must have the declared type, the body is then
+                // checked with the value substituted for the bound variable.
+                let (value_ty, value_cs) = try!(self.type_infer_term(value));
+                constraints.extend(value_cs.into_iter());
+
+                let just =
+                    Justification::Asserted(
+                        AssertedBy::ExpectedFound(
+                            value_ty.clone(),
+                            (**ty).clone()));
+
+                constraints.push(
+                    Constraint::Unification(
+                        value_ty,
+                        (**ty).clone(),
+                        just));
+
+                let (body_ty, body_cs) = try!(self.type_infer_term(&body.instantiate(value)));
+                constraints.extend(body_cs.into_iter());
+
+                Ok(constrain(body_ty, constraints))
+            }
+            &Term::Sort { ref level } =>
+                Ok(constrain(Term::sort(level.clone().succ()), vec![])),
         };
 
         let (t, cs) = try!(result);
@@ -690,50 +1170,143 @@ impl TyCtxt {
         Ok((t, cs))
     }
 
-    pub fn evaluate(&self, term: &Term) -> Term {
-        term.clone()
+    /// Check that `term` has type `expected_ty`, returning the constraints
+    /// which must hold for it to. Unlike inferring the type and comparing it
+    /// afterwards, the expected type is pushed into `fun`s, `let`s and the
+    /// arguments of applications, so the types of placeholder binders come
+    /// from it and a mismatch is reported at the subterm responsible.
+    pub fn check(&self, term: &Term, expected_ty: &Term) -> Result<ConstraintSeq, Error> {
+        self.check_at(term, expected_ty, term.get_span())
     }
-}
 
-fn def_eq_modulo(
-    t1: &Term,
-    t2: &Term,
-    constraints: &mut ConstraintSeq) -> bool {
-    use core::Term::*;
-
-    debug!("equal_modulo: {} == {}", t1, t2);
-
-    match (t1, t2) {
-        (&App { fun: ref fun1, arg: ref arg1, .. },
-         &App { fun: ref fun2, arg: ref arg2, .. }) => {
-            def_eq_modulo(fun1, fun2, constraints) &&
-            def_eq_modulo(arg1, arg2, constraints)
-        }
-        (&Forall { binder: ref binder1, term: ref term1, .. },
-         &Forall { binder: ref binder2, term: ref term2, .. }) => {
-            def_eq_modulo(&*binder1.ty, &*binder2.ty, constraints) &&
-            def_eq_modulo(term1, term2, constraints)
-        }
-        (&Lambda { binder: ref binder1, body: ref body1, .. },
-         &Lambda { binder: ref binder2, body: ref body2, ..}) => {
-            def_eq_modulo(&*binder1.ty, &*binder2.ty, constraints) &&
-            def_eq_modulo(body1, body2, constraints)
-        }
-        (&Var { name: ref name1 }, &Var { name: ref name2 }) => {
-            def_eq_name_modulo(name1, name2)
-        }
-        (t, u) => {
-            if t.is_stuck().is_some() || u.is_stuck().is_some() {
-                // let constraint = Constraint::Unification(
-                //     t.clone(),
-                //     u.clone(), Justification::Asserted);
-                // constraints.push(constraint);
-                true
-            } else {
-                t == u
+    /// `span` is where to report a mismatch when `term` has no span of its
+    /// own.
+    fn check_at(&self, term: &Term, expected_ty: &Term, span: Span) -> Result<ConstraintSeq, Error> {
+        debug!("check: term={} expected_ty={}", term, expected_ty);
+
+        let span = span_or(term, span);
+
+        match term {
+            &Term::Lambda { ref binder, ref body, .. } => {
+                let expected_ty = if expected_ty.is_forall() {
+                    expected_ty.clone()
+                } else {
+                    try!(self.whnf(expected_ty)).0
+                };
+
+                let (expected_binder, codomain) = match expected_ty {
+                    Term::Forall { binder, term, .. } => (binder, term),
+                    _ => return self.infer_and_compare(term, &expected_ty, span),
+                };
+
+                let mut constraints = vec![];
+
+                let (arg_ty, arg_cs) = try!(self.type_infer_term(&binder.ty));
+                let (_, sort_cs) = try!(self.ensure_sort(arg_ty));
+
+                constraints.extend(arg_cs.into_iter());
+                constraints.extend(sort_cs.into_iter());
+
+                let just =
+                    Justification::Asserted(
+                        AssertedBy::Checked(
+                            span_or(&binder.ty, span),
+                            (*binder.ty).clone(),
+                            (*expected_binder.ty).clone()));
+
+                constraints.push(
+                    Constraint::Unification(
+                        (*binder.ty).clone(),
+                        (*expected_binder.ty).clone(),
+                        just));
+
+                // A placeholder binder is given the type we expected.
+                let local = if binder.ty.is_stuck().is_some() {
+                    self.local(Binder { ty: expected_binder.ty.clone(), ..binder.clone() })
+                } else {
+                    self.local(binder.clone())
+                };
+
+                let body = body.instantiate(&local.to_term());
+                let codomain = codomain.instantiate(&local.to_term());
+
+                constraints.extend(try!(self.check_at(&body, &codomain, span)).into_iter());
+
+                Ok(constraints)
             }
+            &Term::Let { ref binder, ref value, ref body, .. } => {
+                let ty = &binder.ty;
+
+                let mut constraints = vec![];
+
+                let (sort, ty_cs) = try!(self.type_infer_term(ty));
+                let (_, sort_cs) = try!(self.ensure_sort(sort));
+
+                constraints.extend(ty_cs.into_iter());
+                constraints.extend(sort_cs.into_iter());
+                constraints.extend(try!(self.check_at(value, ty, span)).into_iter());
+
+                let body = body.instantiate(value);
+                constraints.extend(try!(self.check_at(&body, expected_ty, span)).into_iter());
+
+                Ok(constraints)
+            }
+            &Term::App { .. } => {
+                let (head, args) = term.uncurry();
+
+                let (mut fun_ty, mut constraints) = try!(self.type_infer_term(&head));
+
+                // Each argument is checked against the type of the binder it
+                // is applied to.
+                for arg in &args {
+                    if !fun_ty.is_forall() {
+                        fun_ty = try!(self.whnf(&fun_ty)).0;
+                    }
+
+                    fun_ty = match fun_ty {
+                        Term::Forall { binder, term: codomain, .. } => {
+                            constraints.extend(try!(self.check_at(arg, &binder.ty, span)).into_iter());
+                            codomain.instantiate(arg)
+                        }
+                        // Leave it to inference to work out what the head is.
+                        _ => return self.infer_and_compare(term, expected_ty, span),
+                    };
+                }
+
+                let just =
+                    Justification::Asserted(
+                        AssertedBy::Checked(span, fun_ty.clone(), expected_ty.clone()));
+
+                constraints.push(Constraint::Unification(fun_ty, expected_ty.clone(), just));
+
+                Ok(constraints)
+            }
+            _ => self.infer_and_compare(term, expected_ty, span),
         }
     }
+
+    fn infer_and_compare(&self, term: &Term, expected_ty: &Term, span: Span) -> Result<ConstraintSeq, Error> {
+        let (infer_ty, mut constraints) = try!(self.type_infer_term(term));
+
+        let just =
+            Justification::Asserted(
+                AssertedBy::Checked(span, infer_ty.clone(), expected_ty.clone()));
+
+        constraints.push(Constraint::Unification(infer_ty, expected_ty.clone(), just));
+
+        Ok(constraints)
+    }
+
+    pub fn evaluate(&self, term: &Term) -> Term {
+        term.clone()
+    }
+}
+
+/// The span of `term`, or `span` if it does not have one.
+fn span_or(term: &Term, span: Span) -> Span {
+    let term_span = term.get_span();
+
+    if term_span.lo == term_span.hi { span } else { term_span }
 }
 
 fn def_eq_name_modulo(n1: &Name, n2: &Name) -> bool {
@@ -750,10 +1323,17 @@ fn def_eq_name_modulo(n1: &Name, n2: &Name) -> bool {
         (_, &Name::Meta { .. }) => {
             false
         }
-        _ => n1 == n2
+        _ => n1.same_variable(n2) && def_eq_levels(n1.levels(), n2.levels())
     }
 }
 
+/// Two references to the same global are only equal if they are
+/// instantiated at the same levels.
+fn def_eq_levels(ls1: &[Level], ls2: &[Level]) -> bool {
+    ls1.len() == ls2.len() &&
+    ls1.iter().zip(ls2.iter()).all(|(l1, l2)| l1.is_equiv(l2))
+}
+
 fn name_to_path(name: &Name) -> Option<PathBuf> {
     match name {
         &Name::Qual { ref components, .. } => {
@@ -776,6 +1356,6 @@ fn name_to_path(name: &Name) -> Option<PathBuf> {
 
 #[test]
 fn test_is_bi_reducible() {
-    let ty_cx = TyCtxt::new();
+    let ty_cx = TyCtxt::new(Session::empty());
     panic!()
 }
@@ -0,0 +1,748 @@
+//! Normalization by evaluation.
+//!
+//! Terms are evaluated to `Value`s in an environment holding the value of
+//! each bound variable. A binder evaluates to a closure over its
+//! environment, so instantiating it extends the environment rather than
+//! substituting into the body, and arguments are only evaluated once they
+//! are needed. Values are read back into terms in normal form by `quote`,
+//! or only as far as they have been evaluated by `quote_head`.
+//!
+//! The conversion checker behind `TyCtxt::def_eq` works on values too, see
+//! `Evaluator::is_def_eq`.
+
+use ast::Span;
+use core::{Name, Term, TermRef, Binder, BindingMode, Level};
+use super::{TyCtxt, Error, def_eq_name_modulo};
+use super::constraint::*;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A term evaluated to weak head normal form, leaving the definitions at
+/// the head folded if the evaluator doesn't unfold them.
+#[derive(Clone)]
+pub enum Value {
+    /// A variable or constant applied to arguments, which can't reduce any
+    /// further.
+    Neutral(Head, Vec<Thunk>),
+    Lambda(Name, BindingMode, Thunk, Closure),
+    Forall(Name, BindingMode, Thunk, Closure),
+    Sort(Level),
+}
+
+#[derive(Clone)]
+pub enum Head {
+    /// A global, local or meta-variable.
+    Name(Name),
+    /// A variable bound while reading back a closure, as a de Bruijn level,
+    /// along with the name of its binder.
+    Bound(usize, Name),
+}
+
+/// The body of a binder along with the environment it was evaluated in.
+#[derive(Clone)]
+pub struct Closure {
+    env: Env,
+    body: TermRef,
+}
+
+/// A value which is computed the first time it is needed.
+#[derive(Clone)]
+pub struct Thunk(Rc<RefCell<ThunkState>>);
+
+enum ThunkState {
+    Delayed(Env, TermRef),
+    Forced(Value),
+}
+
+impl Thunk {
+    fn delayed(env: Env, term: TermRef) -> Thunk {
+        Thunk(Rc::new(RefCell::new(ThunkState::Delayed(env, term))))
+    }
+
+    fn forced(value: Value) -> Thunk {
+        Thunk(Rc::new(RefCell::new(ThunkState::Forced(value))))
+    }
+
+    pub fn force(&self, ev: &Evaluator) -> Result<Value, Error> {
+        let (env, term) = match *self.0.borrow() {
+            ThunkState::Forced(ref value) => return Ok(value.clone()),
+            ThunkState::Delayed(ref env, ref term) => (env.clone(), term.clone()),
+        };
+
+        let value = try!(ev.eval(&env, &*term));
+        *self.0.borrow_mut() = ThunkState::Forced(value.clone());
+
+        Ok(value)
+    }
+}
+
+/// The values of the bound variables, innermost first.
+#[derive(Clone)]
+pub struct Env(Option<Rc<(Thunk, Env)>>);
+
+impl Env {
+    pub fn empty() -> Env {
+        Env(None)
+    }
+
+    fn extend(&self, thunk: Thunk) -> Env {
+        Env(Some(Rc::new((thunk, self.clone()))))
+    }
+
+    fn lookup(&self, index: usize) -> &Thunk {
+        let mut env = self;
+        let mut index = index;
+
+        while let Some(ref node) = env.0 {
+            if index == 0 {
+                return &node.0;
+            }
+
+            index -= 1;
+            env = &node.1;
+        }
+
+        panic!("type checking bug: unbound de Bruijn index")
+    }
+}
+
+pub struct Evaluator<'tcx> {
+    ty_cx: &'tcx TyCtxt,
+    /// Whether definitions are unfolded as they are evaluated, otherwise
+    /// they are left for the conversion checker to unfold lazily.
+    delta: bool,
+}
+
+impl<'tcx> Evaluator<'tcx> {
+    pub fn new(ty_cx: &'tcx TyCtxt, delta: bool) -> Evaluator<'tcx> {
+        Evaluator {
+            ty_cx: ty_cx,
+            delta: delta,
+        }
+    }
+
+    pub fn eval(&self, env: &Env, term: &Term) -> Result<Value, Error> {
+        match term {
+            &Term::Var { ref name } => self.eval_name(env, name),
+            &Term::App { .. } => {
+                let mut head = term;
+                let mut args = vec![];
+
+                while let &Term::App { ref fun, ref arg, .. } = head {
+                    args.push(self.thunk(env, arg));
+                    head = &**fun;
+                }
+
+                args.reverse();
+
+                let fun = try!(self.eval(env, head));
+                self.apply_all(fun, args)
+            }
+            &Term::Forall { ref binder, ref term, .. } => {
+                Ok(Value::Forall(
+                    binder.name.clone(),
+                    binder.mode.clone(),
+                    self.thunk(env, &binder.ty),
+                    Closure { env: env.clone(), body: term.clone() }))
+            }
+            &Term::Lambda { ref binder, ref body, .. } => {
+                Ok(Value::Lambda(
+                    binder.name.clone(),
+                    binder.mode.clone(),
+                    self.thunk(env, &binder.ty),
+                    Closure { env: env.clone(), body: body.clone() }))
+            }
+            &Term::Let { ref value, ref body, .. } => {
+                let value = self.thunk(env, value);
+                self.eval(&env.extend(value), body)
+            }
+            &Term::Sort { ref level } => Ok(Value::Sort(level.clone())),
+        }
+    }
+
+    fn eval_name(&self, env: &Env, name: &Name) -> Result<Value, Error> {
+        match name {
+            &Name::DeBruijn { index, .. } => env.lookup(index).force(self),
+            &Name::Qual { .. } if self.delta && self.ty_cx.delta_height(name).is_some() => {
+                let unfolded = try!(self.ty_cx.unfold_name(name));
+                self.eval(&Env::empty(), &unfolded)
+            }
+            _ => Ok(Value::Neutral(Head::Name(name.clone()), vec![])),
+        }
+    }
+
+    /// Delay evaluating `term`, bound variables are looked up straight away
+    /// instead of allocating a thunk for them.
+    fn thunk(&self, env: &Env, term: &TermRef) -> Thunk {
+        match **term {
+            Term::Var { name: Name::DeBruijn { index, .. } } => env.lookup(index).clone(),
+            Term::Sort { ref level } => Thunk::forced(Value::Sort(level.clone())),
+            _ => Thunk::delayed(env.clone(), term.clone()),
+        }
+    }
+
+    pub fn instantiate(&self, closure: &Closure, arg: Thunk) -> Result<Value, Error> {
+        self.eval(&closure.env.extend(arg), &closure.body)
+    }
+
+    pub fn apply_all(&self, fun: Value, args: Vec<Thunk>) -> Result<Value, Error> {
+        let mut fun = fun;
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            fun = match fun {
+                Value::Lambda(_, _, _, closure) => try!(self.instantiate(&closure, arg)),
+                Value::Neutral(head, mut spine) => {
+                    spine.push(arg);
+                    spine.extend(args.by_ref());
+                    return self.iota(head, spine);
+                }
+                other => return Err(Error::NotAFunction(self.quote_head(&other), self.quote_thunk(&arg))),
+            };
+        }
+
+        Ok(fun)
+    }
+
+    /// Reduce an application of a recursor, or `cases_on`, whose major
+    /// premise is a constructor.
+    ///
+    /// The computation rules work on terms, so we hand them the application
+    /// with the major premise in weak head normal form and every other
+    /// argument replaced by a placeholder, a local standing for it. We then
+    /// abstract the result over the placeholders and apply it to the
+    /// arguments, so they are never read back, nor evaluated unless the
+    /// result needs them.
+    fn iota(&self, head: Head, spine: Vec<Thunk>) -> Result<Value, Error> {
+        let reduced = match head {
+            Head::Name(ref name) => try!(self.apply_computation_rule(name, &spine)),
+            Head::Bound(..) => None,
+        };
+
+        match reduced {
+            Some(value) => Ok(value),
+            None => Ok(Value::Neutral(head, spine)),
+        }
+    }
+
+    fn apply_computation_rule(&self, name: &Name, spine: &[Thunk]) -> Result<Option<Value>, Error> {
+        let axiom = match self.ty_cx.axioms.get(&name.without_levels()) {
+            None => return Ok(None),
+            Some(axiom) => axiom,
+        };
+
+        let (rule, major) = match (axiom.computation_rule.as_ref(), axiom.major_premise) {
+            (Some(rule), Some(major)) if major < spine.len() => (rule, major),
+            _ => return Ok(None),
+        };
+
+        let major_premise = try!(self.whnf(try!(spine[major].force(self))));
+
+        // Only a constructor application can reduce.
+        let (ctor, ctor_spine) = match &major_premise {
+            &Value::Neutral(Head::Name(ref head), ref spine) if !head.is_meta() => (head, spine),
+            _ => return Ok(None),
+        };
+
+        let mut placeholders = vec![];
+
+        let (ctor_args, _) = try!(self.placeholders(ctor, ctor_spine, None, &mut placeholders));
+        let major_view = Term::apply_all(ctor.to_term(), ctor_args);
+
+        let (args, rest) =
+            try!(self.placeholders(name, spine, Some((major, major_view)), &mut placeholders));
+
+        if major >= args.len() {
+            return Ok(None);
+        }
+
+        let term = Term::apply_all(name.to_term(), args);
+        let reduced = try!(rule(self.ty_cx, term.clone()));
+
+        // The rule gives back the term when it is stuck.
+        if reduced == term {
+            return Ok(None);
+        }
+
+        let (locals, mut thunks): (Vec<_>, Vec<_>) = placeholders.into_iter().unzip();
+        thunks.extend(rest.iter().cloned());
+
+        let fun = try!(self.eval(&Env::empty(), &Term::abstract_lambda(locals, reduced)));
+
+        Ok(Some(try!(self.apply_all(fun, thunks))))
+    }
+
+    /// Placeholders for the arguments in `spine` which `head` takes
+    /// according to its type, each with the type of the binder it is passed
+    /// for, along with the arguments left over. The argument at the index
+    /// given with `major` is the term given with it instead. Each
+    /// placeholder is added to `placeholders` along with its argument.
+    fn placeholders<'s>(&self,
+                        head: &Name,
+                        spine: &'s [Thunk],
+                        major: Option<(usize, Term)>,
+                        placeholders: &mut Vec<(Name, Thunk)>)
+                        -> Result<(Vec<Term>, &'s [Thunk]), Error> {
+        let mut ty = match head {
+            &Name::Local { ref ty, .. } => (**ty).clone(),
+            _ => try!(self.ty_cx.global_type(head)),
+        };
+
+        let mut args = vec![];
+
+        for arg in spine {
+            let (domain, codomain) = match ty {
+                Term::Forall { binder, term, .. } => (binder.ty, term),
+                _ => break,
+            };
+
+            let arg_term = match major {
+                Some((index, ref term)) if index == args.len() => term.clone(),
+                _ => {
+                    let local = self.ty_cx.local_with_repr("_".to_string(), domain.into_term());
+                    placeholders.push((local.clone(), arg.clone()));
+                    local.to_term()
+                }
+            };
+
+            ty = codomain.instantiate(&arg_term);
+            args.push(arg_term);
+        }
+
+        let rest = &spine[args.len()..];
+
+        Ok((args, rest))
+    }
+
+    /// Unfold the definitions at the head of `value` until it is a
+    /// constructor, a binder or stuck.
+    pub fn whnf(&self, value: Value) -> Result<Value, Error> {
+        let mut value = value;
+
+        loop {
+            let unfolded = match value {
+                Value::Neutral(Head::Name(ref name), ref spine)
+                    if self.ty_cx.delta_height(name).is_some() =>
+                    Some(try!(self.unfold(name, spine))),
+                _ => None,
+            };
+
+            match unfolded {
+                Some(v) => value = v,
+                None => return Ok(value),
+            }
+        }
+    }
+
+    fn unfold(&self, name: &Name, spine: &[Thunk]) -> Result<Value, Error> {
+        let body = try!(self.ty_cx.unfold_name(name));
+        let fun = try!(self.eval(&Env::empty(), &body));
+        self.apply_all(fun, spine.to_vec())
+    }
+
+    /// Read `value` back into a term without evaluating it any further, so
+    /// only its head is in weak head normal form. The arguments and binders
+    /// which haven't been evaluated are read back as the terms they were
+    /// delayed on, with the values of their free variables substituted in.
+    pub fn quote_head(&self, value: &Value) -> Term {
+        match value {
+            &Value::Neutral(ref head, ref spine) => {
+                let head = match head {
+                    &Head::Name(ref name) => name.to_term(),
+                    &Head::Bound(..) =>
+                        panic!("type checking bug: reading back a variable bound by `quote`"),
+                };
+
+                Term::apply_all(head, spine.iter().map(|arg| self.quote_thunk(arg)).collect())
+            }
+            &Value::Lambda(ref name, ref mode, ref ty, ref closure) => {
+                Term::Lambda {
+                    span: Span::dummy(),
+                    binder: Binder::with_mode(name.clone(), self.quote_thunk(ty), mode.clone()),
+                    body: self.close(&closure.env, 1, &closure.body),
+                }
+            }
+            &Value::Forall(ref name, ref mode, ref ty, ref closure) => {
+                Term::Forall {
+                    span: Span::dummy(),
+                    binder: Binder::with_mode(name.clone(), self.quote_thunk(ty), mode.clone()),
+                    term: self.close(&closure.env, 1, &closure.body),
+                }
+            }
+            &Value::Sort(ref level) => Term::sort(level.normalize()),
+        }
+    }
+
+    fn quote_thunk(&self, thunk: &Thunk) -> Term {
+        match *thunk.0.borrow() {
+            ThunkState::Forced(ref value) => self.quote_head(value),
+            ThunkState::Delayed(ref env, ref term) => self.close(env, 0, term).into_term(),
+        }
+    }
+
+    /// Substitute the values in `env` for the variables free in `term`
+    /// under `depth` binders. The values of an environment are closed, so
+    /// they don't need shifting.
+    fn close(&self, env: &Env, depth: usize, term: &TermRef) -> TermRef {
+        if term.loose_bvar_range() <= depth {
+            return term.clone();
+        }
+
+        let closed = match &**term {
+            &Term::Var { name: Name::DeBruijn { index, .. } } if index >= depth =>
+                self.quote_thunk(env.lookup(index - depth)),
+            &Term::Var { .. } | &Term::Sort { .. } => return term.clone(),
+            &Term::App { span, ref fun, ref arg } => Term::App {
+                span: span,
+                fun: self.close(env, depth, fun),
+                arg: self.close(env, depth, arg),
+            },
+            &Term::Forall { span, ref binder, ref term } => Term::Forall {
+                span: span,
+                binder: self.close_binder(env, depth, binder),
+                term: self.close(env, depth + 1, term),
+            },
+            &Term::Lambda { span, ref binder, ref body } => Term::Lambda {
+                span: span,
+                binder: self.close_binder(env, depth, binder),
+                body: self.close(env, depth + 1, body),
+            },
+            &Term::Let { span, ref binder, ref value, ref body } => Term::Let {
+                span: span,
+                binder: self.close_binder(env, depth, binder),
+                value: self.close(env, depth, value),
+                body: self.close(env, depth + 1, body),
+            },
+        };
+
+        TermRef::new(closed)
+    }
+
+    fn close_binder(&self, env: &Env, depth: usize, binder: &Binder) -> Binder {
+        Binder {
+            name: binder.name.clone(),
+            ty: self.close(env, depth, &binder.ty),
+            mode: binder.mode.clone(),
+        }
+    }
+
+    /// Read `value` back into a term in normal form, `depth` is the number
+    /// of binders we are under.
+    pub fn quote(&self, depth: usize, value: &Value) -> Result<Term, Error> {
+        match value {
+            &Value::Neutral(ref head, ref spine) => {
+                let head = match head {
+                    &Head::Name(ref name) => name.to_term(),
+                    &Head::Bound(level, ref name) =>
+                        bound_name(name, depth - level - 1).to_term(),
+                };
+
+                let mut args = vec![];
+
+                for arg in spine {
+                    args.push(try!(self.quote(depth, &try!(arg.force(self)))));
+                }
+
+                Ok(Term::apply_all(head, args))
+            }
+            &Value::Lambda(ref name, ref mode, ref ty, ref closure) => {
+                let (binder, body) = try!(self.quote_binder(depth, name, mode, ty, closure));
+
+                Ok(Term::Lambda {
+                    span: Span::dummy(),
+                    binder: binder,
+                    body: TermRef::new(body),
+                })
+            }
+            &Value::Forall(ref name, ref mode, ref ty, ref closure) => {
+                let (binder, body) = try!(self.quote_binder(depth, name, mode, ty, closure));
+
+                Ok(Term::Forall {
+                    span: Span::dummy(),
+                    binder: binder,
+                    term: TermRef::new(body),
+                })
+            }
+            &Value::Sort(ref level) => Ok(Term::sort(level.normalize())),
+        }
+    }
+
+    fn quote_binder(&self,
+                    depth: usize,
+                    name: &Name,
+                    mode: &BindingMode,
+                    ty: &Thunk,
+                    closure: &Closure) -> Result<(Binder, Term), Error> {
+        let ty = try!(self.quote(depth, &try!(ty.force(self))));
+        let var = Thunk::forced(Value::Neutral(Head::Bound(depth, name.clone()), vec![]));
+        let body = try!(self.quote(depth + 1, &try!(self.instantiate(closure, var))));
+
+        Ok((Binder::with_mode(name.clone(), ty, mode.clone()), body))
+    }
+
+    /// Check whether `t` and `u` are convertible. Equations stuck on a
+    /// meta-variable can't be decided here, they are added to `constraints`
+    /// for the solver, and `true` means `t` and `u` are convertible once
+    /// they are solved.
+    pub fn is_def_eq(&self, t: Value, u: Value, constraints: &mut ConstraintSeq) -> Result<bool, Error> {
+        let mut t = t;
+        let mut u = u;
+
+        // Lazy delta reduction, when both sides are headed by definitions we
+        // unfold the one of greater height first, as it can't appear in the
+        // unfolding of the other, or both if their heights are the same.
+        loop {
+            if is_stuck(&t) || is_stuck(&u) {
+                return self.postpone(&t, &u, constraints);
+            }
+
+            if try!(self.attempt(constraints, |cs| self.is_def_eq_whnf(&t, &u, cs))) {
+                return Ok(true);
+            }
+
+            match (self.head_height(&t), self.head_height(&u)) {
+                (None, None) => break,
+                (Some(h1), Some(h2)) => {
+                    if h1 >= h2 {
+                        t = try!(self.unfold_head(t));
+                    }
+
+                    if h2 >= h1 {
+                        u = try!(self.unfold_head(u));
+                    }
+                }
+                (Some(_), None) => t = try!(self.unfold_head(t)),
+                (None, Some(_)) => u = try!(self.unfold_head(u)),
+            }
+        }
+
+        Ok(try!(self.attempt(constraints, |cs| self.is_eta_equal(&t, &u, cs))) ||
+           try!(self.attempt(constraints, |cs| self.is_eta_equal(&u, &t, cs))) ||
+           try!(self.attempt(constraints, |cs| self.is_structure_eta(&t, &u, cs))) ||
+           try!(self.attempt(constraints, |cs| self.is_structure_eta(&u, &t, cs))) ||
+           try!(self.attempt(constraints, |cs| self.is_proof_irrelevant(&t, &u, cs))))
+    }
+
+    /// Compare two values without unfolding the definitions at their heads,
+    /// their arguments and the parts of binders are compared with
+    /// `is_def_eq`.
+    fn is_def_eq_whnf(&self, t: &Value, u: &Value, constraints: &mut ConstraintSeq) -> Result<bool, Error> {
+        match (t, u) {
+            (&Value::Sort(ref l1), &Value::Sort(ref l2)) => {
+                if l1.is_equiv(l2) {
+                    Ok(true)
+                } else if l1.has_meta() || l2.has_meta() {
+                    let just = Justification::Asserted(
+                        AssertedBy::ExpectedFound(Term::sort(l1.clone()), Term::sort(l2.clone())));
+                    constraints.push(Constraint::Universe(l1.clone(), l2.clone(), just));
+                    Ok(true)
+                } else {
+                    Ok(false)
+                }
+            }
+            (&Value::Neutral(ref h1, ref spine1), &Value::Neutral(ref h2, ref spine2)) => {
+                let same_head = match (h1, h2) {
+                    (&Head::Name(ref n1), &Head::Name(ref n2)) => def_eq_name_modulo(n1, n2),
+                    (&Head::Bound(l1, _), &Head::Bound(l2, _)) => l1 == l2,
+                    _ => false,
+                };
+
+                if !same_head || spine1.len() != spine2.len() {
+                    return Ok(false);
+                }
+
+                for (a, b) in spine1.iter().zip(spine2.iter()) {
+                    if !try!(self.is_def_eq(try!(a.force(self)), try!(b.force(self)), constraints)) {
+                        return Ok(false);
+                    }
+                }
+
+                Ok(true)
+            }
+            (&Value::Forall(ref name, ref mode, ref ty1, ref closure1),
+             &Value::Forall(_, _, ref ty2, ref closure2)) |
+            (&Value::Lambda(ref name, ref mode, ref ty1, ref closure1),
+             &Value::Lambda(_, _, ref ty2, ref closure2)) => {
+                if !try!(self.is_def_eq(try!(ty1.force(self)), try!(ty2.force(self)), constraints)) {
+                    return Ok(false);
+                }
+
+                let local = try!(self.open(name, mode, ty1));
+
+                self.is_def_eq(
+                    try!(self.instantiate(closure1, local.clone())),
+                    try!(self.instantiate(closure2, local)),
+                    constraints)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// A fresh local standing for the variable bound by a binder.
+    fn open(&self, name: &Name, mode: &BindingMode, ty: &Thunk) -> Result<Thunk, Error> {
+        let ty = try!(self.quote(0, &try!(ty.force(self))));
+        let local = self.ty_cx.local(Binder::with_mode(name.clone(), ty, mode.clone()));
+
+        Ok(Thunk::forced(Value::Neutral(Head::Name(local), vec![])))
+    }
+
+    fn head_height(&self, value: &Value) -> Option<usize> {
+        match value {
+            &Value::Neutral(Head::Name(ref name), _) => self.ty_cx.delta_height(name),
+            _ => None,
+        }
+    }
+
+    fn unfold_head(&self, value: Value) -> Result<Value, Error> {
+        match value {
+            Value::Neutral(Head::Name(ref name), ref spine) => self.unfold(name, spine),
+            _ => panic!("type checking bug: unfolding a value without a definition at its head"),
+        }
+    }
+
+    /// Eta for functions, `fun x => f x` is equal to `f`.
+    fn is_eta_equal(&self, t: &Value, u: &Value, constraints: &mut ConstraintSeq) -> Result<bool, Error> {
+        match (t, u) {
+            (&Value::Lambda(ref name, ref mode, ref ty, ref closure), &Value::Neutral(..)) => {
+                let local = try!(self.open(name, mode, ty));
+
+                self.is_def_eq(
+                    try!(self.instantiate(closure, local.clone())),
+                    try!(self.apply_all(u.clone(), vec![local])),
+                    constraints)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Eta for structures, `t` is equal to `MkT ps as` when each of its
+    /// projections is equal to the corresponding field in `as`.
+    fn is_structure_eta(&self, t: &Value, u: &Value, constraints: &mut ConstraintSeq) -> Result<bool, Error> {
+        let (ctor, spine) = match u {
+            &Value::Neutral(Head::Name(ref ctor), ref spine) => (ctor, spine),
+            _ => return Ok(false),
+        };
+
+        let data_type = match self.ty_cx.structure_of_ctor(ctor) {
+            None => return Ok(false),
+            Some(data_type) => data_type,
+        };
+
+        let fields = data_type.fields.as_ref().unwrap();
+        let num_params = data_type.parameters.len();
+
+        if spine.len() != num_params + fields.len() {
+            return Ok(false);
+        }
+
+        for (field, arg) in fields.iter().zip(spine[num_params..].iter()) {
+            let projection = data_type.projection(field).with_levels(ctor.levels().to_vec());
+
+            let mut projection_args = spine[..num_params].to_vec();
+            projection_args.push(Thunk::forced(t.clone()));
+
+            let projected = try!(self.apply_all(
+                try!(self.eval(&Env::empty(), &projection.to_term())),
+                projection_args));
+
+            if !try!(self.is_def_eq(projected, try!(arg.force(self)), constraints)) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Any two proofs of the same proposition are definitionally equal.
+    ///
+    /// Finding the proposition means inferring the type of each side, so we
+    /// only try when both could be proofs, and give up on `u` as soon as `t`
+    /// turns out not to be one.
+    fn is_proof_irrelevant(&self, t: &Value, u: &Value, constraints: &mut ConstraintSeq) -> Result<bool, Error> {
+        if !could_be_proof(t) || !could_be_proof(u) {
+            return Ok(false);
+        }
+
+        let p = match self.ty_cx.proof_of(&self.quote_head(t)) {
+            None => return Ok(false),
+            Some(p) => p,
+        };
+
+        let q = match self.ty_cx.proof_of(&self.quote_head(u)) {
+            None => return Ok(false),
+            Some(q) => q,
+        };
+
+        let p = try!(self.eval(&Env::empty(), &p));
+        let q = try!(self.eval(&Env::empty(), &q));
+
+        self.is_def_eq(p, q, constraints)
+    }
+
+    /// Leave an equation stuck on a meta-variable to the solver, as a
+    /// constraint which must hold for `t` and `u` to be convertible. When
+    /// both sides are the same meta-variable we compare their arguments
+    /// instead.
+    fn postpone(&self, t: &Value, u: &Value, constraints: &mut ConstraintSeq) -> Result<bool, Error> {
+        if is_stuck(t) && is_stuck(u) &&
+           try!(self.attempt(constraints, |cs| self.is_def_eq_whnf(t, u, cs))) {
+            return Ok(true);
+        }
+
+        let t = self.quote_head(t);
+        let u = self.quote_head(u);
+
+        let just = Justification::Asserted(AssertedBy::ExpectedFound(t.clone(), u.clone()));
+        constraints.push(Constraint::Unification(t, u, just));
+
+        Ok(true)
+    }
+
+    /// Run a conversion check, only keeping the constraints it generated
+    /// if it succeeds.
+    fn attempt<F>(&self, constraints: &mut ConstraintSeq, check: F) -> Result<bool, Error>
+        where F: FnOnce(&mut ConstraintSeq) -> Result<bool, Error>
+    {
+        let mut cs = vec![];
+
+        if try!(check(&mut cs)) {
+            constraints.extend(cs.into_iter());
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+/// Values which are sorts or `forall`s are types, never proofs.
+fn could_be_proof(value: &Value) -> bool {
+    match value {
+        &Value::Neutral(..) | &Value::Lambda(..) => true,
+        &Value::Forall(..) | &Value::Sort(..) => false,
+    }
+}
+
+fn is_stuck(value: &Value) -> bool {
+    match value {
+        &Value::Neutral(Head::Name(ref name), _) => name.is_meta(),
+        _ => false,
+    }
+}
+
+/// The name of the variable bound `index` binders up, keeping the name it
+/// was given in the source.
+fn bound_name(binder: &Name, index: usize) -> Name {
+    match binder {
+        &Name::DeBruijn { ref repr, span, .. } => Name::DeBruijn {
+            index: index,
+            repr: repr.clone(),
+            span: span,
+        },
+        n => Name::DeBruijn {
+            index: index,
+            repr: format!("{}", n),
+            span: Span::dummy(),
+        },
+    }
+}
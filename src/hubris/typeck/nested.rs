@@ -0,0 +1,277 @@
+use super::{TyCtxt, Error};
+use super::super::core::*;
+
+/// A group of inductive types declared together, along with an auxiliary
+/// type for each occurrence of another inductive type nested in their
+/// constructors.
+///
+/// For `inductive Rose (A : Type) | Node : A -> List (Rose A) -> Rose A` we
+/// introduce `Rose.nested_1 (A : Type)`, whose constructors are those of
+/// `List` instantiated at `Rose A`, and replace `List (Rose A)` in `Node`
+/// with `Rose.nested_1 A`. The group is then checked and given recursors as
+/// if the types were mutually inductive, afterwards the auxiliary types and
+/// constructors are defined to be the occurrences they stand for.
+pub struct Group {
+    /// The declared types followed by the auxiliary types, with nested
+    /// occurrences in the constructors replaced.
+    pub types: Vec<Data>,
+    /// The number of declared types.
+    pub num_declared: usize,
+    /// The constructor each of the constructors in the group stands for,
+    /// in order, along with the number of parameters it takes.
+    pub ctors: Vec<(Name, usize)>,
+    /// The definitions of the auxiliary types and their constructors.
+    pub aux_defs: Vec<Definition>,
+}
+
+/// Find the nested occurrences in the constructors of `mutual`, and build
+/// the group with their auxiliary types.
+pub fn nested_group(ty_cx: &TyCtxt, mutual: &[Data]) -> Result<Group, Error> {
+    let ctors = mutual.iter()
+                      .flat_map(|d| d.ctors.iter().map(move |c| (c.0.clone(), d.parameters.len())))
+                      .collect();
+
+    let mut ncx = NestedCx {
+        ty_cx: ty_cx,
+        params: mutual[0].parameters.clone(),
+        group: Group {
+            types: mutual.to_vec(),
+            num_declared: mutual.len(),
+            ctors: ctors,
+            aux_defs: vec![],
+        },
+        occurrences: vec![],
+    };
+
+    // Translating the constructors of an auxiliary type may uncover further
+    // occurrences, `List (List (Rose A))` contains `List (Rose A)` for
+    // example, so we keep going until every type has been translated.
+    let mut k = 0;
+    while k < ncx.group.types.len() {
+        let mut ctors = vec![];
+
+        for (name, ty) in ncx.group.types[k].ctors.clone() {
+            ctors.push((name, try!(ncx.translate_ctor(ty))));
+        }
+
+        ncx.group.types[k].ctors = ctors;
+        k += 1;
+    }
+
+    ncx.define_aux_types();
+
+    Ok(ncx.group)
+}
+
+struct NestedCx<'tcx> {
+    ty_cx: &'tcx TyCtxt,
+    /// The parameters shared by the group.
+    params: Vec<Name>,
+    group: Group,
+    /// The nested occurrence each auxiliary type stands for, `F qs` where
+    /// `qs` are the parameters of `F` which mention the group.
+    occurrences: Vec<Term>,
+}
+
+impl<'tcx> NestedCx<'tcx> {
+    fn translate_ctor(&mut self, ctor_ty: Term) -> Result<Term, Error> {
+        let mut pi = ctor_ty.clone();
+
+        for param in &self.params {
+            pi = match pi {
+                Term::Forall { term, .. } => term.instantiate(&param.to_term()),
+                // Leave malformed constructors for `check_inductive` to report.
+                _ => return Ok(ctor_ty),
+            };
+        }
+
+        let pi = try!(self.replace_nested(&pi));
+
+        Ok(Term::abstract_pi_implicit(self.params.clone(), pi))
+    }
+
+    /// Replace each occurrence `F qs is` of a previously declared inductive
+    /// type whose parameters `qs` mention the group with the auxiliary type
+    /// standing for `F qs`.
+    fn replace_nested(&mut self, term: &Term) -> Result<Term, Error> {
+        let (head, args) = term.uncurry();
+
+        if let Term::Var { ref name } = head {
+            let num_params = match self.nested_type(name) {
+                None => 0,
+                Some(data_type) => data_type.parameters.len(),
+            };
+
+            if num_params > 0 && args.len() >= num_params &&
+               args[..num_params].iter().any(|a| self.mentions_group(a)) {
+                let aux = try!(self.aux_type(name, &args[..num_params]));
+
+                let mut aux_args: Vec<_> = self.params.iter().map(|p| p.to_term()).collect();
+
+                for index in &args[num_params..] {
+                    aux_args.push(try!(self.replace_nested(index)));
+                }
+
+                return Ok(Term::apply_all(aux.to_term(), aux_args));
+            }
+        }
+
+        Ok(match term {
+            &Term::App { ref fun, ref arg, span } => Term::App {
+                fun: TermRef::new(try!(self.replace_nested(fun))),
+                arg: TermRef::new(try!(self.replace_nested(arg))),
+                span: span,
+            },
+            &Term::Forall { ref binder, ref term, span } => Term::Forall {
+                binder: try!(self.replace_in_binder(binder)),
+                term: TermRef::new(try!(self.replace_nested(term))),
+                span: span,
+            },
+            &Term::Lambda { ref binder, ref body, span } => Term::Lambda {
+                binder: try!(self.replace_in_binder(binder)),
+                body: TermRef::new(try!(self.replace_nested(body))),
+                span: span,
+            },
+            &Term::Let { ref binder, ref value, ref body, span } => Term::Let {
+                binder: try!(self.replace_in_binder(binder)),
+                value: TermRef::new(try!(self.replace_nested(value))),
+                body: TermRef::new(try!(self.replace_nested(body))),
+                span: span,
+            },
+            t => t.clone(),
+        })
+    }
+
+    fn replace_in_binder(&mut self, binder: &Binder) -> Result<Binder, Error> {
+        Ok(Binder {
+            ty: TermRef::new(try!(self.replace_nested(&binder.ty))),
+            ..binder.clone()
+        })
+    }
+
+    /// A previously declared inductive type, outside of the group.
+    fn nested_type(&self, name: &Name) -> Option<&'tcx Data> {
+        if self.group.types.iter().any(|d| d.name.same_variable(name)) {
+            None
+        } else {
+            self.ty_cx.types.get(&name.without_levels())
+        }
+    }
+
+    fn mentions_group(&self, term: &Term) -> bool {
+        self.group.types.iter().any(|d| term.occurs(&d.name))
+    }
+
+    /// The auxiliary type standing for `F qs`, introducing it if this is
+    /// the first time we have seen the occurrence.
+    fn aux_type(&mut self, f: &Name, qs: &[Term]) -> Result<Name, Error> {
+        let occurrence = Term::apply_all(f.to_term(), qs.to_vec());
+        let num_declared = self.group.num_declared;
+
+        if let Some(i) = self.occurrences.iter().position(|o| o == &occurrence) {
+            return Ok(self.group.types[num_declared + i].name.clone());
+        }
+
+        let nested_type = self.nested_type(f).unwrap();
+
+        let aux_name = self.group.types[0]
+                           .name
+                           .in_scope(format!("nested_{}", self.occurrences.len() + 1))
+                           .unwrap();
+
+        let ty = instantiate_params(try!(self.ty_cx.global_type(f)), qs);
+
+        // The constructors are translated once we reach this type in
+        // `nested_group`.
+        let mut ctors = vec![];
+
+        for &(ref ctor_name, _) in &nested_type.ctors {
+            let ctor_name = ctor_name.clone().with_levels(f.levels().to_vec());
+            let ctor_ty = instantiate_params(try!(self.ty_cx.global_type(&ctor_name)), qs);
+
+            ctors.push((
+                aux_name.in_scope(last_component(&ctor_name)).unwrap(),
+                Term::abstract_pi_implicit(self.params.clone(), ctor_ty)));
+
+            self.group.ctors.push((ctor_name, qs.len()));
+        }
+
+        self.group.types.push(Data {
+            span: self.group.types[0].span,
+            name: aux_name.clone(),
+            univ_params: self.group.types[0].univ_params.clone(),
+            parameters: self.params.clone(),
+            ty: Term::abstract_pi(self.params.clone(), ty),
+            ctors: ctors,
+            mutual: vec![],
+            fields: None,
+            class: false,
+        });
+
+        self.occurrences.push(occurrence);
+
+        Ok(aux_name)
+    }
+
+    /// Each auxiliary type is defined to be `fun ps => F qs`, and each of its
+    /// constructors `fun ps => c qs` for the constructor `c` of `F` it
+    /// stands for.
+    fn define_aux_types(&mut self) {
+        let num_declared = self.group.num_declared;
+        let mut ctor_offset = self.group.types[..num_declared]
+                                  .iter()
+                                  .map(|d| d.ctors.len())
+                                  .sum::<usize>();
+
+        let mut aux_defs = vec![];
+
+        for (aux, occurrence) in self.group.types[num_declared..].iter().zip(self.occurrences.iter()) {
+            let (_, qs) = occurrence.uncurry();
+
+            aux_defs.push(self.aux_def(aux, &aux.name, &aux.ty, occurrence.clone()));
+
+            for (i, ctor) in aux.ctors.iter().enumerate() {
+                let &(ref nested_ctor, _) = &self.group.ctors[ctor_offset + i];
+                let value = Term::apply_all(nested_ctor.to_term(), qs.clone());
+                aux_defs.push(self.aux_def(aux, &ctor.0, &ctor.1, value));
+            }
+
+            ctor_offset += aux.ctors.len();
+        }
+
+        self.group.aux_defs = aux_defs;
+    }
+
+    fn aux_def(&self, aux: &Data, name: &Name, ty: &Term, value: Term) -> Definition {
+        Definition {
+            name: name.clone(),
+            univ_params: aux.univ_params.clone(),
+            args: vec![],
+            ty: ty.clone(),
+            body: Term::abstract_lambda(self.params.clone(), value),
+            reduction: DeltaReduction::Reducible,
+            coercion: false,
+        }
+    }
+}
+
+/// Instantiate the leading binders of `ty` with `args`.
+fn instantiate_params(ty: Term, args: &[Term]) -> Term {
+    let mut ty = ty;
+
+    for arg in args {
+        ty = match ty {
+            Term::Forall { term, .. } => term.instantiate(arg),
+            _ => panic!("type checking bug: {} has too few parameters", ty),
+        };
+    }
+
+    ty
+}
+
+fn last_component(name: &Name) -> String {
+    match name {
+        &Name::Qual { ref components, .. } => components.last().unwrap().clone(),
+        _ => panic!("type checking bug: {} is not a global", name),
+    }
+}
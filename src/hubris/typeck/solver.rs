@@ -1,21 +1,24 @@
-use hubris_syntax::ast::HasSpan;
+use hubris_syntax::ast::{HasSpan, Span};
 use super::TyCtxt;
 use super::constraint::*;
+use super::universe::{self, UniverseCx};
 use super::super::session::{HasSession, Session, Reportable};
-use core::{Term, Binder, Name};
+use core::{Term, TermRef, Binder, Name, Level};
 use util::*;
 
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::io;
 use std::rc::Rc;
 
+/// A choice point, the state of the solver when we made a case split along
+/// with the alternatives we have yet to try.
 pub struct Choice {
     constraints: BinaryHeap<CategorizedConstraint>,
     constraint_mapping: HashMap<Name, Vec<CategorizedConstraint>>,
     solution_mapping: HashMap<Name, (Term, Justification)>,
-    assumption_justification: Justification,
-    constraint_justification: Justification,
-    list: (),
+    universes: UniverseCx,
+    /// The remaining alternatives, the next one to try is at the end.
+    alternatives: Vec<ConstraintSeq>,
 }
 
 pub struct Solver<'tcx> {
@@ -23,6 +26,7 @@ pub struct Solver<'tcx> {
     constraints: BinaryHeap<CategorizedConstraint>,
     constraint_mapping: HashMap<Name, Vec<CategorizedConstraint>>,
     pub solution_mapping: HashMap<Name, (Term, Justification)>,
+    universes: UniverseCx,
     choice_stack: Vec<Choice>,
 }
 
@@ -32,6 +36,12 @@ pub enum Error {
     Justification(Justification),
     TypeCk(Box<super::Error>),
     NoSolution(Vec<Name>, Term),
+    /// Solving the meta-variable would make it part of its own solution.
+    Occurs(Name, Term, Justification),
+    /// The solution for the meta-variable mentions a local that is not in
+    /// its context.
+    Scope(Name, Name, Term, Justification),
+    Universe(universe::Error),
     Many(Vec<Error>),
 }
 
@@ -41,26 +51,72 @@ impl From<super::Error> for Error {
     }
 }
 
+impl From<universe::Error> for Error {
+    fn from(err: universe::Error) -> Error {
+        Error::Universe(err)
+    }
+}
+
+fn report_justified(cx: &Session, j: &Justification, message: String) -> io::Result<()> {
+    match j.span() {
+        None => cx.error(message),
+        Some(span) => cx.span_error(span, message),
+    }
+}
+
+/// Report the equation `j` asserts. A join is reported by the first of its
+/// justifications which asserts an equation, it is the one which failed.
+fn report_justification(cx: &Session, j: Justification) -> io::Result<()> {
+    match j {
+        Justification::Asserted(by) => match by {
+            AssertedBy::Application(span, u, t) =>
+                cx.span_error(span,
+                    format!("a term with type `{}` can not be applied to an argument with \
+                             type `{}`", u, t)),
+            AssertedBy::ExpectedFound(infer_ty, ty) =>
+                cx.span_error(ty.get_span(),
+                    format!("expected type `{}` found `{}`", ty, infer_ty)),
+            AssertedBy::Checked(span, infer_ty, ty) =>
+                cx.span_error(span,
+                    format!("expected type `{}` found `{}`", ty, infer_ty)),
+        },
+        Justification::Assumption => cx.error("assumption".to_string()),
+        Justification::Join(j1, j2) => match *j1 {
+            Justification::Assumption => report_justification(cx, (*j2).clone()),
+            ref j1 => report_justification(cx, j1.clone()),
+        },
+    }
+}
+
 impl Reportable for Error {
     fn report(self, cx: &Session) -> io::Result<()> {
         match self {
-            Error::Justification(j) => match j {
-                Justification::Asserted(by) => match by {
-                    AssertedBy::Application(span, u, t) =>
-                        cx.span_error(span,
-                            format!("a term with type `{}` can not be applied to an argument with \
-                                     type `{}`", u, t)),
-                    AssertedBy::ExpectedFound(infer_ty, ty) =>
-                        cx.span_error(ty.get_span(),
-                            format!("expected type `{}` found `{}`", ty, infer_ty)),
-                },
-                Justification::Assumption => cx.error("assumption".to_string()),
-                j @ Justification::Join(_, _) => panic!(), // cx.error(format!("{}", j)),
-            },
+            Error::Justification(j) |
+            Error::Simplification(j) => report_justification(cx, j),
             Error::NoSolution(ns, term) => {
                 // TODO: fix this
                 cx.error(format!("unable to find a solution for {} in {}", ns[0], term))
             }
+            Error::Occurs(meta, term, j) => {
+                report_justified(cx, &j,
+                    format!("unable to solve `{}`, its solution `{}` would have to \
+                             contain itself", meta, term))
+            }
+            Error::Scope(meta, local, term, j) => {
+                report_justified(cx, &j,
+                    format!("unable to solve `{}` with `{}`, `{}` is not in scope \
+                             where `{}` was introduced", meta, term, local, meta))
+            }
+            Error::Universe(universe::Error::Cycle(l1, l2, j)) => {
+                report_justified(cx, &j,
+                    format!("universe level `{}` can not be equal to `{}`, \
+                             solving this constraint would create a cycle", l1, l2))
+            }
+            Error::Universe(universe::Error::Mismatch(l1, l2, j)) => {
+                report_justified(cx, &j,
+                    format!("universe level `{}` is not equal to `{}`", l1, l2))
+            }
+            Error::TypeCk(err) => cx.report(*err),
             Error::Many(errs) => {
                 for err in errs {
                     try!(err.report(cx));
@@ -68,7 +124,6 @@ impl Reportable for Error {
 
                 Ok(())
             }
-            _ => panic!()
         }
     }
 }
@@ -80,6 +135,7 @@ impl<'tcx> Solver<'tcx> {
             constraints: BinaryHeap::new(),
             constraint_mapping: HashMap::new(),
             solution_mapping: HashMap::new(),
+            universes: UniverseCx::new(),
             choice_stack: vec![],
         }
     }
@@ -88,22 +144,28 @@ impl<'tcx> Solver<'tcx> {
     /// instance of the solver.
     pub fn new(ty_cx: &'tcx mut TyCtxt, cs: ConstraintSeq) -> Result<Solver, Error> {
         let mut solver = Solver::empty(ty_cx);
+        try!(solver.add_constraints(cs));
+        Ok(solver)
+    }
+
+    /// Simplify and visit each of the constraints in `cs`.
+    fn add_constraints(&mut self, cs: ConstraintSeq) -> Result<(), Error> {
         for c in cs {
-            // debug!("Solver::new: c={}", c);
-            match &c {
-                &Constraint::Unification(ref t, ref u, ref j) => {
-                    let simple_cs =
-                        try!(solver.simplify(t.clone(), u.clone(), j.clone()));
+            match c {
+                Constraint::Unification(t, u, j) => {
+                    let simple_cs = try!(self.simplify(t, u, j));
                     for sc in simple_cs {
-                        try!(solver.visit(sc));
+                        try!(self.visit(sc));
                     }
                 },
-                &Constraint::Choice(..) => {
-                    try!(solver.visit(c.clone().categorize()))
+                c @ Constraint::Universe(..) |
+                c @ Constraint::Choice(..) => {
+                    try!(self.visit(c.categorize()))
                 }
             }
         }
-        Ok(solver)
+
+        Ok(())
     }
 
     pub fn visit(&mut self, c: CategorizedConstraint) -> Result<(), Error> {
@@ -115,8 +177,16 @@ impl<'tcx> Solver<'tcx> {
         match constraint {
             Constraint::Unification(t, u, j) =>
                 self.visit_unification(t, u, j, category),
-            Constraint::Choice(..) =>
-                panic!("choice constraints aren't impl"),
+            Constraint::Universe(l1, l2, j) =>
+                Ok(try!(self.universes.add_constraint(l1, l2, j))),
+            Constraint::Choice(t, ty, f, j) => {
+                self.constraints.push(CategorizedConstraint {
+                    category: category,
+                    constraint: Constraint::Choice(t, ty, f, j),
+                });
+
+                Ok(())
+            }
         }
     }
 
@@ -208,24 +278,14 @@ impl<'tcx> Solver<'tcx> {
 
             debug!("rhs: {}", s);
 
+            let s = self.instantiate_solutions(s);
+            try!(self.check_solution(&meta, &locals, &s, &j));
+
             let solution = Term::abstract_lambda(locals, s);
 
             debug!("soultion: {} ", solution);
 
-            assert!(meta.is_meta());
-
-            self.solution_mapping.insert(meta.clone(), (solution, j));
-
-            let cs = match self.constraint_mapping.get(&meta) {
-                None => vec![],
-                Some(cs) => cs.clone(),
-            };
-
-            for c in cs {
-                try!(self.visit(c.clone()));
-            }
-
-            Ok(())
+            self.assign(meta, solution, j)
         } else {
             debug!("category: {:?}", category);
 
@@ -248,6 +308,41 @@ impl<'tcx> Solver<'tcx> {
         }
     }
 
+    /// Check that `meta := fun locals => s` is a valid solution, `meta` may
+    /// not occur in `s`, and `s` may only mention the locals in the context
+    /// of `meta`, which are the ones it is applied to.
+    fn check_solution(&self, meta: &Name, locals: &[Name], s: &Term, j: &Justification) -> Result<(), Error> {
+        if s.occurs(meta) {
+            let j = try!(self.eval_justification(j.clone()));
+            return Err(Error::Occurs(meta.clone(), s.clone(), j));
+        }
+
+        if let Some(local) = s.find_name(&|n| n.is_local() && !locals.contains(n)) {
+            let j = try!(self.eval_justification(j.clone()));
+            return Err(Error::Scope(meta.clone(), local.clone(), s.clone(), j));
+        }
+
+        Ok(())
+    }
+
+    /// Assign `meta` and revisit the constraints that were waiting on it.
+    fn assign(&mut self, meta: Name, solution: Term, j: Justification) -> Result<(), Error> {
+        assert!(meta.is_meta());
+
+        self.solution_mapping.insert(meta.clone(), (solution, j));
+
+        let cs = match self.constraint_mapping.get(&meta) {
+            None => vec![],
+            Some(cs) => cs.clone(),
+        };
+
+        for c in cs {
+            try!(self.visit(c));
+        }
+
+        Ok(())
+    }
+
     pub fn simplify(&self, t: Term, u: Term, j: Justification) -> Result<Vec<CategorizedConstraint>, Error> {
         debug!("simplify: t={} u={}", t, u);
         // Case 1: t and u are precisely the same term
@@ -255,14 +350,22 @@ impl<'tcx> Solver<'tcx> {
         // no more constraints since this is discharge-able here.
         if t == u {
             debug!("simplify: equal case");
-            // debug!("exactly equal");
             return Ok(vec![]);
         }
 
+        // The terms only differ in the levels of the globals and sorts they
+        // mention, two sorts for example are equal exactly when their levels
+        // are, we hand this off to the universe solver.
+        let mut cs = vec![];
+        if level_constraints(&t, &u, &j, &mut cs) {
+            debug!("simplify: equal up to levels case");
+            return Ok(cs);
+        }
+
         // Case 2: if t can beta/iota reduce to then
         // we reduce t ==> t' and create a constraint
         // between t' and u (t' = u).
-        else if self.ty_cx.is_bi_reducible(&t) &&
+        if self.ty_cx.is_bi_reducible(&t) &&
                 self.ty_cx.is_bi_reducible(&u) {
             debug!("simplify: reduce case (both)");
             self.simplify(try!(self.ty_cx.eval(&t)),
@@ -295,53 +398,71 @@ impl<'tcx> Solver<'tcx> {
 
         else if t.head_is_global() &&
                 u.head_is_global() &&
-                t.head() == u.head() {
+                same_head(&t, &u) &&
+                t.args().map(|a| a.len()) == u.args().map(|a| a.len()) {
             debug!("head is global");
 
             let (f, f_args) = t.uncurry();
             let (g, g_args) = u.uncurry();
 
-            if f_args.len() == 0 && g_args.len() == 0 {
-                panic!()
+            let mut cs = vec![];
+            level_constraints(&f, &g, &j, &mut cs);
+
+            match self.simplify_args(f_args, g_args, &j) {
+                Ok(arg_cs) => {
+                    cs.extend(arg_cs.into_iter());
+                    Ok(cs)
+                }
+                // The arguments of a definition may differ while the
+                // applications agree once we unfold it, when they don't we
+                // report the arguments which differ.
+                Err(err) => match try!(self.unfold_heads(&t, &u)) {
+                    Some((t, u)) => self.simplify(t, u, j).map_err(|_| err),
+                    None => Err(err),
+                },
             }
+        }
 
-            // THERE IS A BUG HERE!
-            let t_args_meta_free =
-                f_args.iter().all(|a| !a.is_meta());
+        else if t.is_lambda() && u.is_lambda() {
+            debug!("inside lambda");
+            match (t, u) {
+                (Term::Lambda { binder: binder1, body: body1, .. },
+                 Term::Lambda { binder: binder2, body: body2, .. }) => {
+                     let ty1 = binder1.ty.clone();
+                     let ty2 = binder2.ty;
 
-            let u_args_meta_free =
-                g_args.iter().all(|a| !a.is_meta());
+                     let local = self.ty_cx.local(binder1).to_term();
+                     let mut arg_cs = try!(self.simplify(ty1.into_term(), ty2.into_term(), j.clone()));
 
-            if (self.ty_cx.is_bi_reducible(&t) ||
-                self.ty_cx.is_bi_reducible(&u))  &&
-               t_args_meta_free &&
-               u_args_meta_free {
-                panic!("var are free")
-                    //      self.simplify(t.unfold(f) = u.unfold(f))
-                    // } else if !f.reducible() {
-                    //     t.args = u.args
-                    // } else { panic!() }
-            } else if !self.ty_cx.is_bi_reducible(&f) && f == g {
-                let mut cs = vec![];
-                for (t_i, s_i) in f_args.into_iter().zip(g_args.into_iter()) {
-                    debug!("arg_equal {} {}", t_i, s_i);
-                    cs.extend(try!(self.simplify(t_i, s_i, j.clone())).into_iter());
-                }
-                Ok(cs)
-            } else {
-                panic!("f is reducible but metavars are ")
+                     let t_sub = body1.instantiate(&local);
+                     let u_sub = body2.instantiate(&local);
+
+                     let body_cs = try!(self.simplify(t_sub, u_sub, j.clone()));
+                     arg_cs.extend(body_cs.into_iter());
+
+                     Ok(arg_cs)
+                 }
+                 _ => panic!("this should be impossible")
             }
         }
 
-        // This should be the case dealing with depth, haven't implemented it
-        // yet.
-        else if false {
-            panic!()
-        }
+        // Eta for functions, `fun x => b = u` holds when `b = u x`.
+        else if t.is_lambda() || u.is_lambda() {
+            debug!("simplify: eta case");
+            let (lambda, other) = if t.is_lambda() { (t, u) } else { (u, t) };
 
-        // else if t.is_lambda() && u.is_lambda() {
-        //     panic!()
-        // }
+            match lambda {
+                Term::Lambda { binder, body, .. } => {
+                    let local = self.ty_cx.local(binder).to_term();
+
+                    self.simplify(
+                        body.instantiate(&local),
+                        Term::apply(other, local),
+                        j)
+                }
+                _ => panic!("this should be impossible")
+            }
+        }
 
         else if t.is_forall() && u.is_forall() {
             debug!("inside forall");
@@ -352,7 +473,7 @@ impl<'tcx> Solver<'tcx> {
                      let ty2 = binder2.ty;
 
                      let local = self.ty_cx.local(binder1).to_term();
-                     let mut arg_cs = try!(self.simplify(*ty1, *ty2, j.clone()));
+                     let mut arg_cs = try!(self.simplify(ty1.into_term(), ty2.into_term(), j.clone()));
 
                      let t_sub = term1.instantiate(&local);
                      let u_sub = term2.instantiate(&local);
@@ -368,14 +489,54 @@ impl<'tcx> Solver<'tcx> {
             if t.is_stuck().is_some() ||
                u.is_stuck().is_some() {
                 Ok(vec![Constraint::Unification(t, u, j).categorize()])
+            } else if let Some(pairs) = self.ty_cx.structure_eta(&t, &u)
+                                            .or_else(|| self.ty_cx.structure_eta(&u, &t)) {
+                // Eta for structures, each projection must equal its field.
+                debug!("simplify: structure eta case");
+                let mut cs = vec![];
+                for (projected, field) in pairs {
+                    cs.extend(try!(self.simplify(projected, field, j.clone())).into_iter());
+                }
+                Ok(cs)
+            } else if let Ok((_, cs)) = self.ty_cx.def_eq(Span::dummy(), &t, &u) {
+                // The heads differ, but may agree once we unfold definitions
+                // or by proof irrelevance.
+                Ok(cs.into_iter().map(|c| c.categorize()).collect())
             } else {
                 let j = try!(self.eval_justification(j));
-                panic!("{} {}", t, u);
                 Err(Error::Justification(j))
             }
         }
     }
 
+    fn simplify_args(&self, t_args: Vec<Term>, u_args: Vec<Term>, j: &Justification) -> Result<Vec<CategorizedConstraint>, Error> {
+        let mut cs = vec![];
+
+        for (t_i, u_i) in t_args.into_iter().zip(u_args.into_iter()) {
+            debug!("arg_equal {} {}", t_i, u_i);
+            cs.extend(try!(self.simplify(t_i, u_i, j.clone())).into_iter());
+        }
+
+        Ok(cs)
+    }
+
+    /// Unfold the definition at the head of both `t` and `u`, which must be
+    /// the same global, or `None` if it can't be unfolded.
+    fn unfold_heads(&self, t: &Term, u: &Term) -> Result<Option<(Term, Term)>, Error> {
+        let (f, f_args) = t.uncurry();
+        let (g, g_args) = u.uncurry();
+
+        match (f, g) {
+            (Term::Var { name: ref f }, Term::Var { name: ref g }) if self.ty_cx.delta_height(f).is_some() => {
+                let f_body = try!(self.ty_cx.unfold_name(f));
+                let g_body = try!(self.ty_cx.unfold_name(g));
+
+                Ok(Some((Term::apply_all(f_body, f_args), Term::apply_all(g_body, g_args))))
+            }
+            _ => Ok(None),
+        }
+    }
+
     /// Will take the justification that was created at constraint generation time, and substitute
     /// all known meta-variable solutions and then simplify it. This is particularly useful in
     /// error reporting where we want to show the simplest term possible.
@@ -398,6 +559,12 @@ impl<'tcx> Solver<'tcx> {
 
                     AssertedBy::ExpectedFound(t, u)
                 }
+                AssertedBy::Checked(span, t, u) => {
+                    let t = try!(self.ty_cx.eval(&replace_metavars_with_err(t, &self.solution_mapping, &mut errs)));
+                    let u = try!(self.ty_cx.eval(&replace_metavars_with_err(u, &self.solution_mapping, &mut errs)));
+
+                    AssertedBy::Checked(span, t, u)
+                }
             }),
             Assumption => Assumption,
             Join(j1, j2) => {
@@ -409,117 +576,323 @@ impl<'tcx> Solver<'tcx> {
         Ok(j)
     }
 
-    // The set of constraints should probably be a lazy list.
-    fn process(&self, cs: &mut Iterator<Item=CategorizedConstraint>, j: Justification) -> Result<(), Error> {
-        match cs.next() {
-            None => self.resolve(j),
-            Some(c) => panic!(),
+    /// Create a choice point, trying the first of `alternatives` and saving
+    /// the state of the solver so we can come back to try the rest if it
+    /// leads to failure.
+    fn case_split(&mut self, mut alternatives: Vec<ConstraintSeq>, j: Justification) -> Result<(), Error> {
+        if alternatives.len() == 0 {
+            let j = try!(self.eval_justification(j));
+            return Err(Error::Justification(j));
         }
+
+        alternatives.reverse();
+        let first = alternatives.pop().unwrap();
+
+        if alternatives.len() > 0 {
+            self.choice_stack.push(Choice {
+                constraints: self.constraints.clone(),
+                constraint_mapping: self.constraint_mapping.clone(),
+                solution_mapping: self.solution_mapping.clone(),
+                universes: self.universes.clone(),
+                alternatives: alternatives,
+            });
+        }
+
+        self.add_constraints(first)
     }
 
-    // /// Create a case split (choice point) by storing the current solver state with
-    // /// the arguments provided.
-    // fn case_split(&mut self, j_a: Justification, j_c: Justification, list: () ) {
-    //
-    // }
-    pub fn solve(mut self) -> Result<HashMap<Name, (Term, Justification)>, Error> {
-        while let Some(c) = self.constraints.pop() {
-            debug!("Solver::solve: constraint={}", c.constraint);
-            match c.constraint {
-                Constraint::Choice(term, ty, f, j) =>
-                    // self.process(f(term, ty, subst), j)
-                    panic!(),
-                Constraint::Unification(t, u, j) => {
-                    for (m, s) in &self.solution_mapping {
-                        debug!("{} {}", m, s.0)
-                    }
-                    match c.category {
-                        ConstraintCategory::Delta => {
-                            panic!("can't handle delta constraints yet")
-                        }
-                        ConstraintCategory::QuasiPattern |
-                        ConstraintCategory::FlexRigid => {
-                            let (t_head, t_args) = t.uncurry();
-                            let (u_head, u_args) = u.uncurry();
+    /// Return to the most recent choice point and try its next alternative,
+    /// `err` is the reason we are backtracking and is returned once there
+    /// are no alternatives left.
+    fn backtrack(&mut self, mut err: Error) -> Result<(), Error> {
+        while let Some(mut choice) = self.choice_stack.pop() {
+            debug!("Solver::backtrack: err={:?}", err);
 
-                            let term = u_head;
+            let alternative = choice.alternatives.pop().unwrap();
 
-                            let bound_vars = t_args.clone();
+            self.constraints = choice.constraints.clone();
+            self.constraint_mapping = choice.constraint_mapping.clone();
+            self.solution_mapping = choice.solution_mapping.clone();
+            self.universes = choice.universes.clone();
 
-                            for arg in u_args {
-                                debug!("{}", arg)
-                            }
+            if choice.alternatives.len() > 0 {
+                self.choice_stack.push(choice);
+            }
 
+            match self.add_constraints(alternative) {
+                Ok(()) => return Ok(()),
+                Err(e) => err = e,
+            }
+        }
 
-                            debug!("t_head {}", t_head);
-                            let infer_ty = try!(self.ty_cx.type_infer_term(&t_head));
+        Err(err)
+    }
 
-                            let t_head = match t_head {
-                                Term::Var { name } => name,
-                                _ => panic!()
-                            };
+    pub fn solve(mut self) -> Result<(HashMap<Name, (Term, Justification)>, HashMap<usize, Level>), Error> {
+        loop {
+            let result = match self.constraints.pop() {
+                Some(c) => self.process(c),
+                None => match self.universes.clone().solve() {
+                    Ok(levels) => return Ok((self.solution_mapping, levels)),
+                    Err(err) => Err(Error::from(err)),
+                },
+            };
 
-                            let locals =
-                                infer_ty.0.binders()
-                                          .unwrap()
-                                          .into_iter()
-                                          .map(|t| self.ty_cx.local_with_repr("x".to_string(), t.clone()))
-                                          .collect();
+            if let Err(err) = result {
+                try!(self.backtrack(err));
+            }
+        }
+    }
 
-                            let solution = Term::abstract_lambda(locals, term);
-                            debug!("infer_ty {}", infer_ty.0);
+    fn process(&mut self, c: CategorizedConstraint) -> Result<(), Error> {
+        debug!("Solver::process: constraint={}", c.constraint);
 
-                            for arg in bound_vars {
-                                debug!("to_bind: {}", arg);
-                            }
+        match c.constraint {
+            Constraint::Choice(term, ty, f, j) => {
+                let term = self.instantiate_solutions(term);
+                let ty = self.instantiate_solutions(ty);
+                let alternatives = try!((f.0)(self.ty_cx, &term, &ty));
+                self.case_split(alternatives, j)
+            }
+            Constraint::Universe(..) =>
+                panic!("universe constraints should never reach the queue"),
+            Constraint::Unification(t, u, j) => {
+                // The meta-variables may have been solved since the constraint
+                // was queued.
+                if self.is_solved(&t) || self.is_solved(&u) {
+                    return self.visit_unification(t, u, j, c.category);
+                }
 
-                            debug!("sol {}; {} = {}", solution, t_head, u);
-                            self.add_solution(t_head, (solution, j));
-                        }
-                        ConstraintCategory::FlexFlex => {
-                            // Need to clean this code up
-                            let t_head = match t.head().unwrap() {
-                                Term::Var { name , .. } => name,
-                                _ => panic!()
-                            };
-
-                            let u_head = match t.head().unwrap() {
-                                Term::Var { name , .. } => name,
-                                _ => panic!()
-                            };
-
-                            if self.solution_for(&t_head) == self.solution_for(&u_head) {
-                                debug!("t {} u {}", t_head, u_head);
-                            } else {
-                                panic!("flex-flex solution is not eq")
-                            }
-                        }
-                        ConstraintCategory::Pattern => {
-                            panic!("solver failure pattern constraints should never reach here")
-                        }
-                        ConstraintCategory::Recursor => {
-                            panic!("can't handle recursor constraints yet")
-                        }
-                        ConstraintCategory::OnDemand  => {
-                            panic!("can't handle on demand constraints yet")
-                        }
-                        ConstraintCategory::Ready |
-                        ConstraintCategory::Regular |
-                        ConstraintCategory::Postponed => {
-                            panic!("unification constraints should never be one of these")
+                match c.category {
+                    ConstraintCategory::QuasiPattern |
+                    ConstraintCategory::FlexRigid => {
+                        self.flex_rigid(t, u, j)
+                    }
+                    ConstraintCategory::FlexFlex => {
+                        self.flex_flex(t, u, j)
+                    }
+                    ConstraintCategory::Pattern => {
+                        panic!("solver failure pattern constraints should never reach here")
+                    }
+                    // Equations between rigid terms which we couldn't decide
+                    // when they were added, they may reduce with the
+                    // solutions we have found since. `simplify` unfolds
+                    // definitions as needed, or reports the mismatch.
+                    ConstraintCategory::Delta |
+                    ConstraintCategory::Recursor |
+                    ConstraintCategory::OnDemand => {
+                        let t = self.instantiate_solutions(t);
+                        let u = self.instantiate_solutions(u);
+
+                        for sc in try!(self.simplify(t, u, j)) {
+                            try!(self.visit(sc));
                         }
+
+                        Ok(())
+                    }
+                    ConstraintCategory::Ready |
+                    ConstraintCategory::Regular |
+                    ConstraintCategory::Postponed => {
+                        panic!("unification constraints should never be one of these")
                     }
                 }
             }
         }
-        Ok(self.solution_mapping)
     }
 
-    pub fn resolve(&self, just: Justification) -> Result<(), Error> {
-        panic!("{:?}", just);
+    /// Solve `?m a_1 ... a_n = u` where `u` is rigid and the arguments are
+    /// not distinct locals. We case split on the shape of `?m`, first
+    /// projecting onto each of its arguments
+    /// `?m := fun xs => x_i (?h_1 xs) ... (?h_p xs)`, and then imitating the
+    /// head of `u = f b_1 ... b_k` with `?m := fun xs => f (?h_1 xs) ... (?h_k xs)`.
+    fn flex_rigid(&mut self, t: Term, u: Term, j: Justification) -> Result<(), Error> {
+        debug!("Solver::flex_rigid: t={} u={}", t, u);
+
+        let (meta, args) = t.uncurry();
+
+        let meta = match meta {
+            Term::Var { name } => name,
+            _ => panic!("flex-rigid constraint without a meta-variable head"),
+        };
+
+        let (locals, result_ty) = match try!(self.meta_locals(&meta, args.len())) {
+            None => return self.case_split(vec![], j),
+            Some(locals) => locals,
+        };
+
+        let mut bodies = vec![];
+
+        for local in &locals {
+            let local_ty = match local {
+                &Name::Local { ref ty, .. } => (**ty).clone(),
+                _ => panic!("type checking bug: {} is not a local", local),
+            };
+
+            let arity = local_ty.binders().map(|bs| bs.len()).unwrap_or(0);
+
+            if let Some(projection) = try!(self.apply_fresh_metas(local.to_term(), local_ty, arity, &locals)) {
+                bodies.push(projection);
+            }
+        }
+
+        let (head, u_args) = u.uncurry();
+
+        match head {
+            // A local not among the arguments is out of the scope of `?m`,
+            // the ones that are have been covered by the projections.
+            Term::Var { ref name } if name.is_local() => {}
+            Term::Var { ref name } if name.is_qual() => {
+                let (head_ty, _) = try!(self.ty_cx.type_infer_term(&head));
+
+                if let Some(imitation) = try!(self.apply_fresh_metas(head.clone(), head_ty, u_args.len(), &locals)) {
+                    bodies.push(imitation);
+                }
+            }
+            // For any other rigid term we only try the constant solution.
+            _ => bodies.push(u.clone()),
+        }
+
+        let pattern = Term::apply_all(
+            meta.to_term(),
+            locals.iter().map(Name::to_term).collect());
+
+        // Each solution must also have the type of `?m`, we drop the ones
+        // that are ill-typed outright.
+        let mut alternatives = vec![];
+
+        for body in bodies {
+            if let Ok((body_ty, mut cs)) = self.ty_cx.type_infer_term(&body) {
+                cs.push(Constraint::Unification(body_ty, result_ty.clone(), j.clone()));
+                cs.push(Constraint::Unification(pattern.clone(), body, j.clone()));
+                cs.push(Constraint::Unification(t.clone(), u.clone(), j.clone()));
+                alternatives.push(cs);
+            }
+        }
+
+        self.case_split(alternatives, j)
+    }
+
+    /// Solve a postponed constraint `?m as = ?n bs` between unsolved
+    /// meta-variables. If either side is a pattern we solve it by abstracting
+    /// over the arguments, otherwise we fall back to the first order
+    /// approximation `?m = ?n, as = bs`.
+    fn flex_flex(&mut self, t: Term, u: Term, j: Justification) -> Result<(), Error> {
+        debug!("Solver::flex_flex: t={} u={}", t, u);
+
+        if t == u {
+            return Ok(());
+        }
+
+        let (t_head, t_args) = t.uncurry();
+        let (u_head, u_args) = u.uncurry();
+
+        // Solving `?m as = ?m bs` by abstraction would give `?m` a solution
+        // mentioning itself.
+        if t_head != u_head {
+            if is_pattern(&t_args) {
+                return self.visit_unification(t, u, j, ConstraintCategory::Pattern);
+            } else if is_pattern(&u_args) {
+                return self.visit_unification(u, t, j, ConstraintCategory::Pattern);
+            }
+        }
+
+        if t_args.len() == u_args.len() {
+            let mut cs = try!(self.simplify(t_head, u_head, j.clone()));
+
+            for (a, b) in t_args.into_iter().zip(u_args.into_iter()) {
+                cs.extend(try!(self.simplify(a, b, j.clone())).into_iter());
+            }
+
+            for c in cs {
+                try!(self.visit(c));
+            }
+
+            Ok(())
+        } else {
+            let j = try!(self.eval_justification(j));
+            Err(Error::Justification(j))
+        }
+    }
+
+    /// Introduce a local for each of the first `n` arguments `meta` takes,
+    /// returning them along with the type of `meta` applied to them, or
+    /// `None` if its type does not have enough binders.
+    fn meta_locals(&self, meta: &Name, n: usize) -> Result<Option<(Vec<Name>, Term)>, Error> {
+        let mut ty = match meta {
+            &Name::Meta { ref ty, .. } => self.instantiate_solutions((**ty).clone()),
+            _ => panic!("type checking bug: {} is not a meta-variable", meta),
+        };
+
+        let mut locals = vec![];
+
+        for _ in 0..n {
+            if !ty.is_forall() {
+                ty = try!(self.ty_cx.eval(&ty));
+            }
+
+            match ty {
+                Term::Forall { binder, term, .. } => {
+                    let local = self.ty_cx.local(binder);
+                    ty = term.instantiate(&local.to_term());
+                    locals.push(local);
+                }
+                _ => return Ok(None),
+            }
+        }
+
+        Ok(Some((locals, ty)))
+    }
+
+    /// Apply `head` of type `ty` to `arity` fresh meta-variables, each of
+    /// which may depend on `locals`. Returns `None` if `head` does not take
+    /// that many arguments.
+    fn apply_fresh_metas(&self, head: Term, ty: Term, arity: usize, locals: &[Name]) -> Result<Option<Term>, Error> {
+        let mut ty = self.instantiate_solutions(ty);
+        let mut args = vec![];
+
+        for _ in 0..arity {
+            if !ty.is_forall() {
+                ty = try!(self.ty_cx.eval(&ty));
+            }
+
+            match ty {
+                Term::Forall { binder, term, .. } => {
+                    let meta_ty = Term::abstract_pi(locals.to_vec(), binder.ty.into_term());
+                    let meta = self.ty_cx.fresh_meta(meta_ty);
+
+                    let arg = Term::apply_all(
+                        meta.to_term(),
+                        locals.iter().map(Name::to_term).collect());
+
+                    ty = term.instantiate(&arg);
+                    args.push(arg);
+                }
+                _ => return Ok(None),
+            }
+        }
+
+        Ok(Some(Term::apply_all(head, args)))
+    }
+
+    fn is_solved(&self, term: &Term) -> bool {
+        match term.is_stuck() {
+            None => false,
+            Some(meta) => self.solution_mapping.contains_key(&meta),
+        }
+    }
+
+    fn instantiate_solutions(&self, term: Term) -> Term {
+        replace_metavars_with_err(term, &self.solution_mapping, &mut vec![])
     }
 }
 
+/// Whether the arguments a meta-variable is applied to are distinct
+/// constants, in which case we can solve for it by abstraction.
+fn is_pattern(args: &[Term]) -> bool {
+    args.iter().all(|a| a.is_constant()) &&
+        args.iter().collect::<HashSet<_>>().len() == args.len()
+}
+
 pub fn replace_metavars(
         term: Term,
         subst_map: &HashMap<Name, (Term, Justification)>) -> Result<Term, Error> {
@@ -538,25 +911,37 @@ pub fn replace_metavars_with_err(
         errs: &mut Vec<Name>) -> Term {
     use core::Term::*;
 
+    if !t.has_metas() {
+        return t;
+    }
+
     match t {
         App { fun, arg, span } => {
             App {
-                fun: Box::new(replace_metavars_with_err(*fun, subst_map, errs)),
-                arg: Box::new(replace_metavars_with_err(*arg, subst_map, errs)),
+                fun: TermRef::new(replace_metavars_with_err(fun.into_term(), subst_map, errs)),
+                arg: TermRef::new(replace_metavars_with_err(arg.into_term(), subst_map, errs)),
                 span: span,
             }
         }
         Forall { binder, term, span } => {
             Forall {
                 binder: subst_meta_binder(binder, subst_map, errs),
-                term: Box::new((replace_metavars_with_err(*term, subst_map, errs))),
+                term: TermRef::new(replace_metavars_with_err(term.into_term(), subst_map, errs)),
                 span: span,
             }
         }
         Lambda { binder, body, span } => {
             Lambda {
                 binder: subst_meta_binder(binder, subst_map, errs),
-                body: Box::new(replace_metavars_with_err(*body, subst_map, errs)),
+                body: TermRef::new(replace_metavars_with_err(body.into_term(), subst_map, errs)),
+                span: span,
+            }
+        }
+        Let { binder, value, body, span } => {
+            Let {
+                binder: subst_meta_binder(binder, subst_map, errs),
+                value: TermRef::new(replace_metavars_with_err(value.into_term(), subst_map, errs)),
+                body: TermRef::new(replace_metavars_with_err(body.into_term(), subst_map, errs)),
                 span: span,
             }
         }
@@ -572,14 +957,80 @@ pub fn replace_metavars_with_err(
 
         }
         v @ Var { .. } => v,
-        Type => Type,
+        s @ Sort { .. } => s,
     }
 }
 
+/// Replace the universe meta-variables in `term` with their solutions,
+/// meta-variables the solver never constrained are given the default level.
+pub fn replace_level_metas(term: Term, levels: &HashMap<usize, Level>) -> Term {
+    term.map_levels(&|l| universe::default_metas(&l.instantiate_metas(levels)).normalize())
+}
+
 pub fn subst_meta_binder(
         mut b: Binder,
         subst_map: &HashMap<Name, (Term, Justification)>,
         errs: &mut Vec<Name>) -> Binder {
-    b.ty = Box::new(replace_metavars_with_err(*b.ty, subst_map, errs));
+    b.ty = TermRef::new(replace_metavars_with_err(b.ty.into_term(), subst_map, errs));
     b
 }
+
+/// Whether the heads of `t` and `u` are the same global, perhaps
+/// instantiated at different levels.
+fn same_head(t: &Term, u: &Term) -> bool {
+    match (t.uncurry().0, u.uncurry().0) {
+        (Term::Var { name: ref f }, Term::Var { name: ref g }) =>
+            f.same_variable(g) && f.levels().len() == g.levels().len(),
+        _ => false,
+    }
+}
+
+/// Whether `t` and `u` are the same term up to the levels of the globals
+/// and sorts they mention, generating the universe constraints between
+/// those levels.
+fn level_constraints(t: &Term,
+                     u: &Term,
+                     j: &Justification,
+                     cs: &mut Vec<CategorizedConstraint>) -> bool {
+    use core::Term::*;
+
+    match (t, u) {
+        (&Var { name: ref n1 }, &Var { name: ref n2 }) => {
+            let (ls1, ls2) = (n1.levels(), n2.levels());
+
+            if !n1.same_variable(n2) || ls1.len() != ls2.len() {
+                return false;
+            }
+
+            for (l1, l2) in ls1.iter().zip(ls2.iter()) {
+                let c = Constraint::Universe(l1.clone(), l2.clone(), j.clone());
+                cs.push(c.categorize());
+            }
+
+            true
+        }
+        (&Sort { level: ref l1 }, &Sort { level: ref l2 }) => {
+            cs.push(Constraint::Universe(l1.clone(), l2.clone(), j.clone()).categorize());
+            true
+        }
+        (&App { fun: ref fun1, arg: ref arg1, .. },
+         &App { fun: ref fun2, arg: ref arg2, .. }) =>
+            level_constraints(fun1, fun2, j, cs) &&
+            level_constraints(arg1, arg2, j, cs),
+        (&Forall { binder: ref binder1, term: ref body1, .. },
+         &Forall { binder: ref binder2, term: ref body2, .. }) |
+        (&Lambda { binder: ref binder1, body: ref body1, .. },
+         &Lambda { binder: ref binder2, body: ref body2, .. }) =>
+            binder1.name == binder2.name &&
+            binder1.mode == binder2.mode &&
+            level_constraints(&binder1.ty, &binder2.ty, j, cs) &&
+            level_constraints(body1, body2, j, cs),
+        (&Let { binder: ref binder1, value: ref value1, body: ref body1, .. },
+         &Let { binder: ref binder2, value: ref value2, body: ref body2, .. }) =>
+            binder1.name == binder2.name &&
+            level_constraints(&binder1.ty, &binder2.ty, j, cs) &&
+            level_constraints(value1, value2, j, cs) &&
+            level_constraints(body1, body2, j, cs),
+        _ => false,
+    }
+}
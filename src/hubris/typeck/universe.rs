@@ -0,0 +1,150 @@
+use core::Level;
+use super::constraint::Justification;
+
+use std::collections::HashMap;
+
+/// A universe constraint `l1 = l2`, along with the reason it was generated.
+pub type LevelConstraint = (Level, Level, Justification);
+
+#[derive(Debug)]
+pub enum Error {
+    /// Solving the constraint would require `?u = ?u + k` for some `k > 0`.
+    Cycle(Level, Level, Justification),
+    /// The two levels are distinct for any assignment of meta-variables.
+    Mismatch(Level, Level, Justification),
+}
+
+/// `UniverseCx` collects the universe constraints generated while solving
+/// a set of unification constraints, and assigns universe meta-variables.
+///
+/// Constraints that can not be solved immediately (for example ones that
+/// mention a meta-variable under a `max`) are postponed until more
+/// meta-variables have been assigned.
+#[derive(Clone)]
+pub struct UniverseCx {
+    pub solutions: HashMap<usize, Level>,
+    postponed: Vec<LevelConstraint>,
+}
+
+enum Step {
+    Solved,
+    Postpone,
+}
+
+impl UniverseCx {
+    pub fn new() -> UniverseCx {
+        UniverseCx {
+            solutions: HashMap::new(),
+            postponed: vec![],
+        }
+    }
+
+    pub fn add_constraint(&mut self, l1: Level, l2: Level, j: Justification) -> Result<(), Error> {
+        debug!("UniverseCx::add_constraint: {} = {}", l1, l2);
+
+        match try!(self.step(&l1, &l2, &j)) {
+            Step::Solved => Ok(()),
+            Step::Postpone => {
+                self.postponed.push((l1, l2, j));
+                Ok(())
+            }
+        }
+    }
+
+    fn assign(&mut self, meta: usize, level: Level) {
+        debug!("UniverseCx::assign: ?u{} := {}", meta, level);
+        self.solutions.insert(meta, level);
+    }
+
+    /// Try to make progress on a single constraint.
+    fn step(&mut self, l1: &Level, l2: &Level, j: &Justification) -> Result<Step, Error> {
+        let l1 = l1.instantiate_metas(&self.solutions).normalize();
+        let l2 = l2.instantiate_metas(&self.solutions).normalize();
+
+        if l1.is_equiv(&l2) {
+            return Ok(Step::Solved);
+        }
+
+        let (b1, k1) = l1.to_offset();
+        let (b2, k2) = l2.to_offset();
+
+        // ?u + k1 = b2 + k2
+        if let &Level::Meta(m) = b1 {
+            if b2 == b1 {
+                // The only way to get here is if the offsets differ.
+                return Err(Error::Cycle(l1.clone(), l2.clone(), j.clone()));
+            } else if l2.occurs_meta(m) {
+                return Ok(Step::Postpone);
+            } else if let Some(level) = l2.without_offset(k1) {
+                self.assign(m, level);
+                return Ok(Step::Solved);
+            }
+        }
+
+        // b1 + k1 = ?u + k2
+        if let &Level::Meta(m) = b2 {
+            if l1.occurs_meta(m) {
+                return Ok(Step::Postpone);
+            } else if let Some(level) = l1.without_offset(k2) {
+                self.assign(m, level);
+                return Ok(Step::Solved);
+            }
+        }
+
+        if !l1.has_meta() && !l2.has_meta() || b1.is_zero() && b2.is_zero() {
+            Err(Error::Mismatch(l1.clone(), l2.clone(), j.clone()))
+        } else if b1.is_meta() && b2.is_zero() || b2.is_meta() && b1.is_zero() {
+            // ?u + k1 = k2 with k1 > k2 has no solution.
+            Err(Error::Mismatch(l1.clone(), l2.clone(), j.clone()))
+        } else {
+            Ok(Step::Postpone)
+        }
+    }
+
+    /// Revisit the postponed constraints until no more progress can be made,
+    /// returning the assignment of universe meta-variables.
+    pub fn solve(mut self) -> Result<HashMap<usize, Level>, Error> {
+        loop {
+            let postponed = ::std::mem::replace(&mut self.postponed, vec![]);
+            let count = postponed.len();
+
+            for (l1, l2, j) in postponed {
+                try!(self.add_constraint(l1, l2, j));
+            }
+
+            if self.postponed.len() == count {
+                break;
+            }
+        }
+
+        // Anything left at this point is of the form `max ?u l = l'` with `?u`
+        // still unassigned, we check that the constraint holds when we pick
+        // the smallest solution.
+        let postponed = ::std::mem::replace(&mut self.postponed, vec![]);
+
+        for (l1, l2, j) in postponed {
+            let l1 = default_metas(&l1.instantiate_metas(&self.solutions));
+            let l2 = default_metas(&l2.instantiate_metas(&self.solutions));
+
+            if !l1.is_equiv(&l2) {
+                return Err(Error::Mismatch(l1, l2, j));
+            }
+        }
+
+        Ok(self.solutions)
+    }
+}
+
+/// The level we choose for universe meta-variables that were never
+/// constrained by the solver.
+pub fn default_level() -> Level {
+    Level::one()
+}
+
+/// Replace every unassigned meta-variable with the default level.
+pub fn default_metas(level: &Level) -> Level {
+    level.replace(&|l| match l {
+        &Level::Meta(_) => Some(default_level()),
+        _ => None,
+    })
+}
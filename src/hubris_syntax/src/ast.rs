@@ -105,7 +105,7 @@ impl Pretty for Name {
 
         let s = match &self.repr {
             &Unqualified(ref s) => s.clone(),
-            &Qualified(ref qn) => format!("{:?}", qn),
+            &Qualified(ref qn) => qn.join("."),
             &Placeholder => String::from("_"),
         };
         Doc::text(s)
@@ -154,7 +154,12 @@ impl Module {
 #[derive(Debug, PartialEq, Clone)]
 pub enum Item {
     Inductive(Inductive),
+    Mutual(Mutual),
+    Structure(Structure),
     Def(Def),
+    /// An instance of a class, found by instance resolution when an
+    /// argument is bound by an instance-implicit binder.
+    Instance(Def),
     Axiom(Axiom),
     Extern(Extern),
     Comment(String),
@@ -167,7 +172,10 @@ impl HasSpan for Item {
 
         match self {
             &Inductive(ref data) => data.span,
+            &Mutual(ref mutual) => mutual.span,
+            &Structure(ref s) => s.span,
             &Def(ref fun) => fun.span,
+            &Instance(ref inst) => inst.span,
             &Axiom(ref a) => a.span,
             &Extern(ref ext) => ext.span,
             &Comment(_) => Span::dummy(),
@@ -181,8 +189,14 @@ impl HasSpan for Item {
         match self {
             &mut Inductive(ref mut inductive) =>
                 inductive.span = sp,
+            &mut Mutual(ref mut mutual) =>
+                mutual.span = sp,
+            &mut Structure(ref mut s) =>
+                s.span = sp,
             &mut Def(ref mut def) =>
                 def.span = sp,
+            &mut Instance(ref mut inst) =>
+                inst.span = sp,
             &mut Axiom(ref mut a) =>
                 a.span = sp,
             &mut Extern(ref mut ext) =>
@@ -197,6 +211,8 @@ impl HasSpan for Item {
 pub enum BindingMode {
     Explicit,
     Implicit,
+    /// An argument found by instance resolution, `[Ord A]`.
+    InstImplicit,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -211,6 +227,7 @@ pub struct Binder {
 pub struct Inductive {
     pub span: Span,
     pub name: Name,
+    pub univ_params: Vec<Name>,
     pub parameters: Vec<Binder>,
     pub ty: Term,
     pub ctors: Vec<Constructor>
@@ -218,6 +235,31 @@ pub struct Inductive {
 
 pub type Constructor = (Name, Term);
 
+/// A group of inductive types which may refer to each other in their
+/// constructors.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Mutual {
+    pub span: Span,
+    pub inductives: Vec<Inductive>,
+}
+
+/// An inductive type with a single constructor, whose arguments are named
+/// fields, `structure Prod (A B : Type) := MkProd :: (first : A) (second : B) end`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Structure {
+    pub span: Span,
+    pub name: Name,
+    pub univ_params: Vec<Name>,
+    pub parameters: Vec<Binder>,
+    pub ty: Term,
+    /// The name of the constructor, `T.mk` if it is omitted.
+    pub ctor: Option<Name>,
+    pub fields: Vec<Binder>,
+    /// Whether this was declared as a `class`, whose instances can be
+    /// found by instance resolution.
+    pub class: bool,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Extern {
     pub span: Span,
@@ -229,9 +271,19 @@ pub struct Extern {
 pub struct Def {
     pub span: Span,
     pub name: Name,
+    pub univ_params: Vec<Name>,
     pub args: Vec<Binder>,
     pub ty: Term,
     pub body: Term,
+    /// A measure on the arguments which decreases on each recursive call,
+    /// for definitions which aren't structurally recursive.
+    pub termination_by: Option<Term>,
+    /// The proofs that the measure decreases, one for each recursive call
+    /// in the order they appear, or a single one supplied to all of them.
+    pub decreasing_by: Vec<Term>,
+    /// The attributes written before the definition, `@[reducible]` for
+    /// example.
+    pub attributes: Vec<Name>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -245,11 +297,13 @@ pub struct Axiom {
 pub enum Term {
     Literal { span: Span, lit: Literal },
     Var { name: Name, implicit: bool },
-    Match { span: Span, scrutinee: Box<Term>, cases: Vec<Case> },
+    Match { span: Span, scrutinees: Vec<Term>, cases: Vec<Case> },
     App { span: Span, fun: Box<Term>, arg: Box<Term> },
     Forall { span: Span, binders: Vec<Binder>, term: Box<Term> },
     Lambda { span: Span, args: Vec<Binder>, ret_ty: Box<Option<Term>>, body: Box<Term> },
     Let { span: Span, bindings: Vec<(Binder, Term)>, body: Box<Term> },
+    Sort { span: Span, level: Level },
+    Prop,
     Type,
 }
 
@@ -327,12 +381,15 @@ impl Pretty for Term {
                 // "fun ".pretty() + pretty_binders(binders.as_slice()) + " => ".pretty() + cursor.pretty()
             }
             &Let { .. } => panic!(),
-            &Match { ref scrutinee, ref cases, .. } => {
+            &Match { ref scrutinees, ref cases, .. } => {
+                let scrutinees : Vec<_> = scrutinees.iter().map(|x| x.pretty()).collect();
                 let cases : Vec<_> = cases.iter().map(|x| x.pretty()).collect();
-                "match ".pretty() + scrutinee.pretty() + " with\n".pretty() +
+                "match ".pretty() + seperate(&scrutinees[..], &", ".pretty()) + " with\n".pretty() +
                 seperate(&cases[..], &"\n".pretty()) + "\nend".pretty()
             }
             &Literal { .. } => panic!(),
+            &Sort { ref level, .. } => "Sort.{".pretty() + level.pretty() + "}".pretty(),
+            &Prop => Doc::text("Prop"),
             &Type => Doc::text("Type"),
         }
     }
@@ -350,7 +407,8 @@ impl HasSpan for Term {
             &Forall { span, .. } => span,
             &Lambda { span, .. } => span,
             &Let { span, .. } => span,
-            &Type => Span::dummy(),
+            &Sort { span, .. } => span,
+            &Prop | &Type => Span::dummy(),
         }
     }
 
@@ -365,15 +423,49 @@ impl HasSpan for Term {
             &mut Forall { ref mut span, .. } => *span = sp,
             &mut Lambda { ref mut span, .. } => *span = sp,
             &mut Let { ref mut span, .. } => *span = sp,
-            &mut Type => {},
+            &mut Sort { ref mut span, .. } => *span = sp,
+            &mut Prop | &mut Type => {},
         }
     }
 }
 
+/// A universe level as written in the source, for example `u`, `u+1` or
+/// `max u v`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Level {
+    Name(Name),
+    Num(usize),
+    Plus(Box<Level>, usize),
+    App(Name, Vec<Level>),
+}
+
+impl Pretty for Level {
+    fn pretty(&self) -> Doc {
+        use self::Level::*;
+
+        match self {
+            &Name(ref n) => n.pretty(),
+            &Num(n) => Doc::text(format!("{}", n)),
+            &Plus(ref l, n) => l.pretty() + Doc::text(format!("+{}", n)),
+            &App(ref f, ref ls) => {
+                let ls: Vec<_> = ls.iter().map(|l| parens(l.pretty())).collect();
+                f.pretty() + " ".pretty() + seperate(&ls[..], &" ".pretty())
+            }
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        format(self, formatter)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Case {
     pub span: Span,
-    pub pattern: Pattern,
+    /// A pattern for each of the scrutinees of the match.
+    pub patterns: Vec<Pattern>,
     pub rhs: Term,
 }
 
@@ -386,7 +478,8 @@ impl Display for Case {
 impl Pretty for Case {
     fn pretty(&self) -> Doc {
         use self::Term::*;
-        "| ".pretty() + self.pattern.pretty() + " => ".pretty() + self.rhs.pretty()
+        let patterns : Vec<_> = self.patterns.iter().map(|x| x.pretty()).collect();
+        "| ".pretty() + seperate(&patterns[..], &", ".pretty()) + " => ".pretty() + self.rhs.pretty()
     }
 }
 
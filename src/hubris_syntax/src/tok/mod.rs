@@ -32,6 +32,8 @@ pub enum Tok<'input> {
     // Keywords;
     Def,
     Axiom,
+    Class,
+    DecreasingBy,
     End,
     Extern,
     Forall,
@@ -39,9 +41,15 @@ pub enum Tok<'input> {
     Inductive,
     In,
     Import,
+    Instance,
     Let,
     Match,
     Module,
+    Mutual,
+    Prop,
+    Sort,
+    Structure,
+    TerminationBy,
     Type,
     With,
 
@@ -52,10 +60,11 @@ pub enum Tok<'input> {
     Id(&'input str),
     DocComment(String),
     StringLiteral(&'input str),
-    // NumericLit(&'input str),
+    Num(&'input str),
 
     Arrow,
     At,
+    AtLeftBracket,
     Bar,
     BangEquals,
     Colon,
@@ -63,6 +72,7 @@ pub enum Tok<'input> {
     ColonEq,
     Comma,
     DotDot,
+    DotLeftBrace,
     Equals,
     EqualsEquals,
     FatArrow,
@@ -91,6 +101,8 @@ impl<'input> Display for Tok<'input> {
         match self {
             &Def => write!(formatter, "def"),
             &Axiom => write!(formatter, "axiom"),
+            &Class => write!(formatter, "class"),
+            &DecreasingBy => write!(formatter, "decreasing_by"),
             &End => write!(formatter, "end"),
             &Extern => write!(formatter, "extern"),
             &Forall => write!(formatter, "forall"),
@@ -98,15 +110,23 @@ impl<'input> Display for Tok<'input> {
             &Inductive => write!(formatter, "inductive"),
             &In => write!(formatter, "in"),
             &Import => write!(formatter, "import"),
+            &Instance => write!(formatter, "instance"),
             &Let => write!(formatter, "let"),
             &Match => write!(formatter, "match"),
             &Module => write!(formatter, "module"),
+            &Mutual => write!(formatter, "mutual"),
+            &Prop => write!(formatter, "Prop"),
+            &Sort => write!(formatter, "Sort"),
+            &Structure => write!(formatter, "structure"),
+            &TerminationBy => write!(formatter, "termination_by"),
             &Type => write!(formatter, "type"),
             &With => write!(formatter, "with"),
             &Id(id) => write!(formatter, "{}", id),
             &DocComment(_) => panic!(),
             &StringLiteral(lit) => write!(formatter, "{}", lit),
+            &Num(n) => write!(formatter, "{}", n),
             &At => write!(formatter, "@"),
+            &AtLeftBracket => write!(formatter, "@["),
             &Arrow => write!(formatter, "->"),
             &Bar => write!(formatter, "|"),
             &BangEquals => write!(formatter, "!="),
@@ -115,6 +135,7 @@ impl<'input> Display for Tok<'input> {
             &ColonEq => write!(formatter, ":="),
             &Comma => write!(formatter, ","),
             &DotDot => write!(formatter, ".."),
+            &DotLeftBrace => write!(formatter, ".{{"),
             &Equals => write!(formatter, "="),
             &EqualsEquals => write!(formatter, "=="),
             &FatArrow => write!(formatter, "=>"),
@@ -156,6 +177,8 @@ pub type Spanned<T> = (usize, T, usize);
 const KEYWORDS: &'static [(&'static str, Tok<'static>)] = &[
     ("def", Def),
     ("axiom", Axiom),
+    ("class", Class),
+    ("decreasing_by", DecreasingBy),
     ("end", End),
     ("extern", Extern),
     ("forall", Forall),
@@ -163,9 +186,15 @@ const KEYWORDS: &'static [(&'static str, Tok<'static>)] = &[
     ("in", In),
     ("inductive", Inductive),
     ("import", Import),
+    ("instance", Instance),
     ("let", Let),
     ("match", Match),
     ("module", Module),
+    ("mutual", Mutual),
+    ("Prop", Prop),
+    ("Sort", Sort),
+    ("structure", Structure),
+    ("termination_by", TerminationBy),
     ("Type", Type),
     ("with", With),
 ];
@@ -221,6 +250,10 @@ impl<'input> Tokenizer<'input> {
                             self.bump();
                             Some(Ok((idx0, DotDot, idx1+1)))
                         }
+                        Some((idx1, '{')) => {
+                            self.bump();
+                            Some(Ok((idx0, DotLeftBrace, idx1+1)))
+                        }
                         _ => {
                             Some(Ok((idx0, Period, idx0+1)))
                         }
@@ -266,8 +299,15 @@ impl<'input> Tokenizer<'input> {
                     Some(Ok((idx0, LessThan, idx0+1)))
                 }
                 Some((idx0, '@')) => {
-                    self.bump();
-                    Some(Ok((idx0, At, idx0+1)))
+                    match self.bump() {
+                        Some((idx1, '[')) => {
+                            self.bump();
+                            Some(Ok((idx0, AtLeftBracket, idx1+1)))
+                        }
+                        _ => {
+                            Some(Ok((idx0, At, idx0+1)))
+                        }
+                    }
                 }
                 Some((idx0, '+')) => {
                     self.bump();
@@ -347,6 +387,9 @@ impl<'input> Tokenizer<'input> {
                 Some((idx0, c)) if is_identifier_start(c) => {
                     Some(self.identifierish(idx0))
                 }
+                Some((idx0, c)) if c.is_digit(10) => {
+                    Some(self.number(idx0))
+                }
                 Some((_, c)) if c.is_whitespace() => {
                     self.bump();
                     continue;
@@ -449,6 +492,13 @@ impl<'input> Tokenizer<'input> {
         Ok((start, tok, end))
     }
 
+    fn number(&mut self, idx0: usize) -> Result<Spanned<Tok<'input>>, Error> {
+        match self.take_while(|c| c.is_digit(10)) {
+            Some(end) => Ok((idx0, Num(&self.text[idx0..end]), end)),
+            None => Ok((idx0, Num(&self.text[idx0..]), self.text.len())),
+        }
+    }
+
     fn word(&mut self, idx0: usize) -> Spanned<&'input str> {
         match self.take_while(is_identifier_continue) {
             Some(end) => (idx0, &self.text[idx0..end], end),
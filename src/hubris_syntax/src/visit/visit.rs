@@ -13,6 +13,10 @@ pub trait Visitor<'v> : Sized {
         walk_inductive(self, inductive)
     }
 
+    fn visit_structure(&mut self, structure: &'v Structure) {
+        walk_structure(self, structure)
+    }
+
     fn visit_extern(&mut self, ext: &'v Extern) {
         walk_extern(self, ext)
     }
@@ -63,7 +67,14 @@ pub fn walk_module<'v, V: Visitor<'v>>(visitor: &mut V, module: &'v Module) {
 pub fn walk_item<'v, V: Visitor<'v>>(visitor: &mut V, item: &'v Item) {
     match item {
         &Item::Inductive(ref d) => visitor.visit_data(d),
-        &Item::Def(ref def) => visitor.visit_def(def),
+        &Item::Mutual(ref m) => {
+            for d in &m.inductives {
+                visitor.visit_data(d)
+            }
+        }
+        &Item::Structure(ref s) => visitor.visit_structure(s),
+        &Item::Def(ref def) |
+        &Item::Instance(ref def) => visitor.visit_def(def),
         &Item::Axiom(ref a) => visitor.visit_axiom(a),
         &Item::Extern(ref ext) => visitor.visit_extern(ext),
         &Item::Comment(ref _s) => panic!(),
@@ -75,6 +86,10 @@ pub fn walk_inductive<'v, V: Visitor<'v>>(visitor: &mut V, inductive: &'v Induct
     visitor.visit_span(&inductive.span);
     visitor.visit_name(&inductive.name);
 
+    for name in &inductive.univ_params {
+        visitor.visit_name(name);
+    }
+
     for binder in &inductive.parameters {
         visitor.visit_binder(binder);
     }
@@ -87,16 +102,56 @@ pub fn walk_inductive<'v, V: Visitor<'v>>(visitor: &mut V, inductive: &'v Induct
     }
 }
 
+pub fn walk_structure<'v, V: Visitor<'v>>(visitor: &mut V, structure: &'v Structure) {
+    visitor.visit_span(&structure.span);
+    visitor.visit_name(&structure.name);
+
+    for name in &structure.univ_params {
+        visitor.visit_name(name);
+    }
+
+    for binder in &structure.parameters {
+        visitor.visit_binder(binder);
+    }
+
+    visitor.visit_term(&structure.ty);
+
+    if let Some(ref ctor) = structure.ctor {
+        visitor.visit_name(ctor);
+    }
+
+    for binder in &structure.fields {
+        visitor.visit_binder(binder);
+    }
+}
+
 pub fn walk_def<'v, V: Visitor<'v>>(visitor: &mut V, def: &'v Def) {
     visitor.visit_span(&def.span);
+
+    for attribute in &def.attributes {
+        visitor.visit_name(attribute);
+    }
+
     visitor.visit_name(&def.name);
 
+    for name in &def.univ_params {
+        visitor.visit_name(name);
+    }
+
     for binder in &def.args {
         visitor.visit_binder(binder);
     }
 
     visitor.visit_term(&def.ty);
     visitor.visit_term(&def.body);
+
+    if let Some(ref measure) = def.termination_by {
+        visitor.visit_term(measure);
+    }
+
+    for proof in &def.decreasing_by {
+        visitor.visit_term(proof);
+    }
 }
 
 pub fn walk_axiom<'v, V: Visitor<'v>>(visitor: &mut V, a: &'v Axiom) {
@@ -121,9 +176,11 @@ pub fn walk_term<'v, V: Visitor<'v>>(visitor: &mut V, term: &'v Term) {
         }
         &Var { ref name, .. } =>
             visitor.visit_name(name),
-        &Match { ref span, ref scrutinee, ref cases } => {
+        &Match { ref span, ref scrutinees, ref cases } => {
             visitor.visit_span(span);
-            visitor.visit_term(scrutinee);
+            for scrutinee in scrutinees {
+                visitor.visit_term(scrutinee);
+            }
             for case in cases {
                 visitor.visit_case(case);
             }
@@ -157,21 +214,47 @@ pub fn walk_term<'v, V: Visitor<'v>>(visitor: &mut V, term: &'v Term) {
         }
         &Let { ref span, ref bindings, ref body } => {
             visitor.visit_span(span);
-            panic!()
+
+            for &(ref binder, ref value) in bindings {
+                visitor.visit_binder(binder);
+                visitor.visit_term(value);
+            }
+
+            visitor.visit_term(body);
+        }
+        &Sort { ref span, ref level } => {
+            visitor.visit_span(span);
+            walk_level(visitor, level);
+        }
+        &Prop | &Type => {}
+    }
+}
+
+pub fn walk_level<'v, V: Visitor<'v>>(visitor: &mut V, level: &'v Level) {
+    match level {
+        &Level::Name(ref n) => visitor.visit_name(n),
+        &Level::Num(_) => {}
+        &Level::Plus(ref l, _) => walk_level(visitor, l),
+        &Level::App(ref f, ref ls) => {
+            visitor.visit_name(f);
+            for l in ls {
+                walk_level(visitor, l);
+            }
         }
-        &Type => {}
     }
 }
 
 pub fn walk_case<'v, V: Visitor<'v>>(visitor: &mut V, case: &'v Case) {
     let &Case {
         ref span,
-        ref pattern,
+        ref patterns,
         ref rhs,
     } = case;
 
     visitor.visit_span(span);
-    visitor.visit_pattern(pattern);
+    for pattern in patterns {
+        visitor.visit_pattern(pattern);
+    }
     visitor.visit_term(rhs);
 }
 
@@ -13,6 +13,10 @@ pub trait VisitorMut<'v> : Sized {
         walk_mut_inductive(self, inductive)
     }
 
+    fn visit_mut_structure(&mut self, structure: &'v mut Structure) {
+        walk_mut_structure(self, structure)
+    }
+
     fn visit_mut_extern(&mut self, ext: &'v mut Extern) {
         walk_mut_extern(self, ext)
     }
@@ -62,7 +66,14 @@ pub fn walk_mut_module<'v, V: VisitorMut<'v>>(visitor: &mut V, module: &'v mut M
 pub fn walk_mut_item<'v, V: VisitorMut<'v>>(visitor: &mut V, item: &'v mut Item) {
     match item {
         &mut Item::Inductive(ref mut d) => visitor.visit_mut_data(d),
-        &mut Item::Def(ref mut def) => visitor.visit_mut_def(def),
+        &mut Item::Mutual(ref mut m) => {
+            for d in &mut m.inductives {
+                visitor.visit_mut_data(d)
+            }
+        }
+        &mut Item::Structure(ref mut s) => visitor.visit_mut_structure(s),
+        &mut Item::Def(ref mut def) |
+        &mut Item::Instance(ref mut def) => visitor.visit_mut_def(def),
         &mut Item::Axiom(ref mut a) => visitor.visi_mut_axiom(a),
         &mut Item::Extern(ref mut ext) => panic!(),
         &mut Item::Comment(ref mut s) => panic!(),
@@ -74,6 +85,10 @@ pub fn walk_mut_inductive<'v, V: VisitorMut<'v>>(visitor: &mut V, inductive: &'v
     visitor.visit_mut_span(&mut inductive.span);
     visitor.visit_mut_name(&mut inductive.name);
 
+    for name in &mut inductive.univ_params {
+        visitor.visit_mut_name(name);
+    }
+
     for binder in &mut inductive.parameters {
         visitor.visit_mut_binder(binder);
     }
@@ -86,16 +101,56 @@ pub fn walk_mut_inductive<'v, V: VisitorMut<'v>>(visitor: &mut V, inductive: &'v
     }
 }
 
+pub fn walk_mut_structure<'v, V: VisitorMut<'v>>(visitor: &mut V, structure: &'v mut Structure) {
+    visitor.visit_mut_span(&mut structure.span);
+    visitor.visit_mut_name(&mut structure.name);
+
+    for name in &mut structure.univ_params {
+        visitor.visit_mut_name(name);
+    }
+
+    for binder in &mut structure.parameters {
+        visitor.visit_mut_binder(binder);
+    }
+
+    visitor.visit_mut_term(&mut structure.ty);
+
+    if let Some(ref mut ctor) = structure.ctor {
+        visitor.visit_mut_name(ctor);
+    }
+
+    for binder in &mut structure.fields {
+        visitor.visit_mut_binder(binder);
+    }
+}
+
 pub fn walk_mut_def<'v, V: VisitorMut<'v>>(visitor: &mut V, def: &'v mut Def) {
     visitor.visit_mut_span(&mut def.span);
+
+    for attribute in &mut def.attributes {
+        visitor.visit_mut_name(attribute);
+    }
+
     visitor.visit_mut_name(&mut def.name);
 
+    for name in &mut def.univ_params {
+        visitor.visit_mut_name(name);
+    }
+
     for binder in &mut def.args {
         visitor.visit_mut_binder(binder);
     }
 
     visitor.visit_mut_term(&mut def.ty);
     visitor.visit_mut_term(&mut def.body);
+
+    if let Some(ref mut measure) = def.termination_by {
+        visitor.visit_mut_term(measure);
+    }
+
+    for proof in &mut def.decreasing_by {
+        visitor.visit_mut_term(proof);
+    }
 }
 
 pub fn walk_mut_axiom<'v, V: VisitorMut<'v>>(visitor: &mut V, a: &'v mut Axiom) {
@@ -120,9 +175,11 @@ pub fn walk_mut_term<'v, V: VisitorMut<'v>>(visitor: &mut V, term: &'v mut Term)
         }
         &mut Var { ref mut name, .. } =>
             visitor.visit_mut_name(name),
-        &mut Match { ref mut span, ref mut scrutinee, ref mut cases } => {
+        &mut Match { ref mut span, ref mut scrutinees, ref mut cases } => {
             visitor.visit_mut_span(span);
-            visitor.visit_mut_term(scrutinee);
+            for scrutinee in scrutinees {
+                visitor.visit_mut_term(scrutinee);
+            }
             for case in cases {
                 visitor.visit_mut_case(case);
             }
@@ -157,21 +214,47 @@ pub fn walk_mut_term<'v, V: VisitorMut<'v>>(visitor: &mut V, term: &'v mut Term)
         }
         &mut Let { ref mut span, ref mut bindings, ref mut body } => {
             visitor.visit_mut_span(span);
-            panic!()
+
+            for &mut (ref mut binder, ref mut value) in bindings {
+                visitor.visit_mut_binder(binder);
+                visitor.visit_mut_term(value);
+            }
+
+            visitor.visit_mut_term(body);
+        }
+        &mut Sort { ref mut span, ref mut level } => {
+            visitor.visit_mut_span(span);
+            walk_mut_level(visitor, level);
+        }
+        &mut Prop | &mut Type => {}
+    }
+}
+
+pub fn walk_mut_level<'v, V: VisitorMut<'v>>(visitor: &mut V, level: &'v mut Level) {
+    match level {
+        &mut Level::Name(ref mut n) => visitor.visit_mut_name(n),
+        &mut Level::Num(_) => {}
+        &mut Level::Plus(ref mut l, _) => walk_mut_level(visitor, l),
+        &mut Level::App(ref mut f, ref mut ls) => {
+            visitor.visit_mut_name(f);
+            for l in ls {
+                walk_mut_level(visitor, l);
+            }
         }
-        &mut Type => {}
     }
 }
 
 pub fn walk_mut_case<'v, V: VisitorMut<'v>>(visitor: &mut V, case: &'v mut Case) {
     let &mut Case {
         ref mut span,
-        ref mut pattern,
+        ref mut patterns,
         ref mut rhs,
     } = case;
 
     visitor.visit_mut_span(span);
-    visitor.visit_mut_pattern(pattern);
+    for pattern in patterns {
+        visitor.visit_mut_pattern(pattern);
+    }
     visitor.visit_mut_term(rhs);
 }
 